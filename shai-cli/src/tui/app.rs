@@ -3,7 +3,7 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use chrono::Utc;
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind};
 use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode};
 use crossterm::{execute, cursor, ExecutableCommand};
 use futures::{future::FutureExt, select, StreamExt};
@@ -12,16 +12,20 @@ use ratatui::prelude::CrosstermBackend;
 use ratatui::style::Stylize;
 use ratatui::text::{Line, Span, Text};
 use ratatui::Terminal;
-use shai_core::agent::{Agent, AgentRequest, AgentEvent, AgentController, PublicAgentState};
+use shai_core::agent::{Agent, AgentError, AgentRequest, AgentEvent, AgentController, AgentEnvelope, AgentPool, PublicAgentState, SessionUsage};
 use shai_core::agent::events::{PermissionRequest, PermissionResponse};
 use shai_core::agent::output::PrettyFormatter;
 use shai_core::config::config::ShaiConfig;
 use shai_core::config::agent::AgentConfig;
+use shai_core::config::settings::Settings;
 use shai_core::agent::builder::AgentBuilder;
 use shai_core::logging::LoggingConfig;
 use shai_core::runners::coder::coder::coder;
 use shai_core::tools::{ToolCall, ToolResult};
-use shai_llm::{LlmClient, ToolCallMethod};
+use shai_core::runners::compacter::CompressionOutcome;
+use shai_core::runners::compacter::compact::COMPRESSION_THRESHOLD;
+use shai_llm::{get_max_context, LlmClient, PriceTable, ToolCallMethod};
+use shai_llm::max_context::DEFAULT_MAX_CONTEXT;
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     style::{Color, Style},
@@ -29,13 +33,14 @@ use ratatui::{
     Frame, TerminalOptions, Viewport
 };
 use tokio::sync::broadcast;
-use tokio::task::JoinHandle;
 use tokio::time::{interval, Duration};
 use tui_textarea::Input;
 use ansi_to_tui::IntoText;
 use std::collections::{HashMap, VecDeque};
 
-use crate::tui::input::InputArea;
+use crate::tui::hyperlink::{link_paths, print_links};
+use crate::tui::input::{InputArea, PathStyle};
+use crate::tui::theme::Theme;
 use super::input::UserAction;
 use crate::tui::perm::PermissionWidget;
 use crate::tui::perm_alt_screen::AlternateScreenPermissionModal;
@@ -49,10 +54,12 @@ pub enum AppModalState<'a> {
     }
 }
 
+/// Agents of the session, the input talks to the focused one
 pub struct AppRunningAgent {
-    pub(crate) handle:     JoinHandle<()>,
-    pub(crate) events:     broadcast::Receiver<AgentEvent>,
-    pub(crate) controller: AgentController,
+    pub(crate) pool:       AgentPool,
+    pub(crate) events:     broadcast::Receiver<AgentEnvelope>,
+    pub(crate) focused:    String,
+    pub(crate) controller: AgentController, // controller of the focused agent
 }
 
 pub struct App<'a> {
@@ -68,54 +75,105 @@ pub struct App<'a> {
     pub(crate) input: InputArea<'a>,       // input text
     pub(crate) commands: HashMap<(String, String),Vec<String>>,
     pub(crate) exit: bool,
-    pub(crate) permission_queue: VecDeque<(String, PermissionRequest)>, // (request_id, request)
-
-    pub(crate) total_input_tokens: u32,
-    pub(crate) total_output_tokens: u32,
+    pub(crate) permission_queue: VecDeque<(String, String, PermissionRequest)>, // (agent, request_id, request)
+
+    pub(crate) session_usage: HashMap<String, SessionUsage>, // tokens spent by each agent of the pool
+    pub(crate) prices: PriceTable,         // to estimate the cost of the session
+    pub(crate) max_context: u32,
+    pub(crate) streaming_text: String,     // assistant answer being streamed
+    pub(crate) retrying: bool,             // a retry of the llm call is shown in the status line
+    pub(crate) hyperlinks: bool,           // file paths are printed as OSC 8 hyperlinks
 }
 
 
 // Agent-related Internals
 impl App<'_> {
     pub async fn start_agent(&mut self, agent_name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
-        let mut agent: Box<dyn Agent> = if let Some(agent_name) = agent_name {
+        let (agent, description) = self.build_agent(agent_name).await?;
+        println!("\x1b[2m░ {}\x1b[0m", description);
+        let name = agent_name.unwrap_or("shai").to_string();
+
+        let mut pool = AgentPool::new();
+        let events = pool.watch();
+        let controller = pool.spawn(name.clone(), agent)?;
+        // the method was picked from what the model supports, show it
+        if let Ok(method) = controller.set_method(None).await {
+            self.input.set_tool_call_method(method);
+        }
+
+        self.agent = Some(AppRunningAgent{
+            pool,
+            events,
+            focused: name,
+            controller,
+        });
+        Ok(())
+    }
+
+    /// Custom agent from its config, or the default coder agent, with a line describing it
+    async fn build_agent(&mut self, agent_name: Option<&str>) -> Result<(Box<dyn Agent>, String), Box<dyn std::error::Error>> {
+        let (agent, description): (Box<dyn Agent>, String) = if let Some(agent_name) = agent_name {
             // Load custom agent config
             let config = AgentConfig::load(agent_name)?;
             
-            println!("\x1b[2m░ agent {} - {} on {}\x1b[0m", agent_name, config.llm_provider.model, config.llm_provider.provider);
+            let description = format!("agent {} - {} on {}", agent_name, config.llm_provider.model, config.llm_provider.provider);
+            self.max_context = get_max_context(&config.llm_provider.model);
             
             // Create agent from config
             let agent_builder = AgentBuilder::from_config(config).await?;
-            Box::new(agent_builder.build())
+            (Box::new(agent_builder.build()), description)
         } else {
             // Use default coder agent
             let (llm, model) = ShaiConfig::get_llm().await?;
-            println!("\x1b[2m░ {} on {}\x1b[0m", model, llm.provider().name());
+            let description = format!("{} on {}", model, llm.provider().name());
+            self.max_context = get_max_context(&model);
             
-            Box::new(coder(Arc::new(llm), model))
+            let stream = Settings::load().stream.unwrap_or(true);
+            (Box::new(coder(Arc::new(llm), model, stream)), description)
         };
-        
-        // Get Agent I/O
-        let controller = agent.controller();
-        let events = agent.watch();
+        Ok((agent, description))
+    }
 
-        // Run the agent in background
-        let handle = tokio::spawn(async move {
-            match agent.run().await {
-                Ok(result) => eprintln!("Agent completed: {:?}", result),
-                Err(error) => eprintln!("Agent failed: {:?}", error),
+    /// Talk to another agent of the session, it is started first if there is none by that name:
+    /// from the custom agent config of that name if any, as a default coder agent otherwise
+    pub(crate) async fn focus_agent(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(running) = self.agent.as_ref() else {
+            return Err("no agent is running".into());
+        };
+        if running.focused == name {
+            return Ok(());
+        }
+        if running.pool.get(name).is_none() {
+            let (agent, description) = self.build_agent(AgentConfig::exists(name).then_some(name)).await?;
+            let _ = self.print_above(&format!("\x1b[2m░ agent {} started, {}\x1b[0m", name, description));
+            if let Some(ref mut running) = self.agent {
+                running.pool.spawn(name, agent)?;
             }
-        });
+        }
 
-        self.agent = Some(AppRunningAgent{
-            handle,
-            controller,
-            events
-        });
+        let Some(ref mut running) = self.agent else {
+            return Ok(());
+        };
+        running.controller = running.pool.focus(name)?.clone();
+        running.focused = name.to_string();
+
+        // the status line and the running tools were those of the previous agent
+        self.running_tools.clear();
+        self.input.set_running_tools(vec![]);
+        self.streaming_text.clear();
+        self.retrying = false;
+        self.input.clear_status();
+        self.input.set_token_usage(0, self.max_context);
+        if let Ok(state) = running.controller.get_state().await {
+            self.input.set_agent_state(state);
+        }
+        if let Ok(method) = running.controller.set_method(None).await {
+            self.input.set_tool_call_method(method);
+        }
         Ok(())
     }
 
-    async fn receive_agent_event(&mut self) -> Option<AgentEvent> {
+    async fn receive_agent_event(&mut self) -> Option<AgentEnvelope> {
         if let Some(ref mut agent) = self.agent {
             agent.events.recv().await.ok()
         } else {
@@ -123,41 +181,139 @@ impl App<'_> {
         }
     }
 
+    /// Print ansi formatted text in the scrollback, above the input area
+    pub(crate) fn print_above(&mut self, formatted: &str) -> io::Result<()> {
+        if let Some(ref mut terminal) = self.terminal {
+            let wrapped = formatted.into_text().unwrap();
+            let line_count = wrapped.lines.iter().len() as u16;
+            let hyperlinks = self.hyperlinks;
+            terminal.clear()?; // this is to avoid visual artifact
+            terminal.insert_before(line_count, |buf| {
+                wrapped.render(buf.area, buf);
+                if hyperlinks {
+                    link_paths(buf);
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Agents that are not focused only report what needs the user: a permission to give,
+    /// the end of their turn or an error
+    /// Tokens spent by all the agents of the session
+    pub(crate) fn total_usage(&self) -> SessionUsage {
+        let mut total = SessionUsage::default();
+        for usage in self.session_usage.values() {
+            total.merge(usage);
+        }
+        total
+    }
+
+    async fn handle_background_event(&mut self, agent_id: String, event: AgentEvent) -> io::Result<()> {
+        let notice = match &event {
+            AgentEvent::PermissionRequired { request_id, request } => {
+                self.permission_queue.push_back((agent_id.clone(), request_id.clone(), request.clone()));
+                None
+            }
+            AgentEvent::StatusChanged { new_status: PublicAgentState::Paused, .. } => Some("is waiting for you"),
+            AgentEvent::StatusChanged { new_status: PublicAgentState::Failed { .. }, .. } => Some("failed"),
+            AgentEvent::Error { .. } => Some("ran into an error"),
+            _ => None,
+        };
+        if let Some(notice) = notice {
+            self.print_above(&format!("\x1b[2m░ agent {} {}, /agent {} to switch to it\x1b[0m", agent_id, notice, agent_id))?;
+        }
+        Ok(())
+    }
+
     async fn handle_agent_event(&mut self, event: AgentEvent) -> io::Result<()> {
         // Update agent state
         if let AgentEvent::StatusChanged { new_status, .. } = &event {
-            self.input.set_agent_running(!matches!(new_status, PublicAgentState::Paused));
+            self.input.set_agent_state(new_status.clone());
         }
 
         // updated inprogress list
         if let AgentEvent::ToolCallStarted { call, .. }= &event {
             self.running_tools.insert(call.tool_call_id.clone(), call.clone());
+            self.input.set_running_tools(self.running_tools.values().map(|tc| tc.tool_name.clone()).collect());
         }
         if let AgentEvent::ToolCallCompleted { call, .. }= &event {
             self.running_tools.remove(&call.tool_call_id);
+            self.input.set_running_tools(self.running_tools.values().map(|tc| tc.tool_name.clone()).collect());
         }
 
         // Format and display event
         if let Some(formatted) = self.formatter.format_event(&event) {
-            if let Some(ref mut terminal) = self.terminal {
-                let wrapped = formatted.into_text().unwrap();
-                let line_count = wrapped.lines.iter().len() as u16;
-                terminal.clear()?; // this is to avoid visual artifact
-                terminal.insert_before(line_count, |buf| {
-                    wrapped.render(buf.area, buf);
-                })?;
-            }
+            self.print_above(&formatted)?;
         }
 
         // Handle permission requests - just add to queue
         if let AgentEvent::PermissionRequired { request_id, request } = &event {
-            self.permission_queue.push_back((request_id.clone(), request.clone()));
+            if let Some(ref agent) = self.agent {
+                self.permission_queue.push_back((agent.focused.clone(), request_id.clone(), request.clone()));
+            }
+        }
+
+        // Show compression progress and the answer being streamed in the status line
+        match &event {
+            AgentEvent::BrainDelta { text } => {
+                self.streaming_text.push_str(text);
+                let last_line = self.streaming_text.lines().last().unwrap_or("").trim();
+                self.input.set_status(last_line);
+            }
+            AgentEvent::BrainResult { .. } | AgentEvent::UserInput { .. } if !self.streaming_text.is_empty() || self.retrying => {
+                self.streaming_text.clear();
+                self.retrying = false;
+                self.input.clear_status();
+            }
+            AgentEvent::RetryingRequest { attempt, max_retries, delay } => {
+                self.retrying = true;
+                self.input.set_status(&format!("retrying ({}/{}) in {}s", attempt, max_retries, delay.as_secs_f32().ceil()));
+            }
+            AgentEvent::ToolOutputChunk { chunk, .. } => {
+                if let Some(last_line) = chunk.lines().rev().map(str::trim).find(|l| !l.is_empty()) {
+                    self.input.set_status(last_line);
+                }
+            }
+            AgentEvent::ToolCallCompleted { .. } if self.running_tools.is_empty() => {
+                self.input.clear_status();
+            }
+            AgentEvent::ToolCallMethodChanged { method } => {
+                self.input.set_tool_call_method(*method);
+            }
+            AgentEvent::ModeChanged { mode } => {
+                self.input.set_mode(*mode);
+            }
+            AgentEvent::CompressionStarted { messages_to_summarize, .. } => {
+                self.input.set_status(&format!("Summarizing {} messages...", messages_to_summarize));
+            }
+            AgentEvent::CompressionProgress { step, total } => {
+                self.input.set_status(&format!("Summarizing conversation ({}/{})...", step, total));
+            }
+            AgentEvent::CompressionFinished { outcome } => {
+                self.input.clear_status();
+                match outcome {
+                    CompressionOutcome::Compressed(info) => self.input.set_token_usage(info.current_tokens, info.max_tokens),
+                    CompressionOutcome::Skipped { reason, current_tokens, threshold } => self.input.alert_msg(
+                        &format!("nothing compressed: {} ({}/{} tokens)", reason, current_tokens, threshold),
+                        Duration::from_secs(3)
+                    ),
+                }
+            }
+            AgentEvent::ContextTruncated { current_tokens, max_tokens, .. } => {
+                self.input.set_token_usage(*current_tokens, *max_tokens);
+            }
+            AgentEvent::ConversationCleared => {
+                self.streaming_text.clear();
+                self.input.clear_status();
+                self.input.set_token_usage(0, self.max_context);
+            }
+            _ => {}
         }
 
         // Handle token usage tracking
-        if let AgentEvent::TokenUsage { input_tokens, output_tokens } = &event {
-            self.total_input_tokens += input_tokens;
-            self.total_output_tokens += output_tokens;
+        if let AgentEvent::TokenUsage { input_tokens, .. } = &event {
+            self.input.set_context_usage(*input_tokens, self.max_context, COMPRESSION_THRESHOLD);
         }
         
         Ok(())
@@ -168,6 +324,8 @@ impl App<'_> {
 // UI-related Internals
 impl App<'_> {
     pub fn new() -> Self {
+        let settings = Settings::load();
+        let hyperlinks = settings.hyperlinks.unwrap_or(false);
         Self {
             terminal: None,
             terminal_height: 5,
@@ -175,18 +333,39 @@ impl App<'_> {
             custom_agent: None,
             formatter: PrettyFormatter::new(),
             state: AppModalState::InputShown,
-            input: InputArea::new(),
+            input: {
+                let mut input = InputArea::new();
+                input.set_theme(Theme::from_env());
+                // SHAI_DONE_FLASH_MS=0 turns the done confirmation off
+                if let Some(ms) = std::env::var("SHAI_DONE_FLASH_MS").ok().and_then(|ms| ms.parse().ok()) {
+                    input.set_done_flash_duration(Duration::from_millis(ms));
+                }
+                if let Some(style) = settings.path_style.as_deref().and_then(PathStyle::parse) {
+                    input.set_path_style(style);
+                }
+                input.set_hyperlinks(hyperlinks);
+                if let Some(kb) = settings.suggestion_max_kb {
+                    input.set_max_suggested_file_size(kb * 1024);
+                }
+                input.set_line_rule(settings.line_rule);
+                input
+            },
             commands: Self::list_command(),
             exit: false,
             running_tools: HashMap::new(),
             permission_queue: VecDeque::new(),
-            total_input_tokens: 0,
-            total_output_tokens: 0,
+            session_usage: HashMap::new(),
+            prices: settings.price_table(),
+            max_context: DEFAULT_MAX_CONTEXT,
+            streaming_text: String::new(),
+            retrying: false,
+            hyperlinks,
         }
     }
 
     pub async fn run(&mut self, agent_name: Option<String>) -> io::Result<()> {
         let x = self.try_run(agent_name).await;
+        let _ = execute!(stdout(), DisableBracketedPaste);
         let _ = disable_raw_mode();
 
         if let Err(e) = x {
@@ -216,6 +395,9 @@ impl App<'_> {
             viewport: Viewport::Inline(8)
         }));
 
+        // pasted text is received as a single Paste event instead of keystrokes
+        let _ = execute!(stdout(), EnableBracketedPaste);
+
         // Create a timer for animation updates
         let mut animation_timer = interval(Duration::from_millis(100));
         let mut reader = crossterm::event::EventStream::new();
@@ -228,8 +410,17 @@ impl App<'_> {
             tokio::select! {
                 // Handle agent events (only when not in permission modal)
                 agent_event = self.receive_agent_event(), if self.agent.is_some() => {
-                    if let Some(event) = agent_event {
-                        self.handle_agent_event(event).await?;
+                    if let Some(AgentEnvelope { agent_id, event: AgentEvent::SessionUsage { usage } }) = &agent_event {
+                        self.session_usage.insert(agent_id.clone(), usage.clone());
+                        let total = self.total_usage();
+                        self.input.set_session_usage(total.input_tokens, total.output_tokens, total.estimate_cost(&self.prices));
+                    }
+                    match agent_event {
+                        Some(envelope) if self.agent.as_ref().is_some_and(|agent| agent.focused == envelope.agent_id) => {
+                            self.handle_agent_event(envelope.event).await?;
+                        }
+                        Some(envelope) => self.handle_background_event(envelope.agent_id, envelope.event).await?,
+                        None => {}
                     }
                 }
                 
@@ -246,6 +437,7 @@ impl App<'_> {
                     if let Some(action) = self.input.check_pending_enter() {
                         self.handle_user_action(action).await?;
                     }
+                    self.input.check_pending_search();
                     // Timer ticked, UI will be redrawn in next iteration
                 }
             }
@@ -253,6 +445,11 @@ impl App<'_> {
             // Check permission queue and update state
             self.check_permission_queue().await?;
         }
+
+        // let the running tasks wind down before the runtime goes away
+        if let Some(ref mut agent) = self.agent {
+            agent.pool.shutdown(Duration::from_secs(2)).await;
+        }
         Ok(())
     }
 
@@ -266,6 +463,11 @@ impl App<'_> {
             Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
                 self.handle_key_event(key_event).await?;
             }
+            Event::Paste(text) => {
+                if let AppModalState::InputShown = self.state {
+                    self.input.handle_paste(&text);
+                }
+            }
             _ => {}
         }
         Ok(())
@@ -294,12 +496,14 @@ impl App<'_> {
     async fn handle_permission_action(&mut self, action: PermissionModalAction) -> io::Result<()> {
         match action {
             PermissionModalAction::Response { request_id, choice } => {
-                // Send response to agent
-                if let Some(ref agent) = self.agent {     
+                // Send response to the agent that asked, which may not be the focused one
+                let controller = self.agent.as_ref().zip(self.permission_queue.front())
+                    .and_then(|(agent, (agent_id, _, _))| agent.pool.get(agent_id));
+                if let Some(controller) = controller {
                     if matches!(choice, PermissionResponse::AllowAlways) {
-                        let _ = agent.controller.sudo().await;
-                    }        
-                    match agent.controller.response_permission_request(request_id, choice).await {
+                        let _ = controller.sudo().await;
+                    }
+                    match controller.response_permission_request(request_id, choice).await {
                         Err(e) => {
                             self.input.alert_msg("channel with agent closed. Please restart the app", Duration::from_secs(3));
                         },
@@ -321,7 +525,7 @@ impl App<'_> {
     async fn check_permission_queue(&mut self) -> io::Result<()> {
         match &self.state {
             AppModalState::InputShown if !self.permission_queue.is_empty() => {
-                let (request_id, request) = self.permission_queue.front().unwrap();
+                let (_, request_id, request) = self.permission_queue.front().unwrap();
                 let widget = PermissionWidget::new(
                     request_id.clone(), 
                     request.clone(), 
@@ -357,28 +561,60 @@ impl App<'_> {
             UserAction::Nope => {}
             UserAction::CancelTask => {
                 if let Some(ref agent) = self.agent {
+                    self.input.mark_task_cancelled();
                     let _ = agent.controller.test_stop_current_task().await;
                     self.input.alert_msg("Task cancelled", Duration::from_secs(1));
                 }
             }
-            UserAction::UserInput { input } => {
-                if let Some(ref agent) = self.agent {                                
-                    match agent.controller.send_user_input(input.clone()).await {
-                        Err(e) => {
-                            self.input.alert_msg("channel with agent closed. Please restart the app", Duration::from_secs(3));
-                        },
-                        _ => {},
+            UserAction::CancelTool => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.cancel_current_tool().await {
+                        Ok(()) => self.input.alert_msg("Tool cancelled", Duration::from_secs(1)),
+                        Err(_) => self.input.alert_msg("no tool is running", Duration::from_secs(1)),
                     }
                 }
             }
+            UserAction::UserInput { input } => {
+                self.send_user_input(input).await;
+            }
             UserAction::UserAppCommand { command } => {
                 let _ = self.handle_app_command(&command).await;
             }
+            UserAction::SwitchToolCallMethod { method } => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.set_method(Some(method)).await {
+                        Ok(method) => self.input.set_tool_call_method(method),
+                        Err(_) => self.input.alert_msg("could not switch the tool call method", Duration::from_secs(2)),
+                    }
+                }
+            }
+            UserAction::SwitchMode { mode } => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.set_mode(mode).await {
+                        Ok(()) => self.input.alert_msg(&format!("{} mode, from the next step", mode), Duration::from_secs(2)),
+                        Err(_) => self.input.alert_msg("could not switch the mode", Duration::from_secs(2)),
+                    }
+                }
+            }
         }
         Ok(())
     }
 
 
+    pub(crate) async fn send_user_input(&mut self, input: String) {
+        if let Some(ref agent) = self.agent {
+            match agent.controller.send_user_input(input).await {
+                Err(AgentError::ExecutionError(error)) => {
+                    self.input.alert_msg(&error, Duration::from_secs(3));
+                },
+                Err(e) => {
+                    self.input.alert_msg("channel with agent closed. Please restart the app", Duration::from_secs(3));
+                },
+                _ => {},
+            }
+        }
+    }
+
     fn draw_ui(&mut self) -> io::Result<()> {
         let modal_height = match &self.state {
             AppModalState::InputShown => self.input.height(),
@@ -419,6 +655,8 @@ impl App<'_> {
                     }
                 }
             })?;
+
+            print_links(&mut stdout(), &self.input.take_links())?;
         }
         Ok(())
     }
@@ -9,6 +9,7 @@ use ratatui::{
     Frame, Terminal, TerminalOptions, Viewport,
 };
 use shai_core::config::config::ShaiConfig;
+use shai_core::config::settings::Settings;
 use shai_llm::client::LlmClient;
 use shai_llm::provider::ProviderInfo;
 use super::config_list::ModalConfig;
@@ -223,6 +224,7 @@ impl AppAuth {
         
         // Save the updated config
         config.save()?;
+        let _ = Settings::remember(provider_name, &modal_model.selected_model());
         
         Ok(())
     }
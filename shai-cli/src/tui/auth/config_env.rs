@@ -10,7 +10,7 @@ use ratatui::{
     Frame,
 };
 use shai_core::config::config::ShaiConfig;
-use shai_llm::provider::ProviderInfo;
+use shai_llm::provider::{ModelInfo, ProviderInfo};
 use shai_llm::client::LlmClient;
 use tui_textarea::TextArea;
 use tokio::task::JoinHandle;
@@ -21,7 +21,7 @@ use super::auth::NavAction;
 pub enum FetchState {
     Idle,
     Fetching,
-    Success(Vec<String>),
+    Success(Vec<ModelInfo>),
     Error(String),
 }
 
@@ -35,7 +35,7 @@ pub struct ModalEnvs {
     env_values: HashMap<String, String>,
     error_message: Option<String>,
     fetch_state: FetchState,
-    fetch_task: Option<JoinHandle<Result<Vec<String>, String>>>,
+    fetch_task: Option<JoinHandle<Result<Vec<ModelInfo>, String>>>,
 }
 
 impl ModalEnvs {
@@ -99,9 +99,9 @@ impl ModalEnvs {
         self.fetch_task = Some(tokio::spawn(async move {
             match LlmClient::create_provider(&provider_name, &env_values) {
                 Ok(client) => {
-                    match client.models().await {
+                    match client.list_models_detailed().await {
                         Ok(models) => {
-                            Ok(models.data.into_iter().map(|m| m.id).collect())
+                            Ok(models)
                         },
                         Err(e) => {
                             Err(format!("Failed to fetch models: {}", e))
@@ -120,7 +120,7 @@ impl ModalEnvs {
         matches!(self.fetch_state, FetchState::Fetching)
     }
 
-    pub fn poll_fetch(&mut self) -> Option<Result<Vec<String>, String>> {
+    pub fn poll_fetch(&mut self) -> Option<Result<Vec<ModelInfo>, String>> {
         if self.fetch_task.as_ref()?.is_finished() {
             let task = self.fetch_task.take()?;
             let result = futures::executor::block_on(task).unwrap_or_else(|_| Err("Cancelled".to_string()));
@@ -241,7 +241,7 @@ impl ModalEnvs {
 
                 match env_var.name.as_str() {
                         "OVH_BASE_URL" => self.input_fields[i].set_placeholder_text("https://oai.endpoints.kepler.ai.cloud.ovh.net/v1"),
-                        "OLLAMA_BASE_URL" => self.input_fields[i].set_placeholder_text("http://localhost:11434/v1"),
+                        "OLLAMA_HOST" => self.input_fields[i].set_placeholder_text("http://localhost:11434"),
                         _ => {}
                 }
 
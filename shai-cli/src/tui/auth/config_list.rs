@@ -9,6 +9,7 @@ use ratatui::{
     Frame,
 };
 use shai_core::config::config::ShaiConfig;
+use shai_core::config::settings::Settings;
 
 use super::auth::NavAction;
 use shai_llm::client::LlmClient;
@@ -76,6 +77,9 @@ impl ModalConfig {
                         return NavAction::None;
                     }
                     
+                    if let Some(provider_config) = self.config.get_selected_provider() {
+                        let _ = Settings::remember(&provider_config.provider, &provider_config.model);
+                    }
                     self.config.set_env_vars();
                     NavAction::Done
                 }
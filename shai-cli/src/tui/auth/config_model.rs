@@ -9,7 +9,7 @@ use ratatui::{
     Frame,
 };
 use shai_core::config::config::ShaiConfig;
-use shai_llm::provider::ProviderInfo;
+use shai_llm::provider::{ModelInfo, ProviderInfo};
 
 use super::auth::NavAction;
 
@@ -17,6 +17,7 @@ use super::auth::NavAction;
 pub struct ModalModel {
     pub all_models: Vec<String>,
     pub filtered_models: Vec<String>,
+    pub context_lengths: HashMap<String, u32>,
     pub selected_index: usize,
     pub scroll_offset: usize,
     pub search_query: String,
@@ -32,11 +33,16 @@ const MAX_VISIBLE_MODELS: usize = 20;
 const SCROLL_MARGIN: usize = 10;
 
 impl ModalModel {
-    pub fn new(available_models: Vec<String>, config: ShaiConfig, providers: Vec<ProviderInfo>, provider: ProviderInfo, env_values: HashMap<String, String>) -> Self {
-        let filtered_models = available_models.clone();
+    pub fn new(available_models: Vec<ModelInfo>, config: ShaiConfig, providers: Vec<ProviderInfo>, provider: ProviderInfo, env_values: HashMap<String, String>) -> Self {
+        let context_lengths = available_models.iter()
+            .map(|m| (m.id.clone(), m.context_length))
+            .collect();
+        let all_models: Vec<String> = available_models.into_iter().map(|m| m.id).collect();
+        let filtered_models = all_models.clone();
         Self {
-            all_models: available_models,
+            all_models,
             filtered_models,
+            context_lengths,
             selected_index: 0,
             scroll_offset: 0,
             search_query: String::new(),
@@ -247,7 +253,9 @@ impl ModalModel {
             let is_duplicate = self.config.is_duplicate_config(&self.provider.name, &self.env_values, model);
             
             let prefix = if is_selected { "● " } else { "○ " };
-            let line = format!("{}{}", prefix, model);
+            let context = self.context_lengths.get(model)
+                .map(|tokens| format!("  {}k ctx", tokens / 1024))
+                .unwrap_or_default();
             
             let style = if is_duplicate {
                 Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM)
@@ -257,7 +265,11 @@ impl ModalModel {
                 Style::default().fg(Color::DarkGray)
             };
             
-            let paragraph = Paragraph::new(line).style(style);
+            let line = Line::from(vec![
+                Span::styled(format!("{}{}", prefix, model), style),
+                Span::styled(context, Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM)),
+            ]);
+            let paragraph = Paragraph::new(line);
             frame.render_widget(paragraph, layout_areas[area_index]);
             area_index += 1;
         }
@@ -1,7 +1,10 @@
 use std::{collections::HashMap, io, time::Duration};
-use shai_llm::ToolCallMethod;
+use shai_core::agent::TraceFormat;
+use shai_core::config::settings::Settings;
+use shai_llm::{estimate_message_tokens, ChatMessage, ChatMessageContent, ToolCallMethod};
 
 use crate::tui::App;
+use crate::tui::input::PathStyle;
 
 impl App<'_> {
     pub(crate) fn list_command() -> HashMap<(String, String),Vec<String>> {
@@ -9,13 +12,52 @@ impl App<'_> {
             (("/exit","exit from the tui"), vec![]),
             (("/auth","select a provider"), vec![]),
             (("/tc","set the tool call method: [fc | fc2 | so]"), vec!["method"]),
-            (("/tokens","display token usage (input/output)"), vec![]),
+            (("/model","switch to another model of the provider"), vec!["name"]),
+            (("/agent","list the agents, or talk to (and start) the agent of that name"), vec!["name"]),
+            (("/tokens","display the tokens spent this session, per model"), vec![]),
+            (("/why","explain the last error"), vec![]),
+            (("/reset","accept input again after the provider kept failing"), vec![]),
+            (("/retry","send your last prompt again, replacing the answer it got"), vec![]),
+            (("/clear","start a new conversation"), vec![]),
+            (("/compact","summarize the conversation to free up context"), vec![]),
+            (("/context","list the messages sent to the model, drop or edit one while paused"), vec!["drop | edit", "index"]),
+            (("/pin","keep your last message verbatim when the context is compressed"), vec![]),
+            (("/save","save the conversation to a file (.md or .json)"), vec!["path"]),
+            (("/resume","resume a conversation saved as json"), vec!["file"]),
+            (("/set","change a setting: suggestions [on | all | off], sandbox [dir | off], paths [relative | absolute | pwd], links [on | off], rule [column | off]"), vec!["setting", "value"]),
         ])
         .into_iter()
         .map(|((cmd,desc),args)|((cmd.to_string(),desc.to_string()),args.into_iter().map(|s|s.to_string()).collect()))
         .collect()
     }
 
+    /// One line per message of the context: index, role, estimated tokens and the start of the content
+    fn format_context(trace: &[ChatMessage]) -> String {
+        let mut text = format!("\x1b[2m  {:>3}  {:<10} {:>7}  content\x1b[0m\n", "#", "role", "tokens");
+        for (i, message) in trace.iter().enumerate() {
+            let (role, content) = match message {
+                ChatMessage::System { content, .. } => ("system", Some(content)),
+                ChatMessage::Developer { content, .. } => ("developer", Some(content)),
+                ChatMessage::User { content, .. } => ("user", Some(content)),
+                ChatMessage::Assistant { content, .. } => ("assistant", content.as_ref()),
+                ChatMessage::Tool { .. } => ("tool", None),
+            };
+            let preview = match (message, content) {
+                (ChatMessage::Tool { content, .. }, _) => content.clone(),
+                (ChatMessage::Assistant { tool_calls: Some(calls), .. }, None) => calls.iter()
+                    .map(|call| format!("→ {}({})", call.function.name, call.function.arguments))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                (_, Some(ChatMessageContent::Text(text))) => text.clone(),
+                (_, Some(_)) => "[content parts]".to_string(),
+                (_, None) => String::new(),
+            };
+            let preview: String = preview.lines().find(|l| !l.trim().is_empty()).unwrap_or_default().chars().take(80).collect();
+            text += &format!("  {:>3}  {:<10} {:>7}  {}\n", i, role, estimate_message_tokens(message), preview);
+        }
+        text
+    }
+
     pub(crate) async fn handle_app_command(&mut self, command: &str) -> io::Result<()> {
         let mut parts = command.split_whitespace();
         let cmd = parts.next().unwrap();
@@ -56,14 +98,265 @@ impl App<'_> {
                     }
                 }
             }
+            "/model" => {
+                let Some(model) = args.into_iter().next() else {
+                    self.input.alert_msg("usage: /model <name>", Duration::from_secs(2));
+                    return Ok(());
+                };
+                if let Some(ref agent) = self.agent {
+                    if self.input.is_agent_running() {
+                        self.input.alert_msg("wait for the agent to pause before switching model", Duration::from_secs(2));
+                        return Ok(());
+                    }
+                    match agent.controller.switch_model(model.to_string()).await {
+                        Ok(switched) => {
+                            let _ = Settings::remember(&switched.provider, &switched.model);
+                            self.max_context = switched.max_context;
+                            let current = self.input.token_usage().map(|(current, _)| current).unwrap_or(0);
+                            self.input.set_token_usage(current, switched.max_context);
+                            self.input.alert_msg(&format!("now using {} on {}", switched.model, switched.provider), Duration::from_secs(3));
+                        }
+                        Err(e) => self.input.alert_msg(&format!("could not switch model: {}", e), Duration::from_secs(3)),
+                    }
+                }
+            }
             "/tokens" => {
-                let msg = format!(
-                    "Token Usage - Input: {}, Output: {}, Total: {}",
-                    self.total_input_tokens,
-                    self.total_output_tokens,
-                    self.total_input_tokens + self.total_output_tokens
-                );
-                self.input.alert_msg(&msg, Duration::from_secs(5));
+                let usage = self.total_usage();
+                let cost = match usage.estimate_cost(&self.prices) {
+                    Some(cost) => format!("~${:.2}", cost),
+                    None => "cost unknown".to_string(),
+                };
+                let mut text = format!("\x1b[2m░ session: {} input + {} output = {} tokens, {}\x1b[0m\n",
+                    usage.input_tokens, usage.output_tokens, usage.total_tokens(), cost);
+                if usage.by_model.len() > 1 {
+                    for (model, tokens) in &usage.by_model {
+                        text += &format!("\x1b[2m    {}: {} input + {} output\x1b[0m\n", model, tokens.input_tokens, tokens.output_tokens);
+                    }
+                }
+                self.print_above(&text)?;
+            }
+            "/reset" => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.reset_circuit_breaker().await {
+                        Ok(()) => self.input.alert_msg("circuit breaker reset, the next message goes to the provider", Duration::from_secs(2)),
+                        Err(e) => self.input.alert_msg(&format!("could not reset: {}", e), Duration::from_secs(2)),
+                    }
+                }
+            }
+            "/retry" => {
+                let Some(prompt) = self.input.last_prompt() else {
+                    self.input.alert_msg("no prompt to retry", Duration::from_secs(2));
+                    return Ok(());
+                };
+                if self.input.is_agent_running() {
+                    self.input.alert_msg("wait for the agent to pause before retrying", Duration::from_secs(2));
+                    return Ok(());
+                }
+                if let Some(ref agent) = self.agent {
+                    // the previous answer goes away so the model does not see the prompt twice,
+                    // after a /clear there is nothing to take back and the prompt is just sent
+                    let _ = agent.controller.rewind_last_turn().await;
+                }
+                self.send_user_input(prompt).await;
+            }
+            "/why" => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.last_error().await {
+                        Ok(Some(report)) => {
+                            let mut text = format!("\x1b[31m● error\x1b[0m at {}\n  {}\n",
+                                report.timestamp.with_timezone(&chrono::Local).format("%H:%M:%S"), report.error);
+                            if let Some(request) = &report.request {
+                                let request: String = request.lines().next().unwrap_or_default().chars().take(120).collect();
+                                text += &format!("  \x1b[2mwhile working on: {}\x1b[0m\n", request);
+                            }
+                            if let Some(hint) = report.hint() {
+                                text += &format!("  \x1b[33mhint: {}\x1b[0m\n", hint);
+                            }
+                            self.print_above(&text)?;
+                        }
+                        Ok(None) => self.input.alert_msg("no error so far", Duration::from_secs(2)),
+                        Err(e) => self.input.alert_msg(&format!("could not get the last error: {}", e), Duration::from_secs(3)),
+                    }
+                }
+            }
+            "/context" => {
+                let Some(ref agent) = self.agent else {
+                    return Ok(());
+                };
+                let index = args.get(1).and_then(|i| i.parse::<usize>().ok());
+                let result = match (args.first().copied(), index) {
+                    (None, _) => match agent.controller.get_trace().await {
+                        Ok(trace) => {
+                            let text = Self::format_context(&trace);
+                            self.print_above(&text)?;
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    },
+                    (Some("drop"), Some(index)) => agent.controller.remove_message(index).await
+                        .map(|_| self.input.alert_msg(&format!("message #{} removed from the context", index), Duration::from_secs(2))),
+                    (Some("edit"), Some(index)) if args.len() > 2 => agent.controller.edit_message(index, args[2..].join(" ")).await
+                        .map(|_| self.input.alert_msg(&format!("message #{} edited", index), Duration::from_secs(2))),
+                    _ => {
+                        self.input.alert_msg("usage: /context | /context drop <index> | /context edit <index> <text>", Duration::from_secs(3));
+                        Ok(())
+                    }
+                };
+                if let Err(e) = result {
+                    self.input.alert_msg(&format!("{}", e), Duration::from_secs(3));
+                }
+            }
+            "/clear" => {
+                if let Some(ref agent) = self.agent {
+                    if self.input.is_agent_running() {
+                        self.input.alert_msg("wait for the agent to pause before clearing", Duration::from_secs(2));
+                    } else if let Err(e) = agent.controller.clear_trace().await {
+                        self.input.alert_msg(&format!("could not clear the conversation: {}", e), Duration::from_secs(3));
+                    }
+                }
+            }
+            "/compact" => {
+                if let Some(ref agent) = self.agent {
+                    if agent.controller.compress_context().await.is_err() {
+                        self.input.alert_msg("channel with agent closed. Please restart the app", Duration::from_secs(3));
+                    } else if self.input.is_agent_running() {
+                        self.input.alert_msg("context will be compressed once the current step completes", Duration::from_secs(3));
+                    }
+                }
+            }
+            "/pin" => {
+                if let Some(ref agent) = self.agent {
+                    match agent.controller.pin_last_message().await {
+                        Ok(()) => self.input.alert_msg("last message pinned", Duration::from_secs(2)),
+                        Err(e) => self.input.alert_msg(&format!("could not pin the message: {}", e), Duration::from_secs(3)),
+                    }
+                }
+            }
+            "/save" => {
+                if let Some(ref agent) = self.agent {
+                    let path = args.into_iter().next()
+                        .map(|p| p.to_string())
+                        .unwrap_or_else(|| format!("shai-{}.md", chrono::Local::now().format("%Y%m%d_%H%M%S")));
+                    let format = if path.ends_with(".json") { TraceFormat::Json } else { TraceFormat::Markdown };
+                    let result = match agent.controller.export_trace(format).await {
+                        Ok(content) => std::fs::write(&path, content).map_err(|e| e.to_string()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    match result {
+                        Ok(()) => self.input.alert_msg(&format!("conversation saved to {}", path), Duration::from_secs(3)),
+                        Err(e) => self.input.alert_msg(&format!("could not save the conversation: {}", e), Duration::from_secs(3)),
+                    }
+                }
+            }
+            "/agent" => {
+                let Some(name) = args.into_iter().next() else {
+                    if let Some(ref agent) = self.agent {
+                        let names: Vec<String> = agent.pool.names().into_iter()
+                            .map(|name| if name == agent.focused { format!("[{}]", name) } else { name.to_string() })
+                            .collect();
+                        self.input.alert_msg(&format!("agents: {}", names.join(" ")), Duration::from_secs(3));
+                    }
+                    return Ok(());
+                };
+                // the agent left keeps working in the background
+                match self.focus_agent(name).await {
+                    Ok(()) => self.input.alert_msg(&format!("now talking to {}", name), Duration::from_secs(2)),
+                    Err(e) => self.input.alert_msg(&format!("could not switch to {}: {}", name, e), Duration::from_secs(3)),
+                }
+            }
+            "/resume" => {
+                let Some(path) = args.into_iter().next() else {
+                    self.input.alert_msg("usage: /resume <file.json>", Duration::from_secs(2));
+                    return Ok(());
+                };
+                if let Some(ref agent) = self.agent {
+                    let messages = std::fs::read_to_string(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|content| serde_json::from_str::<Vec<ChatMessage>>(&content).map_err(|e| e.to_string()));
+                    let result = match messages {
+                        Ok(messages) => {
+                            let count = messages.len();
+                            agent.controller.load_trace(messages).await.map(|_| count).map_err(|e| e.to_string())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    match result {
+                        Ok(count) => self.input.alert_msg(&format!("resumed {} messages from {}", count, path), Duration::from_secs(3)),
+                        Err(e) => self.input.alert_msg(&format!("could not resume the conversation: {}", e), Duration::from_secs(3)),
+                    }
+                }
+            }
+            "/set" => {
+                match (args.first().copied(), args.get(1).copied()) {
+                    (Some("suggestions"), Some("on")) => {
+                        self.input.set_file_suggestions_enabled(true);
+                        self.input.set_suggest_all_files(false);
+                        self.input.alert_msg("@ file suggestions enabled, binary and large files left out", Duration::from_secs(2));
+                    }
+                    (Some("suggestions"), Some("all")) => {
+                        self.input.set_file_suggestions_enabled(true);
+                        self.input.set_suggest_all_files(true);
+                        self.input.alert_msg("@ suggests every file, binary and large ones included", Duration::from_secs(2));
+                    }
+                    (Some("suggestions"), Some("off")) => {
+                        self.input.set_file_suggestions_enabled(false);
+                        self.input.alert_msg("@ file suggestions disabled", Duration::from_secs(2));
+                    }
+                    (Some("sandbox"), Some(value)) => {
+                        if let Some(ref agent) = self.agent {
+                            let root = (value != "off").then(|| std::path::PathBuf::from(value));
+                            match agent.controller.set_sandbox_root(root.clone()).await {
+                                Ok(()) => {
+                                    // the @ picker only offers files the tools are allowed to read
+                                    self.input.set_search_root(root.clone().unwrap_or_else(|| ".".into()));
+                                    let msg = match root {
+                                        Some(root) => format!("tools are now confined to {}", root.display()),
+                                        None => "tools can access any path again".to_string(),
+                                    };
+                                    self.input.alert_msg(&msg, Duration::from_secs(3));
+                                }
+                                Err(e) => self.input.alert_msg(&format!("could not set the sandbox: {}", e), Duration::from_secs(3)),
+                            }
+                        }
+                    }
+                    (Some("links"), Some(value @ ("on" | "off"))) => {
+                        let enabled = value == "on";
+                        self.hyperlinks = enabled;
+                        self.input.set_hyperlinks(enabled);
+                        let _ = Settings::remember_hyperlinks(enabled);
+                        let msg = if enabled { "file paths are now clickable (OSC 8)" } else { "file paths are plain text again" };
+                        self.input.alert_msg(msg, Duration::from_secs(2));
+                    }
+                    (Some("rule"), Some(value)) => {
+                        let column = match value {
+                            "off" => Ok(None),
+                            column => column.parse::<u16>().ok().filter(|column| *column > 0).map(Some).ok_or(()),
+                        };
+                        match column {
+                            Ok(column) => {
+                                self.input.set_line_rule(column);
+                                let _ = Settings::remember_line_rule(column);
+                                let msg = match column {
+                                    Some(column) => format!("line rule at column {}", column),
+                                    None => "line rule removed".to_string(),
+                                };
+                                self.input.alert_msg(&msg, Duration::from_secs(2));
+                            }
+                            Err(()) => self.input.alert_msg("usage: /set rule [column | off]", Duration::from_secs(2)),
+                        }
+                    }
+                    (Some("paths"), Some(value)) => match PathStyle::parse(value) {
+                        Some(style) => {
+                            self.input.set_path_style(style);
+                            let _ = Settings::remember_path_style(value);
+                            self.input.alert_msg(&format!("@ inserts {} paths", value), Duration::from_secs(2));
+                        }
+                        None => self.input.alert_msg("usage: /set paths [relative | absolute | pwd]", Duration::from_secs(2)),
+                    },
+                    _ => {
+                        self.input.alert_msg("usage: /set suggestions [on | all | off] | /set sandbox [dir | off] | /set paths [relative | absolute | pwd] | /set links [on | off] | /set rule [column | off]", Duration::from_secs(2));
+                    }
+                }
             }
             _ => {
                 self.input.alert_msg("command unknown", Duration::from_secs(1));
@@ -1,37 +1,125 @@
-use ansi_to_tui::IntoText;
-use ratatui::{layout::Rect, style::{Color, Style, Stylize}, symbols::border, text::{Line, Span}, widgets::{Block, Borders, Padding, Widget}, Frame};
+use ratatui::{layout::Rect, style::{Color, Modifier, Style, Stylize}, text::{Line, Span}, widgets::Paragraph, Frame};
 
+/// Key mappings shown by `?`, grouped by what they act on
+const SECTIONS: &[(&str, &[(&str, &str)])] = &[
+    ("Editing", &[
+        ("enter", "send the message"),
+        ("alt+enter", "new line"),
+        ("esc esc", "clear the input"),
+        ("ctrl^z", "restore the input cleared with esc esc"),
+        ("ctrl^a / ctrl^e", "start / end of the line"),
+        ("ctrl^← / ctrl^→", "previous / next word"),
+        ("ctrl^w", "delete the previous word"),
+        ("ctrl^v", "paste from the clipboard"),
+        ("ctrl^g", "insert a code fence (wraps the selection)"),
+    ]),
+    ("History", &[
+        ("↑ / ↓", "previous / next prompt"),
+        ("pgup / pgdn", "10 prompts back / forward"),
+        ("ctrl^home", "oldest prompt"),
+        ("ctrl^end", "back to the prompt being typed"),
+    ]),
+    ("Files", &[
+        ("@", "search a file to mention"),
+        ("↑ / ↓", "move in the suggestions"),
+        ("tab", "select several files"),
+        ("enter", "insert the selection"),
+        ("ctrl^p", "show / hide the preview"),
+        ("@file:40-80", "mention lines 40 to 80 only"),
+        ("@@file", "insert the contents of the file instead of its path"),
+    ]),
+    ("Agent Control", &[
+        ("esc", "cancel the running task"),
+        ("ctrl^x", "cancel the running tool only"),
+        ("ctrl^t", "cycle the tool call method"),
+        ("shift+tab", "toggle plan (read-only tools) / execute mode"),
+        ("ctrl^c", "exit"),
+    ]),
+    ("Commands", &[
+        ("/", "list the commands as you type"),
+        ("/model <name>", "switch model"),
+        ("/agent <name>", "talk to another agent, started if needed"),
+        ("/compact", "summarize the conversation to free up context"),
+        ("/context", "list the context, drop <i> or edit <i> <text> a message"),
+        ("/why", "explain the last error and what to do about it"),
+        ("/retry", "send the last prompt again, e.g. after /model"),
+        ("/reset", "accept input again once the provider is fixed"),
+        ("/clear", "start a new conversation"),
+        ("/set suggestions", "turn the file suggestions on, off or to all files"),
+        ("/set sandbox <dir>", "keep the file tools inside dir (off to lift)"),
+        ("/set paths <style>", "insert @ files as relative, absolute or pwd paths"),
+        ("/set links on", "clickable file paths, for terminals with OSC 8"),
+        ("/set rule 80", "mark column 80 of the input and count what is typed"),
+    ]),
+];
 
+/// Width of the key column
+const KEY_WIDTH: usize = 20;
 
-pub struct HelpArea;
+/// Help overlay, scrolled with pgup/pgdn when it does not fit in the space it was given
+pub struct HelpArea {
+    scroll: usize,
+    max_height: u16,
+}
 
 impl HelpArea {
-    fn helper_msg(&self) -> String {
-        [
-            "  ? to print help      tap esc twice to clear input",
-            "  / for commands       tap esc while agent is running to cancel",
-            "                       ctrl^c to exit",
-            "",
-            "  Available Commands:",
-            "  /exit                exit from the tui",
-            "  /tc <method>         set tool call method: [auto | fc | fc2 | so]",
-            "  /tokens              display token usage"
-        ].join("\n").to_string()
+    pub fn new(max_height: u16) -> Self {
+        Self { scroll: 0, max_height: max_height.max(3) }
+    }
+
+    fn lines(&self) -> Vec<Line<'static>> {
+        let mut lines = Vec::new();
+        for (i, (title, keys)) in SECTIONS.iter().enumerate() {
+            if i > 0 {
+                lines.push(Line::default());
+            }
+            lines.push(Line::from(Span::styled(format!("  {}", title), Style::default().add_modifier(Modifier::BOLD))));
+            for (key, description) in keys.iter() {
+                lines.push(Line::from(format!("  {:<width$} {}", key, description, width = KEY_WIDTH)));
+            }
+        }
+        lines
+    }
+
+    fn content_height(&self) -> usize {
+        SECTIONS.iter().map(|(_, keys)| keys.len() + 2).sum::<usize>() - 1
+    }
+
+    fn is_scrollable(&self) -> bool {
+        self.content_height() > self.max_height as usize
+    }
+
+    /// Lines of content visible at once, the last line is kept for the scroll hint if needed
+    fn page(&self) -> usize {
+        if self.is_scrollable() {
+            self.max_height as usize - 1
+        } else {
+            self.content_height()
+        }
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(self.page());
+    }
+
+    pub fn scroll_down(&mut self) {
+        let last = self.content_height().saturating_sub(self.page());
+        self.scroll = (self.scroll + self.page()).min(last);
     }
 }
 
 impl HelpArea {
     pub fn height(&self) -> u16 {
-        8 // content (3 general help lines + 1 blank + 1 header + 3 command lines)
+        (self.content_height() as u16).min(self.max_height)
     }
 
     pub fn draw(&self, f: &mut Frame, area: Rect) {
-        let helper_text = self.helper_msg();
-        let x = helper_text.into_text().unwrap();
-        let x = x.style(Style::default().fg(Color::DarkGray).dim());
-        f.render_widget(
-            x, 
-            area
-        );
+        let style = Style::default().fg(Color::DarkGray).dim();
+        let mut lines: Vec<Line> = self.lines().into_iter().skip(self.scroll).take(self.page()).collect();
+        if self.is_scrollable() {
+            let shown = (self.scroll + self.page()).min(self.content_height());
+            lines.push(Line::from(format!("  pgup/pgdn to scroll ({}/{})", shown, self.content_height())).italic());
+        }
+        f.render_widget(Paragraph::new(lines).style(style), area);
     }
 }
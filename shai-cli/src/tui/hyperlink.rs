@@ -0,0 +1,115 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crossterm::cursor;
+use crossterm::queue;
+use crossterm::style::{Attribute, Print, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use ratatui::buffer::Buffer;
+use ratatui::style::Style;
+
+/// Text printed after this sequence is a link to `url`, until CLOSE
+fn open(url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\", url)
+}
+
+const CLOSE: &str = "\x1b]8;;\x1b\\";
+
+/// file:// url of a path that exists, relative paths are resolved from the current directory
+pub fn file_url(path: impl AsRef<Path>) -> Option<String> {
+    let absolute = path.as_ref().canonicalize().ok()?;
+    let encoded: String = absolute.to_string_lossy().bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect();
+    Some(format!("file://{}", encoded))
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-' | '~')
+}
+
+/// Runs of path characters that could be a file path, as char ranges of the line.
+/// A trailing dot is left out, it usually ends a sentence.
+fn path_candidates(line: &[char]) -> Vec<(usize, usize)> {
+    let mut candidates = Vec::new();
+    let mut start = None;
+    for i in 0..=line.len() {
+        match (start, line.get(i).copied().filter(|c| is_path_char(*c))) {
+            (None, Some(_)) => start = Some(i),
+            (Some(s), None) => {
+                let mut end = i;
+                while end > s && line[end - 1] == '.' {
+                    end -= 1;
+                }
+                let word = &line[s..end];
+                if word.len() > 1 && word.iter().any(|c| matches!(c, '/' | '.')) {
+                    candidates.push((s, end));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    candidates
+}
+
+/// Turn the paths of existing files found in a rendered buffer into OSC 8 hyperlinks. The
+/// escape sequences ride on the first and last cell of each path so the text keeps its width,
+/// which only suits buffers written cell by cell like the ones of `Terminal::insert_before`.
+pub fn link_paths(buf: &mut Buffer) {
+    let area = buf.area;
+    for y in area.top()..area.bottom() {
+        let line: Vec<char> = (area.left()..area.right())
+            .map(|x| {
+                let mut chars = buf[(x, y)].symbol().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => '\0',
+                }
+            })
+            .collect();
+        for (start, end) in path_candidates(&line) {
+            let path: String = line[start..end].iter().collect();
+            let Some(url) = file_url(&path) else {
+                continue;
+            };
+            let (first, last) = (area.x + start as u16, area.x + end as u16 - 1);
+            let symbol = format!("{}{}", open(&url), buf[(first, y)].symbol());
+            buf[(first, y)].set_symbol(&symbol);
+            let symbol = format!("{}{}", buf[(last, y)].symbol(), CLOSE);
+            buf[(last, y)].set_symbol(&symbol);
+        }
+    }
+}
+
+/// Text of a frame that should be a hyperlink, printed by `print_links` once the frame is drawn
+#[derive(Debug, Clone)]
+pub struct LinkSpan {
+    pub x: u16,
+    pub y: u16,
+    pub text: String,
+    pub url: String,
+    pub style: Style,
+}
+
+/// Print the spans over the frame that was just drawn, this time as hyperlinks. Cells holding
+/// escape sequences would throw off the width computations of ratatui's diff, so the links are
+/// written on the side with the same text and style.
+pub fn print_links(out: &mut impl Write, links: &[LinkSpan]) -> io::Result<()> {
+    if links.is_empty() {
+        return Ok(());
+    }
+    queue!(out, cursor::SavePosition)?;
+    for link in links {
+        queue!(out, cursor::MoveTo(link.x, link.y))?;
+        if let Some(fg) = link.style.fg {
+            queue!(out, SetForegroundColor(fg.into()))?;
+        }
+        if let Some(bg) = link.style.bg {
+            queue!(out, SetBackgroundColor(bg.into()))?;
+        }
+        queue!(out, Print(format!("{}{}{}", open(&link.url), link.text, CLOSE)), SetAttribute(Attribute::Reset))?;
+    }
+    queue!(out, cursor::RestorePosition)?;
+    out.flush()
+}
@@ -1,40 +1,204 @@
-use std::time::{Instant, Duration};
+use std::collections::HashMap;
+use std::time::{Instant, Duration, SystemTime};
 use std::fs;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
 
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use futures::io;
 use cli_clipboard::{ClipboardContext, ClipboardProvider};
 use jwalk::WalkDir;
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Margin, Rect},
     style::{Color, Style, Stylize},
     symbols::border,
     text::{Line, Span},
-    widgets::{Block, Borders, Padding, Paragraph, Widget, List, ListItem},
+    widgets::{Block, Borders, Padding, Paragraph, Widget, List, ListItem, Scrollbar, ScrollbarOrientation, ScrollbarState},
     Frame,
 };
-use shai_core::agent::{AgentController, AgentEvent, PublicAgentState};
+use shai_core::agent::{AgentController, AgentEvent, AgentMode, PublicAgentState};
 use shai_llm::{tool::call_fc_auto::ToolCallFunctionCallingAuto, ToolCallMethod};
 use tui_textarea::{Input, TextArea};
 
 use crate::{tui::{cmdnav::CommandNav, helper::HelpArea}};
 
-use super::theme::SHAI_YELLOW;
+use super::hyperlink::{file_url, LinkSpan};
+use super::theme::{Theme, SHAI_YELLOW};
+use shai_core::runners::compacter::compact::COMPRESSION_THRESHOLD;
+use shai_core::tools::fs::read::structs::split_line_range;
+
+/// Number of file suggestions displayed at once
+const MAX_VISIBLE_SUGGESTIONS: usize = 5;
+
+/// Lines of the highlighted file read for the preview panel
+const PREVIEW_LINES: usize = 20;
+
+/// Height of the suggestions area (borders included) while the preview is shown
+const PREVIEW_HEIGHT: u16 = 12;
+
+/// Below this width the preview would squeeze the file list, it is not drawn
+const PREVIEW_MIN_WIDTH: u16 = 80;
+
+/// Quiet time after the last keystroke before the file picker walks the tree
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(120);
+
+/// History entries skipped by PageUp/PageDown
+const HISTORY_PAGE: usize = 10;
+
+/// Terminal rows kept for the input and the rest of the ui when sizing the help overlay
+const HELP_RESERVED_ROWS: u16 = 10;
+
+/// Files returned by a search of the @ picker
+const MAX_SUGGESTIONS: usize = 20;
+
+/// Searches up to this length match almost every file, they list the recently modified ones first
+const RECENT_FIRST_MAX_PATTERN: usize = 2;
+
+/// Entries compared by modification time before the walk is cut, on huge trees
+const RECENT_SCAN_LIMIT: usize = 5000;
+
+/// Files larger than this are not suggested by default, they would not fit in a prompt anyway
+pub const DEFAULT_MAX_SUGGESTED_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Extensions of files that are not text, left out of the @ picker without opening them
+const BINARY_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "webp", "tiff", "psd",
+    "pdf", "zip", "gz", "tgz", "xz", "bz2", "7z", "rar", "tar", "jar",
+    "o", "a", "so", "dylib", "dll", "exe", "bin", "wasm", "class", "pyc", "rlib", "rmeta",
+    "woff", "woff2", "ttf", "otf", "mp3", "mp4", "mov", "avi", "wav", "flac", "ogg",
+    "sqlite", "db",
+];
+
+/// Bytes read at the start of a file to tell whether it is binary, like git does
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+fn has_binary_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| BINARY_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// A null byte near the start means binary content, unreadable files are not suggested either
+fn sniffs_binary(path: &Path) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return true;
+    };
+    let mut head = Vec::with_capacity(BINARY_SNIFF_BYTES);
+    if file.take(BINARY_SNIFF_BYTES as u64).read_to_end(&mut head).is_err() {
+        return true;
+    }
+    head.contains(&0)
+}
+
+/// Prompts kept in the history by default, the oldest are dropped first
+const DEFAULT_MAX_HISTORY: usize = 1000;
+
+/// How the paths picked with @ are written in the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PathStyle {
+    /// as found from the search root, e.g. ./src/main.rs
+    #[default]
+    Relative,
+    /// canonicalized, e.g. /home/me/project/src/main.rs
+    Absolute,
+    /// from the working directory through $PWD, e.g. $PWD/src/main.rs
+    Pwd,
+}
+
+impl PathStyle {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "relative" => Some(Self::Relative),
+            "absolute" => Some(Self::Absolute),
+            "pwd" => Some(Self::Pwd),
+            _ => None,
+        }
+    }
+
+    /// Write a picked path (and the line range typed after it) in this style. Paths a shell
+    /// would split or expand are quoted so they can be handed to bash as they are.
+    pub fn format(&self, path: &str, range: &str) -> String {
+        let absolute = || Path::new(path).canonicalize().ok();
+        match self {
+            Self::Relative => shell_quote(&format!("{}{}", path, range)),
+            Self::Absolute => match absolute() {
+                Some(absolute) => shell_quote(&format!("{}{}", absolute.display(), range)),
+                None => shell_quote(&format!("{}{}", path, range)),
+            },
+            Self::Pwd => {
+                let below_cwd = std::env::current_dir().ok()
+                    .and_then(|cwd| cwd.canonicalize().ok())
+                    .zip(absolute())
+                    .and_then(|(cwd, absolute)| absolute.strip_prefix(&cwd).ok().map(Path::to_path_buf));
+                match below_cwd {
+                    Some(relative) => {
+                        let relative = format!("{}{}", relative.display(), range);
+                        if needs_quoting(&relative) {
+                            // double quotes so that $PWD still expands
+                            let escaped: String = relative.chars()
+                                .flat_map(|c| match c {
+                                    '"' | '\\' | '$' | '`' => vec!['\\', c],
+                                    _ => vec![c],
+                                })
+                                .collect();
+                            format!("\"$PWD/{}\"", escaped)
+                        } else {
+                            format!("$PWD/{}", relative)
+                        }
+                    }
+                    // outside of the working directory $PWD would not help, fall back to the absolute path
+                    None => Self::Absolute.format(path, range),
+                }
+            }
+        }
+    }
+}
+
+/// Whether a shell would not take the text as a single plain word
+fn needs_quoting(text: &str) -> bool {
+    text.chars().any(|c| c.is_whitespace() || "'\"\\$`;&|<>()*?[]{}!#~".contains(c))
+}
+
+/// Single quote a word for the shell if it needs it
+fn shell_quote(text: &str) -> String {
+    if needs_quoting(text) {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Fraction of the context window above which the gauge turns red
+const NEAR_LIMIT_THRESHOLD: f32 = 0.95;
+
+/// Cells of the context usage bar
+const CONTEXT_BAR_WIDTH: usize = 10;
+
+/// The turns left before auto-compaction are shown from this many turns
+const COMPACTION_WARNING_TURNS: u32 = 3;
+
+/// How long "✓ done" stays in the status line once a task finishes
+pub const DEFAULT_DONE_FLASH: Duration = Duration::from_millis(1500);
 
 pub enum UserAction {
     Nope,
     CancelTask,
+    CancelTool,
     UserInput {
         input: String
     },
     UserAppCommand {
         command: String
+    },
+    SwitchToolCallMethod {
+        method: ToolCallMethod
+    },
+    SwitchMode {
+        mode: AgentMode
     }
 }
 
 pub struct InputArea<'a> {
-    agent_running: bool,
 
     // input text
     input: TextArea<'a>,
@@ -42,10 +206,17 @@ pub struct InputArea<'a> {
 
     // draft saving for history navigation
     current_draft: Option<String>,
+    // input cleared with esc esc, restored by a ctrl^z right after it
+    cleared_input: Option<String>,
 
     // alert top left
     animation_start: Option<Instant>,
     status_message: Option<String>,
+    agent_state: Option<PublicAgentState>,
+    running_tools: Vec<String>,
+    done_flash: Option<Instant>,
+    done_flash_duration: Duration,
+    task_cancelled: bool,
 
     // status bottom left
     last_keystroke_time: Option<Instant>,
@@ -57,6 +228,23 @@ pub struct InputArea<'a> {
 
     // method info bottom right
     method: ToolCallMethod,
+    // plan or execute, shown left of the method
+    mode: AgentMode,
+
+    // context usage gauge bottom right (current, max), with the compression threshold marked
+    token_usage: Option<(u32, u32)>,
+    compress_threshold: f32,
+    // growth of the context over the last turn, to predict when compression triggers
+    context_growth: Option<u32>,
+    // tokens spent since the start of the session (input, output) and their cost if known, left of the gauge
+    session_tokens: Option<(u64, u64)>,
+    session_cost: Option<f64>,
+
+    // colors and spinner
+    theme: Theme,
+
+    // set once the system clipboard could not be reached, so ctrl^v stops retrying
+    clipboard_unavailable: bool,
 
     // bottom helper
     help: Option<HelpArea>,
@@ -64,25 +252,64 @@ pub struct InputArea<'a> {
 
     history: Vec<String>,
     history_index: usize,
+    max_history: usize,
 
-    // file suggestions
+    // file suggestions, when disabled @ is a plain character
+    file_suggestions_enabled: bool,
+    // binary and large files are suggested too
+    suggest_all_files: bool,
+    max_suggested_file_size: u64,
     file_suggestions: Vec<String>,
     suggestion_index: Option<usize>,
     suggestion_search: Option<String>,
+    selected_suggestions: Vec<usize>,
+    suggestion_scroll: usize,
+    pending_search: Option<Instant>,
+
+    // preview of the highlighted suggestion, toggled with ctrl^p
+    show_preview: bool,
+    preview_cache: HashMap<String, Vec<String>>,
+
+    // directory walked by the @ file picker
+    search_root: PathBuf,
+    path_style: PathStyle,
+
+    // OSC 8 links on the suggestions, printed by the app once the frame is drawn
+    hyperlinks: bool,
+    links: Vec<LinkSpan>,
+
+    // soft line length limit marked with a rule in the prompt, with a count below it
+    line_rule: Option<u16>,
 
-    // gitignore patterns (loaded once)
+    // gitignore patterns (loaded once per search root)
     gitignore_patterns: Vec<String>,
 }
 
+/// Drop the earlier copies of repeated entries, each one keeps its most recent place
+fn keep_latest_occurrences(history: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut deduped: Vec<String> = history.into_iter()
+        .rev()
+        .filter(|entry| seen.insert(entry.clone()))
+        .collect();
+    deduped.reverse();
+    deduped
+}
+
 impl Default for InputArea<'_> {
     fn default() -> Self {
         Self {
-            agent_running: false,
             input: TextArea::default(),
             placeholder: "? for shortcuts".to_string(),
             current_draft: None,
+            cleared_input: None,
             animation_start: None,
             status_message: None,
+            agent_state: None,
+            running_tools: Vec::new(),
+            done_flash: None,
+            done_flash_duration: DEFAULT_DONE_FLASH,
+            task_cancelled: false,
             last_keystroke_time: None,
             pending_enter: None,
             helper_msg: None,
@@ -90,14 +317,36 @@ impl Default for InputArea<'_> {
             helper_duration: None,
             escape_press_time: None,
             method: ToolCallMethod::FunctionCall,
+            mode: AgentMode::default(),
+            token_usage: None,
+            compress_threshold: COMPRESSION_THRESHOLD,
+            context_growth: None,
+            session_tokens: None,
+            session_cost: None,
+            theme: Theme::default(),
+            clipboard_unavailable: false,
             help: None,
             cmdnav: CommandNav{},
             history: Vec::new(),
             history_index: 0,
+            max_history: DEFAULT_MAX_HISTORY,
+            file_suggestions_enabled: true,
+            suggest_all_files: false,
+            max_suggested_file_size: DEFAULT_MAX_SUGGESTED_FILE_SIZE,
             file_suggestions: Vec::new(),
             suggestion_index: None,
             suggestion_search: None,
-            gitignore_patterns: Self::load_gitignore_patterns(),
+            selected_suggestions: Vec::new(),
+            suggestion_scroll: 0,
+            pending_search: None,
+            show_preview: true,
+            preview_cache: HashMap::new(),
+            search_root: PathBuf::from("."),
+            path_style: PathStyle::default(),
+            hyperlinks: false,
+            line_rule: None,
+            links: Vec::new(),
+            gitignore_patterns: Self::load_gitignore_patterns(Path::new(".")),
         }
     }
 }
@@ -107,14 +356,119 @@ impl InputArea<'_> {
         Self::default()
     }
 
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// How long "✓ done" is shown when a task finishes, zero disables it
+    pub fn set_done_flash_duration(&mut self, duration: Duration) {
+        self.done_flash_duration = duration;
+    }
+
+    /// Load a history, repeated prompts only keep their most recent occurrence
     pub fn set_history(&mut self, history: Vec<String>) {
-        self.history = history;
+        self.history = keep_latest_occurrences(history);
+        self.trim_history();
         self.history_index = self.history.len();
     }
 
+    pub fn set_max_history(&mut self, max_history: usize) {
+        self.max_history = max_history;
+        self.trim_history();
+        self.history_index = self.history.len();
+    }
+
+    /// Record a submitted prompt, an earlier copy is removed so that it only
+    /// shows up once, as the most recent entry (`a, b, a` becomes `b, a`)
+    fn push_history(&mut self, entry: String) {
+        self.history.retain(|e| *e != entry);
+        self.history.push(entry);
+        self.trim_history();
+        // the removed copy may be the one being browsed, start again from the prompt being typed
+        self.history_index = self.history.len();
+    }
+
+    /// Most recent prompt sent to the agent, app commands left out
+    pub fn last_prompt(&self) -> Option<String> {
+        self.history.iter().rev().find(|entry| !entry.starts_with('/')).cloned()
+    }
+
+    fn trim_history(&mut self) {
+        if self.history.len() > self.max_history {
+            let excess = self.history.len() - self.max_history;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Turn the @ file picker on or off, nothing is searched while it is off
+    pub fn set_file_suggestions_enabled(&mut self, enabled: bool) {
+        self.file_suggestions_enabled = enabled;
+        if !enabled {
+            self.pending_search = None;
+            self.file_suggestions.clear();
+            self.suggestion_index = None;
+            self.suggestion_search = None;
+            self.selected_suggestions.clear();
+            self.suggestion_scroll = 0;
+        }
+    }
+
+    /// Suggest every file, binary and large ones included, or only what the agent can read
+    pub fn set_suggest_all_files(&mut self, all: bool) {
+        self.suggest_all_files = all;
+        self.suggestion_search = None;
+    }
+
+    /// Files larger than `size` bytes are not suggested, unless every file is
+    pub fn set_max_suggested_file_size(&mut self, size: u64) {
+        self.max_suggested_file_size = size;
+        self.suggestion_search = None;
+    }
+
+    /// Walk the @ file picker from another directory than the current one,
+    /// suggested paths include the root so they stay valid for the tools
+    pub fn set_search_root(&mut self, root: impl Into<PathBuf>) {
+        self.search_root = root.into();
+        self.gitignore_patterns = Self::load_gitignore_patterns(&self.search_root);
+        self.suggestion_search = None;
+    }
+
+    /// How the files picked with @ are written in the prompt
+    pub fn set_path_style(&mut self, style: PathStyle) {
+        self.path_style = style;
+    }
+
+    /// Make the suggested files clickable in terminals supporting OSC 8 hyperlinks
+    pub fn set_hyperlinks(&mut self, enabled: bool) {
+        self.hyperlinks = enabled;
+    }
+
+    /// Mark column `column` of the prompt with a dim rule and count the characters and words
+    /// typed, None (the default) shows neither
+    pub fn set_line_rule(&mut self, column: Option<u16>) {
+        self.line_rule = column.filter(|column| *column > 0);
+    }
+
+    /// "1234 chars · 210 words" of the prompt, while the line rule is on
+    fn composition_count(&self) -> Option<String> {
+        self.line_rule?;
+        let lines = self.input.lines();
+        let chars: usize = lines.iter().map(|line| line.chars().count()).sum::<usize>() + lines.len() - 1;
+        if chars == 0 {
+            return None;
+        }
+        let words: usize = lines.iter().map(|line| line.split_whitespace().count()).sum();
+        Some(format!("{} chars · {} words", chars, words))
+    }
+
+    /// Links of the last drawn frame
+    pub fn take_links(&mut self) -> Vec<LinkSpan> {
+        std::mem::take(&mut self.links)
+    }
+
     // Parse .gitignore and return list of patterns to ignore
-    fn load_gitignore_patterns() -> Vec<String> {
-        if let Ok(content) = fs::read_to_string(".gitignore") {
+    fn load_gitignore_patterns(root: &Path) -> Vec<String> {
+        if let Ok(content) = fs::read_to_string(root.join(".gitignore")) {
             content
                 .lines()
                 .filter_map(|line| {
@@ -187,7 +541,7 @@ impl InputArea<'_> {
         let pattern_lower = pattern.to_lowercase();
         let include_hidden = pattern.starts_with('.');
         
-        WalkDir::new(".")
+        let matches = WalkDir::new(&self.search_root)
             .max_depth(5)
             .skip_hidden(!include_hidden)
             .into_iter()
@@ -195,38 +549,143 @@ impl InputArea<'_> {
             .filter_map(|e| {
                 let path = e.path();
                 let path_str = path.to_string_lossy().to_string();
+
+                // match against the part below the root, the root itself is only there for the tools
+                let relative = Path::new(".").join(path.strip_prefix(&self.search_root).unwrap_or(&path)).to_string_lossy().to_string();
                 
                 // Skip if matches gitignore patterns
-                if Self::should_ignore(&path_str, &self.gitignore_patterns) {
+                if Self::should_ignore(&relative, &self.gitignore_patterns) {
                     return None;
                 }
+
+                // images, archives and huge files make no sense in a prompt
+                if !self.suggest_all_files && e.file_type().is_file() {
+                    let too_large = e.metadata().is_ok_and(|m| m.len() > self.max_suggested_file_size);
+                    if too_large || has_binary_extension(&path) {
+                        return None;
+                    }
+                }
                 
-                if pattern.is_empty() || path_str.to_lowercase().contains(&pattern_lower) {
-                    Some(path_str)
+                if pattern.is_empty() || relative.to_lowercase().contains(&pattern_lower) {
+                    Some((path_str, e))
                 } else {
                     None
                 }
+            });
+
+        // the content is only sniffed for the files about to be listed, opening every file of the walk would be slow
+        let is_text = |path: &String| self.suggest_all_files || !Path::new(path).is_file() || !sniffs_binary(Path::new(path));
+
+        if pattern.chars().count() > RECENT_FIRST_MAX_PATTERN {
+            return matches.map(|(path, _)| path).filter(is_text).take(MAX_SUGGESTIONS).collect();
+        }
+
+        // the file just saved is the likely pick, directories keep the walk order after the files
+        let mut candidates: Vec<(String, SystemTime)> = matches
+            .take(RECENT_SCAN_LIMIT)
+            .map(|(path, e)| {
+                let modified = e.metadata().ok()
+                    .filter(|m| m.is_file())
+                    .and_then(|m| m.modified().ok())
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                (path, modified)
             })
-            .take(20)
-            .collect()
+            .collect();
+        candidates.sort_by(|a, b| b.1.cmp(&a.1));
+        candidates.into_iter().map(|(path, _)| path).filter(is_text).take(MAX_SUGGESTIONS).collect()
     }
 
-    // Update suggestions based on current input
+    // Update suggestions based on current input, a changed search only runs once typing settles
     fn update_suggestions(&mut self) {
-        if let Some((at_pos, search)) = self.detect_file_search() {
+        if !self.file_suggestions_enabled {
+            return;
+        }
+        if let Some((_, search)) = self.detect_file_search() {
+            if self.suggestion_search.as_ref() != Some(&search) {
+                self.pending_search = Some(Instant::now());
+            }
+        } else {
+            self.pending_search = None;
+            self.file_suggestions.clear();
+            self.suggestion_index = None;
+            self.suggestion_search = None;
+            self.selected_suggestions.clear();
+            self.suggestion_scroll = 0;
+        }
+    }
+
+    /// Run the debounced file search, called from the periodic tick like `check_pending_enter`
+    pub fn check_pending_search(&mut self) {
+        if let Some(changed) = self.pending_search {
+            if changed.elapsed() >= SEARCH_DEBOUNCE {
+                self.run_pending_search();
+            }
+        }
+    }
+
+    fn run_pending_search(&mut self) {
+        self.pending_search = None;
+        if !self.file_suggestions_enabled {
+            return;
+        }
+        if let Some((_, search)) = self.detect_file_search() {
             if self.suggestion_search.as_ref() != Some(&search) {
                 self.suggestion_search = Some(search.clone());
-                self.file_suggestions = self.search_files(&search);
+                // a :start-end line range typed after the path is not part of the file name
+                let path_part = search.split(':').next().unwrap_or_default();
+                self.file_suggestions = self.search_files(path_part);
+                self.preview_cache.clear();
+                self.selected_suggestions.clear();
+                self.suggestion_scroll = 0;
                 self.suggestion_index = if self.file_suggestions.is_empty() {
                     None
                 } else {
                     Some(0)
                 };
             }
-        } else {
-            self.file_suggestions.clear();
-            self.suggestion_index = None;
-            self.suggestion_search = None;
+        }
+    }
+
+    // Scroll the suggestions window so the highlighted item stays in view
+    fn ensure_suggestion_visible(&mut self) {
+        if let Some(idx) = self.suggestion_index {
+            if idx < self.suggestion_scroll {
+                self.suggestion_scroll = idx;
+            } else if idx >= self.suggestion_scroll + MAX_VISIBLE_SUGGESTIONS {
+                self.suggestion_scroll = idx + 1 - MAX_VISIBLE_SUGGESTIONS;
+            }
+        }
+    }
+
+    // First lines of a suggested file, read once and cached until the next search
+    fn preview_lines(&mut self, path: &str) -> &[String] {
+        self.preview_cache.entry(path.to_string()).or_insert_with(|| {
+            if Path::new(path).is_dir() {
+                return vec!["(directory)".to_string()];
+            }
+            match fs::File::open(path) {
+                Ok(file) => {
+                    let lines: Vec<String> = BufReader::new(file)
+                        .lines()
+                        .map_while(Result::ok)
+                        .take(PREVIEW_LINES)
+                        .map(|line| line.replace('\t', "    "))
+                        .collect();
+                    if lines.is_empty() { vec!["(empty or binary file)".to_string()] } else { lines }
+                }
+                Err(e) => vec![format!("(cannot read: {})", e)],
+            }
+        })
+    }
+
+    // Toggle the checkmark of the highlighted suggestion
+    fn toggle_selected_suggestion(&mut self) {
+        if let Some(idx) = self.suggestion_index {
+            if let Some(pos) = self.selected_suggestions.iter().position(|&i| i == idx) {
+                self.selected_suggestions.remove(pos);
+            } else {
+                self.selected_suggestions.push(idx);
+            }
         }
     }
 }
@@ -238,6 +697,28 @@ impl InputArea<'_> {
         self.method = method;
     }
 
+    pub fn set_mode(&mut self, mode: AgentMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode_str(&self) -> &str {
+        match self.mode {
+            AgentMode::Plan => "📋 plan",
+            AgentMode::Execute => "⚡ execute",
+        }
+    }
+
+    /// Next method in the Ctrl+T cycle
+    fn next_method(method: ToolCallMethod) -> ToolCallMethod {
+        match method {
+            ToolCallMethod::Auto => ToolCallMethod::FunctionCall,
+            ToolCallMethod::FunctionCall => ToolCallMethod::FunctionCallRequired,
+            ToolCallMethod::FunctionCallRequired => ToolCallMethod::StructuredOutput,
+            ToolCallMethod::StructuredOutput => ToolCallMethod::Parsing,
+            ToolCallMethod::Parsing => ToolCallMethod::Auto,
+        }
+    }
+
     pub fn method_str(&self) -> &str {
         match self.method {
             ToolCallMethod::Auto => {
@@ -260,16 +741,111 @@ impl InputArea<'_> {
 }
 
 
-/// alert message in yellow, top left
+/// context usage gauge bottom right
 impl InputArea<'_> {
-    pub fn set_agent_running(&mut self, running: bool) {
-        self.agent_running = running;
-        if running {
-            self.animation_start = Some(Instant::now());
+    pub fn set_token_usage(&mut self, current: u32, max: u32) {
+        self.set_context_usage(current, max, self.compress_threshold);
+    }
+
+    /// Update the context bar, `compress_threshold` is the ratio of `max` at which the
+    /// conversation gets compressed
+    pub fn set_context_usage(&mut self, current: u32, max: u32, compress_threshold: f32) {
+        // a drop means a compression or a new conversation, the previous growth says nothing anymore
+        self.context_growth = match self.token_usage {
+            Some((previous, _)) if current > previous => Some(current - previous),
+            Some((previous, _)) if current == previous => self.context_growth,
+            _ => None,
+        };
+        self.token_usage = Some((current, max));
+        self.compress_threshold = compress_threshold.clamp(0.0, 1.0);
+    }
+
+    /// Turns left before auto-compaction if the context keeps growing like it did last turn
+    pub fn turns_to_compaction(&self) -> Option<u32> {
+        let (current, max) = self.token_usage?;
+        let growth = self.context_growth.filter(|growth| *growth > 0)?;
+        let threshold = (max as f32 * self.compress_threshold) as u32;
+        Some(threshold.saturating_sub(current).div_ceil(growth))
+    }
+
+    /// Filled cells of the bar and the cell holding the compression threshold marker
+    fn context_bar_cells(&self) -> Option<(usize, usize)> {
+        let (current, max) = self.token_usage?;
+        let ratio = if max > 0 { (current as f32 / max as f32).min(1.0) } else { 0.0 };
+        let filled = (ratio * CONTEXT_BAR_WIDTH as f32).round() as usize;
+        let marker = ((self.compress_threshold * CONTEXT_BAR_WIDTH as f32) as usize).min(CONTEXT_BAR_WIDTH - 1);
+        Some((filled, marker))
+    }
+
+    fn context_bar(&self, fill: Color) -> Option<Vec<Span<'static>>> {
+        let (filled, marker) = self.context_bar_cells()?;
+        let spans = (0..CONTEXT_BAR_WIDTH).map(|cell| {
+            if cell == marker {
+                Span::styled("┃", Style::default().fg(self.theme.accent))
+            } else if cell < filled {
+                Span::styled("━", Style::default().fg(fill))
+            } else {
+                Span::styled("─", Style::default().fg(self.theme.dim))
+            }
+        }).collect();
+        Some(spans)
+    }
+
+    pub fn token_usage(&self) -> Option<(u32, u32)> {
+        self.token_usage
+    }
+
+    pub fn set_session_usage(&mut self, input_tokens: u64, output_tokens: u64, cost: Option<f64>) {
+        self.session_tokens = Some((input_tokens, output_tokens));
+        self.session_cost = cost;
+    }
+
+    /// Cumulative tokens of the session, e.g. "Σ 120k↑ 8k↓ ~$0.42", the cost is left out
+    /// when a model has no known price
+    pub fn session_summary(&self) -> Option<String> {
+        let (input, output) = self.session_tokens?;
+        let mut text = format!("Σ {}↑ {}↓", Self::format_tokens(input), Self::format_tokens(output));
+        if let Some(cost) = self.session_cost {
+            text += &format!(" ~${:.2}", cost);
+        }
+        Some(text)
+    }
+
+    fn format_tokens(tokens: impl Into<u64>) -> String {
+        let tokens = tokens.into();
+        if tokens >= 1000 {
+            format!("{}k", tokens / 1000)
+        } else {
+            tokens.to_string()
+        }
+    }
+
+    pub fn token_gauge(&self) -> Option<(String, Color)> {
+        let (current, max) = self.token_usage?;
+        let ratio = if max > 0 { current as f32 / max as f32 } else { 0.0 };
+        let color = if ratio >= NEAR_LIMIT_THRESHOLD {
+            Color::Red
+        } else if ratio >= self.compress_threshold {
+            self.theme.accent
         } else {
-            self.status_message = None;
-            self.animation_start = None;
+            self.theme.dim
+        };
+        let mut text = format!("{}/{}", Self::format_tokens(current), Self::format_tokens(max));
+        match self.turns_to_compaction() {
+            Some(turns) if ratio < self.compress_threshold && turns <= COMPACTION_WARNING_TURNS => {
+                text = format!("~{} turn{} to compaction {}", turns, if turns == 1 { "" } else { "s" }, text);
+            }
+            _ => {}
         }
+        Some((text, color))
+    }
+}
+
+
+/// alert message in yellow, top left
+impl InputArea<'_> {
+    pub fn is_agent_running(&self) -> bool {
+        self.agent_state.as_ref().is_some_and(PublicAgentState::is_working)
     }
 
     pub fn with_placeholder(mut self, placeholder: &str) -> Self {
@@ -277,10 +853,65 @@ impl InputArea<'_> {
         self
     }
 
+    /// Current agent state, used to describe what the agent is doing in the status line
+    pub fn set_agent_state(&mut self, state: PublicAgentState) {
+        // the animation runs from the moment the agent starts working until it stops
+        match (self.is_agent_running(), state.is_working()) {
+            (false, true) => {
+                self.animation_start = Some(Instant::now());
+                self.done_flash = None;
+                self.task_cancelled = false;
+            }
+            (running, false) => {
+                // a task that went to its end is confirmed with a short flash
+                let finished = matches!(state, PublicAgentState::Paused | PublicAgentState::Completed { success: true });
+                if running && finished && !self.task_cancelled && !self.done_flash_duration.is_zero() {
+                    self.done_flash = Some(Instant::now());
+                }
+                self.status_message = None;
+                self.animation_start = None;
+            }
+            _ => {}
+        }
+        self.agent_state = Some(state);
+    }
+
+    /// The running task was cancelled by the user, it will stop without the done flash
+    pub fn mark_task_cancelled(&mut self) {
+        self.task_cancelled = true;
+    }
+
+    fn is_done_flashing(&self) -> bool {
+        self.done_flash.is_some_and(|start| start.elapsed() < self.done_flash_duration)
+    }
+
+    /// Name of the tools currently being executed
+    pub fn set_running_tools(&mut self, tools: Vec<String>) {
+        self.running_tools = tools;
+    }
+
+    fn activity_text(&self) -> String {
+        match &self.agent_state {
+            Some(PublicAgentState::Processing { task_name, .. }) => match task_name.as_str() {
+                "next_step" => "Thinking...".to_string(),
+                "tools" if !self.running_tools.is_empty() => format!("Running tool: {}", self.running_tools.join(", ")),
+                "tools" => "Running tools...".to_string(),
+                "compression" => "Compressing context...".to_string(),
+                other => format!("{}...", other),
+            },
+            Some(PublicAgentState::Starting) => "Starting...".to_string(),
+            _ => "Agent is working...".to_string(),
+        }
+    }
+
     pub fn set_status(&mut self, text: &str) {
         self.status_message = Some(text.to_string());
     }
 
+    pub fn clear_status(&mut self) {
+        self.status_message = None;
+    }
+
     pub fn is_animating(&self) -> bool {
         self.animation_start.is_some()
     }
@@ -291,10 +922,12 @@ impl InputArea<'_> {
             format!(" {}", msg)
         } else if let Some(animation_start) = self.animation_start {
             // Show spinner when agent is working
-            let spinner_chars = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+            let spinner_chars = self.theme.spinner.frames();
             let elapsed = animation_start.elapsed().as_millis();
             let index = (elapsed / 100) % spinner_chars.len() as u128;
-            format!(" {} Agent is working... (press esc to cancel)", spinner_chars[index as usize])
+            format!(" {} {} (press esc to cancel)", spinner_chars[index as usize], self.activity_text())
+        } else if self.is_done_flashing() {
+            " ✓ done".to_string()
         } else {
             // Agent is waiting for input, no status to show
             String::new()
@@ -315,15 +948,14 @@ impl InputArea<'_> {
             if enter_time.elapsed() >= Duration::from_millis(100) {
                 self.pending_enter = None;
                 
-                if self.agent_running {
+                if self.is_agent_running() {
                     return Some(UserAction::Nope);
                 }
 
                 let lines = self.input.lines();
                 if !lines[0].is_empty() {
                     let input = lines.join("\n");
-                    self.history.push(input.clone());
-                    self.history_index = self.history.len();
+                    self.push_history(input.clone());
                     
                     // Handle app commands vs agent input
                     self.input = TextArea::default();
@@ -379,6 +1011,32 @@ impl InputArea<'_> {
         }
     }
 
+    /// Move to a history entry, the end of the history being the draft the user was typing.
+    /// The draft is saved when leaving the end and restored when coming back to it
+    fn navigate_history(&mut self, target: usize) {
+        let target = target.min(self.history.len());
+        if self.history.is_empty() || target == self.history_index {
+            return;
+        }
+
+        if self.history_index == self.history.len() {
+            let is_empty = self.input.lines().iter().all(|line| line.is_empty());
+            if !is_empty {
+                self.current_draft = Some(self.input.lines().join("\n"));
+            }
+        }
+
+        self.history_index = target;
+        if self.history_index < self.history.len() {
+            self.load_historic_prompt(self.history_index);
+        } else if let Some(draft) = self.current_draft.take() {
+            self.input = TextArea::new(draft.lines().map(|s| s.to_string()).collect());
+            self.move_cursor_to_end_of_text();
+        } else {
+            self.input = TextArea::default();
+        }
+    }
+
     pub async fn handle_event(&mut self, key_event: KeyEvent) -> UserAction{
         let now = Instant::now();
         self.last_keystroke_time = Some(now);
@@ -395,13 +1053,35 @@ impl InputArea<'_> {
             let event: Input = Event::Key(fake_event).into();
             self.input.input(event);
         }
+
+        // ctrl^z right after an esc esc clear brings the input back, any other key forgets it
+        if let Some(cleared) = self.cleared_input.take() {
+            if key_event.code == KeyCode::Char('z') && key_event.modifiers.contains(KeyModifiers::CONTROL) {
+                self.input = TextArea::new(cleared.lines().map(|s| s.to_string()).collect());
+                self.move_cursor_to_end_of_text();
+                self.helper_msg = None;
+                return UserAction::Nope;
+            }
+        }
         
         match key_event.code {
             KeyCode::Char('?') if self.input.lines()[0].is_empty() && self.help.is_none() => {
-                self.help = Some(HelpArea);
+                // leave room for the input and the running tools above it
+                let rows = crossterm::terminal::size().map(|(_, rows)| rows).unwrap_or(24);
+                self.help = Some(HelpArea::new(rows.saturating_sub(HELP_RESERVED_ROWS)));
+            }
+            KeyCode::PageUp if self.help.is_some() => {
+                if let Some(help) = self.help.as_mut() {
+                    help.scroll_up();
+                }
+            }
+            KeyCode::PageDown if self.help.is_some() => {
+                if let Some(help) = self.help.as_mut() {
+                    help.scroll_down();
+                }
             }
             KeyCode::Esc => {
-                if self.agent_running {
+                if self.is_agent_running() {
                     return UserAction::CancelTask;
                 }
                 
@@ -409,9 +1089,10 @@ impl InputArea<'_> {
                 if let Some(escape_time) = self.escape_press_time {
                     // Second escape within 1 second - clear input
                     if escape_time.elapsed() < Duration::from_secs(1) {
+                        self.cleared_input = Some(self.input.lines().join("\n"));
                         self.input = TextArea::default();
                         self.escape_press_time = None;
-                        self.helper_msg = None;
+                        self.alert_msg(" ctrl^z to restore", Duration::from_secs(2));
                         return UserAction::Nope;
                     }
                 }
@@ -424,17 +1105,43 @@ impl InputArea<'_> {
                     self.helper_msg = Some(" press esc again to clear".to_string());
                 }
             }
+            KeyCode::Char('p') if key_event.modifiers.contains(KeyModifiers::CONTROL) && !self.file_suggestions.is_empty() => {
+                self.show_preview = !self.show_preview;
+                return UserAction::Nope;
+            }
+            KeyCode::Char('x') if key_event.modifiers.contains(KeyModifiers::CONTROL) && self.is_agent_running() => {
+                // unlike esc, only the running tools are stopped and the agent carries on
+                return UserAction::CancelTool;
+            }
+            KeyCode::Char('g') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.insert_code_fence();
+                return UserAction::Nope;
+            }
+            KeyCode::Char('t') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                // cycle through tool call methods, indicator is updated right away
+                self.method = Self::next_method(self.method);
+                return UserAction::SwitchToolCallMethod { method: self.method };
+            }
+            KeyCode::BackTab => {
+                // toggle plan / execute, indicator is updated right away
+                self.mode = self.mode.toggle();
+                return UserAction::SwitchMode { mode: self.mode };
+            }
             KeyCode::Char('v') if key_event.modifiers.contains(KeyModifiers::CONTROL) || key_event.modifiers.contains(KeyModifiers::SUPER) => {                
                 // Handle Ctrl+V or Cmd+V paste directly from clipboard
-                if let Ok(mut ctx) = ClipboardContext::new() {
-                    if let Ok(text) = ctx.get_contents() {
-                        self.input.insert_str(text);
-                        return UserAction::Nope;
+                if !self.clipboard_unavailable {
+                    match ClipboardContext::new() {
+                        Ok(mut ctx) => {
+                            if let Ok(text) = ctx.get_contents() {
+                                self.input.insert_str(text);
+                            }
+                            return UserAction::Nope;
+                        }
+                        Err(_) => self.clipboard_unavailable = true,
                     }
                 }
-                // Fallback: let TextArea handle it normally
-                let event: Input = Event::Key(key_event).into();
-                self.input.input(event);
+                // e.g. over ssh without a display, the terminal's own paste still works (bracketed paste)
+                self.alert_msg(" clipboard unavailable (no display), use your terminal's paste instead", Duration::from_secs(3));
                 return UserAction::Nope;
             }
             KeyCode::Enter => {
@@ -454,10 +1161,23 @@ impl InputArea<'_> {
                     return UserAction::Nope;
                 }
 
-                // Tab to select current suggestion
+                // don't let the debounce turn an Enter meant for the picker into a submit
+                if self.pending_search.is_some() {
+                    self.run_pending_search();
+                }
+
+                // Enter inserts the checked suggestions, or the highlighted one if none are checked
                 if let Some(idx) = self.suggestion_index {
-                    if let Some(file_path) = self.file_suggestions.get(idx).cloned() {
-                        self.replace_file_search(&file_path);
+                    let mut selected = self.selected_suggestions.clone();
+                    if selected.is_empty() {
+                        selected.push(idx);
+                    }
+                    selected.sort();
+                    let paths: Vec<String> = selected.iter()
+                        .filter_map(|&i| self.file_suggestions.get(i).cloned())
+                        .collect();
+                    if !paths.is_empty() {
+                        self.replace_file_search(&paths);
                     }
                     return UserAction::Nope;
                 }
@@ -470,12 +1190,31 @@ impl InputArea<'_> {
                 self.pending_enter = Some(now);
                 return UserAction::Nope;
             }
+            KeyCode::Tab if !self.file_suggestions.is_empty() => {
+                // Tab checks/unchecks the highlighted suggestion for multi-selection, space still types a space
+                self.toggle_selected_suggestion();
+                return UserAction::Nope;
+            }
+            // jumps through long histories: oldest / back to the draft, and pages of 10 entries
+            KeyCode::Home if key_event.modifiers.contains(KeyModifiers::CONTROL) && self.file_suggestions.is_empty() && !self.history.is_empty() => {
+                self.navigate_history(0);
+            }
+            KeyCode::End if key_event.modifiers.contains(KeyModifiers::CONTROL) && self.file_suggestions.is_empty() && !self.history.is_empty() => {
+                self.navigate_history(self.history.len());
+            }
+            KeyCode::PageUp if self.file_suggestions.is_empty() && !self.history.is_empty() => {
+                self.navigate_history(self.history_index.saturating_sub(HISTORY_PAGE));
+            }
+            KeyCode::PageDown if self.file_suggestions.is_empty() && !self.history.is_empty() => {
+                self.navigate_history(self.history_index + HISTORY_PAGE);
+            }
             KeyCode::Up => {
                 // If we have suggestions, navigate through them
                 if !self.file_suggestions.is_empty() {
                     if let Some(idx) = self.suggestion_index {
                         self.suggestion_index = Some(if idx > 0 { idx - 1 } else { self.file_suggestions.len() - 1 });
                     }
+                    self.ensure_suggestion_visible();
                     return UserAction::Nope;
                 }
 
@@ -487,13 +1226,7 @@ impl InputArea<'_> {
                 // 1. Input is empty, OR
                 // 2. Cursor is at the first line
                 if !self.history.is_empty() && self.history_index > 0 && (is_empty || cursor_row == 0) {
-                    if self.history_index == self.history.len() && !is_empty {
-                        let current_text = self.input.lines().join("\n");
-                        self.current_draft = Some(current_text);
-                    }
-
-                    self.history_index -= 1;
-                    self.load_historic_prompt(self.history_index);
+                    self.navigate_history(self.history_index - 1);
                 } else if !is_empty && cursor_row > 0 {
                     self.input.move_cursor(tui_textarea::CursorMove::Up);
                 }
@@ -504,6 +1237,7 @@ impl InputArea<'_> {
                     if let Some(idx) = self.suggestion_index {
                         self.suggestion_index = Some((idx + 1) % self.file_suggestions.len());
                     }
+                    self.ensure_suggestion_visible();
                     return UserAction::Nope;
                 }
 
@@ -515,24 +1249,30 @@ impl InputArea<'_> {
                 // Navigate history only if:
                 // 1. Cursor is at the last line
                 if !self.history.is_empty() && (is_empty || cursor_row == line_count - 1) {
-                    if self.history_index < self.history.len() {
-                        self.history_index += 1;
-                        if self.history_index < self.history.len() {
-                            self.load_historic_prompt(self.history_index);
-                        } else {
-                            // Restore draft or create empty input
-                            if let Some(draft) = self.current_draft.take() {
-                                self.input = TextArea::new(draft.lines().map(|s| s.to_string()).collect());
-                                self.move_cursor_to_end_of_text();
-                            } else {
-                                self.input = TextArea::default();
-                            }
-                        }
-                    }
+                    self.navigate_history(self.history_index + 1);
                 } else if !is_empty && cursor_row < line_count - 1 {
                     self.input.move_cursor(tui_textarea::CursorMove::Down);
                 }
             }
+            // readline style word and line movements, explicit so they don't depend on the textarea defaults
+            KeyCode::Left if key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.input.move_cursor(tui_textarea::CursorMove::WordBack);
+            }
+            KeyCode::Right if key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.input.move_cursor(tui_textarea::CursorMove::WordForward);
+            }
+            KeyCode::Char('a') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.move_cursor(tui_textarea::CursorMove::Head);
+            }
+            KeyCode::Char('e') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.move_cursor(tui_textarea::CursorMove::End);
+            }
+            KeyCode::Char('w') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.delete_word();
+            }
+            KeyCode::Backspace if key_event.modifiers.contains(KeyModifiers::ALT) => {
+                self.input.delete_word();
+            }
             _ => {
                 // Convert to ratatui event format for tui-textarea
                 self.help = None;
@@ -548,13 +1288,88 @@ impl InputArea<'_> {
         UserAction::Nope
     }
 
-    // Replace @search with the file path
-    fn replace_file_search(&mut self, file_path: &str) {
+    /// Wrap the selection in a code fence, or open an empty one.
+    /// The fence always starts on its own line so that it renders as a block,
+    /// and the cursor is left right after the opening ``` to type the language.
+    fn insert_code_fence(&mut self) {
+        self.last_keystroke_time = Some(Instant::now());
+        let selected = if self.input.is_selecting() && self.input.cut() {
+            Some(self.input.yank_text())
+        } else {
+            None
+        };
+
+        let (_, col) = self.input.cursor();
+        if col > 0 {
+            self.input.insert_newline();
+        }
+        let (fence_row, _) = self.input.cursor();
+        match selected {
+            Some(code) => {
+                self.input.insert_str(format!("```\n{}\n```", code.trim_end_matches('\n')));
+            }
+            None => {
+                self.input.insert_str("```\n\n```");
+            }
+        }
+        self.input.move_cursor(tui_textarea::CursorMove::Jump(fence_row as u16, 3));
+        self.update_suggestions();
+    }
+
+    /// Insert pasted text as is, newlines in the paste never submit the input
+    pub fn handle_paste(&mut self, text: &str) {
+        self.last_keystroke_time = Some(Instant::now());
+
+        // an Enter typed right before the paste is a newline, not a submit
+        if self.pending_enter.take().is_some() {
+            self.input.insert_newline();
+        }
+
+        self.cleared_input = None;
+
+        self.help = None;
+        self.input.insert_str(text.replace("\r\n", "\n").replace('\r', "\n"));
+        self.update_suggestions();
+    }
+
+    /// Contents of a file picked with @@ (the :start-end lines only if a range was typed), in a
+    /// fence labeled with its path. None when the file is better mentioned by path: binary,
+    /// larger than the suggestion size limit or unreadable.
+    fn inline_file(&self, path: &str, range: &str) -> Option<String> {
+        let file = Path::new(path);
+        let too_large = fs::metadata(file).map_or(true, |m| !m.is_file() || m.len() > self.max_suggested_file_size);
+        if too_large || has_binary_extension(file) || sniffs_binary(file) {
+            return None;
+        }
+        let content = fs::read_to_string(file).ok()?;
+        let (content, label) = match split_line_range(&format!("{}{}", path, range)) {
+            Some((_, start, end)) => {
+                let lines: Vec<&str> = content.lines()
+                    .skip(start.saturating_sub(1) as usize)
+                    .take(end.map_or(usize::MAX, |end| (end + 1).saturating_sub(start.max(1)) as usize))
+                    .collect();
+                (lines.join("\n"), format!("{}{}", path, range))
+            }
+            None => (content.trim_end_matches('\n').to_string(), path.to_string()),
+        };
+
+        // longer than any run of backticks in the file, so that the file cannot close the fence
+        let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+        let language = file.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        Some(format!("{}{} {}\n{}\n{}", fence, language, label, content, fence))
+    }
+
+    // Replace @search with the file path(s), a :start-end suffix of the search is kept on each.
+    // After @@ the contents of the files are inserted instead, for providers without file tools.
+    fn replace_file_search(&mut self, paths: &[String]) {
         if let Some((at_pos, search_text)) = self.detect_file_search() {
             let (row, _) = self.input.cursor();
+            let inline = at_pos > 0 && self.input.lines()[row].chars().nth(at_pos - 1) == Some('@');
+            let at_pos = if inline { at_pos - 1 } else { at_pos };
 
-            // Calculate how many characters to delete (@ + search text)
-            let chars_to_delete = 1 + search_text.len(); // @ + text after
+            // Calculate how many characters to delete (@ or @@ + search text)
+            let chars_to_delete = if inline { 2 } else { 1 } + search_text.chars().count();
 
             // Move cursor to @ position
             self.input.move_cursor(tui_textarea::CursorMove::Head);
@@ -567,13 +1382,38 @@ impl InputArea<'_> {
                 self.input.delete_next_char();
             }
 
-            // Insert file path
-            self.input.insert_str(file_path);
+            // Insert the paths, keeping the :start-end range typed after the search if any
+            let range = search_text.find(':').map(|i| &search_text[i..]).unwrap_or_default();
+            if inline {
+                let (inlined, referenced): (Vec<_>, Vec<_>) = paths.iter()
+                    .map(|path| (path, self.inline_file(path, range)))
+                    .partition(|(_, content)| content.is_some());
+                if !inlined.is_empty() {
+                    // a fence only renders as a block from the start of a line
+                    if at_pos > 0 {
+                        self.input.insert_newline();
+                    }
+                    let blocks: Vec<String> = inlined.into_iter().filter_map(|(_, content)| content).collect();
+                    self.input.insert_str(blocks.join("\n"));
+                    self.input.insert_newline();
+                }
+                if !referenced.is_empty() {
+                    let formatted: Vec<String> = referenced.iter().map(|(path, _)| self.path_style.format(path, range)).collect();
+                    self.input.insert_str(formatted.join(" "));
+                    self.alert_msg("binary or large files are mentioned by path, not inlined", Duration::from_secs(3));
+                }
+            } else {
+                let formatted: Vec<String> = paths.iter().map(|path| self.path_style.format(path, range)).collect();
+                self.input.insert_str(formatted.join(" "));
+            }
 
             // Reset suggestions
             self.file_suggestions.clear();
             self.suggestion_index = None;
             self.suggestion_search = None;
+            self.pending_search = None;
+            self.selected_suggestions.clear();
+            self.suggestion_scroll = 0;
         }
     }
 }
@@ -581,24 +1421,33 @@ impl InputArea<'_> {
 
 /// drawing logic
 impl InputArea<'_> {
+    /// the preview is only drawn when there is room for it next to the list
+    fn preview_shown(&self, width: u16) -> bool {
+        self.show_preview && width >= PREVIEW_MIN_WIDTH
+    }
+
+    fn suggestions_height(&self) -> u16 {
+        // the input spans the whole terminal, so its width is the one the suggestions get
+        let width = crossterm::terminal::size().map(|(cols, _)| cols).unwrap_or(0);
+        if self.file_suggestions.is_empty() {
+            0
+        } else if self.preview_shown(width) {
+            PREVIEW_HEIGHT
+        } else {
+            self.file_suggestions.len().min(MAX_VISIBLE_SUGGESTIONS) as u16 + 2
+        }
+    }
+
     pub fn height(&self) -> u16 {
         // +2 for top/bottom borders
         // +N for lines inside input
         // +1 for helper text below input
-        let suggestions_height = if !self.file_suggestions.is_empty() {
-            self.file_suggestions.len().min(5) as u16 + 2
-        } else {
-            0
-        };
-        self.input.lines().len().max(1) as u16 + 4 + self.help.as_ref().map_or(0, |h| h.height()) + suggestions_height
+        self.input.lines().len().max(1) as u16 + 4 + self.help.as_ref().map_or(0, |h| h.height()) + self.suggestions_height()
     }
 
     pub fn draw(&mut self, f: &mut Frame, area: Rect) {
-        let suggestions_height = if !self.file_suggestions.is_empty() {
-            self.file_suggestions.len().min(5) as u16 + 2
-        } else {
-            0
-        };
+        self.links.clear();
+        let suggestions_height = self.suggestions_height();
 
         let [status, input_area, suggestions_area, helper, help_area] = Layout::vertical([
             Constraint::Length(1),
@@ -609,14 +1458,19 @@ impl InputArea<'_> {
         ]).areas(area);
         
         // status
-        f.render_widget(Span::styled(self.get_status_text(), Style::default().fg(Color::Yellow)), status);
+        let status_color = if self.status_message.is_none() && self.animation_start.is_none() && self.is_done_flashing() {
+            self.theme.success
+        } else {
+            self.theme.accent
+        };
+        f.render_widget(Span::styled(self.get_status_text(), Style::default().fg(status_color)), status);
 
         // Input - clone and apply block styling
         let block = Block::default()
             .borders(Borders::ALL)
             .border_set(border::ROUNDED)
             .padding(Padding { left: 1, right: 1, top: 0, bottom: 0 })
-            .border_style(Style::default().fg(Color::DarkGray));
+            .border_style(Style::default().fg(self.theme.dim));
             //.border_style(Style::default().bold().fg(Color::Rgb(SHAI_YELLOW.0, SHAI_YELLOW.1, SHAI_YELLOW.2)));
         let inner = block.inner(input_area);
         f.render_widget(block, input_area);
@@ -626,47 +1480,93 @@ impl InputArea<'_> {
 
         // Set placeholder and block
         self.input.set_placeholder_text("? for help");
-        self.input.set_placeholder_style(Style::default().fg(Color::DarkGray));
-        self.input.set_style(Style::default().fg(Color::White));
+        self.input.set_placeholder_style(Style::default().fg(self.theme.dim));
+        self.input.set_style(Style::default().fg(self.theme.text));
         self.input.set_cursor_style(Style::default()
-            .fg(Color::White)
-            .bg(if !self.input.lines()[0].is_empty() { Color::White } else { Color::Reset }));
+            .fg(self.theme.cursor)
+            .bg(if !self.input.lines()[0].is_empty() { self.theme.cursor } else { Color::Reset }));
         self.input.set_cursor_line_style(Style::default());
         f.render_widget(&self.input, prompt);
+
+        // Soft line length rule, drawn over blank cells only so the text stays readable. Left out
+        // while the textarea is scrolled sideways, the column would not match anymore.
+        if let Some(column) = self.line_rule {
+            let (_, cursor_col) = self.input.cursor();
+            if column < prompt.width && cursor_col < prompt.width as usize {
+                let buf = f.buffer_mut();
+                for y in prompt.top()..prompt.bottom() {
+                    let cell = &mut buf[(prompt.x + column, y)];
+                    if cell.symbol() == " " {
+                        cell.set_symbol("│").set_fg(self.theme.dim);
+                    }
+                }
+            }
+        }
         
         // Helper text area below input
-        let [helper_left, _, helper_right] = Layout::horizontal([
+        let gauge = self.token_gauge();
+        let session = self.session_summary();
+        let [helper_left, helper_count, helper_session, helper_gauge, helper_mode, helper_right] = Layout::horizontal([
             Constraint::Fill(1), 
             Constraint::Fill(1), 
+            Constraint::Length(session.as_ref().map_or(0, |text| text.chars().count() as u16 + 2)),
+            Constraint::Length(gauge.as_ref().map_or(0, |(text, _)| (text.chars().count() + CONTEXT_BAR_WIDTH) as u16 + 3)),
+            Constraint::Length(self.mode_str().chars().count() as u16 + 2),
             Constraint::Length(self.method_str().len() as u16)
         ]).areas(helper);
 
         let helper_text = self.check_helper_msg();
         f.render_widget(
-            Span::styled(helper_text, Style::default().fg(Color::DarkGray).dim()), 
+            Span::styled(helper_text, Style::default().fg(self.theme.dim).dim()), 
             helper_left
         );
                 
+        // Length of the prompt being composed
+        if let Some(text) = self.composition_count() {
+            f.render_widget(Line::from(Span::styled(text, Style::default().fg(self.theme.dim))).right_aligned(), helper_count);
+        }
+
+        // Session usage
+        if let Some(text) = session {
+            f.render_widget(Span::styled(text, Style::default().fg(self.theme.dim)), helper_session);
+        }
+
+        // Context usage
+        if let Some((text, color)) = gauge {
+            let mut spans = self.context_bar(color).unwrap_or_default();
+            spans.push(Span::styled(format!(" {}", text), Style::default().fg(color)));
+            f.render_widget(Line::from(spans), helper_gauge);
+        }
+
+        // Mode, plan stands out since the agent will not change anything
+        let mode_color = match self.mode {
+            AgentMode::Plan => Color::Rgb(SHAI_YELLOW.0, SHAI_YELLOW.1, SHAI_YELLOW.2),
+            AgentMode::Execute => self.theme.dim,
+        };
+        f.render_widget(Span::styled(self.mode_str(), Style::default().fg(mode_color)), helper_mode);
+
         // Status
         f.render_widget(
-            Span::styled(self.method_str(), Style::default().fg(Color::DarkGray)), 
+            Span::styled(self.method_str(), Style::default().fg(self.theme.dim)), 
             helper_right
         );
 
-        // File suggestions
+        // File suggestions, with the preview of the highlighted one on the right
         if !self.file_suggestions.is_empty() {
-            let max_visible = 5;
+            let show_preview = self.preview_shown(suggestions_area.width);
+            let (suggestions_area, preview_area) = if show_preview {
+                let [list, preview] = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(suggestions_area);
+                (list, Some(preview))
+            } else {
+                (suggestions_area, None)
+            };
+
+            let max_visible = MAX_VISIBLE_SUGGESTIONS;
             let total = self.file_suggestions.len();
             let selected = self.suggestion_index.unwrap_or(0);
             
-            // Calculate scrolling window
-            let start = if total <= max_visible {
-                0
-            } else {
-                // Center the selected item in the window when possible
-                let ideal_start = selected.saturating_sub(max_visible / 2);
-                ideal_start.min(total.saturating_sub(max_visible))
-            };
+            // Scrolling window, kept around the highlighted item by ensure_suggestion_visible
+            let start = self.suggestion_scroll.min(total.saturating_sub(max_visible));
             
             let end = (start + max_visible).min(total);
             
@@ -676,28 +1576,68 @@ impl InputArea<'_> {
                 .map(|(window_idx, path)| {
                     let actual_idx = start + window_idx;
                     let style = if Some(actual_idx) == self.suggestion_index {
-                        Style::default().fg(Color::Yellow).bg(Color::DarkGray)
+                        Style::default().fg(self.theme.accent).bg(self.theme.dim)
                     } else {
-                        Style::default().fg(Color::White)
+                        Style::default().fg(self.theme.text)
                     };
-                    ListItem::new(path.as_str()).style(style)
+                    let mark = if self.selected_suggestions.contains(&actual_idx) { "✓ " } else { "  " };
+                    ListItem::new(format!("{}{}", mark, path)).style(style)
                 })
                 .collect();
 
-            let title = if total > max_visible {
+            if self.hyperlinks {
+                // inside the border, after the selection mark
+                let width = suggestions_area.width.saturating_sub(4) as usize;
+                for (window_idx, path) in self.file_suggestions[start..end].iter().enumerate() {
+                    let Some(url) = file_url(self.search_root.join(path)) else {
+                        continue;
+                    };
+                    let style = if Some(start + window_idx) == self.suggestion_index {
+                        Style::default().fg(self.theme.accent).bg(self.theme.dim)
+                    } else {
+                        Style::default().fg(self.theme.text)
+                    };
+                    self.links.push(LinkSpan {
+                        x: suggestions_area.x + 3,
+                        y: suggestions_area.y + 1 + window_idx as u16,
+                        text: path.chars().take(width).collect(),
+                        url,
+                        style,
+                    });
+                }
+            }
+
+            let mut title = if total > max_visible {
                 format!("Files ({}/{})", selected + 1, total)
             } else {
                 "Files".to_string()
             };
+            if !self.selected_suggestions.is_empty() {
+                title = format!("{} - {} selected", title, self.selected_suggestions.len());
+            }
 
             let suggestions_list = List::new(items)
                 .block(Block::default()
                     .borders(Borders::ALL)
                     .border_set(border::ROUNDED)
-                    .border_style(Style::default().fg(Color::DarkGray))
+                    .border_style(Style::default().fg(self.theme.dim))
                     .title(title));
 
             f.render_widget(suggestions_list, suggestions_area);
+
+            // scrollbar when some suggestions are off-screen
+            if total > max_visible {
+                let mut scrollbar_state = ScrollbarState::new(total - max_visible).position(start);
+                let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                    .begin_symbol(Some("▲"))
+                    .end_symbol(Some("▼"))
+                    .style(Style::default().fg(self.theme.dim));
+                f.render_stateful_widget(scrollbar, suggestions_area.inner(Margin { vertical: 1, horizontal: 0 }), &mut scrollbar_state);
+            }
+
+            if let (Some(preview_area), Some(path)) = (preview_area, self.file_suggestions.get(selected).cloned()) {
+                self.draw_preview(f, preview_area, &path);
+            }
         }
 
         // help
@@ -705,4 +1645,26 @@ impl InputArea<'_> {
             help.draw(f, help_area);
         }
     }
+
+    fn draw_preview(&mut self, f: &mut Frame, area: Rect, path: &str) {
+        let (dim, text) = (self.theme.dim, self.theme.text);
+        let width = self.preview_lines(path).len().to_string().len();
+        let lines: Vec<Line> = self.preview_lines(path)
+            .iter()
+            .enumerate()
+            .map(|(i, line)| Line::from(vec![
+                Span::styled(format!("{:>width$} ", i + 1, width = width), Style::default().fg(dim).dim()),
+                Span::styled(line.clone(), Style::default().fg(text)),
+            ]))
+            .collect();
+
+        let preview = Paragraph::new(lines)
+            .block(Block::default()
+                .borders(Borders::ALL)
+                .border_set(border::ROUNDED)
+                .border_style(Style::default().fg(self.theme.dim))
+                .title(path.to_string())
+                .title_bottom(Line::from(" ctrl^p to hide ").right_aligned()));
+        f.render_widget(preview, area);
+    }
 }
\ No newline at end of file
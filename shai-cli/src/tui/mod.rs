@@ -6,6 +6,7 @@ pub mod perm_alt_screen;
 pub mod theme;
 pub mod command;
 pub mod helper;
+pub mod hyperlink;
 pub mod cmdnav;
 
 pub use app::App;
\ No newline at end of file
@@ -1,4 +1,5 @@
 use rand::Rng;
+use ratatui::style::Color;
 
 pub fn shai_logo() -> String {
     format!(r#"
@@ -17,6 +18,80 @@ pub static SHAI_GREEN: (u8, u8, u8)  = (18,200,124);
 pub static SHAI_BLUE: (u8,u8,u8) = (148,220,239);
 pub static SHAI_WHITE: (u8,u8,u8) = (200,200,200);
 
+/// Animation shown in the status line while the agent works
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinnerStyle {
+    Braille,
+    Line,
+    Dots,
+}
+
+impl SpinnerStyle {
+    pub fn frames(&self) -> &'static [&'static str] {
+        match self {
+            SpinnerStyle::Braille => &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+            SpinnerStyle::Line => &["-", "\\", "|", "/"],
+            SpinnerStyle::Dots => &[".  ", ".. ", "...", "   "],
+        }
+    }
+}
+
+/// Colors of the input area, the default matches a dark terminal
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// status line, highlighted suggestion and warnings
+    pub accent: Color,
+    /// confirmation that a task is done
+    pub success: Color,
+    /// borders, placeholder and helper texts
+    pub dim: Color,
+    /// typed text and file suggestions
+    pub text: Color,
+    pub cursor: Color,
+    pub spinner: SpinnerStyle,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            accent: Color::Yellow,
+            success: Color::Green,
+            dim: Color::DarkGray,
+            text: Color::White,
+            cursor: Color::White,
+            spinner: SpinnerStyle::Braille,
+        }
+    }
+}
+
+impl Theme {
+    /// For terminals with a light background, where white text is unreadable
+    pub fn light() -> Self {
+        Self {
+            accent: Color::Rgb(180, 110, 0),
+            success: Color::Rgb(0, 130, 60),
+            dim: Color::Gray,
+            text: Color::Black,
+            cursor: Color::Black,
+            spinner: SpinnerStyle::Braille,
+        }
+    }
+
+    /// Pick the theme from SHAI_THEME (dark or light) and the spinner from SHAI_SPINNER (braille, line or dots)
+    pub fn from_env() -> Self {
+        let mut theme = match std::env::var("SHAI_THEME").as_deref() {
+            Ok("light") => Self::light(),
+            _ => Self::default(),
+        };
+        theme.spinner = match std::env::var("SHAI_SPINNER").as_deref() {
+            Ok("line") => SpinnerStyle::Line,
+            Ok("dots") => SpinnerStyle::Dots,
+            _ => theme.spinner,
+        };
+        theme
+    }
+}
+
 fn rgb_to_256_color(r: u8, g: u8, b: u8) -> u8 {
     let r_index = (r as f32 / 255.0 * 5.0).round() as u8;
     let g_index = (g as f32 / 255.0 * 5.0).round() as u8;
@@ -1,40 +1,198 @@
-use chrono::Utc;
-use shai_llm::ChatMessage;
-use tracing::info;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use serde::{Serialize, Deserialize};
+use shai_llm::{ChatMessage, ChatMessageContent, PriceTable};
+use shai_llm::tool::validate_arguments;
+use tracing::{debug, info, warn};
+use tokio::sync::{broadcast, mpsc, RwLock};
 use tokio_util::sync::CancellationToken;
-use crate::agent::{AgentCore, AgentError, AgentEvent, InternalAgentEvent, InternalAgentState, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
+use crate::agent::{AgentCore, AgentError, AgentEvent, Brain, ErrorReport, InternalAgentEvent, InternalAgentState, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
+use crate::runners::compacter::{latest_user_message, CompressionInfo, SkipReason};
+use crate::tools::AnyTool;
+
+/// Steps in a row the brain may answer with unparsable tool arguments before the agent gives up
+const MAX_INVALID_TOOL_CALL_RETRIES: u32 = 2;
+
+/// Steps in a row the brain may continue without calling a tool before the agent pauses.
+/// Without tool calls nothing new comes into the conversation, the model only talks to itself.
+pub const DEFAULT_IDLE_STEP_LIMIT: u32 = 3;
+
+/// Limits of a single task, from the user input until the agent pauses. A task going past one
+/// of them is paused with a BudgetExceeded event, None means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TaskBudget {
+    pub max_steps: Option<u32>,
+    pub max_duration: Option<Duration>,
+}
+
+impl TaskBudget {
+    pub fn is_exceeded(&self, steps: u32, elapsed: TimeDelta) -> bool {
+        self.max_steps.is_some_and(|max| steps >= max)
+            || self.max_duration.is_some_and(|max| elapsed.to_std().unwrap_or(Duration::ZERO) >= max)
+    }
+}
+
+/// Brain errors in a row after which the agent refuses new input until the breaker is reset,
+/// so that a broken provider is not hit again at every enter. Errors further apart than
+/// `window` start a new count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CircuitBreaker {
+    pub max_failures: u32,
+    pub window: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self { max_failures: 3, window: Duration::from_secs(300) }
+    }
+}
+
+/// Error given to the input refused while the circuit breaker is open
+pub const CIRCUIT_OPEN_ERROR: &str = "provider appears to be failing - check configuration, then reset the circuit breaker";
+
+/// Tokens sent to and received from one model
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// Tokens spent since the agent started, by all the turns of the session. Conversations that
+/// were cleared or compressed since are still counted, they were paid for. Brains that do not
+/// tell which model answered are counted under "unknown".
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SessionUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub by_model: BTreeMap<String, ModelUsage>,
+}
+
+impl SessionUsage {
+    pub fn add(&mut self, model: Option<&str>, input_tokens: u32, output_tokens: u32) {
+        self.input_tokens += input_tokens as u64;
+        self.output_tokens += output_tokens as u64;
+        let usage = self.by_model.entry(model.unwrap_or("unknown").to_string()).or_default();
+        usage.input_tokens += input_tokens as u64;
+        usage.output_tokens += output_tokens as u64;
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+
+    /// Cost in USD of the session, None as soon as one of the models used has no known price
+    /// rather than a total that leaves it out
+    pub fn estimate_cost(&self, prices: &PriceTable) -> Option<f64> {
+        self.by_model.iter()
+            .map(|(model, usage)| prices.estimate_cost(model, usage.input_tokens, usage.output_tokens))
+            .sum()
+    }
+
+    /// Sum of the usages of several agents, e.g. those of a pool
+    pub fn merge(&mut self, other: &SessionUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        for (model, usage) in &other.by_model {
+            let total = self.by_model.entry(model.clone()).or_default();
+            total.input_tokens += usage.input_tokens;
+            total.output_tokens += usage.output_tokens;
+        }
+    }
+}
 
 impl AgentCore {
     /// Launch a brain task to decide next step
     pub async fn spawn_next_step(&mut self) {         
+        // a task still in flight would also send its result and push to the trace, stop it first.
+        // What it already sent carries its step id and is dropped
+        if let InternalAgentState::Processing { cancellation_token, .. } = &self.state {
+            cancellation_token.cancel();
+        }
+        if let Some(task) = self.running_task.take() {
+            task.abort();
+        }
+        self.brain_step += 1;
+        let step_id = self.brain_step;
+        self.task_started_at.get_or_insert_with(Utc::now);
+
         let cancellation_token = CancellationToken::new();
         let cancel_token_clone = cancellation_token.clone();
         let trace = self.trace.clone();
+        let full_trace = self.full_trace.clone();
+        let tx_event = self.socket.tx_event.clone();
         let tx_clone = self.internal_tx.clone();
-        let available_tools = self.available_tools.clone();
+        // plan mode keeps the tools that write out of the brain's reach
+        let available_tools: Vec<Arc<dyn AnyTool>> = self.available_tools.iter()
+            .filter(|tool| self.mode.allows(tool.as_ref()))
+            .cloned()
+            .collect();
+        let tool_definitions = Self::tool_definitions(&available_tools);
         let method = self.method.clone();
+        let (delta_tx, mut delta_rx) = mpsc::unbounded_channel();
         let context = ThinkerContext {
-            trace,
+            trace: trace.clone(),
             available_tools,
-            method
+            method,
+            delta_tx: Some(delta_tx),
+            retry_policy: self.retry_policy,
+            event_tx: self.socket.tx_event.clone(),
         };
         let brain = self.brain.clone();
         
         //////////////////////// TOKIO SPAWN
-        tokio::spawn(async move {
-            tokio::select! {
-                result = async {
-                    brain.write().await.next_step(context).await
-                } => {
-                    let _ = tx_clone.send(InternalAgentEvent::BrainResult {
-                        result
-                    });
-                }
-                _ = cancel_token_clone.cancelled() => {
-                    // Brain thinking was cancelled, no need to send result
+        self.running_task = Some(tokio::spawn(async move {
+            let step = async {
+                // the summary may take a while, it is part of the step and is cancelled along with it
+                Self::compress_context(brain.clone(), trace.clone(), full_trace.clone(), tx_event.clone(), false, Some(cancel_token_clone.clone())).await;
+                Self::ensure_context_fits(brain.clone(), trace, full_trace, tx_event, tool_definitions, cancel_token_clone.clone()).await;
+                brain.write().await.next_step(context).await
+            };
+            tokio::pin!(step);
+
+            // deltas are forwarded from this task so that they are always sent before the final result
+            let mut partial = String::new();
+            loop {
+                tokio::select! {
+                    result = &mut step => {
+                        while let Ok(text) = delta_rx.try_recv() {
+                            let _ = tx_clone.send(InternalAgentEvent::BrainDelta { step: step_id, text });
+                        }
+                        let _ = tx_clone.send(InternalAgentEvent::BrainResult {
+                            step: step_id,
+                            result
+                        });
+                        break;
+                    }
+                    Some(text) = delta_rx.recv() => {
+                        partial.push_str(&text);
+                        let _ = tx_clone.send(InternalAgentEvent::BrainDelta { step: step_id, text });
+                    }
+                    _ = cancel_token_clone.cancelled() => {
+                        // Brain thinking was cancelled, hand over what was streamed so far (if any)
+                        while let Ok(text) = delta_rx.try_recv() {
+                            partial.push_str(&text);
+                        }
+                        if !partial.is_empty() {
+                            let _ = tx_clone.send(InternalAgentEvent::BrainCancelled {
+                                step: step_id,
+                                partial: ChatMessage::Assistant {
+                                    content: Some(ChatMessageContent::Text(partial)),
+                                    reasoning_content: None,
+                                    tool_calls: None,
+                                    refusal: None,
+                                    name: None,
+                                    audio: None,
+                                }
+                            });
+                        }
+                        break;
+                    }
                 }
             }
-        });
+        }));
         //////////////////////// TOKIO SPAWN
         
         self.set_state(InternalAgentState::Processing { 
@@ -47,17 +205,32 @@ impl AgentCore {
 
     /// Process a brain task result
     pub async fn process_next_step(&mut self, result: Result<ThinkerDecision, AgentError>) -> Result<(), AgentError> {
-        let ThinkerDecision{message, flow, token_usage} = self.handle_brain_error(result).await?;
+        let ThinkerDecision{message, flow, token_usage, method, model} = self.handle_brain_error(result).await?;
         let ChatMessage::Assistant { content, reasoning_content, tool_calls, .. } = message.clone() else {
             return self.handle_brain_error::<ThinkerDecision>(
                 Err(AgentError::InvalidResponse(format!("ChatMessage::Assistant expected, but got {:?} instead", message)))).await.map(|_| ()
             );
         };
     
+        // the provider rejected the method in use, remember the one it accepted for the rest of the session
+        if let Some(method) = method {
+            if method != self.method {
+                info!(target: "agent::think", from = ?self.method, to = ?method, "tool call method changed");
+                self.method = method;
+                let _ = self.emit_event(AgentEvent::ToolCallMethodChanged { method }).await;
+            }
+        }
+
+        // Keep track of the context size, the next step compresses it if we are close to the limit
+        if let Some((input_tokens, _)) = token_usage {
+            if let Some(compressor) = self.brain.write().await.context_compressor() {
+                compressor.update_token_count(input_tokens);
+            }
+        }
+
         // Add the message to trace
         info!(target: "agent::think", reasoning_content = ?reasoning_content, content = ?content);
-        let trace = self.trace.clone();
-        trace.write().await.push(message.clone());
+        self.append_message(message.clone()).await;
         
         // Emit event to external consumers
         let _ = self.emit_event(AgentEvent::BrainResult {
@@ -71,11 +244,44 @@ impl AgentCore {
                 input_tokens,
                 output_tokens
             }).await;
+            self.session_usage.add(model.as_deref(), input_tokens, output_tokens);
+            let _ = self.emit_event(AgentEvent::SessionUsage {
+                usage: self.session_usage.clone()
+            }).await;
         }
     
+        // a runaway task is stopped before it goes on with more tools or steps
+        self.task_steps += 1;
+        let goes_on = tool_calls.as_ref().is_some_and(|calls| !calls.is_empty())
+            || matches!(flow, ThinkerFlowControl::AgentContinue);
+        let elapsed = self.task_started_at.map_or(TimeDelta::zero(), |start| Utc::now() - start);
+        if goes_on && self.task_budget.is_exceeded(self.task_steps, elapsed) {
+            warn!(target: "agent::think", steps = self.task_steps, elapsed_ms = elapsed.num_milliseconds(), "task budget exceeded");
+            // the calls already are in the trace, each one needs an answer for the trace to stay valid
+            let skipped: Vec<ChatMessage> = tool_calls.iter().flatten()
+                .map(|tc| ChatMessage::Tool { content: "[not run: task budget exceeded]".to_string(), tool_call_id: tc.id.clone() })
+                .collect();
+            Self::append_to_traces(&self.trace, &self.full_trace, skipped).await;
+            let _ = self.emit_event(AgentEvent::BudgetExceeded { steps: self.task_steps, elapsed }).await;
+            self.set_state(InternalAgentState::Paused).await;
+            return Ok(())
+        }
+
         // run tool call if any
         let tool_calls_from_brain = tool_calls.unwrap_or(vec![]);
+        if self.reject_invalid_tool_calls(&tool_calls_from_brain).await {
+            if self.invalid_tool_call_retries > MAX_INVALID_TOOL_CALL_RETRIES {
+                self.invalid_tool_call_retries = 0;
+                return self.handle_brain_error::<()>(
+                    Err(AgentError::InvalidToolArguments("the model kept sending tool calls with invalid arguments".to_string()))).await;
+            }
+            // let the brain try again with the parse errors in its context
+            self.set_state(InternalAgentState::Running).await;
+            return Ok(())
+        }
+        self.invalid_tool_call_retries = 0;
         if !tool_calls_from_brain.is_empty() {
+            self.idle_steps = 0;
             self.spawn_tools(tool_calls_from_brain).await;
             return Ok(())
         }
@@ -83,27 +289,270 @@ impl AgentCore {
         // no tool call, thus we rely on flow control
         match flow {
             ThinkerFlowControl::AgentContinue => {
-                self.set_state(InternalAgentState::Running).await;
+                self.idle_steps += 1;
+                if self.idle_step_limit.is_some_and(|limit| self.idle_steps >= limit) {
+                    warn!(target: "agent::think", steps = self.idle_steps, "brain keeps continuing without tool calls");
+                    let _ = self.emit_event(AgentEvent::IdleLoopDetected { steps: self.idle_steps }).await;
+                    self.idle_steps = 0;
+                    self.set_state(InternalAgentState::Paused).await;
+                } else {
+                    self.set_state(InternalAgentState::Running).await;
+                }
             }
             ThinkerFlowControl::AgentPause => {
+                self.idle_steps = 0;
                 self.set_state(InternalAgentState::Paused).await;
             }
         }
         Ok(())
     }
 
+    /// If some tool call arguments are not valid JSON or do not match the tool's parameter schema,
+    /// answer every call of the message with an error result instead of running them, so the brain
+    /// can correct itself on the next step
+    async fn reject_invalid_tool_calls(&mut self, tool_calls: &[shai_llm::ToolCall]) -> bool {
+        let errors: Vec<(String, String)> = tool_calls.iter()
+            .filter_map(|tc| self.check_tool_arguments(tc).err()
+                .map(|e| (tc.id.clone(), format!("Invalid arguments for tool {}: {}. Send the tool call again with valid arguments.", tc.function.name, e))))
+            .collect();
+        if errors.is_empty() {
+            return false;
+        }
+
+        self.invalid_tool_call_retries += 1;
+        warn!(target: "agent::think", invalid = errors.len(), retry = self.invalid_tool_call_retries, "tool calls with invalid arguments");
+        let messages = tool_calls.iter()
+            .map(|tc| {
+                let content = errors.iter()
+                    .find(|(id, _)| id == &tc.id)
+                    .map(|(_, error)| error.clone())
+                    .unwrap_or_else(|| "Not executed, another tool call of the same message had invalid arguments.".to_string());
+                ChatMessage::Tool { content, tool_call_id: tc.id.clone() }
+            })
+            .collect();
+        Self::append_to_traces(&self.trace, &self.full_trace, messages).await;
+        true
+    }
+
+    /// Parse the arguments and validate them against the schema of the tool, unknown tools
+    /// are left to the tool dispatch which reports them
+    fn check_tool_arguments(&self, tool_call: &shai_llm::ToolCall) -> Result<(), String> {
+        let arguments: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)
+            .map_err(|e| e.to_string())?;
+        match self.available_tools.iter().find(|t| t.name() == tool_call.function.name) {
+            Some(tool) => validate_arguments(&arguments, &tool.parameters_schema()),
+            None => Ok(()),
+        }
+    }
+
+    /// Guarantee the next request fits in the context window: if the trace would exceed it next
+    /// to the system prompt and the tool definitions, compress it and, if the summary is not
+    /// enough, drop the oldest messages
+    async fn ensure_context_fits(
+        brain: Arc<RwLock<Box<dyn Brain>>>,
+        trace: Arc<RwLock<Vec<ChatMessage>>>,
+        full_trace: Arc<RwLock<Vec<ChatMessage>>>,
+        tx_event: Option<broadcast::Sender<AgentEvent>>,
+        tool_definitions: Vec<ChatMessage>,
+        cancellation_token: CancellationToken,
+    ) {
+        let messages = trace.read().await.clone();
+        let reserved_tokens = {
+            let mut brain = brain.write().await;
+            let mut reserved = tool_definitions;
+            reserved.extend(brain.system_prompt().map(|prompt| ChatMessage::System {
+                content: ChatMessageContent::Text(prompt),
+                name: None,
+            }));
+            let Some(compressor) = brain.context_compressor() else {
+                return;
+            };
+            let reserved_tokens = compressor.count_tokens(&reserved);
+            if !compressor.exceeds_window(&messages, reserved_tokens) {
+                return;
+            }
+            reserved_tokens
+        };
+
+        Self::compress_context(
+            brain.clone(),
+            trace.clone(),
+            full_trace,
+            tx_event.clone(),
+            true,
+            Some(cancellation_token.clone())
+        ).await;
+        if cancellation_token.is_cancelled() {
+            return;
+        }
+
+        let mut brain = brain.write().await;
+        let mut trace = trace.write().await;
+        let Some(compressor) = brain.context_compressor() else {
+            return;
+        };
+        if !compressor.exceeds_window(&trace, reserved_tokens) {
+            return;
+        }
+        let (kept, messages_dropped) = compressor.truncate_to_fit(trace.clone(), reserved_tokens);
+        let (current_tokens, max_tokens) = (compressor.current_tokens(), compressor.max_tokens);
+        drop(brain);
+
+        warn!(target: "compacter", messages_dropped, current_tokens, max_tokens, "context still too large after compression, oldest messages dropped");
+        *trace = kept;
+        if let Some(tx) = &tx_event {
+            let _ = tx.send(AgentEvent::ContextTruncated {
+                messages_dropped,
+                current_tokens,
+                max_tokens,
+            });
+        }
+    }
+
+    /// Tool definitions as sent to the llm, only used to count the room they take in the window
+    fn tool_definitions(tools: &[Arc<dyn AnyTool>]) -> Vec<ChatMessage> {
+        tools.iter()
+            .map(|tool| ChatMessage::System {
+                content: ChatMessageContent::Text(serde_json::json!({
+                    "name": tool.name(),
+                    "description": tool.description(),
+                    "parameters": tool.parameters_schema(),
+                }).to_string()),
+                name: None,
+            })
+            .collect()
+    }
+
+    /// Compress the trace on user request. The summary runs in a cancellable task,
+    /// if cancelled (CancelTask) the trace is left unchanged.
+    /// If `resume` is set (the request came in while the agent was working on a task), the agent
+    /// goes back to Running once the compression is done and thus continues the task with the
+    /// compressed trace. Otherwise it goes back to Paused and waits for the user.
+    pub async fn check_and_compress_context_manual(&mut self, resume: bool) -> Result<(), AgentError> {
+        let cancellation_token = CancellationToken::new();
+        let cancel_token_clone = cancellation_token.clone();
+        let brain = self.brain.clone();
+        let trace = self.trace.clone();
+        let full_trace = self.full_trace.clone();
+        let tx_event = self.socket.tx_event.clone();
+        let tx_clone = self.internal_tx.clone();
+
+        //////////////////////// TOKIO SPAWN
+        self.running_task = Some(tokio::spawn(async move {
+            Self::compress_context(brain, trace, full_trace, tx_event, true, Some(cancel_token_clone.clone())).await;
+            if !cancel_token_clone.is_cancelled() {
+                let _ = tx_clone.send(InternalAgentEvent::CompressionCompleted { resume });
+            }
+        }));
+        //////////////////////// TOKIO SPAWN
+
+        self.set_state(InternalAgentState::Processing { 
+            task_name: "compression".to_string(), 
+            tools_exec_at: Utc::now(), 
+            cancellation_token
+        }).await;
+        Ok(())
+    }
+
+    /// Summarize the older part of the trace using the brain compressor (if any)
+    async fn compress_context(
+        brain: Arc<RwLock<Box<dyn Brain>>>,
+        trace: Arc<RwLock<Vec<ChatMessage>>>,
+        full_trace: Arc<RwLock<Vec<ChatMessage>>>,
+        tx_event: Option<broadcast::Sender<AgentEvent>>,
+        force: bool,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Option<CompressionInfo> {
+        let emit = |event: AgentEvent| {
+            if let Some(tx) = &tx_event {
+                let _ = tx.send(event);
+            }
+        };
+
+        let mut brain = brain.write().await;
+        let compressor = brain.context_compressor()?;
+        let messages = trace.read().await.clone();
+        if !force && !compressor.should_compress_messages(&messages) {
+            debug!(target: "compacter", reason = %SkipReason::BelowThreshold, current_tokens = compressor.current_tokens(), threshold = compressor.threshold_tokens(), "compression skipped");
+            return None;
+        }
+
+        let full_trace = full_trace.read().await.clone();
+        let preview = compressor.preview_compression(&messages, &full_trace);
+        emit(AgentEvent::CompressionStarted {
+            messages_to_summarize: preview.messages_to_summarize.len(),
+            current_tokens: preview.tokens_before,
+            max_tokens: compressor.max_tokens,
+        });
+
+        // forward the summarization progress to the controller
+        let progress_tx = tx_event.clone();
+        compressor.set_progress_handler(Some(Arc::new(move |step: usize, total: usize| {
+            if let Some(tx) = &progress_tx {
+                let _ = tx.send(AgentEvent::CompressionProgress { step, total });
+            }
+        })));
+        // without an llm the summary falls back to an outline, compression never fails here
+        let (compressed, outcome) = match &cancellation_token {
+            Some(token) => compressor.compress_messages_cancellable(messages, &full_trace, token).await,
+            None => compressor.compress_messages_now(messages, &full_trace).await,
+        };
+        compressor.set_progress_handler(None);
+
+        if outcome.info().is_some() {
+            *trace.write().await = compressed;
+        }
+        emit(AgentEvent::CompressionFinished { outcome: outcome.clone() });
+        outcome.into_info()
+    }
+
     // Helper method that emits error events before returning the error
     async fn handle_brain_error<T>(&mut self, result: Result<T, AgentError>) -> Result<T, AgentError> {
         match result {
-            Ok(value) => Ok(value),
+            Ok(value) => {
+                self.brain_failures = 0;
+                self.first_failure_at = None;
+                Ok(value)
+            }
             Err(error) => {
+                self.last_error = Some(ErrorReport {
+                    error: error.clone(),
+                    timestamp: Utc::now(),
+                    request: latest_user_message(&self.trace.read().await),
+                });
                 self.set_state(InternalAgentState::Paused).await;
                 let _ = self.emit_event(AgentEvent::BrainResult { 
                     timestamp: Utc::now(),
                     thought: Err(error.clone())
                 }).await;
+                // only a failing provider trips the breaker, malformed answers have their own retries
+                if matches!(error, AgentError::LlmError(_)) {
+                    self.record_brain_failure().await;
+                }
                 Err(error)
             }
         }
     }
+
+    /// Count a failed step, the circuit breaker opens once too many fail within its window
+    async fn record_brain_failure(&mut self) {
+        let now = Utc::now();
+        let window = self.circuit_breaker.map_or(Duration::MAX, |breaker| breaker.window);
+        let in_window = self.first_failure_at
+            .is_some_and(|first| (now - first).to_std().unwrap_or(Duration::ZERO) <= window);
+        if !in_window {
+            self.brain_failures = 0;
+            self.first_failure_at = Some(now);
+        }
+        self.brain_failures += 1;
+
+        let Some(breaker) = self.circuit_breaker else {
+            return;
+        };
+        if !self.circuit_open && self.brain_failures >= breaker.max_failures {
+            warn!(target: "agent::think", failures = self.brain_failures, "circuit breaker opened");
+            self.circuit_open = true;
+            let _ = self.emit_event(AgentEvent::CircuitOpened { failures: self.brain_failures }).await;
+        }
+    }
 }
\ No newline at end of file
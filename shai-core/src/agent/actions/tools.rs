@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use chrono::{TimeDelta, Utc};
+use serde::{Serialize, Deserialize};
 use shai_llm::{ChatMessage, ToolCall as LlmToolCall};
 use tokio::sync::{broadcast, RwLock};
 use tokio::task::JoinHandle;
@@ -9,15 +10,118 @@ use tracing::info;
 use serde_json::from_str;
 use uuid::Uuid;
 use crate::agent::{AgentCore, AgentEvent, ClaimManager, InternalAgentEvent, InternalAgentState, PermissionRequest, PermissionResponse};
-use crate::tools::{AnyTool, ToolCall, ToolCapability, ToolResult};
+use crate::tools::{AnyTool, Sandbox, ToolCall, ToolCapability, ToolOutputSink, ToolResult};
 use tracing::debug;
 
+/// Default size (in bytes) above which a tool output is cut before entering the trace
+pub const DEFAULT_MAX_TOOL_OUTPUT: usize = 30_000;
+
+/// How the tool calls requested in a single step are run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolConcurrency {
+    /// one after the other, in the order of the calls
+    Sequential,
+    /// calls of parallel safe tools run together, the others run alone once the previous calls are done
+    Auto,
+    /// all the calls at once
+    Concurrent,
+}
+
+impl Default for ToolConcurrency {
+    fn default() -> Self {
+        ToolConcurrency::Auto
+    }
+}
+
+/// What the agent is allowed to do: look around and talk in plan mode, also change things in
+/// execute mode. A safety rail before letting the agent touch the files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentMode {
+    /// only the tools that do not write are offered to the brain, and run
+    Plan,
+    /// every tool
+    #[default]
+    Execute,
+}
+
+impl AgentMode {
+    pub fn allows(&self, tool: &dyn AnyTool) -> bool {
+        match self {
+            AgentMode::Plan => !tool.capabilities().contains(&ToolCapability::Write),
+            AgentMode::Execute => true,
+        }
+    }
+
+    pub fn toggle(&self) -> Self {
+        match self {
+            AgentMode::Plan => AgentMode::Execute,
+            AgentMode::Execute => AgentMode::Plan,
+        }
+    }
+}
+
+impl std::fmt::Display for AgentMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AgentMode::Plan => write!(f, "plan"),
+            AgentMode::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+impl ToolConcurrency {
+    fn runs_concurrently(&self, tool: Option<&Arc<dyn AnyTool>>) -> bool {
+        match self {
+            ToolConcurrency::Sequential => false,
+            ToolConcurrency::Concurrent => true,
+            // an unknown tool fails right away, it can go with the others
+            ToolConcurrency::Auto => tool.map_or(true, |t| t.parallel_safe()),
+        }
+    }
+}
+
+/// Keep the head and the tail of an oversized tool output so one `cat` of a huge log does not
+/// fill the context. The whole output is saved to a temp file mentioned in the marker.
+pub fn truncate_tool_output(call_id: &str, content: &str, max_bytes: usize) -> String {
+    if content.len() <= max_bytes {
+        return content.to_string();
+    }
+
+    let mut head_end = max_bytes / 2;
+    while !content.is_char_boundary(head_end) {
+        head_end -= 1;
+    }
+    let mut tail_start = content.len() - max_bytes / 2;
+    while !content.is_char_boundary(tail_start) {
+        tail_start += 1;
+    }
+
+    let path = std::env::temp_dir().join(format!("shai-tool-output-{}.txt", call_id));
+    let saved = match std::fs::write(&path, content) {
+        Ok(()) => format!(", full output saved to {}", path.display()),
+        Err(_) => String::new(),
+    };
+    format!(
+        "{}\n[... {} bytes omitted{} ...]\n{}",
+        &content[..head_end],
+        tail_start - head_end,
+        saved,
+        &content[tail_start..]
+    )
+}
+
 impl AgentCore {
 
-    /// Spawn a cancellable coroutine that runs all tool call in parrallel and waits for them to finish
+    /// Spawn a cancellable coroutine that runs the tool calls, concurrently or not depending on
+    /// tool_concurrency, and waits for them to finish. Results enter the trace in the order of the calls.
     pub async fn spawn_tools(&mut self, tool_calls: Vec<LlmToolCall>) {
         let cancellation_token = CancellationToken::new();
         let cancel_clone = cancellation_token.clone();
+        // cancelling the task cancels the tools, but the tools alone can be cancelled and still complete the step
+        let tools_token = cancellation_token.child_token();
+        self.tools_cancellation = Some(tools_token.clone());
         let internal_tx = self.internal_tx.clone();
 
         // Clone all needed data from self before spawning
@@ -25,45 +129,70 @@ impl AgentCore {
         let available_tools = self.available_tools.clone();
         let claims = self.permissions.clone();
         let trace = self.trace.clone();
+        let full_trace = self.full_trace.clone();
+        let max_tool_output = self.max_tool_output;
+        let sandbox = self.sandbox.clone();
+        let concurrency = self.tool_concurrency;
+        let mode = self.mode;
 
-        // Spawn a task to wait for all tool executions
-        let mut join_handles = Vec::new();
-        
-        // Spawn all tool executions
-        for tc in tool_calls {
-            let handle = Self::spawn_tool_static(
-                tc,
-                cancel_clone.clone(),
-                public_event_tx.clone(),
-                available_tools.clone(),
-                claims.clone(),
-                internal_tx.clone(),
-                trace.clone(),
-            );
-            join_handles.push(handle);
-        }
-            
-        // Wait for all tools to complete or be cancelled
-        tokio::spawn(async move {
-            tokio::select! {
-                _ = cancel_clone.cancelled() => {
-                    // Tools were cancelled, no need to send completion event
-                }
-                any_denied = async {
-                    // wait for all tools completion and collect denial status
-                    let mut result = false;
-                    for handle in join_handles {
-                        if let Ok(was_denied) = handle.await {
-                            result = result || was_denied;
-                        }
+        // Run the tools and wait for all of them
+        self.running_task = Some(tokio::spawn(async move {
+            let mut outcomes = Vec::new();
+            let mut running: Vec<JoinHandle<(bool, Option<ChatMessage>)>> = Vec::new();
+
+            for tc in tool_calls {
+                let tool = available_tools.iter().find(|t| t.name() == tc.function.name);
+                let concurrent = concurrency.runs_concurrently(tool);
+                if !concurrent {
+                    // a sequential call waits for every call before it
+                    for handle in running.drain(..) {
+                        outcomes.push(handle.await);
                     }
-                    result
-                } => {
-                    // All tools completed, move to Running state
-                    let _ = internal_tx.send(InternalAgentEvent::ToolsCompleted { any_denied });
+                }
+                // calls not started yet are answered as cancelled once the tools are cancelled
+                if tools_token.is_cancelled() {
+                    outcomes.push(Ok((false, Some(ChatMessage::Tool {
+                        tool_call_id: tc.id.clone(),
+                        content: ToolResult::error("tool call was cancelled by the user".to_string()).to_string(),
+                    }))));
+                    continue;
+                }
+
+                let handle = Self::spawn_tool_static(
+                    tc,
+                    tools_token.clone(),
+                    public_event_tx.clone(),
+                    available_tools.clone(),
+                    claims.clone(),
+                    internal_tx.clone(),
+                    max_tool_output,
+                    sandbox.clone(),
+                    mode,
+                );
+                if concurrent {
+                    running.push(handle);
+                } else {
+                    outcomes.push(handle.await);
                 }
             }
-        });
+            for handle in running {
+                outcomes.push(handle.await);
+            }
+
+            // results are added in the order of the calls, whatever order they completed in
+            let mut any_denied = false;
+            let mut messages = Vec::new();
+            for (was_denied, message) in outcomes.into_iter().flatten() {
+                any_denied = any_denied || was_denied;
+                messages.extend(message);
+            }
+            Self::append_to_traces(&trace, &full_trace, messages).await;
+
+            // Tools were cancelled with the task, no need to send completion event
+            if !cancel_clone.is_cancelled() {
+                let _ = internal_tx.send(InternalAgentEvent::ToolsCompleted { any_denied });
+            }
+        }));
         
         // Set state to Processing with cancellation token
         self.set_state(InternalAgentState::Processing { 
@@ -74,7 +203,8 @@ impl AgentCore {
     }
 
     /// Spawn a cancellable coroutine that runs a single tool call
-    /// coordinating the appropriate tool specific event (start/completed)
+    /// coordinating the appropriate tool specific event (start/completed).
+    /// Returns whether the call was denied and the tool message to add to the trace
     fn spawn_tool_static(
         tc: LlmToolCall,
         cancel_token: CancellationToken,
@@ -82,25 +212,33 @@ impl AgentCore {
         available_tools: Vec<Arc<dyn AnyTool>>,
         claims: Arc<RwLock<ClaimManager>>,
         internal_tx: broadcast::Sender<InternalAgentEvent>,
-        trace: Arc<RwLock<Vec<ChatMessage>>>,
-    ) -> tokio::task::JoinHandle<bool> {
+        max_tool_output: usize,
+        sandbox: Option<Sandbox>,
+        mode: AgentMode,
+    ) -> tokio::task::JoinHandle<(bool, Option<ChatMessage>)> {
         tokio::spawn(async move {
             let tc_for_error = tc.clone();
             match Self::tool_exist(available_tools, tc) {
                 // tool does not exist, we fail immediately
+                // still as a started/completed pair so timelines can match every completion
                 Err(tool_result) => {
                     if let Some(tx) = public_event_tx.clone() {
+                        let call = ToolCall {
+                            tool_call_id: tc_for_error.id.clone(),
+                            tool_name: tc_for_error.function.name.clone(),
+                            parameters: serde_json::Value::Null
+                        };
+                        let _ = tx.send(AgentEvent::ToolCallStarted {
+                            timestamp: Utc::now(),
+                            call: call.clone(),
+                        });
                         let _ = tx.send(AgentEvent::ToolCallCompleted { 
                             duration: TimeDelta::zero(), 
-                            call: ToolCall {
-                                tool_call_id: tc_for_error.id.clone(),
-                                tool_name: tc_for_error.function.name.clone(),
-                                parameters: serde_json::Value::Null
-                            }, 
+                            call, 
                             result: tool_result
                         });
                     }
-                    false
+                    (false, None)
                 }
 
                 // emit tool call
@@ -118,36 +256,52 @@ impl AgentCore {
                     }
                     
                     // execute tool
-                    let tool_handle = Self::spawn_tool_exec(
+                    let (output_sink, mut output_rx) = ToolOutputSink::new();
+                    let mut tool_handle = Self::spawn_tool_exec(
                         tool, call.clone(), 
                         cancel_token.clone(), 
                         claims, 
                         public_event_tx.clone(), 
-                        internal_tx.subscribe());
-
-                    // wait for result (or for cancellation)
-                    let result: ToolResult = tokio::select! {
-                        join_result = tool_handle => {
-                            match join_result {
-                                Ok(tool_result) => tool_result,
-                                Err(join_error) => {
-                                    debug!(target: "agent::tool_completed", "tool execution task failed: {}", join_error);
-                                    ToolResult::error(format!("tool execution task failed: {}", join_error))
-                                }
+                        internal_tx.subscribe(),
+                        output_sink,
+                        sandbox,
+                        mode);
+
+                    // wait for result (or for cancellation), forwarding the output as it comes
+                    let mut streamed = false;
+                    let result: ToolResult = loop {
+                        tokio::select! {
+                            join_result = &mut tool_handle => {
+                                break match join_result {
+                                    Ok(tool_result) => tool_result,
+                                    Err(join_error) => {
+                                        debug!(target: "agent::tool_completed", "tool execution task failed: {}", join_error);
+                                        ToolResult::error(format!("tool execution task failed: {}", join_error))
+                                    }
+                                };
+                            },
+                            Some(chunk) = output_rx.recv() => {
+                                streamed = true;
+                                Self::emit_output_chunk(&public_event_tx, &call, chunk);
+                            }
+                            _ = cancel_token.cancelled() => {
+                                debug!(target: "agent::tool_completed", "cancelled by user");
+                                break ToolResult::error("tool call was cancelled by the user".to_string());
                             }
-                         },
-                        _ = cancel_token.cancelled() => {
-                            debug!(target: "agent::tool_completed", "cancelled by user");
-                            ToolResult::error("tool call was cancelled by the user".to_string())
                         }
                     };
+                    while let Ok(chunk) = output_rx.try_recv() {
+                        streamed = true;
+                        Self::emit_output_chunk(&public_event_tx, &call, chunk);
+                    }
+                    // tools that do not stream send their whole output at once
+                    if !streamed && !result.is_denied() {
+                        Self::emit_output_chunk(&public_event_tx, &call, result.to_string());
+                    }
 
-                    // let's first add tool result to trace
-                    let _ = {
-                        trace.write().await.push(ChatMessage::Tool { 
-                            tool_call_id: call.tool_call_id.clone(),
-                            content: result.to_string()
-                        });
+                    let message = ChatMessage::Tool { 
+                        tool_call_id: call.tool_call_id.clone(),
+                        content: truncate_tool_output(&call.tool_call_id, &result.to_string(), max_tool_output)
                     };
 
                     // Emit tool call finish event
@@ -161,12 +315,21 @@ impl AgentCore {
                         });   
                     }
 
-                    tool_was_denied                    
+                    (tool_was_denied, Some(message))
                 }
             }
         })
     }
 
+    fn emit_output_chunk(public_event_tx: &Option<broadcast::Sender<AgentEvent>>, call: &ToolCall, chunk: String) {
+        if let Some(tx) = public_event_tx {
+            let _ = tx.send(AgentEvent::ToolOutputChunk {
+                tool_call_id: call.tool_call_id.clone(),
+                chunk
+            });
+        }
+    }
+
     /// execute a single tool call
     /// checking for permission, requesting it, executing the tool
     fn spawn_tool_exec(
@@ -175,8 +338,23 @@ impl AgentCore {
         cancel_token: CancellationToken,
         claims: Arc<RwLock<ClaimManager>>, 
         public_event_tx: Option<broadcast::Sender<AgentEvent>>, 
-        mut internal_rx: broadcast::Receiver<InternalAgentEvent>) -> JoinHandle<ToolResult> {
+        mut internal_rx: broadcast::Receiver<InternalAgentEvent>,
+        output_sink: ToolOutputSink,
+        sandbox: Option<Sandbox>,
+        mode: AgentMode) -> JoinHandle<ToolResult> {
         tokio::spawn(async move {
+            // the brain was not offered the tool, but a parsed or stale call could still name it
+            if !mode.allows(tool.as_ref()) {
+                return ToolResult::error(format!("{} is not available in plan mode, the user has to switch to execute mode first", tool.name()));
+            }
+
+            // a path outside of the sandbox is refused before even asking for permission
+            if let Some(sandbox) = &sandbox {
+                if let Err(error) = sandbox.check_call(tool.as_ref(), &call.parameters) {
+                    return ToolResult::error(error);
+                }
+            }
+
             // check permission, we allow all Read Tool
             let can_run = tool.capabilities().is_empty()  
             || tool.capabilities() == &[ToolCapability::Read]
@@ -194,7 +372,7 @@ impl AgentCore {
             
             // Execute tool with cancellation support
             tokio::select! {
-                result = tool.execute_json(call.parameters.clone(), Some(cancel_token.clone())) => result,
+                result = output_sink.scope(tool.execute_json(call.parameters.clone(), Some(cancel_token.clone()))) => result,
                 _ = cancel_token.cancelled() => {
                     ToolResult::error("tool call was cancelled by the user".to_string())
                 }
@@ -1,22 +1,41 @@
 use std::sync::Arc;
 use std::boxed::Box;
-use shai_llm::{ChatMessage, ChatMessageContent, ToolCallMethod};
+use std::collections::HashSet;
+use chrono::{DateTime, Utc};
+use shai_llm::{ChatMessage, ChatMessageContent, RetryPolicy, ToolCallMethod};
+use std::time::Duration;
 use tokio::sync::{mpsc, broadcast, RwLock, oneshot};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use serde::{Serialize, Deserialize};
 use async_trait::async_trait;
-use crate::tools::AnyTool;
+use crate::tools::{AnyTool, Sandbox};
 use crate::agent::ClaimManager;
 
 // Helper functions to make the main loop more readable
 
 use crate::agent::{Brain, InternalAgentEvent};
-use crate::agent::AgentError;
+use crate::agent::{AgentError, ErrorReport};
 use crate::agent::{AgentRequest, AgentEvent};
 use crate::agent::InternalAgentState;
-use tracing::debug;
+use tracing::{debug, info};
 
 use super::protocol::{AgentController, SentCommand};
 use super::{AgentResponse, AgentEventHandler};
+use super::output::export_trace;
+use crate::runners::compacter::{is_summary, PINNED_NAME};
+use super::actions::tools::{AgentMode, ToolConcurrency, DEFAULT_MAX_TOOL_OUTPUT};
+use super::actions::brain::{CircuitBreaker, SessionUsage, TaskBudget, CIRCUIT_OPEN_ERROR, DEFAULT_IDLE_STEP_LIMIT};
+
+/// Name of the system message holding the instructions appended to the brain's system prompt
+pub const SYSTEM_PROMPT_SUFFIX_NAME: &str = "instructions";
+
+/// Content left in a tool result removed by the user, the call it answers still needs a result
+pub const REMOVED_TOOL_OUTPUT: &str = "[output removed by the user]";
+
+pub fn is_system_prompt_suffix(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::System { name: Some(name), .. } if name == SYSTEM_PROMPT_SUFFIX_NAME)
+}
 
 /// Trait defining the public interface for agents
 #[async_trait]
@@ -52,6 +71,20 @@ pub struct AgentResult {
     pub trace:   Vec<ChatMessage>,
 }
 
+/// Whether the full trace still holds every message of the trace. Compression replaces part of
+/// the trace with a summary and may drop messages from it, and a tool output removed by the user
+/// is only emptied in the trace, but nothing else is ever added.
+pub fn traces_are_consistent(trace: &[ChatMessage], full_trace: &[ChatMessage]) -> bool {
+    let key = |message: &ChatMessage| serde_json::to_string(message).unwrap_or_default();
+    let full: HashSet<String> = full_trace.iter().map(key).collect();
+    let is_removed_output = |message: &ChatMessage| matches!(message, ChatMessage::Tool { content, tool_call_id }
+        if content == REMOVED_TOOL_OUTPUT
+            && full_trace.iter().any(|m| matches!(m, ChatMessage::Tool { tool_call_id: id, .. } if id == tool_call_id)));
+    trace.iter()
+        .filter(|message| !is_summary(message) && !is_removed_output(message))
+        .all(|message| full.contains(&key(message)))
+}
+
 /// Core agent implementation that orchestrates any Thinker implementation
 pub struct AgentCore {
     pub session_id: String,
@@ -65,9 +98,31 @@ pub struct AgentCore {
 
     /// agent state (manipulated by main looper + brain/tool coroutines)
     pub trace:           Arc<RwLock<Vec<ChatMessage>>>,
+    pub full_trace:      Arc<RwLock<Vec<ChatMessage>>>, // uncompressed history, trace may be summarized
     pub available_tools: Vec<Arc<dyn AnyTool>>,
     pub permissions:     Arc<RwLock<ClaimManager>>,
     pub state:           InternalAgentState,
+    pub pending_compression: bool, // compression requested while a task was running
+    pub invalid_tool_call_retries: u32, // consecutive steps whose tool calls had unparsable arguments
+    pub tools_cancellation: Option<CancellationToken>, // cancels the running tools without cancelling the task
+    pub max_tool_output: usize, // tool outputs larger than this (in bytes) are cut in the middle
+    pub sandbox: Option<Sandbox>, // when set, file system tools cannot touch paths outside of its root
+    pub retry_policy: RetryPolicy, // retries of the llm calls failing for a transient reason
+    pub tool_concurrency: ToolConcurrency, // whether the tool calls of a step run together or one at a time
+    pub mode: AgentMode, // plan mode only offers and runs the tools that do not write
+    pub task_budget: TaskBudget, // steps and time a task may take before it is paused
+    pub task_steps: u32, // brain steps of the current task
+    pub task_started_at: Option<DateTime<Utc>>, // start of the current task, set on its first step
+    pub idle_step_limit: Option<u32>, // steps in a row without tool calls before the agent pauses, None never pauses
+    pub idle_steps: u32, // steps in a row the brain continued without calling any tool
+    pub circuit_breaker: Option<CircuitBreaker>, // brain failures in a row before new input is refused, None never refuses
+    pub brain_failures: u32, // brain steps failed in a row
+    pub first_failure_at: Option<DateTime<Utc>>, // first failure of the current count
+    pub circuit_open: bool, // too many failures, input is refused until reset
+    pub running_task: Option<JoinHandle<()>>, // brain, tools or compression task of the Processing state
+    pub brain_step: u64, // id of the latest brain task, what older ones still send is dropped
+    pub last_error: Option<ErrorReport>, // last error of the brain, explained by /why
+    pub session_usage: SessionUsage, // tokens of every turn since the agent started
 
     /// internal event
     pub internal_tx: broadcast::Sender<InternalAgentEvent>,   // event may be produced from many part of the agent
@@ -100,10 +155,32 @@ impl AgentCore {
             },
             brain: Arc::new(RwLock::new(brain)),
             method: ToolCallMethod::FunctionCall,
+            full_trace: Arc::new(RwLock::new(trace.clone())),
             trace: Arc::new(RwLock::new(trace)),
             available_tools: available_tools.into_iter().map(|t| Arc::from(t) as Arc<dyn AnyTool>).collect(),
             permissions: Arc::new(RwLock::new(permissions)),
             state: InternalAgentState::Starting,
+            pending_compression: false,
+            invalid_tool_call_retries: 0,
+            tools_cancellation: None,
+            max_tool_output: DEFAULT_MAX_TOOL_OUTPUT,
+            sandbox: None,
+            retry_policy: RetryPolicy::default(),
+            tool_concurrency: ToolConcurrency::default(),
+            mode: AgentMode::default(),
+            task_budget: TaskBudget::default(),
+            task_steps: 0,
+            task_started_at: None,
+            idle_step_limit: Some(DEFAULT_IDLE_STEP_LIMIT),
+            idle_steps: 0,
+            circuit_breaker: Some(CircuitBreaker::default()),
+            brain_failures: 0,
+            first_failure_at: None,
+            circuit_open: false,
+            running_task: None,
+            brain_step: 0,
+            last_error: None,
+            session_usage: SessionUsage::default(),
             internal_tx,
             internal_rx,
         }
@@ -211,14 +288,24 @@ impl AgentCore {
     where 
         H: AgentEventHandler + 'static
     {
+        self.add_event_sink(Arc::new(handler));
+        self
+    }
+
+    /// Forward the events to a handler from a task of its own, a slow handler only loses
+    /// the events it lagged behind on
+    fn add_event_sink(&mut self, sink: Arc<dyn AgentEventHandler>) {
         self.assert_socket_created();
         let mut rx = self.socket.tx_event.as_ref().unwrap().subscribe();
         _ = tokio::spawn(async move {
-            while let Ok(event) = rx.recv().await {
-                handler.handle_event(event).await;
+            loop {
+                match rx.recv().await {
+                    Ok(event) => sink.handle_event(event).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
             }
         });
-        self
     }
 
 
@@ -265,6 +352,195 @@ impl AgentCore {
         });
     }
 
+    /// Replace the system prompt suffix, kept at the head of both traces so that it comes right
+    /// after the prompt the brain inserts
+    async fn set_system_prompt_suffix(&mut self, text: Option<String>) {
+        for trace in [&self.trace, &self.full_trace] {
+            let mut trace = trace.write().await;
+            trace.retain(|message| !is_system_prompt_suffix(message));
+            if let Some(text) = &text {
+                trace.insert(0, ChatMessage::System {
+                    content: ChatMessageContent::Text(text.clone()),
+                    name: Some(SYSTEM_PROMPT_SUFFIX_NAME.to_string()),
+                });
+            }
+        }
+    }
+
+    /// Start over: only the system prompt suffix is kept and the token count is reset
+    async fn clear_trace(&mut self) -> Result<(), AgentError> {
+        if self.state.to_public().is_working() {
+            return Err(AgentError::InvalidState("cannot clear the conversation while the agent is working".to_string()));
+        }
+
+        for trace in [&self.trace, &self.full_trace] {
+            trace.write().await.retain(is_system_prompt_suffix);
+        }
+        if let Some(compressor) = self.brain.write().await.context_compressor() {
+            compressor.update_token_count(0);
+        }
+        let _ = self.emit_event(AgentEvent::ConversationCleared).await;
+        Ok(())
+    }
+
+    /// Mark the latest user message of the trace as pinned so compression keeps it verbatim.
+    /// Like an edit, the same message of the full trace is pinned too, if it is still there.
+    async fn pin_last_message(&mut self) -> Result<(), AgentError> {
+        let mut trace = self.trace.write().await;
+        let mut full_trace = self.full_trace.write().await;
+        let Some(index) = trace.iter().rposition(|m| matches!(m, ChatMessage::User { .. })) else {
+            return Err(AgentError::InvalidState("no user message to pin".to_string()));
+        };
+
+        let key = |message: &ChatMessage| serde_json::to_string(message).unwrap_or_default();
+        let original = key(&trace[index]);
+        if let ChatMessage::User { name, .. } = &mut trace[index] {
+            *name = Some(PINNED_NAME.to_string());
+        }
+        if let Some(message) = full_trace.iter_mut().rev().find(|m| key(m) == original) {
+            *message = trace[index].clone();
+        }
+        Ok(())
+    }
+
+    /// The conversation is only edited by hand between two turns
+    fn ensure_paused(&self) -> Result<(), AgentError> {
+        if !matches!(self.state, InternalAgentState::Paused) {
+            return Err(AgentError::InvalidState("the conversation can only be edited while the agent is paused".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Replace the content of a message of the trace. Like a pin, the same message of the full
+    /// trace is rewritten too so that both traces stay consistent.
+    async fn edit_message(&mut self, index: usize, text: String) -> Result<(), AgentError> {
+        self.ensure_paused()?;
+        let mut trace = self.trace.write().await;
+        let mut full_trace = self.full_trace.write().await;
+        let Some(message) = trace.get(index) else {
+            return Err(AgentError::InvalidState(format!("no message #{} in a trace of {}", index, trace.len())));
+        };
+
+        let mut edited = message.clone();
+        match &mut edited {
+            ChatMessage::System { content, .. }
+            | ChatMessage::Developer { content, .. }
+            | ChatMessage::User { content, .. } => *content = ChatMessageContent::Text(text),
+            ChatMessage::Assistant { content, .. } => *content = Some(ChatMessageContent::Text(text)),
+            ChatMessage::Tool { content, .. } => *content = text,
+        }
+        let key = |message: &ChatMessage| serde_json::to_string(message).unwrap_or_default();
+        let original = key(message);
+        if let Some(message) = full_trace.iter_mut().rev().find(|m| key(m) == original) {
+            *message = edited.clone();
+        }
+        trace[index] = edited;
+        drop(full_trace);
+
+        self.reestimate_tokens(&trace).await;
+        Ok(())
+    }
+
+    /// Take a message out of the trace sent to the brain, the full trace keeps it. A tool result
+    /// is emptied instead since its call must keep a result, and an assistant message goes away
+    /// with the results of its tool calls.
+    async fn remove_message(&mut self, index: usize) -> Result<(), AgentError> {
+        self.ensure_paused()?;
+        let mut trace = self.trace.write().await;
+        match trace.get(index) {
+            None => return Err(AgentError::InvalidState(format!("no message #{} in a trace of {}", index, trace.len()))),
+            Some(ChatMessage::Tool { .. }) => {
+                if let Some(ChatMessage::Tool { content, .. }) = trace.get_mut(index) {
+                    *content = REMOVED_TOOL_OUTPUT.to_string();
+                }
+            }
+            Some(ChatMessage::Assistant { tool_calls: Some(calls), .. }) => {
+                let ids: HashSet<String> = calls.iter().map(|call| call.id.clone()).collect();
+                trace.remove(index);
+                trace.retain(|m| !matches!(m, ChatMessage::Tool { tool_call_id, .. } if ids.contains(tool_call_id)));
+            }
+            Some(_) => {
+                trace.remove(index);
+            }
+        }
+
+        self.reestimate_tokens(&trace).await;
+        Ok(())
+    }
+
+    /// Drop the last user message and everything that answered it, so that the same prompt can
+    /// be sent again without the model seeing it twice. The full trace keeps the first attempt.
+    async fn rewind_last_turn(&mut self) -> Result<(), AgentError> {
+        self.ensure_paused()?;
+        let mut trace = self.trace.write().await;
+        let Some(index) = trace.iter().rposition(|m| matches!(m, ChatMessage::User { .. })) else {
+            return Err(AgentError::InvalidState("no user message to rewind to".to_string()));
+        };
+        trace.truncate(index);
+
+        self.reestimate_tokens(&trace).await;
+        Ok(())
+    }
+
+    /// The last usage report does not match a trace edited by hand anymore
+    async fn reestimate_tokens(&self, trace: &[ChatMessage]) {
+        if let Some(compressor) = self.brain.write().await.context_compressor() {
+            compressor.estimate_token_count(trace);
+        }
+    }
+
+    /// Seed both traces with a saved conversation. A leading system prompt is dropped since
+    /// the brain injects the current one at every step, summaries and suffix are kept.
+    async fn load_trace(&mut self, mut messages: Vec<ChatMessage>) {
+        if matches!(messages.first(), Some(ChatMessage::System { name: None, .. })) {
+            messages.remove(0);
+        }
+
+        // no usage was reported for this conversation yet, estimate it so compression triggers when needed
+        if let Some(compressor) = self.brain.write().await.context_compressor() {
+            compressor.estimate_token_count(&messages);
+        }
+
+        *self.full_trace.write().await = messages.clone();
+        *self.trace.write().await = messages;
+    }
+
+    /// Append messages to both traces within a single critical section so that an interrupted
+    /// turn cannot leave them diverging. The trace is always locked before the full trace.
+    pub async fn append_to_traces(trace: &RwLock<Vec<ChatMessage>>, full_trace: &RwLock<Vec<ChatMessage>>, messages: Vec<ChatMessage>) {
+        let mut trace = trace.write().await;
+        let mut full_trace = full_trace.write().await;
+        full_trace.extend(messages.iter().cloned());
+        trace.extend(messages);
+        debug_assert!(traces_are_consistent(&trace, &full_trace), "the trace holds messages missing from the full trace");
+    }
+
+    pub async fn append_message(&self, message: ChatMessage) {
+        Self::append_to_traces(&self.trace, &self.full_trace, vec![message]).await;
+    }
+
+    /// Stop for good: the running task is cancelled and given `grace` to wind down (cancelled
+    /// tools still record their result) before being aborted, then the agent completes
+    async fn shutdown(&mut self, grace: Duration) {
+        let was_working = self.state.to_public().is_working();
+        if let InternalAgentState::Processing { cancellation_token, .. } = &self.state {
+            cancellation_token.cancel();
+        }
+        if let Some(token) = self.tools_cancellation.take() {
+            token.cancel();
+        }
+        if let Some(mut task) = self.running_task.take() {
+            if tokio::time::timeout(grace, &mut task).await.is_err() {
+                debug!(target: "agent::shutdown", "task did not stop within {:?}, aborting it", grace);
+                task.abort();
+            }
+        }
+        if let Some(ref mut rx_command) = self.socket.rx_command {
+            rx_command.close();
+        }
+        self.set_state(InternalAgentState::Completed { success: !was_working }).await;
+    }
+
     /// Returns true if there's a controller 
     pub fn has_io(&self) -> bool {
         match &self.socket.rx_command {
@@ -363,6 +639,10 @@ impl AgentCore {
                 }
                 Ok(AgentResponse::Ack)
             }
+            AgentRequest::Shutdown { grace } => {
+                self.shutdown(grace).await;
+                Ok(AgentResponse::Ack)
+            }
             AgentRequest::GetState => {
                 Ok(AgentResponse::State { state: self.state.to_public()})
             }
@@ -390,12 +670,80 @@ impl AgentCore {
                     Ok(AgentResponse::Ack)
                 })
             }
+            AgentRequest::CancelCurrentTool => {
+                if matches!(self.state, InternalAgentState::Processing { .. }) {
+                    self.handle_event(InternalAgentEvent::CancelCurrentTool).await
+                        .map(|_| AgentResponse::Ack)
+                } else {
+                    Err(AgentError::InvalidState("no tool is running".to_string()))
+                }
+            }
+            AgentRequest::SwitchModel { model } => {
+                if self.state.to_public().is_working() {
+                    Err(AgentError::InvalidState("cannot switch model while the agent is working".to_string()))
+                } else {
+                    self.brain.write().await.switch_model(model).await
+                        .map(|model| AgentResponse::Model { model })
+                }
+            }
+            AgentRequest::AddEventSink { sink } => {
+                self.add_event_sink(sink.0);
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::SetSandboxRoot { root } => {
+                match root {
+                    Some(root) => Sandbox::new(&root)
+                        .map(|sandbox| self.sandbox = Some(sandbox))
+                        .map_err(|e| AgentError::ConfigurationError(format!("invalid sandbox root {}: {}", root.display(), e))),
+                    None => {
+                        self.sandbox = None;
+                        Ok(())
+                    }
+                }
+                .map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::SetRetryPolicy { policy } => {
+                self.retry_policy = policy;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::SetTaskBudget { budget } => {
+                self.task_budget = budget;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::SetIdleStepLimit { limit } => {
+                self.idle_step_limit = limit;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::SetMode { mode } => {
+                if mode != self.mode {
+                    info!(target: "agent::command", from = %self.mode, to = %mode, "mode changed");
+                    self.mode = mode;
+                    let _ = self.emit_event(AgentEvent::ModeChanged { mode }).await;
+                }
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::SetCircuitBreaker { breaker } => {
+                self.circuit_breaker = breaker;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::ResetCircuitBreaker => {
+                self.circuit_open = false;
+                self.brain_failures = 0;
+                self.first_failure_at = None;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::GetBrainFailures => {
+                Ok(AgentResponse::BrainFailures { failures: self.brain_failures, circuit_open: self.circuit_open })
+            }
             AgentRequest::SwitchToolCallMethod { method } => {
                 if let Some(method) = method {
                     self.method = method;   
                 }
                 Ok(AgentResponse::Method { method: self.method })
             }
+            AgentRequest::SendUserInput{ .. } if self.circuit_open => {
+                Err(AgentError::InvalidState(CIRCUIT_OPEN_ERROR.to_string()))
+            }
             AgentRequest::SendUserInput{ input } => {
                 self.handle_event(InternalAgentEvent::CancelTask).await
                 .and({
@@ -404,11 +752,16 @@ impl AgentCore {
                         input: input.clone() 
                     }).await;
                     
-                    self.trace.write().await.push(ChatMessage::User { 
+                    let message = ChatMessage::User { 
                         content: ChatMessageContent::Text(input), 
                         name: None 
-                    });
+                    };
+                    self.append_message(message).await;
                     
+                    // a new task starts with a fresh budget
+                    self.task_steps = 0;
+                    self.task_started_at = None;
+                    self.idle_steps = 0;
                     self.set_state(InternalAgentState::Running).await;
                     Ok(AgentResponse::Ack)
                 })
@@ -429,6 +782,52 @@ impl AgentCore {
                 }).map_err(|_| AgentError::SessionClosed)?;
                 Ok(AgentResponse::Ack)
             }
+            AgentRequest::CompressContext => {
+                // handled by the main loop so the controller does not wait for the summary
+                let _ = self.internal_tx.send(InternalAgentEvent::ManualCompressionRequested)
+                    .map_err(|_| AgentError::SessionClosed)?;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::ExportTrace { format } => {
+                let trace = self.full_trace.read().await;
+                export_trace(&trace, format).map(|content| AgentResponse::Trace { content })
+            }
+            AgentRequest::SetSystemPromptSuffix { text } => {
+                self.set_system_prompt_suffix(text).await;
+                Ok(AgentResponse::Ack)
+            }
+            AgentRequest::ClearTrace => {
+                self.clear_trace().await.map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::PinLastMessage => {
+                self.pin_last_message().await.map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::GetLastError => {
+                Ok(AgentResponse::LastError { report: self.last_error.clone() })
+            }
+            AgentRequest::GetSessionUsage => {
+                Ok(AgentResponse::SessionUsage { usage: self.session_usage.clone() })
+            }
+            AgentRequest::GetTrace => {
+                Ok(AgentResponse::Messages { messages: self.trace.read().await.clone() })
+            }
+            AgentRequest::RemoveMessage { index } => {
+                self.remove_message(index).await.map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::EditMessage { index, content } => {
+                self.edit_message(index, content).await.map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::RewindLastTurn => {
+                self.rewind_last_turn().await.map(|_| AgentResponse::Ack)
+            }
+            AgentRequest::LoadTrace { messages } => {
+                self.handle_event(InternalAgentEvent::CancelTask).await
+                .and({
+                    self.load_trace(messages).await;
+                    self.set_state(InternalAgentState::Paused).await;
+                    Ok(AgentResponse::Ack)
+                })
+            }
             AgentRequest::WaitTurn => {
                 self.handle_wait_turn(backchannel).await;
                 return Ok(()); // We handle the response in the spawned task
@@ -442,8 +841,17 @@ impl AgentCore {
 
 
     /// Handle an event
-    async fn handle_event(&mut self, event: InternalAgentEvent) -> Result<(), AgentError> {
+    pub(crate) async fn handle_event(&mut self, event: InternalAgentEvent) -> Result<(), AgentError> {
         debug!(target: "agent::internal_event", event = ?event);
+        // a brain task replaced by a newer one may still have sent something before it was stopped
+        if let InternalAgentEvent::BrainResult { step, .. }
+            | InternalAgentEvent::BrainDelta { step, .. }
+            | InternalAgentEvent::BrainCancelled { step, .. } = &event {
+            if *step != self.brain_step {
+                debug!(target: "agent::internal_event", step, current = self.brain_step, "event of a replaced brain task dropped");
+                return Ok(());
+            }
+        }
         match self.state {
             InternalAgentState::Starting => {
                 self.state_starting_handle_event(event).await
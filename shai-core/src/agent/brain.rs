@@ -1,17 +1,46 @@
 use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
-use shai_llm::{ChatMessage, ToolCallMethod};
-use tokio::sync::RwLock;
+use shai_llm::{ChatMessage, RetryPolicy, ToolCallMethod};
+use tokio::sync::{broadcast, mpsc, RwLock};
 
+use crate::runners::compacter::ContextCompressor;
 use crate::tools::types::AnyToolBox;
 use super::error::AgentError;
+use super::AgentEvent;
 
 
 /// ThinkerContext is the agent internal state
 pub struct ThinkerContext {
     pub trace:           Arc<RwLock<Vec<ChatMessage>>>,
     pub available_tools: AnyToolBox,
-    pub method:          ToolCallMethod
+    pub method:          ToolCallMethod,
+    /// brains streaming their answer send the text chunks here as they arrive
+    pub delta_tx:        Option<mpsc::UnboundedSender<String>>,
+    /// how the llm calls of this step are retried on transient failures
+    pub retry_policy:    RetryPolicy,
+    /// public events of the agent, for brains to report what happens during the step
+    pub event_tx:        Option<broadcast::Sender<AgentEvent>>,
+}
+
+impl ThinkerContext {
+    /// Forward a chunk of streamed assistant text to the agent, if anyone is listening
+    pub fn send_delta(&self, text: &str) {
+        if let Some(tx) = &self.delta_tx {
+            let _ = tx.send(text.to_string());
+        }
+    }
+
+    /// Tell the agent an llm call failed and is about to be retried
+    pub fn notify_retry(&self, attempt: u32, delay: Duration) {
+        if let Some(tx) = &self.event_tx {
+            let _ = tx.send(AgentEvent::RetryingRequest {
+                attempt,
+                max_retries: self.retry_policy.max_retries,
+                delay,
+            });
+        }
+    }
 }
 
 /// ThinkerFlowControl drives the agentic flow
@@ -29,6 +58,10 @@ pub struct ThinkerDecision {
     pub message: ChatMessage,
     pub flow:    ThinkerFlowControl,
     pub token_usage: Option<(u32, u32)>, // (input_tokens, output_tokens)
+    /// tool call method that produced the message, set by brains that negotiate it with the provider
+    pub method:  Option<ToolCallMethod>,
+    /// model that answered, the session usage is broken down by model
+    pub model:   Option<String>,
 }
 
 impl ThinkerDecision {
@@ -37,6 +70,8 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: None,
+            method: None,
+            model: None,
         }
     }
 
@@ -45,6 +80,8 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentContinue,
             token_usage: None,
+            method: None,
+            model: None,
         }
     }
 
@@ -53,6 +90,8 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: None,
+            method: None,
+            model: None,
         }
     }
 
@@ -61,6 +100,8 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentContinue,
             token_usage: Some((input_tokens, output_tokens)),
+            method: None,
+            model: None,
         }
     }
 
@@ -69,20 +110,58 @@ impl ThinkerDecision {
             message,
             flow: ThinkerFlowControl::AgentPause,
             token_usage: Some((input_tokens, output_tokens)),
+            method: None,
+            model: None,
         }
     }
 
+    pub fn with_method(mut self, method: ToolCallMethod) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
     pub fn unwrap(self) -> ChatMessage {
         self.message
     }
 }
 
+/// Model a brain talks to, as reported after switching to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrainModel {
+    pub provider: String,
+    pub model: String,
+    pub max_context: u32,
+}
+
 /// Core thinking interface - pure decision making
 #[async_trait]
 pub trait Brain: Send + Sync {
     /// This method is called at every step of the agent to decide next step
     /// note that if the message contains toolcall, it will always continue
     async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError>;
+
+    /// Context compressor used by the agent to keep the trace within the model context window.
+    /// Brains that do not support compression return None
+    fn context_compressor(&mut self) -> Option<&mut ContextCompressor> {
+        None
+    }
+
+    /// System prompt the brain puts in front of the trace, the agent keeps room for it
+    /// when making sure the next request fits in the context window
+    fn system_prompt(&self) -> Option<String> {
+        None
+    }
+
+    /// Use another model of the same provider for the next steps.
+    /// Brains not backed by an llm client cannot switch
+    async fn switch_model(&mut self, model: String) -> Result<BrainModel, AgentError> {
+        Err(AgentError::InvalidState("this agent cannot switch models".to_string()))
+    }
 }
 
 
@@ -1,4 +1,4 @@
-use shai_llm::{ChatMessage, LlmClient};
+use shai_llm::{ChatMessage, LlmClient, ToolCallMethod};
 use uuid::Uuid;
 use std::sync::Arc;
 
@@ -12,6 +12,8 @@ use super::Brain;
 use super::AgentCore;
 use super::claims::ClaimManager;
 use super::AgentError;
+use super::actions::tools::{AgentMode, ToolConcurrency};
+use super::actions::brain::{CircuitBreaker, TaskBudget, DEFAULT_IDLE_STEP_LIMIT};
 
 /// Builder for AgentCore
 pub struct AgentBuilder {
@@ -21,6 +23,13 @@ pub struct AgentBuilder {
     pub trace: Vec<ChatMessage>,
     pub available_tools: Vec<Box<dyn AnyTool>>,
     pub permissions: ClaimManager,
+    pub max_tool_output: Option<usize>,
+    pub tool_concurrency: ToolConcurrency,
+    pub task_budget: TaskBudget,
+    pub idle_step_limit: Option<u32>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub mode: AgentMode,
+    pub method: ToolCallMethod,
 }
 
 impl AgentBuilder {
@@ -32,6 +41,13 @@ impl AgentBuilder {
             trace: vec![],
             available_tools: vec![],
             permissions: ClaimManager::new(),
+            max_tool_output: None,
+            tool_concurrency: ToolConcurrency::default(),
+            task_budget: TaskBudget::default(),
+            idle_step_limit: Some(DEFAULT_IDLE_STEP_LIMIT),
+            circuit_breaker: Some(CircuitBreaker::default()),
+            mode: AgentMode::default(),
+            method: ToolCallMethod::FunctionCall,
         }
     }
 }
@@ -67,6 +83,51 @@ impl AgentBuilder {
         self
     }
 
+    /// Cap (in bytes) on the tool output kept in the trace, the middle of longer outputs is omitted
+    pub fn max_tool_output(mut self, max_bytes: usize) -> Self {
+        self.max_tool_output = Some(max_bytes);
+        self
+    }
+
+    /// How the tool calls of a single step are run, see ToolConcurrency
+    pub fn tool_concurrency(mut self, concurrency: ToolConcurrency) -> Self {
+        self.tool_concurrency = concurrency;
+        self
+    }
+
+    /// Steps and wall-clock time a task may take before the agent pauses it
+    pub fn task_budget(mut self, budget: TaskBudget) -> Self {
+        self.task_budget = budget;
+        self
+    }
+
+    /// Steps in a row the brain may continue without calling a tool before the agent pauses
+    /// and asks the user for guidance, None never pauses
+    pub fn idle_step_limit(mut self, limit: Option<u32>) -> Self {
+        self.idle_step_limit = limit;
+        self
+    }
+
+    /// How the brain calls tools to begin with, e.g. `LlmClient::detect_tool_call_method`.
+    /// The user can still switch to another method while the agent runs.
+    pub fn method(mut self, method: ToolCallMethod) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Start in plan mode, with the read-only tools only, or in execute mode (the default)
+    pub fn mode(mut self, mode: AgentMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Brain failures in a row after which new input is refused until the breaker is reset,
+    /// None never refuses
+    pub fn circuit_breaker(mut self, breaker: Option<CircuitBreaker>) -> Self {
+        self.circuit_breaker = breaker;
+        self
+    }
+
     /// Enable sudo mode - bypasses all permission checks
     pub fn sudo(mut self) -> Self {
         self.permissions.sudo();
@@ -80,13 +141,23 @@ impl AgentBuilder {
         }
 
 
-        AgentCore::new(
+        let mut agent = AgentCore::new(
             self.session_id.clone(),
             self.brain,
             self.trace,
             self.available_tools,
             self.permissions
-        )
+        );
+        if let Some(max_bytes) = self.max_tool_output {
+            agent.max_tool_output = max_bytes;
+        }
+        agent.tool_concurrency = self.tool_concurrency;
+        agent.task_budget = self.task_budget;
+        agent.idle_step_limit = self.idle_step_limit;
+        agent.circuit_breaker = self.circuit_breaker;
+        agent.mode = self.mode;
+        agent.method = self.method;
+        agent
     }
 
     /// Create an AgentBuilder from an AgentConfig
@@ -128,8 +199,12 @@ impl AgentBuilder {
             }
         }
 
+        let method = llm_client.detect_tool_call_method(&config.llm_provider.model);
         Ok(Self::new(brain)
+            .method(method)
             .tools(tools)
+            .max_tool_output(config.max_tool_output)
+            .tool_concurrency(config.tool_concurrency)
             .id(&format!("agent-{}", config.name)))
     }
 
@@ -1,7 +1,9 @@
+use chrono::{DateTime, Utc};
 use shai_llm::provider::LlmError;
+use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
-#[derive(Error, Debug, Clone)]
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
 pub enum AgentError {
     #[error("Agent execution error: {0}")]
     ExecutionError(String),
@@ -9,6 +11,8 @@ pub enum AgentError {
     LlmError(String),
     #[error("Tool error: {0}")]
     ToolError(String),
+    #[error("Invalid tool arguments: {0}")]
+    InvalidToolArguments(String),
     #[error("Agent session has been closed")]
     SessionClosed,
     #[error("Invalid response: {0}")]
@@ -31,6 +35,52 @@ pub enum AgentError {
     InvalidStateTransition(String),
 }
 
+impl AgentError {
+    /// What the user can do about the error, when the message is a known one
+    pub fn hint(&self) -> Option<&'static str> {
+        let message = self.to_string().to_lowercase();
+        let mentions = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+        match self {
+            AgentError::LlmError(_) if mentions(&["context length", "context window", "maximum context", "too many tokens", "prompt is too long", "413"]) =>
+                Some("the conversation is too large for the model, try /compact or /clear"),
+            AgentError::LlmError(_) if mentions(&["401", "403", "unauthorized", "api key", "api_key", "authentication"]) =>
+                Some("the provider rejected the credentials, check the api key with /auth"),
+            AgentError::LlmError(_) if mentions(&["429", "rate limit", "too many requests", "quota"]) =>
+                Some("the provider is rate limiting, wait a moment before sending again"),
+            AgentError::LlmError(_) if mentions(&["model not found", "does not exist", "unknown model", "404"]) =>
+                Some("the model may not be served by this provider, pick another one with /model"),
+            AgentError::LlmError(_) if mentions(&["timed out", "connect", "connection", "dns"]) =>
+                Some("the provider could not be reached, check the network and the provider url"),
+            AgentError::LlmError(_) if mentions(&["invalid tool call", "tool_calls", "function"]) =>
+                Some("the model struggles with this tool call method, try another one with /tc or ctrl^t"),
+            AgentError::InvalidToolArguments(_) =>
+                Some("the model keeps sending malformed tool calls, try another tool call method with /tc or ctrl^t"),
+            AgentError::ConfigurationError(_) =>
+                Some("check the agent configuration and the provider settings with /auth"),
+            AgentError::PermissionDenied =>
+                Some("a tool call was refused, approve it when asked or allow the tool"),
+            AgentError::TimeoutError =>
+                Some("the agent did not answer in time, it may still be busy"),
+            _ => None,
+        }
+    }
+}
+
+/// Last error that stopped the agent, kept so it can be explained afterwards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorReport {
+    pub error: AgentError,
+    pub timestamp: DateTime<Utc>,
+    /// the user request the agent was working on
+    pub request: Option<String>,
+}
+
+impl ErrorReport {
+    pub fn hint(&self) -> Option<&'static str> {
+        self.error.hint()
+    }
+}
+
 #[derive(Debug)]
 pub enum AgentExecutionError {
     LlmError(LlmError),
@@ -1,12 +1,16 @@
 use std::sync::Arc;
 use std::future::Future;
+use std::time::Duration;
 use futures::future::BoxFuture;
-use shai_llm::ChatMessage;
-use serde::{Serialize, Deserialize};
+use shai_llm::{ChatMessage, ToolCallMethod};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use async_trait::async_trait;
 use super::brain::ThinkerDecision;
 use super::AgentError;
 use crate::agent::PublicAgentState;
+use crate::agent::actions::brain::SessionUsage;
+use crate::agent::actions::tools::AgentMode;
+use crate::runners::compacter::CompressionOutcome;
 use crate::tools::{ToolResult, ToolCall};
 use chrono::{DateTime, TimeDelta, Utc};
 
@@ -18,12 +22,25 @@ pub enum InternalAgentEvent {
     AgentInitialized,
     /// Request to start thinking operation
     CancelTask,
+    /// Cancel the running tools only, their results become errors and the agent keeps going
+    CancelCurrentTool,
     /// Request to start thinking operation
     ThinkingStart,
     /// Brain completed and returned a result for the next step
     BrainResult {
+        step: u64,
         result: Result<ThinkerDecision, AgentError>
     },
+    /// Chunk of assistant text streamed by the brain before its result
+    BrainDelta {
+        step: u64,
+        text: String
+    },
+    /// Brain was cancelled after streaming part of its answer
+    BrainCancelled {
+        step: u64,
+        partial: ChatMessage
+    },
     /// Agent started executing a tool
     ToolCallStarted { 
         timestamp: DateTime<Utc>,
@@ -48,12 +65,19 @@ pub enum InternalAgentEvent {
     PermissionResponseReceived { 
         request_id: String,
         response: PermissionResponse
-    }
+    },
+    /// User asked to compress the context
+    ManualCompressionRequested,
+    /// Manual compression task completed, resume tells whether the agent should continue its task
+    CompressionCompleted {
+        resume: bool
+    },
 }
 
 /// Public events emitted to external controllers/UI
-/// These events are what external consumers receive and can respond to
-#[derive(Clone)]
+/// These events are what external consumers receive and can respond to.
+/// They serialize to json so a session can be recorded and replayed, see `JsonlEventSink`
+#[derive(Clone, Serialize, Deserialize)]
 pub enum AgentEvent {
     /// Agent status has changed
     StatusChanged { 
@@ -67,13 +91,24 @@ pub enum AgentEvent {
         timestamp: DateTime<Utc>,
         thought: Result<ChatMessage, AgentError>
     },
+    /// Chunk of the assistant answer as it is being streamed, the complete
+    /// message still comes with the following BrainResult
+    BrainDelta {
+        text: String
+    },
     /// Agent started executing a tool
     ToolCallStarted { 
         timestamp: DateTime<Utc>,
         call: ToolCall 
     },
+    /// Output produced by a running tool, tools that cannot stream send a single chunk
+    ToolOutputChunk {
+        tool_call_id: String,
+        chunk: String,
+    },
     /// Tool execution completed and returned a result
     ToolCallCompleted {
+        #[serde(with = "time_delta_ms")]
         duration: TimeDelta,
         call: ToolCall,
         result: ToolResult
@@ -101,6 +136,64 @@ pub enum AgentEvent {
         input_tokens: u32,
         output_tokens: u32
     },
+    /// Running total of the tokens of the session, sent after each TokenUsage
+    SessionUsage {
+        usage: SessionUsage
+    },
+    /// The brain fell back to another tool call method, which is kept for the session
+    ToolCallMethodChanged {
+        method: ToolCallMethod,
+    },
+    /// A llm call failed for a transient reason and is sent again after the delay
+    RetryingRequest {
+        attempt: u32,
+        max_retries: u32,
+        delay: Duration,
+    },
+    /// Context compression started
+    CompressionStarted {
+        messages_to_summarize: usize,
+        current_tokens: u32,
+        max_tokens: u32,
+    },
+    /// Context compression progress, when the conversation is summarized in several steps
+    CompressionProgress {
+        step: usize,
+        total: usize,
+    },
+    /// Context compression finished, a skipped outcome tells why nothing was compressed
+    CompressionFinished {
+        outcome: CompressionOutcome,
+    },
+    /// The conversation did not fit in the context window even after compression,
+    /// the oldest messages were dropped
+    ContextTruncated {
+        messages_dropped: usize,
+        current_tokens: u32,
+        max_tokens: u32,
+    },
+    /// The conversation was reset with `AgentController::clear_trace`
+    ConversationCleared,
+    /// The task went past its budget (see `AgentController::set_task_budget`) and was paused
+    BudgetExceeded {
+        steps: u32,
+        #[serde(with = "time_delta_ms")]
+        elapsed: TimeDelta,
+    },
+    /// The agent switched between plan and execute mode (see `AgentController::set_mode`)
+    ModeChanged {
+        mode: AgentMode,
+    },
+    /// The brain failed too many times in a row (see `AgentController::set_circuit_breaker`),
+    /// new input is refused until `AgentController::reset_circuit_breaker`
+    CircuitOpened {
+        failures: u32,
+    },
+    /// The brain kept going without calling any tool (see `AgentController::set_idle_step_limit`),
+    /// the agent was paused to ask the user for guidance
+    IdleLoopDetected {
+        steps: u32,
+    },
 }
 
 /// Types of user input that an agent can request
@@ -160,6 +253,19 @@ pub enum PermissionResponse {
     NoPermissionSystem,
 }
 
+/// TimeDelta has no serde support, it is recorded in milliseconds
+mod time_delta_ms {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &TimeDelta, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(duration.num_milliseconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TimeDelta, D::Error> {
+        i64::deserialize(deserializer).map(TimeDelta::milliseconds)
+    }
+}
+
 /// Type alias for event handlers (for backwards compatibility)
 pub type DynEventHandler = Arc<dyn Fn(AgentEvent) -> BoxFuture<'static, ()> + Send + Sync>;
 
@@ -168,6 +274,16 @@ pub trait AgentEventHandler: Send + Sync {
     async fn handle_event(&self, event: AgentEvent);
 }
 
+/// Handler registered on a running agent with `AgentController::add_event_sink`
+#[derive(Clone)]
+pub struct EventSink(pub Arc<dyn AgentEventHandler>);
+
+impl std::fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
 // Generic adapter to allow closures to implement AgentEventHandler
 pub struct ClosureHandler<F>
 where
@@ -225,12 +341,23 @@ impl std::fmt::Debug for AgentEvent {
                     .field("thought", thought)
                     .finish()
             }
+            AgentEvent::BrainDelta { text } => {
+                f.debug_struct("BrainDelta")
+                    .field("text", text)
+                    .finish()
+            }
             AgentEvent::ToolCallStarted { timestamp, call } => {
                 f.debug_struct("ToolCallStarted")
                     .field("timestamp", timestamp)
                     .field("call", call)
                     .finish()
             }
+            AgentEvent::ToolOutputChunk { tool_call_id, chunk } => {
+                f.debug_struct("ToolOutputChunk")
+                    .field("tool_call_id", tool_call_id)
+                    .field("chunk", chunk)
+                    .finish()
+            }
             AgentEvent::ToolCallCompleted { duration, call, result } => {
                 f.debug_struct("ToolCallCompleted")
                     .field("timestamp", duration)
@@ -274,6 +401,72 @@ impl std::fmt::Debug for AgentEvent {
                     .field("output_tokens", output_tokens)
                     .finish()
             }
+            AgentEvent::SessionUsage { usage } => {
+                f.debug_struct("SessionUsage")
+                    .field("usage", usage)
+                    .finish()
+            }
+            AgentEvent::ToolCallMethodChanged { method } => {
+                f.debug_struct("ToolCallMethodChanged")
+                    .field("method", method)
+                    .finish()
+            }
+            AgentEvent::RetryingRequest { attempt, max_retries, delay } => {
+                f.debug_struct("RetryingRequest")
+                    .field("attempt", attempt)
+                    .field("max_retries", max_retries)
+                    .field("delay", delay)
+                    .finish()
+            }
+            AgentEvent::CompressionStarted { messages_to_summarize, current_tokens, max_tokens } => {
+                f.debug_struct("CompressionStarted")
+                    .field("messages_to_summarize", messages_to_summarize)
+                    .field("current_tokens", current_tokens)
+                    .field("max_tokens", max_tokens)
+                    .finish()
+            }
+            AgentEvent::CompressionProgress { step, total } => {
+                f.debug_struct("CompressionProgress")
+                    .field("step", step)
+                    .field("total", total)
+                    .finish()
+            }
+            AgentEvent::CompressionFinished { outcome } => {
+                f.debug_struct("CompressionFinished")
+                    .field("outcome", outcome)
+                    .finish()
+            }
+            AgentEvent::ContextTruncated { messages_dropped, current_tokens, max_tokens } => {
+                f.debug_struct("ContextTruncated")
+                    .field("messages_dropped", messages_dropped)
+                    .field("current_tokens", current_tokens)
+                    .field("max_tokens", max_tokens)
+                    .finish()
+            }
+            AgentEvent::ConversationCleared => {
+                f.debug_struct("ConversationCleared").finish()
+            }
+            AgentEvent::BudgetExceeded { steps, elapsed } => {
+                f.debug_struct("BudgetExceeded")
+                    .field("steps", steps)
+                    .field("elapsed", elapsed)
+                    .finish()
+            }
+            AgentEvent::IdleLoopDetected { steps } => {
+                f.debug_struct("IdleLoopDetected")
+                    .field("steps", steps)
+                    .finish()
+            }
+            AgentEvent::ModeChanged { mode } => {
+                f.debug_struct("ModeChanged")
+                    .field("mode", mode)
+                    .finish()
+            }
+            AgentEvent::CircuitOpened { failures } => {
+                f.debug_struct("CircuitOpened")
+                    .field("failures", failures)
+                    .finish()
+            }
         }
     }
 }
@@ -8,6 +8,7 @@ pub mod events;
 pub mod states;
 pub mod actions;
 pub mod output;
+pub mod pool;
 
 #[cfg(test)]
 mod tests;
@@ -20,15 +21,18 @@ pub use agent::{
 pub use states::{InternalAgentState, PublicAgentState};
 
 pub use protocol::{AgentRequest, AgentResponse, AgentController};
+pub use pool::{AgentPool, AgentEnvelope};
 
 pub use events::{
     InternalAgentEvent, AgentEvent,
-    ClosureHandler, AgentEventHandler, DynEventHandler, closure_handler,
+    ClosureHandler, AgentEventHandler, DynEventHandler, EventSink, closure_handler,
     UserRequest, UserResponse, PermissionRequest, PermissionResponse};
-pub use output::StdoutEventManager;
+pub use output::{StdoutEventManager, TraceFormat, JsonlEventSink, replay_event_log};
     
 pub use builder::AgentBuilder;
+pub use actions::tools::{AgentMode, ToolConcurrency};
+pub use actions::brain::{TaskBudget, CircuitBreaker, SessionUsage, ModelUsage};
 pub use claims::{ClaimManager, PermissionError};
-pub use error::{AgentError, AgentExecutionError};
-pub use brain::{Brain, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
+pub use error::{AgentError, AgentExecutionError, ErrorReport};
+pub use brain::{Brain, BrainModel, ThinkerContext, ThinkerDecision, ThinkerFlowControl};
 pub use crate::logging::LoggingConfig;
\ No newline at end of file
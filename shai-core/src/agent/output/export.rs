@@ -0,0 +1,120 @@
+use shai_llm::{ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::chat::ChatMessageContentPart;
+use crate::agent::AgentError;
+
+/// Format of an exported conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// readable transcript of the user, assistant and tool turns
+    Markdown,
+    /// the raw Vec<ChatMessage>, suitable for re-import
+    Json,
+}
+
+impl TraceFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TraceFormat::Markdown => "md",
+            TraceFormat::Json => "json",
+        }
+    }
+}
+
+/// Render a trace in the requested format
+pub fn export_trace(trace: &[ChatMessage], format: TraceFormat) -> Result<String, AgentError> {
+    match format {
+        TraceFormat::Json => serde_json::to_string_pretty(trace)
+            .map_err(|e| AgentError::ExecutionError(format!("failed to serialize the trace: {}", e))),
+        TraceFormat::Markdown => Ok(trace_to_markdown(trace)),
+    }
+}
+
+fn trace_to_markdown(trace: &[ChatMessage]) -> String {
+    let mut out = String::from("# Conversation\n");
+    for message in trace {
+        match message {
+            ChatMessage::System { content, .. } => {
+                out.push_str(&format!("\n## System\n\n{}\n", content_text(content)));
+            }
+            ChatMessage::Developer { content, .. } => {
+                out.push_str(&format!("\n## Developer\n\n{}\n", content_text(content)));
+            }
+            ChatMessage::User { content, .. } => {
+                out.push_str(&format!("\n## User\n\n{}\n", content_text(content)));
+            }
+            ChatMessage::Assistant { content, tool_calls, .. } => {
+                out.push_str("\n## Assistant\n");
+                if let Some(content) = content {
+                    let text = content_text(content);
+                    if !text.is_empty() {
+                        out.push_str(&format!("\n{}\n", text));
+                    }
+                }
+                for call in tool_calls.iter().flatten() {
+                    let arguments = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                        .and_then(|value| serde_json::to_string_pretty(&value))
+                        .unwrap_or_else(|_| call.function.arguments.clone());
+                    out.push_str(&format!("\n**Tool call** `{}` ({})\n\n```json\n{}\n```\n", call.function.name, call.id, arguments));
+                }
+            }
+            ChatMessage::Tool { content, tool_call_id, .. } => {
+                out.push_str(&format!("\n## Tool result ({})\n\n```\n{}\n```\n", tool_call_id, content));
+            }
+        }
+    }
+    out
+}
+
+fn content_text(content: &ChatMessageContent) -> String {
+    match content {
+        ChatMessageContent::Text(text) => text.clone(),
+        ChatMessageContent::ContentPart(parts) => parts.iter()
+            .filter_map(|part| match part {
+                ChatMessageContentPart::Text(text_part) => Some(text_part.text.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        ChatMessageContent::None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shai_llm::{Function, ToolCall};
+
+    fn sample_trace() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::User { content: ChatMessageContent::Text("list files".to_string()), name: None },
+            ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("Let me look".to_string())),
+                reasoning_content: None,
+                tool_calls: Some(vec![ToolCall {
+                    id: "call_1".to_string(),
+                    r#type: "function".to_string(),
+                    function: Function { name: "ls".to_string(), arguments: "{\"path\":\".\"}".to_string() },
+                }]),
+                refusal: None,
+                name: None,
+                audio: None,
+            },
+            ChatMessage::Tool { content: "Cargo.toml".to_string(), tool_call_id: "call_1".to_string() },
+        ]
+    }
+
+    #[test]
+    fn test_markdown_export_contains_tool_turns() {
+        let markdown = export_trace(&sample_trace(), TraceFormat::Markdown).unwrap();
+        assert!(markdown.contains("## User\n\nlist files"));
+        assert!(markdown.contains("**Tool call** `ls` (call_1)"));
+        assert!(markdown.contains("## Tool result (call_1)\n\n```\nCargo.toml\n```"));
+    }
+
+    #[test]
+    fn test_json_export_round_trips() {
+        let json = export_trace(&sample_trace(), TraceFormat::Json).unwrap();
+        let trace: Vec<ChatMessage> = serde_json::from_str(&json).unwrap();
+        assert_eq!(trace.len(), 3);
+    }
+}
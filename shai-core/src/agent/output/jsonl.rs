@@ -0,0 +1,70 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use crate::agent::{AgentEvent, AgentEventHandler};
+
+/// One line of an event log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub timestamp: DateTime<Utc>,
+    pub event: AgentEvent,
+}
+
+/// Records every agent event to a file, one json object per line, to be replayed
+/// later with `replay_event_log`
+pub struct JsonlEventSink {
+    path: PathBuf,
+}
+
+impl JsonlEventSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl AgentEventHandler for JsonlEventSink {
+    async fn handle_event(&self, event: AgentEvent) {
+        let record = RecordedEvent { timestamp: Utc::now(), event };
+        let Ok(line) = serde_json::to_string(&record) else {
+            return;
+        };
+        // recording must never disturb the agent, a failing write is dropped
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Read a log written by `JsonlEventSink`, lines that cannot be parsed are skipped
+pub fn read_event_log(path: impl AsRef<Path>) -> io::Result<Vec<RecordedEvent>> {
+    let file = std::fs::File::open(path)?;
+    let mut events = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(record) = serde_json::from_str(&line?) {
+            events.push(record);
+        }
+    }
+    Ok(events)
+}
+
+/// Feed a recorded session to a handler (e.g. a UI) without running any agent.
+/// With a speed the original pace is kept (2.0 plays twice as fast), without it
+/// events are sent back to back. Returns the number of events replayed.
+pub async fn replay_event_log(path: impl AsRef<Path>, handler: &dyn AgentEventHandler, speed: Option<f64>) -> io::Result<usize> {
+    let events = read_event_log(path)?;
+    let mut previous: Option<DateTime<Utc>> = None;
+    for record in &events {
+        if let (Some(speed), Some(previous)) = (speed.filter(|s| *s > 0.0), previous) {
+            let gap = (record.timestamp - previous).to_std().unwrap_or(Duration::ZERO);
+            tokio::time::sleep(gap.div_f64(speed)).await;
+        }
+        previous = Some(record.timestamp);
+        handler.handle_event(record.event.clone()).await;
+    }
+    Ok(events.len())
+}
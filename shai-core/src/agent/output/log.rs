@@ -24,7 +24,7 @@ impl FileEventLogger {
         let timestamp = Utc::now();
         let event_str = match event {
             AgentEvent::StatusChanged { old_status, new_status } => {
-                format!("StatusChanged: {:?} -> {:?}", old_status, new_status)
+                format!("StatusChanged: {} -> {}", old_status, new_status)
             }
             AgentEvent::ThinkingStart => {
                 format!("ThinkingStart")
@@ -32,9 +32,15 @@ impl FileEventLogger {
             AgentEvent::BrainResult { timestamp: event_time, thought } => {
                 format!("BrainResult: {:?} - {:?}", event_time, thought)
             }
+            AgentEvent::BrainDelta { text } => {
+                format!("BrainDelta: {:?}", text)
+            }
             AgentEvent::ToolCallStarted { timestamp: event_time, call } => {
                 format!("ToolCallStarted: {:?} - {}", event_time, call.tool_name)
             }
+            AgentEvent::ToolOutputChunk { tool_call_id, chunk } => {
+                format!("ToolOutputChunk: {} - {} bytes", tool_call_id, chunk.len())
+            }
             AgentEvent::ToolCallCompleted { duration, call, result } => {
                 format!("ToolCallCompleted: {} in {:?} - {:?}", call.tool_name, duration, result)
             }
@@ -56,6 +62,42 @@ impl FileEventLogger {
             AgentEvent::TokenUsage { input_tokens, output_tokens } => {
                 format!("Token Usage: input={} output={} total={}", input_tokens, output_tokens, input_tokens + output_tokens)
             }
+            AgentEvent::SessionUsage { usage } => {
+                format!("Session Usage: input={} output={} total={}", usage.input_tokens, usage.output_tokens, usage.total_tokens())
+            }
+            AgentEvent::ToolCallMethodChanged { method } => {
+                format!("ToolCallMethodChanged: {:?}", method)
+            }
+            AgentEvent::RetryingRequest { attempt, max_retries, delay } => {
+                format!("RetryingRequest: {}/{} in {:?}", attempt, max_retries, delay)
+            }
+            AgentEvent::CompressionStarted { messages_to_summarize, current_tokens, max_tokens } => {
+                format!("CompressionStarted: {} messages - {}/{} tokens", messages_to_summarize, current_tokens, max_tokens)
+            }
+            AgentEvent::CompressionProgress { step, total } => {
+                format!("CompressionProgress: {}/{}", step, total)
+            }
+            AgentEvent::CompressionFinished { outcome } => {
+                format!("CompressionFinished: {:?}", outcome)
+            }
+            AgentEvent::ContextTruncated { messages_dropped, current_tokens, max_tokens } => {
+                format!("ContextTruncated: {} messages dropped - {}/{} tokens", messages_dropped, current_tokens, max_tokens)
+            }
+            AgentEvent::ConversationCleared => {
+                "ConversationCleared".to_string()
+            }
+            AgentEvent::BudgetExceeded { steps, elapsed } => {
+                format!("BudgetExceeded: {} steps in {}s", steps, elapsed.num_seconds())
+            }
+            AgentEvent::IdleLoopDetected { steps } => {
+                format!("IdleLoopDetected: {} steps without tool calls", steps)
+            }
+            AgentEvent::ModeChanged { mode } => {
+                format!("ModeChanged: {}", mode)
+            }
+            AgentEvent::CircuitOpened { failures } => {
+                format!("CircuitOpened: {} failures in a row", failures)
+            }
         };
 
         let log_line = format!("[{}] {}\n", timestamp.format("%Y-%m-%d %H:%M:%S%.3f"), event_str);
@@ -1,7 +1,11 @@
 pub mod stdout;
 pub mod pretty;
 pub mod log;
+pub mod export;
+pub mod jsonl;
 
 pub use stdout::StdoutEventManager;
 pub use pretty::PrettyFormatter;
-pub use log::FileEventLogger;
\ No newline at end of file
+pub use log::FileEventLogger;
+pub use export::{export_trace, TraceFormat};
+pub use jsonl::{JsonlEventSink, RecordedEvent, read_event_log, replay_event_log};
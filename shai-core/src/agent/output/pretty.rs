@@ -31,10 +31,18 @@ impl PrettyFormatter {
             AgentEvent::BrainResult { thought, .. } => {
                 self.format_thinking(thought)
             },
+            AgentEvent::BrainDelta { .. } => {
+                // the complete answer is displayed with the BrainResult
+                None
+            },
             AgentEvent::ToolCallStarted { call, .. } => {
                 // do nothing because tool can be call in parallel, we only display the result
                 None
             },
+            AgentEvent::ToolOutputChunk { .. } => {
+                // live output is displayed in the status line, the final result is shown on completion
+                None
+            },
             AgentEvent::ToolCallCompleted { call, result, .. } => {
                 Some(self.format_tool_result(call, result))
             },
@@ -110,10 +118,74 @@ impl PrettyFormatter {
                 
                 Some(completion_skin.term_text(&markdown).to_string())
             },
-            AgentEvent::TokenUsage { .. } => {
+            AgentEvent::TokenUsage { .. } | AgentEvent::SessionUsage { .. } => {
                 // Don't display token usage in the main output - it's handled by /tokens command
                 None
             },
+            AgentEvent::ToolCallMethodChanged { .. } | AgentEvent::ModeChanged { .. } => {
+                // the current method and mode are displayed in the input box
+                None
+            },
+            AgentEvent::RetryingRequest { .. } => {
+                // retries are displayed in the status line
+                None
+            },
+            AgentEvent::CompressionStarted { .. } | AgentEvent::CompressionProgress { .. } => {
+                // progress is displayed in the status line
+                None
+            },
+            AgentEvent::CompressionFinished { outcome } => {
+                outcome.info().map(|info| {
+                    let markdown = format!(
+                        "🗜️ **Context compressed:** {} messages summarized, kept last {} messages, {} → {} tokens ({} saved)",
+                        info.messages_summarized, info.messages_kept, info.tokens_before, info.current_tokens, info.tokens_saved()
+                    );
+                    let mut skin = self.skin.clone();
+                    skin.paragraph.set_fg(rgb(120, 120, 120));
+                    skin.term_text(&markdown).to_string()
+                })
+            },
+            AgentEvent::ContextTruncated { messages_dropped, current_tokens, max_tokens } => {
+                let markdown = format!(
+                    "⚠️ **Context too large:** dropped the {} oldest messages to fit the window ({}/{} tokens)",
+                    messages_dropped, current_tokens, max_tokens
+                );
+                let mut skin = self.skin.clone();
+                skin.paragraph.set_fg(rgb(200, 150, 50));
+                Some(skin.term_text(&markdown).to_string())
+            },
+            AgentEvent::ConversationCleared => {
+                let mut skin = self.skin.clone();
+                skin.paragraph.set_fg(rgb(120, 120, 120));
+                Some(skin.term_text("── *new conversation* ──").to_string())
+            },
+            AgentEvent::BudgetExceeded { steps, elapsed } => {
+                let markdown = format!(
+                    "⚠️ **Task paused:** budget exceeded after {} steps in {}s, send a message to continue",
+                    steps, elapsed.num_seconds()
+                );
+                let mut skin = self.skin.clone();
+                skin.paragraph.set_fg(rgb(200, 150, 50));
+                Some(skin.term_text(&markdown).to_string())
+            },
+            AgentEvent::IdleLoopDetected { steps } => {
+                let markdown = format!(
+                    "⚠️ **Task paused:** {} steps in a row without any tool call, tell the agent how to go on",
+                    steps
+                );
+                let mut skin = self.skin.clone();
+                skin.paragraph.set_fg(rgb(200, 150, 50));
+                Some(skin.term_text(&markdown).to_string())
+            },
+            AgentEvent::CircuitOpened { failures } => {
+                let markdown = format!(
+                    "⛔ **Provider appears to be failing** ({} errors in a row) - check your configuration, then `/reset` to try again",
+                    failures
+                );
+                let mut skin = self.skin.clone();
+                skin.paragraph.set_fg(rgb(255, 100, 100));
+                Some(skin.term_text(&markdown).to_string())
+            },
         }.map(|s| format!("\n{}", s))
     }
 
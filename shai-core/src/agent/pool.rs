@@ -0,0 +1,159 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use super::{Agent, AgentController, AgentError, AgentEvent, AgentResult};
+
+/// Event of an agent of a pool, tagged with the name the agent was spawned under
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentEnvelope {
+    pub agent_id: String,
+    pub event: AgentEvent,
+}
+
+struct PooledAgent {
+    name: String,
+    controller: AgentController,
+    task: JoinHandle<Result<AgentResult, AgentError>>,
+}
+
+/// Named agents running side by side, e.g. a planner and a worker. Each agent keeps its own
+/// brain, traces and channels; the pool fans their events into a single stream of envelopes
+/// and keeps track of the agent the user is talking to.
+pub struct AgentPool {
+    agents: Vec<PooledAgent>,
+    focused: Option<usize>,
+    events: broadcast::Sender<AgentEnvelope>,
+}
+
+impl Default for AgentPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgentPool {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(1024);
+        Self {
+            agents: Vec::new(),
+            focused: None,
+            events,
+        }
+    }
+
+    /// Run an agent in the background under a unique name, the first agent gets the focus
+    pub fn spawn(&mut self, name: impl Into<String>, mut agent: Box<dyn Agent>) -> Result<AgentController, AgentError> {
+        let name = name.into();
+        if self.get(&name).is_some() {
+            return Err(AgentError::ConfigurationError(format!("an agent named '{}' already exists", name)));
+        }
+
+        let controller = agent.controller();
+        let mut rx = agent.watch();
+        let tx = self.events.clone();
+        let agent_id = name.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        let _ = tx.send(AgentEnvelope { agent_id: agent_id.clone(), event });
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // the pool consumer is too slow, the missed events are gone but it should show
+                        warn!(target: "agent::pool", agent = %agent_id, skipped, "events dropped, the pool subscriber lagged behind");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let task = tokio::spawn(async move { agent.run().await });
+
+        self.agents.push(PooledAgent { name, controller: controller.clone(), task });
+        if self.focused.is_none() {
+            self.focused = Some(self.agents.len() - 1);
+        }
+        Ok(controller)
+    }
+
+    /// Events of every agent of the pool, spawned before or after the subscription
+    pub fn watch(&self) -> broadcast::Receiver<AgentEnvelope> {
+        self.events.subscribe()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AgentController> {
+        self.agents.iter().find(|a| a.name == name).map(|a| &a.controller)
+    }
+
+    /// Names of the agents, in the order they were spawned
+    pub fn names(&self) -> Vec<&str> {
+        self.agents.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    /// Whether the agent is still running, false once it completed or failed
+    pub fn is_running(&self, name: &str) -> bool {
+        self.agents.iter().any(|a| a.name == name && !a.task.is_finished())
+    }
+
+    /// Name and controller of the focused agent
+    pub fn focused(&self) -> Option<(&str, &AgentController)> {
+        self.focused.map(|i| (self.agents[i].name.as_str(), &self.agents[i].controller))
+    }
+
+    pub fn focus(&mut self, name: &str) -> Result<&AgentController, AgentError> {
+        let index = self.agents.iter().position(|a| a.name == name)
+            .ok_or_else(|| AgentError::InvalidState(format!("no agent named '{}'", name)))?;
+        self.focused = Some(index);
+        Ok(&self.agents[index].controller)
+    }
+
+    /// Move the focus to the next agent, wrapping around
+    pub fn focus_next(&mut self) -> Option<(&str, &AgentController)> {
+        if self.agents.is_empty() {
+            return None;
+        }
+        self.focused = Some(self.focused.map_or(0, |i| (i + 1) % self.agents.len()));
+        self.focused()
+    }
+
+    /// Shut an agent down and take it out of the pool. When it had the focus, the focus goes
+    /// to the first remaining agent.
+    pub async fn remove(&mut self, name: &str, grace: Duration) -> Result<(), AgentError> {
+        let index = self.agents.iter().position(|a| a.name == name)
+            .ok_or_else(|| AgentError::InvalidState(format!("no agent named '{}'", name)))?;
+        let agent = self.agents.remove(index);
+        self.focused = match self.focused {
+            _ if self.agents.is_empty() => None,
+            Some(focused) if focused > index => Some(focused - 1),
+            Some(focused) if focused == index => Some(0),
+            focused => focused,
+        };
+        Self::stop(agent, grace).await
+    }
+
+    /// Shut every agent down, e.g. before exiting
+    pub async fn shutdown(&mut self, grace: Duration) {
+        self.focused = None;
+        let agents: Vec<_> = self.agents.drain(..).collect();
+        futures::future::join_all(agents.into_iter().map(|agent| Self::stop(agent, grace))).await;
+    }
+
+    async fn stop(agent: PooledAgent, grace: Duration) -> Result<(), AgentError> {
+        let result = agent.controller.shutdown(grace).await;
+        if result.is_err() {
+            debug!(target: "agent::pool", "agent {} did not shut down, aborting it", agent.name);
+            agent.task.abort();
+        }
+        result
+    }
+}
@@ -1,9 +1,12 @@
-use shai_llm::ToolCallMethod;
+use std::path::PathBuf;
+use chrono::Utc;
+use shai_llm::{ChatMessage, ChatMessageContent, RetryPolicy, ToolCallMethod};
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{timeout, Duration};
 use crate::agent::AgentError;
 
-use super::{PermissionResponse, PublicAgentState, UserResponse};
+use std::sync::Arc;
+use super::{AgentEventHandler, AgentMode, BrainModel, CircuitBreaker, ErrorReport, SessionUsage, TaskBudget, EventSink, PermissionResponse, PublicAgentState, TraceFormat, UserResponse};
 
 /// Commands that can be sent to a running agent
 #[derive(Debug, Clone)]
@@ -12,16 +15,54 @@ pub enum AgentRequest {
     Cancel,
     /// Stop the currently executing task
     StopCurrentTask,    
+    /// Cancel the running tools, the brain gets their cancellation as tool errors and continues
+    CancelCurrentTool,
     /// Send user input (cancels current task, adds to trace, resumes agent)
     GetState,
     /// Send user input (cancels current task, adds to trace, resumes agent)
     SendUserInput{
         input: String
     },
+    /// Send every event emitted from now on to this handler too
+    AddEventSink {
+        sink: EventSink
+    },
+    /// Confine the file system tools to a directory, None lifts the restriction
+    SetSandboxRoot {
+        root: Option<PathBuf>
+    },
+    /// Change how llm calls failing for a transient reason are retried
+    SetRetryPolicy {
+        policy: RetryPolicy
+    },
+    /// Change the steps and time a task may take before the agent pauses it
+    SetTaskBudget {
+        budget: TaskBudget
+    },
+    /// Change how many steps in a row without tool calls pause the agent, None never pauses
+    SetIdleStepLimit {
+        limit: Option<u32>
+    },
+    /// Plan with the read-only tools or execute with all of them, from the next step
+    SetMode {
+        mode: AgentMode
+    },
+    /// Change how many brain failures in a row make the agent refuse input, None never refuses
+    SetCircuitBreaker {
+        breaker: Option<CircuitBreaker>
+    },
+    /// Accept input again after the circuit breaker opened
+    ResetCircuitBreaker,
+    /// Brain failures in a row and whether the circuit breaker is open
+    GetBrainFailures,
     /// Switch method for tool call
     SwitchToolCallMethod {
         method: Option<ToolCallMethod>
     },
+    /// Use another model of the provider, refused while the agent is working
+    SwitchModel {
+        model: String
+    },
     /// Send user input (cancels current task, adds to trace, resumes agent)
     UserQueryResponse{
         request_id: String,
@@ -34,12 +75,51 @@ pub enum AgentRequest {
     },
     /// Wait until the agent reaches the Paused state
     WaitTurn,
+    /// Summarize the conversation to free up context
+    CompressContext,
+    /// Export the full conversation, including tool calls and results
+    ExportTrace {
+        format: TraceFormat
+    },
+    /// Set (or clear with None) instructions appended after the brain's system prompt
+    SetSystemPromptSuffix {
+        text: Option<String>
+    },
+    /// Reset the conversation, refused while the agent is working
+    ClearTrace,
+    /// Keep the latest user message verbatim across context compressions
+    PinLastMessage,
+    /// Get a copy of the current conversation
+    GetTrace,
+    /// Remove a message of the trace, only while the agent is paused
+    RemoveMessage {
+        index: usize
+    },
+    /// Replace the content of a message of the trace, only while the agent is paused
+    EditMessage {
+        index: usize,
+        content: String
+    },
+    /// Remove the last user message and what came after it, only while the agent is paused
+    RewindLastTurn,
+    /// Last error that paused the agent
+    GetLastError,
+    /// Tokens spent since the agent started
+    GetSessionUsage,
+    /// Replace the conversation with a previously exported one
+    LoadTrace {
+        messages: Vec<ChatMessage>
+    },
     /// Manage sudo mode: Some(true) = enable, Some(false) = disable, None = get status
     /// Always returns current sudo status after operation
     Sudo(Option<bool>),
     /// Drop controller IO, this closes it for all controller.
     /// Once this is done, it cannot be reopen!
     Droping,
+    /// Cancel the running task, wait up to `grace` for it to stop and complete the agent
+    Shutdown {
+        grace: Duration
+    },
 }
 
 /// Commands that can be sent to a running agent
@@ -55,6 +135,25 @@ pub enum AgentResponse {
     SudoStatus {
         enabled: bool
     },
+    Trace {
+        content: String
+    },
+    Messages {
+        messages: Vec<ChatMessage>
+    },
+    Model {
+        model: BrainModel
+    },
+    LastError {
+        report: Option<ErrorReport>
+    },
+    SessionUsage {
+        usage: SessionUsage
+    },
+    BrainFailures {
+        failures: u32,
+        circuit_open: bool
+    },
     Error {
         error: String
     }
@@ -96,6 +195,21 @@ impl AgentController {
         Ok(())
     }
 
+    /// Stop the agent before exiting: the running brain, tools or compression task is cancelled
+    /// and aborted if it does not stop within `grace`, then the agent completes. An agent that
+    /// already stopped is not an error.
+    pub async fn shutdown(&self, grace: Duration) -> Result<(), AgentError> {
+        let (tx, rx) = oneshot::channel();
+        if self.txcmd.send(SentCommand { command: AgentRequest::Shutdown { grace }, backchannel: tx }).is_err() {
+            return Ok(());
+        }
+        // the usual command timeout would be shorter than the grace period
+        match timeout(grace + Duration::from_millis(1000), rx).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(AgentError::TimeoutError),
+        }
+    }
+
     pub async fn cancel(&self) -> Result<(), AgentError> {
         self.send(AgentRequest::Cancel).await.map(|_| Ok(()))?
     }
@@ -104,6 +218,72 @@ impl AgentController {
         self.send(AgentRequest::StopCurrentTask).await.map(|_| Ok(()))?
     }
 
+    /// Kill the tools in flight (e.g. a hung command) without stopping the task
+    pub async fn cancel_current_tool(&self) -> Result<(), AgentError> {
+        match self.send(AgentRequest::CancelCurrentTool).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Fan out every event emitted from now on to `sink`, in addition to the watch channels.
+    /// A `JsonlEventSink` records the session so it can be replayed with `replay_event_log`
+    pub async fn add_event_sink(&self, sink: impl AgentEventHandler + 'static) -> Result<(), AgentError> {
+        self.send(AgentRequest::AddEventSink { sink: EventSink(Arc::new(sink)) }).await.map(|_| Ok(()))?
+    }
+
+    /// Reject the tool calls whose paths resolve outside of `root` (None allows any path again).
+    /// Calls already running are not affected.
+    pub async fn set_sandbox_root(&self, root: Option<PathBuf>) -> Result<(), AgentError> {
+        match self.send(AgentRequest::SetSandboxRoot { root }).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Tune the retries of transient llm failures (rate limits, overloaded servers),
+    /// each retry is announced with a RetryingRequest event. Applies from the next step.
+    pub async fn set_retry_policy(&self, policy: RetryPolicy) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetRetryPolicy { policy }).await.map(|_| Ok(()))?
+    }
+
+    /// Cap the brain steps and the wall-clock time of a task, a task going past the budget is
+    /// paused with a BudgetExceeded event. Applies from the next step.
+    pub async fn set_task_budget(&self, budget: TaskBudget) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetTaskBudget { budget }).await.map(|_| Ok(()))?
+    }
+
+    /// Pause the agent, with an IdleLoopDetected event, once the brain asked to continue `limit`
+    /// steps in a row without calling any tool. None lets it go on. Applies from the next step.
+    pub async fn set_idle_step_limit(&self, limit: Option<u32>) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetIdleStepLimit { limit }).await.map(|_| Ok(()))?
+    }
+
+    /// Switch between plan mode, where the brain only gets the tools that do not write, and
+    /// execute mode. Announced with a ModeChanged event, applies from the next step.
+    pub async fn set_mode(&self, mode: AgentMode) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetMode { mode }).await.map(|_| Ok(()))?
+    }
+
+    /// Refuse new input, with a CircuitOpened event, once the brain failed `max_failures` times
+    /// in a row within `window`. None lets the user retry forever.
+    pub async fn set_circuit_breaker(&self, breaker: Option<CircuitBreaker>) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetCircuitBreaker { breaker }).await.map(|_| Ok(()))?
+    }
+
+    /// Accept input again once the provider is fixed, the failure count starts over
+    pub async fn reset_circuit_breaker(&self) -> Result<(), AgentError> {
+        self.send(AgentRequest::ResetCircuitBreaker).await.map(|_| Ok(()))?
+    }
+
+    /// Brain steps failed in a row, and whether input is refused because of them
+    pub async fn brain_failures(&self) -> Result<(u32, bool), AgentError> {
+        match self.send(AgentRequest::GetBrainFailures).await? {
+            AgentResponse::BrainFailures { failures, circuit_open } => Ok((failures, circuit_open)),
+            _ => Err(AgentError::InvalidResponse("Expected BrainFailures response".to_string()))
+        }
+    }
+
     pub async fn set_method(&self, method:Option<ToolCallMethod>) -> Result<ToolCallMethod, AgentError> {
         match self.send(AgentRequest::SwitchToolCallMethod { method }).await? {
             AgentResponse::Method{method} => Ok(method),
@@ -111,8 +291,30 @@ impl AgentController {
         }
     }
 
+    /// Switch the brain to another model, checked against the models listed by the provider.
+    /// Listing them is a network call, thus the longer timeout
+    pub async fn switch_model(&self, model: String) -> Result<BrainModel, AgentError> {
+        let (tx, rx) = oneshot::channel();
+        self.txcmd.send(SentCommand{command: AgentRequest::SwitchModel { model }, backchannel: tx})
+            .map_err(|_| AgentError::SessionClosed)?;
+
+        let response = timeout(Duration::from_secs(15), rx).await
+            .map_err(|_| AgentError::TimeoutError)?
+            .map_err(|_| AgentError::ExecutionError("Command response channel closed".to_string()))?;
+
+        match response {
+            AgentResponse::Model { model } => Ok(model),
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Err(AgentError::InvalidResponse("Expected Model response".to_string()))
+        }
+    }
+
+    /// Refused while the circuit breaker is open
     pub async fn send_user_input(&self, input: String) -> Result<(), AgentError> {
-        self.send(AgentRequest::SendUserInput { input: input }).await.map(|_| Ok(()))?
+        match self.send(AgentRequest::SendUserInput { input: input }).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
     }
 
     pub async fn response_user_query(&self,  request_id: String, response: UserResponse) -> Result<(), AgentError> {
@@ -130,6 +332,132 @@ impl AgentController {
         }
     }
 
+    /// Ask the agent to compress its context, progress is reported through Compression* events
+    pub async fn compress_context(&self) -> Result<(), AgentError> {
+        self.send(AgentRequest::CompressContext).await.map(|_| Ok(()))?
+    }
+
+    /// Export the whole conversation as Markdown or as the raw Vec<ChatMessage> in JSON
+    pub async fn export_trace(&self, format: TraceFormat) -> Result<String, AgentError> {
+        match self.send(AgentRequest::ExportTrace { format }).await? {
+            AgentResponse::Trace { content } => Ok(content),
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Err(AgentError::InvalidResponse("Expected Trace response".to_string()))
+        }
+    }
+
+    /// Append project specific instructions (e.g. the content of an AGENTS.md) to the system prompt,
+    /// None removes them. They are sent as a separate system message right after the brain's own
+    /// prompt and, like it, survive context compression.
+    pub async fn set_system_prompt_suffix(&self, text: Option<String>) -> Result<(), AgentError> {
+        self.send(AgentRequest::SetSystemPromptSuffix { text }).await.map(|_| Ok(()))?
+    }
+
+    /// Start a new conversation with the same agent, the system prompt suffix is kept
+    pub async fn clear_trace(&self) -> Result<(), AgentError> {
+        match self.send(AgentRequest::ClearTrace).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Pin the latest user message (e.g. a key instruction) so it is never folded into a summary
+    pub async fn pin_last_message(&self) -> Result<(), AgentError> {
+        match self.send(AgentRequest::PinLastMessage).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Drop a message from the context sent to the brain (e.g. a bloated tool output), indexes
+    /// are those of `get_trace`. A tool result is emptied rather than removed, and removing an
+    /// assistant message also removes the results of its tool calls. Refused unless paused.
+    pub async fn remove_message(&self, index: usize) -> Result<(), AgentError> {
+        match self.send(AgentRequest::RemoveMessage { index }).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Rewrite the content of a message of the context, tool calls are kept. Refused unless paused.
+    pub async fn edit_message(&self, index: usize, content: String) -> Result<(), AgentError> {
+        match self.send(AgentRequest::EditMessage { index, content }).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// Take the last turn back, from the last user message to the end of the context, before
+    /// sending the same prompt again. Refused unless paused.
+    pub async fn rewind_last_turn(&self) -> Result<(), AgentError> {
+        match self.send(AgentRequest::RewindLastTurn).await? {
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Ok(())
+        }
+    }
+
+    /// The last error that paused the agent, if any
+    pub async fn last_error(&self) -> Result<Option<ErrorReport>, AgentError> {
+        match self.send(AgentRequest::GetLastError).await? {
+            AgentResponse::LastError { report } => Ok(report),
+            _ => Err(AgentError::InvalidResponse("Expected LastError response".to_string()))
+        }
+    }
+
+    /// Tokens spent since the agent started, in total and per model. Also sent after each turn
+    /// with a SessionUsage event.
+    pub async fn session_usage(&self) -> Result<SessionUsage, AgentError> {
+        match self.send(AgentRequest::GetSessionUsage).await? {
+            AgentResponse::SessionUsage { usage } => Ok(usage),
+            _ => Err(AgentError::InvalidResponse("Expected SessionUsage response".to_string()))
+        }
+    }
+
+    /// Get a copy of the current conversation, as sent to the brain on the next step
+    pub async fn get_trace(&self) -> Result<Vec<ChatMessage>, AgentError> {
+        match self.send(AgentRequest::GetTrace).await? {
+            AgentResponse::Messages { messages } => Ok(messages),
+            _ => Err(AgentError::InvalidResponse("Expected Messages response".to_string()))
+        }
+    }
+
+    /// Headless turn: send the prompt, wait for the agent to pause and return its answer.
+    /// Fails if the agent stops (completes, fails or is cancelled) instead of pausing, or with
+    /// the brain error if the turn ended without an answer.
+    pub async fn run_once(&self, prompt: String) -> Result<String, AgentError> {
+        let sent_at = Utc::now();
+        self.send_user_input(prompt.clone()).await?;
+        self.wait_turn(None).await?;
+
+        // answers before the prompt belong to previous turns
+        let trace = self.get_trace().await?;
+        let start = trace.iter()
+            .rposition(|message| matches!(message, ChatMessage::User { content: ChatMessageContent::Text(text), .. } if *text == prompt))
+            .map_or(trace.len(), |index| index + 1);
+        let answer = trace[start..].iter().rev()
+            .find_map(|message| match message {
+                ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } if !text.is_empty() => Some(text.clone()),
+                _ => None,
+            });
+        if let Some(answer) = answer {
+            return Ok(answer);
+        }
+
+        match self.last_error().await? {
+            Some(report) if report.timestamp >= sent_at => Err(report.error),
+            _ => Err(AgentError::InvalidResponse("the agent paused without answering".to_string())),
+        }
+    }
+
+    /// Resume a saved conversation, the agent pauses and waits for the next user input
+    pub async fn load_trace(&self, messages: Vec<ChatMessage>) -> Result<(), AgentError> {
+        match self.send(AgentRequest::LoadTrace { messages }).await? {
+            AgentResponse::Ack => Ok(()),
+            AgentResponse::Error { error } => Err(AgentError::ExecutionError(error)),
+            _ => Err(AgentError::InvalidResponse("Expected Ack response".to_string()))
+        }
+    }
+
     /// Wait until the agent reaches the Paused state
     pub async fn wait_turn(&self, timeout_ms: Option<u64>) -> Result<(), AgentError> {
         let (tx, rx) = oneshot::channel();
@@ -1,4 +1,5 @@
-use crate::agent::{AgentCore, InternalAgentEvent, AgentError};
+use chrono::Utc;
+use crate::agent::{AgentCore, AgentEvent, InternalAgentEvent, AgentError};
 use tracing::error;
 
 impl AgentCore {
@@ -8,6 +9,22 @@ impl AgentCore {
                 // Silently ignore
                 Ok(())
             }
+            InternalAgentEvent::ManualCompressionRequested => {
+                self.check_and_compress_context_manual(false).await
+            }
+            InternalAgentEvent::BrainDelta { .. } => {
+                // late chunk of an answer that was cancelled
+                Ok(())
+            }
+            InternalAgentEvent::BrainCancelled { partial, .. } => {
+                // the user stopped the answer midway, keep what was generated so far
+                self.append_message(partial.clone()).await;
+                let _ = self.emit_event(AgentEvent::BrainResult {
+                    timestamp: Utc::now(),
+                    thought: Ok(partial)
+                }).await;
+                Ok(())
+            }
             _ => {
                 // Paused state: All other events are illegal until user send something
                 // ignore all events but log error
@@ -1,7 +1,8 @@
 use crate::agent::{
-    AgentCore, AgentError, InternalAgentEvent
+    AgentCore, AgentError, AgentEvent, InternalAgentEvent
 };
 use super::InternalAgentState;
+use tracing::debug;
 
 impl AgentCore {
     pub async fn state_processing_handle_event(&mut self, event: InternalAgentEvent) -> Result<(), AgentError> {
@@ -9,8 +10,17 @@ impl AgentCore {
             InternalAgentEvent::CancelTask => {
                 self.cancel_task().await
             },
-            InternalAgentEvent::BrainResult { result } => {
-                self.process_next_step(result).await
+            InternalAgentEvent::CancelCurrentTool => {
+                self.cancel_current_tool().await
+            },
+            InternalAgentEvent::BrainResult { result, .. } => {
+                let result = self.process_next_step(result).await;
+                self.run_pending_compression().await?;
+                result
+            },
+            InternalAgentEvent::BrainDelta { text, .. } => {
+                let _ = self.emit_event(AgentEvent::BrainDelta { text }).await;
+                Ok(())
             },
             InternalAgentEvent::ToolsCompleted { any_denied } => {
                 if any_denied {
@@ -18,6 +28,20 @@ impl AgentCore {
                 } else {
                     self.set_state(InternalAgentState::Running).await;
                 }
+                self.run_pending_compression().await
+            },
+            InternalAgentEvent::CompressionCompleted { resume } => {
+                if resume {
+                    self.set_state(InternalAgentState::Running).await;
+                } else {
+                    self.set_state(InternalAgentState::Paused).await;
+                }
+                Ok(())
+            },
+            InternalAgentEvent::ManualCompressionRequested => {
+                // cannot touch the trace while the brain or tools are working, compress once they are done
+                debug!(target: "agent::compression", "compression queued until the current task completes");
+                self.pending_compression = true;
                 Ok(())
             },
             _ => {
@@ -26,6 +50,29 @@ impl AgentCore {
         }
     }
 
+    /// run a compression queued while processing, once the agent is out of Processing
+    async fn run_pending_compression(&mut self) -> Result<(), AgentError> {
+        if !self.pending_compression || matches!(self.state, InternalAgentState::Processing { .. }) {
+            return Ok(());
+        }
+        self.pending_compression = false;
+        let resume = matches!(self.state, InternalAgentState::Running);
+        self.check_and_compress_context_manual(resume).await
+    }
+
+    /// cancel the running tools only, the task goes on once their (cancelled) results are in the trace
+    async fn cancel_current_tool(&mut self) -> Result<(), AgentError> {
+        let (InternalAgentState::Processing { task_name, .. }, Some(token)) = (&self.state, &self.tools_cancellation) else {
+            return Err(AgentError::InvalidState("no tool is running".to_string()));
+        };
+        if task_name != "tools" {
+            return Err(AgentError::InvalidState("no tool is running".to_string()));
+        }
+
+        token.cancel();
+        Ok(())
+    }
+
     /// cancel all pending tasks
     async fn cancel_task(&mut self) -> Result<(), AgentError> {
         let InternalAgentState::Processing { cancellation_token, .. } = &self.state else {
@@ -15,6 +15,13 @@ impl AgentCore {
             InternalAgentEvent::ThinkingStart => {
                 self.spawn_next_step().await;
             }
+            InternalAgentEvent::ManualCompressionRequested => {
+                // the agent was in the middle of a task, it resumes thinking once compressed
+                self.check_and_compress_context_manual(true).await?;
+            }
+            InternalAgentEvent::BrainDelta { .. } | InternalAgentEvent::BrainCancelled { .. } => {
+                // cancelled because the user sent a new message, the partial answer is dropped
+            }
             _ => {
                 // Running state: Most other events should be handled by main loop or are illegal
                 // ignore all events but log error
@@ -1,5 +1,6 @@
 use tokio_util::sync::CancellationToken;
 use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
 
 /// Internal agent status (contains channels and sync primitives)
 #[derive(Debug)]
@@ -24,7 +25,7 @@ pub enum InternalAgentState {
 
 
 /// Public agent status (clean version without internal channels/sync primitives)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PublicAgentState {
     /// Agent is starting up
     Starting,
@@ -64,4 +65,25 @@ impl InternalAgentState {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+impl PublicAgentState {
+    /// True while the agent is busy, i.e. until it pauses or reaches a terminal state
+    pub fn is_working(&self) -> bool {
+        matches!(self, PublicAgentState::Starting | PublicAgentState::Running | PublicAgentState::Processing { .. })
+    }
+}
+
+impl std::fmt::Display for PublicAgentState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublicAgentState::Starting => write!(f, "Starting"),
+            PublicAgentState::Running => write!(f, "Running"),
+            PublicAgentState::Processing { task_name, .. } => write!(f, "Processing: {}", task_name),
+            PublicAgentState::Paused => write!(f, "Paused"),
+            PublicAgentState::Completed { success } => write!(f, "Completed (success={})", success),
+            PublicAgentState::Cancelled => write!(f, "Cancelled"),
+            PublicAgentState::Failed { error } => write!(f, "Failed: {}", error),
+        }
+    }
+}
@@ -52,10 +52,6 @@ impl SleepingTool {
     }
 }
 
-struct MockLlm {
-
-}
-
 // Test thinker that calls the sleeping tool once then completes
 struct SleepingThinker {
     called_tool: bool,
@@ -461,3 +457,1792 @@ async fn test_agent_with_real_tools() {
         }
     }
 }
+
+// Test thinker with a context compressor (without llm, summaries fall back to a notice)
+// that calls the sleeping tool once then completes
+struct CompressingThinker {
+    compressor: crate::runners::compacter::ContextCompressor,
+    called_tool: bool,
+}
+
+impl CompressingThinker {
+    fn new(max_tokens: u32) -> Self {
+        Self { compressor: crate::runners::compacter::ContextCompressor::new(max_tokens), called_tool: false }
+    }
+}
+
+#[async_trait]
+impl Brain for CompressingThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        if !self.called_tool {
+            self.called_tool = true;
+            return Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+                content: None,
+                reasoning_content: None,
+                tool_calls: Some(vec![shai_llm::ToolCall {
+                    id: "call_1".to_string(),
+                    r#type: "function".to_string(),
+                    function: shai_llm::Function {
+                        name: "sleeping_tool".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                }]),
+                name: None,
+                audio: None,
+                refusal: None,
+            }));
+        }
+        Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("nothing to do".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+
+    fn context_compressor(&mut self) -> Option<&mut crate::runners::compacter::ContextCompressor> {
+        Some(&mut self.compressor)
+    }
+}
+
+fn long_history(turns: usize) -> Vec<ChatMessage> {
+    (0..turns).flat_map(|i| vec![
+        ChatMessage::User {
+            content: ChatMessageContent::Text(format!("question number {}", i)),
+            name: None,
+        },
+        ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(format!("answer number {}", i))),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        },
+    ]).collect()
+}
+
+#[tokio::test]
+async fn test_manual_compression_with_custom_brain() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(CompressingThinker::new(100)))
+        .id("test-compression-agent")
+        .with_traces(long_history(10))
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    controller.compress_context().await.expect("failed to request compression");
+
+    let info = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(super::AgentEvent::CompressionFinished { outcome }) = events.recv().await {
+                break outcome.into_info();
+            }
+        }
+    }).await.expect("compression did not finish");
+    let info = info.expect("conversation should have been compressed");
+    assert!(info.messages_summarized > 0);
+
+    controller.drop().await.expect("failed to drop the controller");
+    let result = handle.await.unwrap().expect("agent should complete");
+    assert!(result.trace.len() < 20, "trace should have been compressed");
+    assert!(result.trace.iter().any(|m| matches!(m, ChatMessage::System { name: Some(name), .. } if name == "summary")));
+}
+
+#[tokio::test]
+async fn test_compression_requested_while_running() {
+    init_test_logging();
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(500));
+    let mut agent = AgentBuilder::new(Box::new(CompressingThinker::new(100)))
+        .id("test-compression-running-agent")
+        .with_traces(long_history(10))
+        .goal("keep working")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    // request compression while the sleeping tool is running
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(matches!(controller.get_state().await.unwrap(), PublicAgentState::Processing { .. }));
+    controller.compress_context().await.expect("failed to request compression");
+
+    let info = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(super::AgentEvent::CompressionFinished { outcome }) = events.recv().await {
+                break outcome.into_info();
+            }
+        }
+    }).await.expect("queued compression never ran");
+    assert!(info.is_some());
+
+    // the agent resumes the task after compression and completes it
+    controller.wait_turn(Some(5000)).await.expect("agent should finish its task");
+    controller.drop().await.expect("failed to drop the controller");
+    let result = handle.await.unwrap().expect("agent should complete");
+    assert!(matches!(result.trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "nothing to do"));
+}
+
+// Test thinker that streams its answer in several chunks
+struct StreamingThinker;
+
+#[async_trait]
+impl Brain for StreamingThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let chunks = ["Hello", ", ", "world"];
+        for chunk in chunks {
+            context.send_delta(chunk);
+        }
+        Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(chunks.concat())),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_streamed_deltas_come_before_result() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(StreamingThinker))
+        .id("test-streaming-agent")
+        .goal("say hello")
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let (streamed, thought) = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut streamed = String::new();
+        loop {
+            match events.recv().await {
+                Ok(super::AgentEvent::BrainDelta { text }) => streamed.push_str(&text),
+                Ok(super::AgentEvent::BrainResult { thought, .. }) => break (streamed, thought),
+                _ => {}
+            }
+        }
+    }).await.expect("brain result never came");
+
+    assert_eq!(streamed, "Hello, world");
+    assert!(matches!(thought, Ok(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == streamed));
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+// Test thinker that streams a first paragraph and then takes forever
+struct RamblingThinker;
+
+#[async_trait]
+impl Brain for RamblingThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        context.send_delta("Useful first paragraph.");
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Err(AgentError::ExecutionError("should have been cancelled".to_string()))
+    }
+}
+
+#[tokio::test]
+async fn test_cancelled_brain_keeps_partial_answer() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(RamblingThinker))
+        .id("test-partial-agent")
+        .goal("ramble")
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    // stop the brain once it started streaming
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !matches!(events.recv().await, Ok(super::AgentEvent::BrainDelta { .. })) {}
+    }).await.expect("brain never streamed");
+    controller.test_stop_current_task().await.expect("failed to stop the task");
+
+    let thought = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(super::AgentEvent::BrainResult { thought, .. }) = events.recv().await {
+                break thought;
+            }
+        }
+    }).await.expect("partial answer was not reported");
+    assert!(matches!(thought, Ok(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "Useful first paragraph."));
+
+    controller.drop().await.expect("failed to drop the controller");
+    let result = handle.await.unwrap().expect("agent should complete");
+    assert!(matches!(result.trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "Useful first paragraph."));
+}
+
+// Test thinker that takes a little while before pausing
+struct SlowThinker;
+
+#[async_trait]
+impl Brain for SlowThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("done".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_back_to_back_next_step_sends_one_result() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(SlowThinker))
+        .id("test-double-spawn-agent")
+        .build();
+    let mut internal_rx = agent.internal_tx.subscribe();
+
+    agent.spawn_next_step().await;
+    agent.spawn_next_step().await;
+
+    let mut results = 0;
+    let _ = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(super::InternalAgentEvent::BrainResult { .. }) = internal_rx.recv().await {
+                results += 1;
+            }
+        }
+    }).await;
+    assert_eq!(results, 1);
+}
+
+// Test thinker numbering its answers
+struct NumberedThinker {
+    calls: u32,
+}
+
+#[async_trait]
+impl Brain for NumberedThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        self.calls += 1;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(format!("answer {}", self.calls))),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_only_the_latest_next_step_is_applied() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(NumberedThinker { calls: 0 }))
+        .id("test-replaced-step-agent")
+        .build();
+    let mut internal_rx = agent.internal_tx.subscribe();
+
+    agent.spawn_next_step().await;
+    let replaced = agent.brain_step;
+    agent.spawn_next_step().await;
+
+    // a result the replaced task sent before being stopped
+    agent.handle_event(super::InternalAgentEvent::BrainResult {
+        step: replaced,
+        result: Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("stale".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        })),
+    }).await.unwrap();
+    assert!(matches!(agent.state.to_public(), PublicAgentState::Processing { .. }), "the stale result must not end the step");
+
+    let event = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(event @ super::InternalAgentEvent::BrainResult { .. }) = internal_rx.recv().await {
+                return event;
+            }
+        }
+    }).await.expect("the latest step should answer");
+    assert!(matches!(&event, super::InternalAgentEvent::BrainResult { step, .. } if *step == agent.brain_step));
+    agent.handle_event(event).await.unwrap();
+
+    let answers: Vec<_> = agent.trace.read().await.iter()
+        .filter_map(|m| match m {
+            ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => Some(text.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(answers.len(), 1);
+    assert_ne!(answers[0], "stale");
+}
+
+// Test thinker that reports having fallen back to structured output
+struct FallbackThinker;
+
+#[async_trait]
+impl Brain for FallbackThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let message = ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("done".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            refusal: None,
+            name: None,
+            audio: None,
+        };
+        Ok(ThinkerDecision::agent_pause(message).with_method(shai_llm::ToolCallMethod::StructuredOutput))
+    }
+}
+
+#[tokio::test]
+async fn test_fallback_method_is_remembered() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(FallbackThinker))
+        .id("test-fallback-agent")
+        .goal("do something")
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let method = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(super::AgentEvent::ToolCallMethodChanged { method }) = events.recv().await {
+                break method;
+            }
+        }
+    }).await.expect("method change never came");
+    assert_eq!(method, shai_llm::ToolCallMethod::StructuredOutput);
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    let current = controller.set_method(None).await.expect("failed to query the method");
+    assert_eq!(current, shai_llm::ToolCallMethod::StructuredOutput);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_load_trace_replaces_conversation() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(FallbackThinker))
+        .id("test-resume-agent")
+        .goal("do something")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+
+    let saved = vec![
+        ChatMessage::System { content: ChatMessageContent::Text("old system prompt".to_string()), name: None },
+        ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None },
+        ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("hi".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            refusal: None,
+            name: None,
+            audio: None,
+        },
+    ];
+    controller.load_trace(saved).await.expect("failed to load the trace");
+
+    let json = controller.export_trace(super::TraceFormat::Json).await.expect("failed to export the trace");
+    let trace: Vec<ChatMessage> = serde_json::from_str(&json).unwrap();
+    assert_eq!(trace.len(), 2, "the saved system prompt should be replaced by the brain's own");
+    assert!(matches!(&trace[0], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "hello"));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct ChattyParams {}
+
+// Test tool that streams its output in two chunks
+struct ChattyTool;
+
+#[tool(name = "chatty_tool", description = "A tool that prints two lines")]
+impl ChattyTool {
+    async fn execute(&self, params: ChattyParams) -> ToolResult {
+        let sink = crate::tools::ToolOutputSink::current().expect("the agent should install an output sink");
+        sink.send("line 1\n");
+        sink.send("line 2\n");
+        ToolResult::success("line 1\nline 2\n".to_string())
+    }
+}
+
+// Test thinker that calls the chatty tool once then pauses
+struct ChattyThinker {
+    called_tool: bool,
+}
+
+#[async_trait]
+impl Brain for ChattyThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let tool_calls = (!self.called_tool).then(|| vec![shai_llm::ToolCall {
+            id: "call_1".to_string(),
+            r#type: "function".to_string(),
+            function: shai_llm::Function {
+                name: "chatty_tool".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }]);
+        self.called_tool = true;
+        let message = ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: tool_calls.clone(),
+            name: None,
+            audio: None,
+            refusal: None,
+        };
+        Ok(match tool_calls {
+            Some(_) => ThinkerDecision::agent_continue(message),
+            None => ThinkerDecision::agent_pause(message),
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_tool_output_is_streamed_in_chunks() {
+    init_test_logging();
+
+    let tools: Vec<Box<dyn AnyTool>> = vec![Box::new(ChattyTool)];
+    let mut agent = AgentBuilder::new(Box::new(ChattyThinker { called_tool: false }))
+        .id("test-chunk-agent")
+        .goal("print something")
+        .tools(tools)
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let chunks = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut chunks = Vec::new();
+        loop {
+            match events.recv().await {
+                Ok(super::AgentEvent::ToolOutputChunk { tool_call_id, chunk }) => {
+                    assert_eq!(tool_call_id, "call_1");
+                    chunks.push(chunk);
+                }
+                Ok(super::AgentEvent::ToolCallCompleted { .. }) => break chunks,
+                _ => {}
+            }
+        }
+    }).await.expect("tool never completed");
+
+    assert_eq!(chunks, vec!["line 1\n".to_string(), "line 2\n".to_string()]);
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+// Test thinker that answers by echoing the last user message
+struct EchoThinker;
+
+#[async_trait]
+impl Brain for EchoThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let last_user = context.trace.read().await.iter().rev().find_map(|m| match m {
+            ChatMessage::User { content: ChatMessageContent::Text(text), .. } => Some(text.clone()),
+            _ => None,
+        }).unwrap_or_default();
+        Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text(format!("echo: {}", last_user))),
+            reasoning_content: None,
+            tool_calls: None,
+            refusal: None,
+            name: None,
+            audio: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_run_once_returns_the_answer() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-run-once-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    let answer = controller.run_once("ping".to_string()).await.expect("run_once failed");
+    assert_eq!(answer, "echo: ping");
+    let answer = controller.run_once("pong".to_string()).await.expect("run_once failed");
+    assert_eq!(answer, "echo: pong");
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_run_once_reports_the_brain_error() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    let mock = MockProvider::new();
+    mock.push_text("first answer", None);
+    mock.push_error("401 invalid api key");
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+    let mut agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+        .id("test-run-once-error-agent")
+        .build();
+
+    let controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    let answer = controller.run_once("first".to_string()).await.expect("run_once failed");
+    assert_eq!(answer, "first answer");
+
+    // the answer of the previous turn is not taken for this one
+    let error = controller.run_once("second".to_string()).await.expect_err("the brain failed");
+    assert!(matches!(error, AgentError::LlmError(message) if message.contains("invalid api key")));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_system_prompt_suffix_is_replaced() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-suffix-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    controller.set_system_prompt_suffix(Some("first".to_string())).await.expect("failed to set the suffix");
+    controller.run_once("hello".to_string()).await.expect("run_once failed");
+    controller.set_system_prompt_suffix(Some("second".to_string())).await.expect("failed to set the suffix");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    let suffixes: Vec<_> = trace.iter().filter(|m| super::agent::is_system_prompt_suffix(m)).collect();
+    assert_eq!(suffixes.len(), 1);
+    assert!(matches!(&trace[0], ChatMessage::System { content: ChatMessageContent::Text(text), .. } if text == "second"));
+
+    controller.set_system_prompt_suffix(None).await.expect("failed to clear the suffix");
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert!(!trace.iter().any(super::agent::is_system_prompt_suffix));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_pin_last_message() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-pin-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    assert!(controller.pin_last_message().await.is_err(), "nothing to pin yet");
+
+    controller.run_once("always answer in french".to_string()).await.expect("run_once failed");
+    controller.run_once("hello".to_string()).await.expect("run_once failed");
+    controller.pin_last_message().await.expect("failed to pin");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    let pinned: Vec<_> = trace.iter().filter(|m| crate::runners::compacter::is_pinned(m)).collect();
+    assert_eq!(pinned.len(), 1);
+    assert!(matches!(pinned[0], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "hello"));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_pin_after_rewind_pins_the_same_message_in_both_traces() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-pin-rewind-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    controller.run_once("always answer in french".to_string()).await.expect("run_once failed");
+    controller.run_once("hello".to_string()).await.expect("run_once failed");
+    // the full trace still has "hello", the trace does not anymore
+    controller.rewind_last_turn().await.expect("failed to rewind");
+    controller.pin_last_message().await.expect("failed to pin");
+
+    let exported = controller.export_trace(super::TraceFormat::Json).await.unwrap();
+    let full_trace: Vec<ChatMessage> = serde_json::from_str(&exported).unwrap();
+    let pinned: Vec<_> = full_trace.iter().filter(|m| crate::runners::compacter::is_pinned(m)).collect();
+    assert_eq!(pinned.len(), 1);
+    assert!(matches!(pinned[0], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "always answer in french"));
+
+    // the traces agree, the next turn goes through
+    controller.run_once("bonjour".to_string()).await.expect("run_once failed");
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_clear_trace_keeps_the_suffix() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-clear-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    controller.set_system_prompt_suffix(Some("use tabs".to_string())).await.expect("failed to set the suffix");
+    controller.run_once("hello".to_string()).await.expect("run_once failed");
+    controller.clear_trace().await.expect("failed to clear the conversation");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert_eq!(trace.len(), 1);
+    assert!(super::agent::is_system_prompt_suffix(&trace[0]));
+
+    let cleared = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(super::AgentEvent::ConversationCleared) = events.recv().await {
+                break;
+            }
+        }
+    }).await;
+    assert!(cleared.is_ok(), "ConversationCleared was not emitted");
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+/// Sends malformed tool arguments until it sees the parse error in its context, or forever if stubborn
+struct BadArgsThinker {
+    arguments: &'static str,
+    stubborn: bool,
+}
+
+#[async_trait]
+impl Brain for BadArgsThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let corrected = context.trace.read().await.iter().any(|m| matches!(m, ChatMessage::Tool { content, .. } if content.starts_with("Invalid arguments")));
+        if corrected && !self.stubborn {
+            return Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("fixed".to_string())),
+                reasoning_content: None,
+                tool_calls: None,
+                name: None,
+                audio: None,
+                refusal: None,
+            }));
+        }
+        Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: Some(vec![shai_llm::ToolCall {
+                id: "call_1".to_string(),
+                r#type: "function".to_string(),
+                function: shai_llm::Function {
+                    name: "ls".to_string(),
+                    arguments: self.arguments.to_string(),
+                },
+            }]),
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_invalid_tool_arguments_are_retried() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(BadArgsThinker { arguments: "{\"path\": ", stubborn: false }))
+        .id("test-bad-args-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    let answer = controller.run_once("list the files".to_string()).await.expect("the agent should recover");
+    assert_eq!(answer, "fixed");
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_invalid_tool_arguments_retries_are_capped() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(BadArgsThinker { arguments: "{\"path\": ", stubborn: true }))
+        .id("test-stubborn-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    controller.send_user_input("list the files".to_string()).await.expect("failed to send input");
+    controller.wait_turn(Some(5000)).await.expect("agent should give up and pause");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    let errors = trace.iter().filter(|m| matches!(m, ChatMessage::Tool { .. })).count();
+    assert_eq!(errors, 3);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_tool_arguments_not_matching_the_schema_are_retried() {
+    init_test_logging();
+
+    let ls_tool: Box<dyn AnyTool> = Box::new(LsTool::new());
+    let mut agent = AgentBuilder::new(Box::new(BadArgsThinker { arguments: "{\"directory\": 42}", stubborn: false }))
+        .id("test-schema-agent")
+        .tools(vec![ls_tool])
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    let answer = controller.run_once("list the files".to_string()).await.expect("the agent should recover");
+    assert_eq!(answer, "fixed");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert!(trace.iter().any(|m| matches!(m, ChatMessage::Tool { content, .. } if content.contains("arguments.directory should be string"))));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_cancel_current_tool_keeps_the_task_going() {
+    init_test_logging();
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(10000));
+    let mut agent = AgentBuilder::new(Box::new(SleepingThinker::new()))
+        .id("test-cancel-tool-agent")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    assert!(controller.cancel_current_tool().await.is_err(), "nothing is running yet");
+
+    controller.send_user_input("sleep".to_string()).await.expect("failed to send input");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    controller.cancel_current_tool().await.expect("the sleeping tool should be cancelled");
+
+    // the brain gets the cancellation as the tool result and finishes its task
+    controller.wait_turn(Some(2000)).await.expect("agent should finish without the tool");
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert!(trace.iter().any(|m| matches!(m, ChatMessage::Tool { content, .. } if content.contains("cancelled"))));
+    assert!(matches!(trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "we are done"));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[test]
+fn test_large_tool_output_is_truncated() {
+    use super::actions::tools::truncate_tool_output;
+
+    assert_eq!(truncate_tool_output("call_small", "short output", 100), "short output");
+
+    let output = format!("{}{}{}", "a".repeat(1000), "b".repeat(10_000), "c".repeat(1000));
+    let truncated = truncate_tool_output("call_large", &output, 2000);
+    assert!(truncated.starts_with(&"a".repeat(1000)));
+    assert!(truncated.ends_with(&"c".repeat(1000)));
+    assert!(truncated.contains("[... 10000 bytes omitted"));
+    assert!(truncated.len() < 2200);
+
+    let saved = std::env::temp_dir().join("shai-tool-output-call_large.txt");
+    assert_eq!(std::fs::read_to_string(&saved).unwrap(), output);
+    let _ = std::fs::remove_file(saved);
+}
+
+#[tokio::test]
+async fn test_switch_model_is_refused_by_brains_without_llm() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-switch-model-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    let result = controller.switch_model("another-model".to_string()).await;
+    assert!(matches!(result, Err(AgentError::ExecutionError(error)) if error.contains("cannot switch models")));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_tool_events_are_paired_and_timed() {
+    init_test_logging();
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(200));
+    let mut agent = AgentBuilder::new(Box::new(SleepingThinker::new()))
+        .id("test-tool-events-agent")
+        .goal("sleep a bit")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let (started, completed) = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut started = None;
+        loop {
+            match events.recv().await {
+                Ok(super::AgentEvent::ToolCallStarted { call, .. }) => started = Some(call),
+                Ok(super::AgentEvent::ToolCallCompleted { call, duration, result }) => break (started, (call, duration, result)),
+                _ => {}
+            }
+        }
+    }).await.expect("the tool never completed");
+
+    let started = started.expect("the completion should follow a start");
+    let (call, duration, result) = completed;
+    assert_eq!(started.tool_call_id, call.tool_call_id);
+    assert_eq!(call.tool_name, "sleeping_tool");
+    assert!(duration.num_milliseconds() >= 200);
+    assert!(result.is_success());
+
+    controller.wait_turn(Some(2000)).await.expect("agent should finish its task");
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_recorded_events_can_be_replayed() {
+    init_test_logging();
+
+    let path = std::env::temp_dir().join(format!("shai-events-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-event-sink-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    controller.add_event_sink(super::JsonlEventSink::new(&path)).await.expect("failed to add the sink");
+    controller.run_once("ping".to_string()).await.expect("run_once failed");
+    // the sink writes from its own task
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let replayed = Arc::new(Mutex::new(Vec::new()));
+    let collected = replayed.clone();
+    let handler = super::closure_handler(move |event| {
+        let collected = collected.clone();
+        async move { collected.lock().await.push(event); }
+    });
+    let count = super::replay_event_log(&path, &handler, None).await.expect("failed to replay the log");
+    let _ = std::fs::remove_file(&path);
+
+    let replayed = replayed.lock().await;
+    assert_eq!(count, replayed.len());
+    assert!(replayed.iter().any(|e| matches!(e, super::AgentEvent::UserInput { input } if input == "ping")));
+    assert!(replayed.iter().any(|e| matches!(e, super::AgentEvent::BrainResult { thought: Ok(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }), .. } if text == "echo: ping")));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+// Test tool that sleeps for the duration given in its parameters
+struct NapTool;
+
+#[tool(name = "nap_tool", description = "A tool that sleeps for the requested duration")]
+impl NapTool {
+    async fn execute(&self, params: SleepParams) -> ToolResult {
+        tokio::time::sleep(Duration::from_millis(params.duration_ms)).await;
+        ToolResult::success(format!("slept {}ms", params.duration_ms))
+    }
+}
+
+// Test thinker that asks for a long nap then a short one in the same step, then pauses
+struct TwoNapsThinker {
+    called_tool: bool,
+}
+
+#[async_trait]
+impl Brain for TwoNapsThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let nap = |id: &str, duration_ms: u64| shai_llm::ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: shai_llm::Function {
+                name: "nap_tool".to_string(),
+                arguments: format!("{{\"duration_ms\": {}}}", duration_ms),
+            },
+        };
+        let tool_calls = (!self.called_tool).then(|| vec![nap("call_1", 300), nap("call_2", 10)]);
+        self.called_tool = true;
+        let message = ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: tool_calls.clone(),
+            name: None,
+            audio: None,
+            refusal: None,
+        };
+        Ok(match tool_calls {
+            Some(_) => ThinkerDecision::agent_continue(message),
+            None => ThinkerDecision::agent_pause(message),
+        })
+    }
+}
+
+/// Run the two naps with the given concurrency, returns the calls in completion order and in trace order
+async fn run_two_naps(concurrency: super::actions::tools::ToolConcurrency) -> (Vec<String>, Vec<String>) {
+    let tools: Vec<Box<dyn AnyTool>> = vec![Box::new(NapTool)];
+    let mut agent = AgentBuilder::new(Box::new(TwoNapsThinker { called_tool: false }))
+        .id("test-tool-concurrency-agent")
+        .goal("take two naps")
+        .tools(tools)
+        .tool_concurrency(concurrency)
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let completed = tokio::time::timeout(Duration::from_secs(5), async {
+        let mut completed = Vec::new();
+        while completed.len() < 2 {
+            if let Ok(super::AgentEvent::ToolCallCompleted { call, .. }) = events.recv().await {
+                completed.push(call.tool_call_id);
+            }
+        }
+        completed
+    }).await.expect("the tools never completed");
+
+    controller.wait_turn(Some(2000)).await.expect("agent should finish its task");
+    let traced = controller.get_trace().await.expect("failed to get the trace").into_iter()
+        .filter_map(|m| match m {
+            ChatMessage::Tool { tool_call_id, .. } => Some(tool_call_id),
+            _ => None,
+        })
+        .collect();
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+    (completed, traced)
+}
+
+#[tokio::test]
+async fn test_tool_results_keep_the_call_order() {
+    init_test_logging();
+    use super::actions::tools::ToolConcurrency;
+
+    let (completed, traced) = run_two_naps(ToolConcurrency::Concurrent).await;
+    assert_eq!(completed, vec!["call_2", "call_1"]);
+    assert_eq!(traced, vec!["call_1", "call_2"]);
+
+    let (completed, traced) = run_two_naps(ToolConcurrency::Sequential).await;
+    assert_eq!(completed, vec!["call_1", "call_2"]);
+    assert_eq!(traced, vec!["call_1", "call_2"]);
+}
+
+#[tokio::test]
+async fn test_task_over_budget_is_paused() {
+    init_test_logging();
+
+    let tools: Vec<Box<dyn AnyTool>> = vec![Box::new(NapTool)];
+    let mut agent = AgentBuilder::new(Box::new(TwoNapsThinker { called_tool: false }))
+        .id("test-task-budget-agent")
+        .goal("take two naps")
+        .tools(tools)
+        .task_budget(super::TaskBudget { max_steps: Some(1), max_duration: None })
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let steps = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match events.recv().await {
+                Ok(super::AgentEvent::BudgetExceeded { steps, .. }) => break steps,
+                Ok(super::AgentEvent::ToolCallStarted { .. }) => panic!("no tool should run past the budget"),
+                _ => {}
+            }
+        }
+    }).await.expect("the budget was never exceeded");
+    assert_eq!(steps, 1);
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    assert!(matches!(controller.get_state().await.unwrap(), PublicAgentState::Paused));
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_trace_is_well_formed_after_a_budget_pause() {
+    init_test_logging();
+
+    let tools: Vec<Box<dyn AnyTool>> = vec![Box::new(NapTool)];
+    let mut agent = AgentBuilder::new(Box::new(TwoNapsThinker { called_tool: false }))
+        .id("test-task-budget-trace-agent")
+        .goal("take two naps")
+        .tools(tools)
+        .task_budget(super::TaskBudget { max_steps: Some(1), max_duration: None })
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !matches!(events.recv().await, Ok(super::AgentEvent::BudgetExceeded { .. })) {}
+    }).await.expect("the budget was never exceeded");
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    let trace = controller.get_trace().await.unwrap();
+    let calls: Vec<String> = trace.iter()
+        .filter_map(|message| match message {
+            ChatMessage::Assistant { tool_calls: Some(calls), .. } => Some(calls.iter().map(|tc| tc.id.clone()).collect::<Vec<_>>()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    let answered: Vec<String> = trace.iter()
+        .filter_map(|message| match message {
+            ChatMessage::Tool { tool_call_id, content } => {
+                assert_eq!(content, "[not run: task budget exceeded]");
+                Some(tool_call_id.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    assert!(!calls.is_empty());
+    assert_eq!(calls, answered);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_appended_messages_keep_traces_consistent() {
+    let agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-trace-consistency-agent")
+        .goal("hello")
+        .build();
+    let user = |text: &str| ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None };
+
+    agent.append_message(user("first")).await;
+    super::AgentCore::append_to_traces(&agent.trace, &agent.full_trace, vec![user("second"), user("third")]).await;
+    let (trace, full_trace) = (agent.trace.read().await.clone(), agent.full_trace.read().await.clone());
+    assert_eq!(trace.len(), 4);
+    assert_eq!(full_trace.len(), 4);
+    assert!(super::agent::traces_are_consistent(&trace, &full_trace));
+
+    // a compressed trace only holds a summary on top of what the full trace has
+    let summary = ChatMessage::System {
+        content: ChatMessageContent::Text("Summary of the previous conversation".to_string()),
+        name: Some("summary".to_string()),
+    };
+    assert!(crate::runners::compacter::is_summary(&summary));
+    let compressed = vec![summary, user("third")];
+    assert!(super::agent::traces_are_consistent(&compressed, &full_trace));
+
+    // a tool output removed by the user is only emptied in the trace
+    let call = shai_llm::ToolCall {
+        id: "call_1".to_string(),
+        r#type: "function".to_string(),
+        function: shai_llm::Function { name: "ls".to_string(), arguments: "{}".to_string() },
+    };
+    let tool_trace = vec![
+        user("first"),
+        ChatMessage::Assistant { content: None, reasoning_content: None, tool_calls: Some(vec![call]), name: None, audio: None, refusal: None },
+        ChatMessage::Tool { content: "a.txt".to_string(), tool_call_id: "call_1".to_string() },
+    ];
+    let mut edited = tool_trace.clone();
+    edited[2] = ChatMessage::Tool { content: super::agent::REMOVED_TOOL_OUTPUT.to_string(), tool_call_id: "call_1".to_string() };
+    assert!(super::agent::traces_are_consistent(&edited, &tool_trace));
+    edited[2] = ChatMessage::Tool { content: super::agent::REMOVED_TOOL_OUTPUT.to_string(), tool_call_id: "call_2".to_string() };
+    assert!(!super::agent::traces_are_consistent(&edited, &tool_trace));
+
+    // a message pushed to the trace alone is caught
+    let diverged = vec![user("first"), user("never recorded")];
+    assert!(!super::agent::traces_are_consistent(&diverged, &full_trace));
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_the_running_tool() {
+    init_test_logging();
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(5000));
+    let mut agent = AgentBuilder::new(Box::new(SleepingThinker::new()))
+        .id("test-shutdown-agent")
+        .goal("sleep")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !matches!(events.recv().await, Ok(super::AgentEvent::ToolCallStarted { .. })) {}
+    }).await.expect("the sleeping tool should start");
+
+    let start_time = std::time::Instant::now();
+    controller.shutdown(Duration::from_millis(500)).await.expect("shutdown should be acknowledged");
+    let result = tokio::time::timeout(Duration::from_secs(1), handle).await
+        .expect("agent should stop right after the shutdown")
+        .unwrap()
+        .expect("agent should complete");
+    assert!(start_time.elapsed() < Duration::from_millis(1500), "shutdown took too long: {:?}", start_time.elapsed());
+    assert!(result.trace.iter().any(|m| matches!(m, ChatMessage::Tool { content, .. } if content.contains("cancelled"))));
+
+    // a stopped agent can be shut down again
+    controller.shutdown(Duration::from_millis(100)).await.expect("shutting down twice is fine");
+}
+
+#[tokio::test]
+async fn test_coder_brain_with_scripted_llm() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    let mock = MockProvider::new();
+    mock.push_tool_calls(&[("sleeping_tool", r#"{"duration_ms":10}"#)], Some((100, 10)))
+        .push_text("all done", Some((150, 5)));
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(10));
+    let mut agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+        .id("test-scripted-llm-agent")
+        .goal("take a nap")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(5000)).await.expect("agent should answer");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert!(trace.iter().any(|m| matches!(m, ChatMessage::Tool { tool_call_id, content } if tool_call_id == "call_1" && content.contains("Finished sleeping"))));
+    assert!(matches!(trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "all done"));
+
+    // the second step is sent the result of the tool
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].messages.iter().any(|m| matches!(m, ChatMessage::Tool { .. })));
+    assert_eq!(mock.remaining(), 0);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_streamed_tool_calls_are_assembled() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    // the mock streams each answer as a single chunk, tool calls in their delta shape
+    let mock = MockProvider::new();
+    mock.push_tool_calls(&[("sleeping_tool", r#"{"duration_ms":10}"#)], None)
+        .push_text("streamed answer", None);
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(10));
+    let brain = CoderBrain::new(llm, "mock-model".to_string()).with_streaming(true);
+    let mut agent = AgentBuilder::new(Box::new(brain))
+        .id("test-streamed-tool-calls-agent")
+        .goal("take a nap")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(5000)).await.expect("agent should answer");
+
+    let mut deltas = String::new();
+    let mut tool_ran = false;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            super::AgentEvent::BrainDelta { text } => deltas.push_str(&text),
+            super::AgentEvent::ToolCallCompleted { .. } => tool_ran = true,
+            _ => {}
+        }
+    }
+    assert!(tool_ran, "the assembled tool call should be dispatched");
+    assert_eq!(deltas, "streamed answer");
+
+    // the tools are sent along with the streamed request
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].tools.as_ref().is_some_and(|tools| !tools.is_empty()));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_last_error_is_kept_with_a_hint() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    let mock = MockProvider::new();
+    // not a rejection of the tool call method, the fallback chain is not walked
+    mock.push_error("This model's maximum context length is 8192 tokens");
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+    let mut agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+        .id("test-last-error-agent")
+        .build();
+
+    let controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+    assert!(controller.last_error().await.unwrap().is_none());
+
+    controller.send_user_input("summarize the repository".to_string()).await.expect("failed to send input");
+    let _ = controller.wait_turn(Some(2000)).await;
+
+    let report = controller.last_error().await.unwrap().expect("the error should be kept");
+    assert!(matches!(report.error, AgentError::LlmError(_)));
+    assert_eq!(report.request.as_deref(), Some("summarize the repository"));
+    assert!(report.hint().is_some_and(|hint| hint.contains("/compact")));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_pool_runs_agents_side_by_side() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+    use super::{AgentEvent, AgentPool};
+
+    init_test_logging();
+
+    let mut pool = AgentPool::new();
+    let mut events = pool.watch();
+    let mut mocks = Vec::new();
+    for name in ["planner", "worker"] {
+        let mock = MockProvider::new();
+        mock.push_text(&format!("{} answer", name), None);
+        let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+        let agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+            .id(name)
+            .build();
+        pool.spawn(name, Box::new(agent)).expect("failed to spawn the agent");
+        mocks.push(mock);
+    }
+    assert!(pool.spawn("worker", Box::new(AgentBuilder::new(Box::new(PausableThinker::new())).build())).is_err(), "names are unique");
+    assert_eq!(pool.names(), vec!["planner", "worker"]);
+    assert_eq!(pool.focused().map(|(name, _)| name), Some("planner"));
+
+    // only the focused agent is talked to
+    let (_, worker) = pool.focus_next().expect("the focus should move");
+    worker.wait_turn(Some(1000)).await.expect("agent should start paused");
+    worker.send_user_input("hello".to_string()).await.expect("failed to send input");
+    worker.wait_turn(Some(2000)).await.expect("agent should answer");
+
+    // events are forwarded from a task of their own, they may come after the turn ended
+    let answered = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            match events.recv().await {
+                Ok(envelope) if matches!(envelope.event, AgentEvent::BrainResult { .. }) => break envelope.agent_id,
+                Ok(_) => continue,
+                Err(e) => panic!("event stream closed: {}", e),
+            }
+        }
+    }).await.expect("the answer should be forwarded");
+    assert_eq!(answered, "worker");
+    assert_eq!(mocks[0].remaining(), 1, "the planner was not called");
+    assert_eq!(mocks[1].remaining(), 0);
+    assert!(pool.get("planner").unwrap().get_trace().await.unwrap().is_empty());
+
+    pool.remove("worker", Duration::from_millis(500)).await.expect("failed to remove the worker");
+    assert_eq!(pool.focused().map(|(name, _)| name), Some("planner"));
+    pool.shutdown(Duration::from_millis(500)).await;
+    assert!(pool.is_empty());
+}
+
+/// Always asks to continue without ever calling a tool
+struct TalkativeThinker {
+    steps: Arc<Mutex<u32>>,
+}
+
+#[async_trait]
+impl Brain for TalkativeThinker {
+    async fn next_step(&mut self, _: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        *self.steps.lock().await += 1;
+        Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+            content: Some(ChatMessageContent::Text("let me think about it some more".to_string())),
+            reasoning_content: None,
+            tool_calls: None,
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_idle_loop_is_paused() {
+    init_test_logging();
+
+    let steps = Arc::new(Mutex::new(0));
+    let mut agent = AgentBuilder::new(Box::new(TalkativeThinker { steps: steps.clone() }))
+        .id("test-idle-loop-agent")
+        .goal("think")
+        .idle_step_limit(Some(3))
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+
+    let idle = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(super::AgentEvent::IdleLoopDetected { steps }) = events.recv().await {
+                break steps;
+            }
+        }
+    }).await.expect("the idle loop was never detected");
+    assert_eq!(idle, 3);
+
+    controller.wait_turn(Some(1000)).await.expect("agent should be paused");
+    assert_eq!(*steps.lock().await, 3);
+
+    // a new message gives the agent a fresh count
+    controller.set_idle_step_limit(Some(2)).await.expect("failed to set the limit");
+    controller.send_user_input("go on".to_string()).await.expect("failed to send input");
+    controller.wait_turn(Some(1000)).await.expect("agent should pause again");
+    assert_eq!(*steps.lock().await, 5);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_context_is_edited_by_hand_while_paused() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+    use super::agent::REMOVED_TOOL_OUTPUT;
+
+    init_test_logging();
+
+    let mock = MockProvider::new();
+    mock.push_tool_calls(&[("sleeping_tool", r#"{"duration_ms":10}"#)], None)
+        .push_text("all done", None);
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(10));
+    let mut agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+        .id("test-context-edit-agent")
+        .goal("take a nap")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(5000)).await.expect("agent should answer");
+    assert_eq!(controller.get_trace().await.unwrap().len(), 4);
+
+    // a tool result is emptied, its call still needs an answer
+    controller.remove_message(2).await.expect("failed to remove the tool output");
+    let trace = controller.get_trace().await.unwrap();
+    assert!(matches!(&trace[2], ChatMessage::Tool { content, .. } if content == REMOVED_TOOL_OUTPUT));
+    let exported = controller.export_trace(super::TraceFormat::Json).await.unwrap();
+    assert!(exported.contains("Finished sleeping"), "the full trace keeps the tool output");
+
+    controller.edit_message(0, "take a short nap".to_string()).await.expect("failed to edit the goal");
+    let exported = controller.export_trace(super::TraceFormat::Json).await.unwrap();
+    assert!(exported.contains("take a short nap"), "the full trace is edited too");
+    assert!(!exported.contains("\"take a nap\""));
+
+    // the tool call goes away with its result
+    controller.remove_message(1).await.expect("failed to remove the tool call");
+    let trace = controller.get_trace().await.unwrap();
+    assert_eq!(trace.len(), 2);
+    assert!(matches!(&trace[0], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "take a short nap"));
+    assert!(matches!(&trace[1], ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } if text == "all done"));
+
+    assert!(controller.remove_message(5).await.is_err());
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_session_usage_is_accumulated() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    let mock = MockProvider::new();
+    mock.push_tool_calls(&[("sleeping_tool", r#"{"duration_ms":10}"#)], Some((10, 5)))
+        .push_text("napped", Some((20, 8)))
+        .push_text("again", Some((30, 2)));
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+
+    let sleeping_tool: Box<dyn AnyTool> = Box::new(SleepingTool::new(10));
+    let mut agent = AgentBuilder::new(Box::new(CoderBrain::new(llm, "mock-model".to_string())))
+        .id("test-session-usage-agent")
+        .goal("take a nap")
+        .tools(vec![sleeping_tool])
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(5000)).await.expect("agent should answer");
+    controller.send_user_input("one more".to_string()).await.expect("failed to send input");
+    controller.wait_turn(Some(5000)).await.expect("agent should answer again");
+
+    let usage = controller.session_usage().await.expect("failed to get the usage");
+    assert_eq!((usage.input_tokens, usage.output_tokens, usage.total_tokens()), (60, 15, 75));
+    assert_eq!(usage.by_model.len(), 1);
+    assert_eq!(usage.by_model["mock-model"], super::ModelUsage { input_tokens: 60, output_tokens: 15 });
+
+    // each turn reports the running total
+    let mut totals = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let super::AgentEvent::SessionUsage { usage } = event {
+            totals.push(usage.total_tokens());
+        }
+    }
+    assert_eq!(totals, vec![15, 43, 75]);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+/// Fails every step, like a brain talking to a misconfigured provider
+struct BrokenThinker;
+
+#[async_trait]
+impl Brain for BrokenThinker {
+    async fn next_step(&mut self, _context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        Err(AgentError::LlmError("401 Unauthorized".to_string()))
+    }
+}
+
+#[tokio::test]
+async fn test_circuit_breaker_refuses_input_after_repeated_failures() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(BrokenThinker))
+        .id("test-circuit-breaker-agent")
+        .circuit_breaker(Some(super::CircuitBreaker { max_failures: 2, window: Duration::from_secs(60) }))
+        .build();
+
+    let controller = agent.controller();
+    let mut events = agent.watch();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    for attempt in 1..=2 {
+        controller.send_user_input(format!("attempt {}", attempt)).await.expect("input should be accepted");
+        let _ = controller.wait_turn(Some(1000)).await;
+    }
+    assert_eq!(controller.brain_failures().await.unwrap(), (2, true));
+    let opened = std::iter::from_fn(|| events.try_recv().ok())
+        .any(|event| matches!(event, super::AgentEvent::CircuitOpened { failures: 2 }));
+    assert!(opened, "the breaker should announce it opened");
+
+    let refused = controller.send_user_input("attempt 3".to_string()).await.expect_err("input should be refused");
+    assert!(refused.to_string().contains("provider appears to be failing"), "{}", refused);
+
+    controller.reset_circuit_breaker().await.expect("failed to reset the breaker");
+    assert_eq!(controller.brain_failures().await.unwrap(), (0, false));
+    controller.send_user_input("attempt 4".to_string()).await.expect("input should be accepted again");
+    let _ = controller.wait_turn(Some(1000)).await;
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_malformed_tool_calls_do_not_trip_the_breaker() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(BadArgsThinker { arguments: "{\"path\": ", stubborn: true }))
+        .id("test-breaker-bad-args-agent")
+        .circuit_breaker(Some(super::CircuitBreaker { max_failures: 2, window: Duration::from_secs(60) }))
+        .build();
+
+    let controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    for attempt in 1..=3 {
+        controller.send_user_input(format!("attempt {}", attempt)).await.expect("input should be accepted");
+        let _ = controller.wait_turn(Some(1000)).await;
+    }
+    assert_eq!(controller.brain_failures().await.unwrap(), (0, false));
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+/// Records the tools it is offered and tries to write a file anyway
+struct PlanningThinker {
+    offered: Arc<Mutex<Vec<Vec<String>>>>,
+    step: u32,
+}
+
+#[async_trait]
+impl Brain for PlanningThinker {
+    async fn next_step(&mut self, context: ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        self.offered.lock().await.push(context.available_tools.iter().map(|t| t.name()).collect());
+        self.step += 1;
+        if self.step > 1 {
+            return Ok(ThinkerDecision::agent_pause(ChatMessage::Assistant {
+                content: Some(ChatMessageContent::Text("done".to_string())),
+                reasoning_content: None,
+                tool_calls: None,
+                name: None,
+                audio: None,
+                refusal: None,
+            }));
+        }
+        Ok(ThinkerDecision::agent_continue(ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: Some(vec![shai_llm::ToolCall {
+                id: "call_write".to_string(),
+                r#type: "function".to_string(),
+                function: shai_llm::Function {
+                    name: "write".to_string(),
+                    arguments: serde_json::json!({ "path": "plan-mode-should-not-write.txt", "content": "oops" }).to_string(),
+                },
+            }]),
+            name: None,
+            audio: None,
+            refusal: None,
+        }))
+    }
+}
+
+#[tokio::test]
+async fn test_plan_mode_keeps_write_tools_away() {
+    init_test_logging();
+
+    let fs_log = Arc::new(crate::tools::FsOperationLog::new());
+    let tools: Vec<Box<dyn AnyTool>> = vec![
+        Box::new(ReadTool::new(fs_log.clone())),
+        Box::new(crate::tools::WriteTool::new(fs_log)),
+    ];
+    let offered = Arc::new(Mutex::new(Vec::new()));
+    let mut agent = AgentBuilder::new(Box::new(PlanningThinker { offered: offered.clone(), step: 0 }))
+        .id("test-plan-mode-agent")
+        .goal("Write a file")
+        .tools(tools)
+        .mode(super::AgentMode::Plan)
+        .sudo()
+        .build();
+
+    let result = agent.run().await.expect("agent should complete");
+
+    assert_eq!(offered.lock().await[0], vec!["read".to_string()]);
+    let refused = result.trace.iter().any(|msg| matches!(msg,
+        ChatMessage::Tool { content, .. } if content.contains("not available in plan mode")));
+    assert!(refused, "the write call should be refused: {:?}", result.trace);
+    assert!(!std::path::Path::new("plan-mode-should-not-write.txt").exists());
+}
+
+#[tokio::test]
+async fn test_truncated_stream_keeps_the_partial_answer() {
+    use shai_llm::providers::mock::MockProvider;
+    use crate::runners::coder::coder::CoderBrain;
+
+    init_test_logging();
+
+    // the server drops the connection before the finish_reason
+    let mock = MockProvider::new();
+    mock.push_truncated("Here is the first half");
+    let llm = Arc::new(shai_llm::LlmClient::from_provider(mock.clone()));
+
+    let brain = CoderBrain::new(llm, "mock-model".to_string()).with_streaming(true);
+    let mut agent = AgentBuilder::new(Box::new(brain))
+        .id("test-truncated-stream-agent")
+        .goal("explain something")
+        .sudo()
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(5000)).await.expect("agent should answer instead of hanging");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert!(matches!(trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. })
+        if text.starts_with("Here is the first half") && text.contains("answer incomplete")), "{:?}", trace.last());
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
+
+#[tokio::test]
+async fn test_rewind_last_turn_before_a_retry() {
+    init_test_logging();
+
+    let mut agent = AgentBuilder::new(Box::new(EchoThinker))
+        .id("test-rewind-agent")
+        .build();
+
+    let mut controller = agent.controller();
+    let handle = tokio::spawn(async move {
+        agent.run().await
+    });
+    controller.wait_turn(Some(1000)).await.expect("agent should start paused");
+
+    assert!(controller.rewind_last_turn().await.is_err(), "nothing to rewind yet");
+
+    controller.run_once("first".to_string()).await.expect("run_once failed");
+    controller.run_once("second".to_string()).await.expect("run_once failed");
+    controller.rewind_last_turn().await.expect("failed to rewind");
+
+    let trace = controller.get_trace().await.expect("failed to get the trace");
+    assert_eq!(trace.len(), 2);
+    assert!(matches!(trace.last(), Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) if text == "echo: first"));
+
+    // sent again, the prompt appears once
+    let answer = controller.run_once("second".to_string()).await.expect("run_once failed");
+    assert_eq!(answer, "echo: second");
+    assert_eq!(controller.get_trace().await.unwrap().len(), 4);
+
+    controller.drop().await.expect("failed to drop the controller");
+    handle.await.unwrap().expect("agent should complete");
+}
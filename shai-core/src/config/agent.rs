@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
 use shai_llm::ToolCallMethod;
 use crate::tools::mcp::McpConfig;
+use crate::agent::actions::tools::{ToolConcurrency, DEFAULT_MAX_TOOL_OUTPUT};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentProviderConfig {
@@ -44,6 +45,10 @@ pub struct AgentConfig {
     pub max_tokens: u32,
     #[serde(default = "default_temperature")]
     pub temperature: f32,
+    #[serde(default = "default_max_tool_output")]
+    pub max_tool_output: usize,
+    #[serde(default)]
+    pub tool_concurrency: ToolConcurrency,
 }
 
 fn default_system_prompt() -> String {
@@ -58,6 +63,10 @@ fn default_temperature() -> f32 {
     0.3
 }
 
+fn default_max_tool_output() -> usize {
+    DEFAULT_MAX_TOOL_OUTPUT
+}
+
 fn default_enabled_tools() -> Vec<String> {
     vec!["*".to_string()]
 }
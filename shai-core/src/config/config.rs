@@ -6,6 +6,7 @@ use reqwest::Url;
 use serde::{Serialize, Deserialize};
 use shai_llm::{LlmClient, ToolCallMethod};
 use crate::tools::mcp::McpConfig;
+use super::settings::Settings;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProviderConfig {
@@ -68,7 +69,8 @@ impl ShaiConfig {
         }
     }
 
-    pub fn config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    /// The shai configuration directory, created if needed
+    pub fn config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
         let config_dir = std::env::var("XDG_CONFIG_HOME")
             .map(PathBuf::from)
             .or_else(|_| {
@@ -80,7 +82,11 @@ impl ShaiConfig {
         let shai_config_dir = config_dir.join("shai");
         std::fs::create_dir_all(&shai_config_dir)?;
         
-        Ok(shai_config_dir.join("auth.config"))
+        Ok(shai_config_dir)
+    }
+
+    pub fn config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        Ok(Self::config_dir()?.join("auth.config"))
     }
 
     pub fn load() -> Result<ShaiConfig, Box<dyn std::error::Error>> {
@@ -238,10 +244,23 @@ impl Default for ShaiConfig {
 
 impl ShaiConfig {
     pub async fn get_llm() -> Result<(LlmClient, String), Box<dyn std::error::Error>>{
-        let config = ShaiConfig::load()
+        let mut config = ShaiConfig::load()
             .unwrap_or_else(|_| ShaiConfig::default());
 
+        // read before set_env_vars overwrites them with the selected provider
+        let env_provider = std::env::var("SHAI_PROVIDER").ok();
+        let env_model = std::env::var("SHAI_MODEL").ok();
+        let settings = Settings::load();
+        let selection = config.resolve_selection(&settings, env_provider.as_deref(), env_model.as_deref());
+        let model = selection.as_ref().and_then(|(_, model)| model.clone());
+        if let Some((index, _)) = selection {
+            config.selected_provider = index;
+        }
+
         config.set_env_vars();
+        if let Some(model) = &model {
+            std::env::set_var("SHAI_MODEL", model);
+        }
         
         let llm = if let Some(provider_config) = config.get_selected_provider() {
             LlmClient::create_provider(
@@ -251,8 +270,92 @@ impl ShaiConfig {
         } else {
             return Err("No provider configured".into());
         };
+
+        // a mistyped url or key is reported now rather than as a cryptic failure of the first prompt,
+        // some servers do not list their models though, so this is only a warning
+        if let Err(e) = llm.health_check().await {
+            eprintln!("\x1b[33m░ {}\x1b[0m", e);
+        }
     
-        let model = llm.default_model().await.map_err(|_| "no Model available")?;
+        let model = match model {
+            Some(model) => model,
+            None => llm.default_model().await.map_err(|_| "no Model available")?,
+        };
         Ok((llm, model))
     }
-}
\ No newline at end of file
+
+    /// Provider index and model to start with. The environment (SHAI_PROVIDER / SHAI_MODEL) wins over
+    /// the selection remembered in settings.json, which wins over the one saved in auth.config
+    fn resolve_selection(&self, settings: &Settings, env_provider: Option<&str>, env_model: Option<&str>) -> Option<(usize, Option<String>)> {
+        let index = env_provider
+            .and_then(|provider| self.find_provider(provider, env_model))
+            .or_else(|| self.remembered_provider(settings))
+            .unwrap_or(self.selected_provider);
+        let provider_config = self.providers.get(index)?;
+        let model = env_model
+            .or_else(|| settings.model_for(&provider_config.provider))
+            .or(Some(provider_config.model.as_str()))
+            .filter(|model| !model.is_empty())
+            .map(str::to_string);
+        Some((index, model))
+    }
+
+    /// Index of the configured provider matching the stored selection, preferring the one with the same model
+    fn remembered_provider(&self, settings: &Settings) -> Option<usize> {
+        self.find_provider(settings.provider.as_deref()?, settings.model.as_deref())
+    }
+
+    /// Index of a configured provider of this type, preferring the one with the same model
+    fn find_provider(&self, provider: &str, model: Option<&str>) -> Option<usize> {
+        let candidates = self.find_providers_by_type(provider);
+        candidates.iter()
+            .find(|&&i| model == Some(self.providers[i].model.as_str()))
+            .or(candidates.first())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_providers() -> ShaiConfig {
+        let provider = |provider: &str, model: &str| ProviderConfig {
+            provider: provider.to_string(),
+            env_vars: HashMap::new(),
+            model: model.to_string(),
+            tool_method: ToolCallMethod::FunctionCall,
+        };
+        ShaiConfig {
+            providers: vec![provider("ollama", "llama3.1"), provider("openai", "gpt-4o")],
+            selected_provider: 0,
+            mcp_configs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_selection_precedence() {
+        let config = two_providers();
+        let settings = Settings {
+            provider: Some("openai".to_string()),
+            model: Some("gpt-4o-mini".to_string()),
+            ..Settings::default()
+        };
+
+        // auth.config alone
+        assert_eq!(config.resolve_selection(&Settings::default(), None, None), Some((0, Some("llama3.1".to_string()))));
+        // settings over auth.config
+        assert_eq!(config.resolve_selection(&settings, None, None), Some((1, Some("gpt-4o-mini".to_string()))));
+        // environment over settings
+        assert_eq!(config.resolve_selection(&settings, Some("ollama"), None), Some((0, Some("llama3.1".to_string()))));
+        assert_eq!(config.resolve_selection(&settings, None, Some("gpt-4.1")), Some((1, Some("gpt-4.1".to_string()))));
+        assert_eq!(config.resolve_selection(&settings, Some("ollama"), Some("qwen3")), Some((0, Some("qwen3".to_string()))));
+    }
+
+    #[test]
+    fn test_unknown_env_provider_is_ignored() {
+        let config = two_providers();
+        assert_eq!(config.resolve_selection(&Settings::default(), Some("mistral"), None), Some((0, Some("llama3.1".to_string()))));
+        assert_eq!(ShaiConfig { providers: vec![], ..config }.resolve_selection(&Settings::default(), None, None), None);
+    }
+}
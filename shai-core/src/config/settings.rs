@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use shai_llm::{ModelPrice, PriceTable};
+use super::config::ShaiConfig;
+
+/// User selection remembered across sessions, stored in ~/.config/shai/settings.json
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// how the files picked with @ are inserted: relative, absolute or pwd
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_style: Option<String>,
+    /// whether file paths are printed as OSC 8 hyperlinks, off unless the terminal supports them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hyperlinks: Option<bool>,
+    /// files larger than this many KB are not suggested by @
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion_max_kb: Option<u64>,
+    /// whether the default agent streams its answers as they are written, on unless set to false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// column of the soft line length rule of the input box, no rule when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_rule: Option<u16>,
+    /// own rates per 1000 tokens (self-hosted models, discounts), keyed by a part of the model
+    /// name, e.g. "llama": { "input_per_1k": 0.0, "output_per_1k": 0.0 }
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+impl Settings {
+    pub fn settings_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        Ok(ShaiConfig::config_dir()?.join("settings.json"))
+    }
+
+    /// Load the stored settings, a missing or unreadable file yields empty settings
+    pub fn load() -> Settings {
+        Self::settings_path()
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(Self::settings_path()?, content)?;
+        Ok(())
+    }
+
+    /// Store the provider and model selection, the file is only written when it changed
+    pub fn remember(provider: &str, model: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let settings = Self::load();
+        let updated = Settings {
+            provider: Some(provider.to_string()),
+            model: Some(model.to_string()),
+            ..settings.clone()
+        };
+        if updated != settings {
+            updated.save()?;
+        }
+        Ok(())
+    }
+
+    /// Store how @ inserts the picked files
+    pub fn remember_path_style(style: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let settings = Self::load();
+        let updated = Settings { path_style: Some(style.to_string()), ..settings.clone() };
+        if updated != settings {
+            updated.save()?;
+        }
+        Ok(())
+    }
+
+    /// Store whether file paths are printed as hyperlinks
+    pub fn remember_hyperlinks(enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let settings = Self::load();
+        let updated = Settings { hyperlinks: Some(enabled), ..settings.clone() };
+        if updated != settings {
+            updated.save()?;
+        }
+        Ok(())
+    }
+
+    /// Store the column of the line length rule, None removes it
+    pub fn remember_line_rule(column: Option<u16>) -> Result<(), Box<dyn std::error::Error>> {
+        let settings = Self::load();
+        let updated = Settings { line_rule: column, ..settings.clone() };
+        if updated != settings {
+            updated.save()?;
+        }
+        Ok(())
+    }
+
+    /// List prices with the user's rates on top, to estimate the cost of a session
+    pub fn price_table(&self) -> PriceTable {
+        PriceTable::new().with_prices(&self.prices)
+    }
+
+    /// The stored model, if it was picked for the given provider
+    pub fn model_for(&self, provider: &str) -> Option<&str> {
+        match (&self.provider, &self.model) {
+            (Some(p), Some(model)) if p == provider => Some(model.as_str()),
+            _ => None,
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::sync::Arc;
 
-use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
-use shai_llm::{client::LlmClient, ChatMessage, ChatMessageContent};
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponse};
+use shai_llm::{client::LlmClient, get_max_context, assemble_stream, ChatMessage, ChatMessageContent, FunctionCallingAutoBuilder, StreamError, ToolCallMethod};
+use shai_llm::client::ExtractThinkContent;
+use shai_llm::retry::with_retry;
 use async_trait::async_trait;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::agent::brain::ThinkerDecision;
-use crate::agent::{Agent, AgentBuilder, AgentError, Brain, ThinkerContext};
+use crate::agent::{Agent, AgentBuilder, AgentError, Brain, BrainModel, ThinkerContext};
+use crate::runners::compacter::ContextCompressor;
 use crate::tools::types::{ContainsAnyTool, IntoToolBox};
 use shai_llm::tool::LlmToolCall;
 use crate::tools::{AnyTool, BashTool, EditTool, FetchTool, FindTool, LsTool, MultiEditTool, ReadTool, TodoReadTool, TodoWriteTool, WriteTool, TodoStorage, FsOperationLog};
@@ -19,28 +22,128 @@ pub struct CoderBrain {
     pub model: String,
     pub system_prompt_template: String,
     pub temperature: f32,
+    pub context_compressor: ContextCompressor,
+    /// stream the answer and forward the text chunks to the agent as they arrive
+    pub stream: bool,
 }
 
 impl CoderBrain {
     pub fn new(llm: Arc<LlmClient>, model: String) -> Self {
         debug!(target: "brain::coder", provider =?llm.provider_name(), model = ?model);
         Self { 
+            context_compressor: ContextCompressor::new_with_llm(get_max_context(&model), llm.clone(), model.clone()),
             llm, 
             model,
             system_prompt_template: "{{CODER_BASE_PROMPT}}".to_string(),
             temperature: 0.3,
+            stream: false,
         }
     }
 
     pub fn with_custom_prompt(llm: Arc<LlmClient>, model: String, system_prompt_template: String, temperature: f32) -> Self {
         debug!(target: "brain::coder", provider =?llm.provider_name(), model = ?model);
         Self { 
+            context_compressor: ContextCompressor::new_with_llm(get_max_context(&model), llm.clone(), model.clone()),
             llm, 
             model,
             system_prompt_template,
             temperature,
+            stream: false,
         }
     }
+
+    pub fn with_streaming(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    /// Whether the next step can be streamed: the text is forwarded as it arrives and tool
+    /// calls are assembled from their fragments, which needs native function calling
+    fn can_stream(&self, context: &ThinkerContext) -> bool {
+        self.stream && context.delta_tx.is_some() && (context.available_tools.is_empty() || (
+            matches!(context.method, ToolCallMethod::Auto | ToolCallMethod::FunctionCall)
+            && self.llm.provider().supports_functions(self.model.clone())
+        ))
+    }
+
+    /// Streaming variant of the next step, text chunks are forwarded through the context
+    /// and the assembled message, tool calls included, is returned once the stream is over.
+    async fn next_step_streaming(&self, request: ChatCompletionParameters, context: &ThinkerContext) -> Result<ThinkerDecision, AgentError> {
+        let request = if context.available_tools.is_empty() {
+            request
+        } else {
+            let toolbox = context.available_tools.clone().into_toolbox();
+            ChatCompletionParametersBuilder::default()
+                .model(&request.model)
+                .messages(request.messages)
+                .temperature(self.temperature)
+                .with_function_calling_auto(&toolbox)
+                .build()
+                .map_err(|e| AgentError::LlmError(e.to_string()))?
+        };
+
+        // only opening the stream is retried, an error in the middle of it would duplicate the deltas already sent
+        let llm = &self.llm;
+        let stream = with_retry(
+                &context.retry_policy,
+                |attempt, delay| context.notify_retry(attempt, delay),
+                || llm.chat_stream(request.clone()))
+            .await
+            .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        let response = match assemble_stream(stream, |text| context.send_delta(text)).await {
+            Ok(response) => response,
+            Err(error) => Self::salvage_partial(error)?,
+        }.extract_think_content();
+
+        Ok(Self::decide(response))
+    }
+
+    /// A stream cut short by the server still gave the user some text, which is kept as the
+    /// answer with a note saying it is incomplete. Half received tool calls cannot be run, the
+    /// error goes up instead and the step can be retried.
+    fn salvage_partial(error: shai_llm::provider::LlmError) -> Result<ChatCompletionResponse, AgentError> {
+        let error = match error.downcast::<StreamError>() {
+            Ok(error) => error,
+            Err(error) => return Err(AgentError::LlmError(error.to_string())),
+        };
+        let text = match error.partial().choices.first().map(|choice| &choice.message) {
+            Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), tool_calls: None, .. }) if !text.trim().is_empty() => text.clone(),
+            _ => return Err(AgentError::LlmError(error.to_string())),
+        };
+        warn!(target: "brain::coder", error = %error, "keeping the partial answer of an incomplete stream");
+        let note = format!("\n\n[answer incomplete: {}]", error);
+        let mut response = (*error).into_partial();
+        if let Some(ChatMessage::Assistant { content, .. }) = response.choices.first_mut().map(|choice| &mut choice.message) {
+            *content = Some(ChatMessageContent::Text(text + &note));
+        }
+        Ok(response)
+    }
+
+    /// Continue while the answer calls tools, hand over to the user otherwise
+    fn decide(response: ChatCompletionResponse) -> ThinkerDecision {
+        let token_usage = response.usage.as_ref().map(|usage| {
+            (usage.prompt_tokens.unwrap_or(0), usage.completion_tokens.unwrap_or(0))
+        });
+        let model = response.model.clone();
+        let message = response.choices.into_iter().next()
+            .map(|choice| choice.message)
+            .unwrap_or(ChatMessage::Assistant {
+                content: None,
+                reasoning_content: None,
+                tool_calls: None,
+                refusal: None,
+                name: None,
+                audio: None,
+            });
+        let calls_tools = matches!(&message, ChatMessage::Assistant { tool_calls: Some(calls), .. } if !calls.is_empty());
+        let decision = match (calls_tools, token_usage) {
+            (true, Some((input_tokens, output_tokens))) => ThinkerDecision::agent_continue_with_tokens(message, input_tokens, output_tokens),
+            (true, None) => ThinkerDecision::agent_continue(message),
+            (false, Some((input_tokens, output_tokens))) => ThinkerDecision::agent_pause_with_tokens(message, input_tokens, output_tokens),
+            (false, None) => ThinkerDecision::agent_pause(message),
+        };
+        if model.is_empty() { decision } else { decision.with_model(model) }
+    }
 }
 
 
@@ -70,40 +173,53 @@ impl Brain for CoderBrain {
             .temperature(self.temperature)
             .build()
             .map_err(|e| AgentError::LlmError(e.to_string()))?;
+
+        if self.can_stream(&context) {
+            return self.next_step_streaming(request, &context).await;
+        }
         
-        let brain_decision = self.llm.chat_with_tools(
-                request,
-                &context.available_tools.into_toolbox(),
-                context.method)
+        let llm = &self.llm;
+        let toolbox = context.available_tools.clone().into_toolbox();
+        let (brain_decision, method) = with_retry(
+                &context.retry_policy,
+                |attempt, delay| context.notify_retry(attempt, delay),
+                || llm.chat_with_tools_fallback(request.clone(), &toolbox, context.method))
                 .await
                 .map_err(|e| AgentError::LlmError(e.to_string()))?;
 
-        // Extract token usage information
-        let token_usage = brain_decision.usage.as_ref().map(|usage| {
-            let input = usage.prompt_tokens.unwrap_or(0);
-            let output = usage.completion_tokens.unwrap_or(0);
-            (input, output)
-        });
+        Ok(Self::decide(brain_decision).with_method(method))
+    }
+
+    fn context_compressor(&mut self) -> Option<&mut ContextCompressor> {
+        Some(&mut self.context_compressor)
+    }
+
+    fn system_prompt(&self) -> Option<String> {
+        Some(render_system_prompt_template(&self.system_prompt_template))
+    }
 
-        // stop here if there's no other tool calls
-        let message = brain_decision.choices.into_iter().next().unwrap().message;
-        if let ChatMessage::Assistant { reasoning_content, content, tool_calls, .. } = &message {
-            if tool_calls.as_ref().map_or(true, |calls| calls.is_empty()) {
-                return Ok(match token_usage {
-                    Some((input_tokens, output_tokens)) => ThinkerDecision::agent_pause_with_tokens(message, input_tokens, output_tokens),
-                    None => ThinkerDecision::agent_pause(message),
-                });
-            }
+    async fn switch_model(&mut self, model: String) -> Result<BrainModel, AgentError> {
+        let models = self.llm.models().await
+            .map_err(|e| AgentError::LlmError(e.to_string()))?;
+        if !models.data.iter().any(|m| m.id == model) {
+            return Err(AgentError::ConfigurationError(format!("model {} is not available on {}", model, self.llm.provider_name())));
         }
-        Ok(match token_usage {
-            Some((input_tokens, output_tokens)) => ThinkerDecision::agent_continue_with_tokens(message, input_tokens, output_tokens),
-            None => ThinkerDecision::agent_continue(message),
+
+        let max_context = get_max_context(&model);
+        debug!(target: "brain::coder", provider =?self.llm.provider_name(), model = ?model, max_context);
+        self.context_compressor.max_tokens = max_context;
+        self.context_compressor.model = Some(model.clone());
+        self.model = model.clone();
+        Ok(BrainModel {
+            provider: self.llm.provider_name().to_string(),
+            model,
+            max_context,
         })
     }
 }
 
 
-pub fn coder(llm: Arc<LlmClient>, model: String) -> impl Agent {
+pub fn coder(llm: Arc<LlmClient>, model: String, stream: bool) -> impl Agent {
     // Create shared storage for todo tools
     let todo_storage = Arc::new(TodoStorage::new());
     
@@ -122,7 +238,10 @@ pub fn coder(llm: Arc<LlmClient>, model: String) -> impl Agent {
     let write = Box::new(WriteTool::new(fs_log.clone()));
     let toolbox: Vec<Box<dyn AnyTool>> = vec![bash, edit, multiedit, fetch, find, ls, read, todoread, todowrite, write];
 
-    AgentBuilder::new(Box::new(CoderBrain::new(llm.clone(), model)))
+    let method = llm.detect_tool_call_method(&model);
+    debug!(target: "brain::coder", method = ?method, "tool call method picked from the provider capabilities");
+    AgentBuilder::new(Box::new(CoderBrain::new(llm.clone(), model).with_streaming(stream)))
+    .method(method)
     .tools(toolbox)
     .build()
 }
\ No newline at end of file
@@ -2,7 +2,7 @@ use super::coder::CoderBrain;
 use crate::agent::{Agent, Brain, StdoutEventManager, ThinkerContext};
 use crate::logging::LoggingConfig;
 use crate::tools::AnyTool;
-use shai_llm::ToolCallMethod;
+use shai_llm::{RetryPolicy, ToolCallMethod};
 use shai_llm::{ChatMessage, ChatMessageContent, client::LlmClient};
 use tokio::sync::RwLock;
 use std::sync::Arc;
@@ -71,7 +71,10 @@ async fn test_coder_brain_think_simple() {
             name: None,
         }])),
         available_tools: vec![],
-        method: ToolCallMethod::FunctionCall
+        method: ToolCallMethod::FunctionCall,
+        delta_tx: None,
+        retry_policy: RetryPolicy::none(),
+        event_tx: None,
     };
     
     let result = brain.next_step(context).await;
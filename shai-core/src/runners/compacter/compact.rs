@@ -0,0 +1,1419 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+use shai_llm::{client::LlmClient, estimate_message_tokens, estimate_tokens, ChatMessage, ChatMessageContent, ToolCall};
+use openai_dive::v1::resources::chat::ChatMessageContentPart;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use super::prompt::{get_chunk_summary_prompt, get_compression_summary_prompt};
+
+/// Fraction of the context window above which compression is triggered
+pub const COMPRESSION_THRESHOLD: f32 = 0.9;
+
+/// Default fraction of the context window kept verbatim as recent messages
+const DEFAULT_RECENT_RATIO: f32 = 0.3;
+
+/// Default minimum number of recent messages kept verbatim
+const DEFAULT_RECENT_MESSAGES_TO_KEEP: usize = 6;
+
+/// Name given to the system message holding a conversation summary
+const SUMMARY_NAME: &str = "summary";
+/// Name marking a user message as pinned, see `is_pinned`
+pub const PINNED_NAME: &str = "pinned";
+
+/// Summary of what a compression pass did to the conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionInfo {
+    pub tokens_before: u32,
+    pub current_tokens: u32,
+    pub max_tokens: u32,
+    pub messages_summarized: usize,
+    pub messages_kept: usize,
+    /// minimum number of recent messages that was configured to be kept
+    pub recent_messages_to_keep: usize,
+    pub summary_tokens: u32,
+}
+
+impl CompressionInfo {
+    pub fn tokens_saved(&self) -> u32 {
+        self.tokens_before.saturating_sub(self.current_tokens)
+    }
+}
+
+/// Why a compression pass left the conversation as it was
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SkipReason {
+    /// the context is below the compression threshold
+    BelowThreshold,
+    /// everything fits in the recent window, there is nothing to summarize
+    NothingToSummarize,
+    /// the compression was cancelled before the summary was ready
+    Cancelled,
+    /// the summarization could not run at all
+    Failed(String),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::BelowThreshold => write!(f, "context is below the compression threshold"),
+            SkipReason::NothingToSummarize => write!(f, "only recent messages, nothing to summarize"),
+            SkipReason::Cancelled => write!(f, "compression cancelled"),
+            SkipReason::Failed(error) => write!(f, "compression failed: {}", error),
+        }
+    }
+}
+
+/// Result of a compression pass, skips carry enough to explain why nothing happened
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CompressionOutcome {
+    Compressed(CompressionInfo),
+    Skipped {
+        reason: SkipReason,
+        current_tokens: u32,
+        /// token count above which the automatic compression kicks in
+        threshold: u32,
+    },
+}
+
+impl CompressionOutcome {
+    pub fn info(&self) -> Option<&CompressionInfo> {
+        match self {
+            CompressionOutcome::Compressed(info) => Some(info),
+            CompressionOutcome::Skipped { .. } => None,
+        }
+    }
+
+    pub fn into_info(self) -> Option<CompressionInfo> {
+        match self {
+            CompressionOutcome::Compressed(info) => Some(info),
+            CompressionOutcome::Skipped { .. } => None,
+        }
+    }
+}
+
+/// Reasons a summarization can fail
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CompressionError {
+    #[error("no llm configured for summarization")]
+    NoLlmClient,
+    #[error("llm call failed: {0}")]
+    LlmFailed(String),
+    #[error("empty summary returned by the llm")]
+    EmptySummary,
+    #[error("summarization cancelled")]
+    Cancelled,
+}
+
+impl CompressionError {
+    /// Misconfiguration that will fail again on retry, as opposed to a failed llm call
+    pub fn is_configuration_error(&self) -> bool {
+        matches!(self, CompressionError::NoLlmClient)
+    }
+}
+
+/// What a compression would do, computed without calling the llm
+#[derive(Debug, Clone)]
+pub struct CompressionPreview {
+    /// messages that would be replaced by the summary
+    pub messages_to_summarize: Vec<ChatMessage>,
+    /// system, pinned and recent messages kept verbatim
+    pub messages_to_keep: Vec<ChatMessage>,
+    /// pinned and recent messages kept, counted like `CompressionInfo::messages_kept`
+    pub messages_kept: usize,
+    pub first_user_message: Option<String>,
+    pub tokens_before: u32,
+    /// upper bound of the savings, the size of the summary is not known in advance
+    pub projected_tokens_saved: u32,
+}
+
+/// Which named system messages survive a compression. The unnamed system prompt and the
+/// summaries are not concerned: the prompt is always kept and summaries are folded into the next one.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum SystemMessageFilter {
+    /// every system message is kept
+    #[default]
+    KeepAll,
+    /// only the system messages with one of these names are kept
+    Allow(HashSet<String>),
+    /// the system messages with one of these names are dropped, e.g. ephemeral reminders
+    Deny(HashSet<String>),
+}
+
+impl SystemMessageFilter {
+    pub fn allow<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        Self::Allow(names.into_iter().map(Into::into).collect())
+    }
+
+    pub fn deny<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        Self::Deny(names.into_iter().map(Into::into).collect())
+    }
+
+    /// Whether a system message with this name is kept
+    pub fn keeps(&self, name: Option<&str>) -> bool {
+        match (self, name) {
+            (_, None) | (Self::KeepAll, _) => true,
+            (Self::Allow(names), Some(name)) => names.contains(name),
+            (Self::Deny(names), Some(name)) => !names.contains(name),
+        }
+    }
+}
+
+/// Split of a conversation between what is kept and what is summarized
+struct Partition {
+    system: Vec<ChatMessage>,
+    /// system messages left out by the filter, neither kept nor summarized
+    dropped: Vec<ChatMessage>,
+    pinned: Vec<ChatMessage>,
+    middle: Vec<ChatMessage>,
+    recent: Vec<ChatMessage>,
+}
+
+/// Callback notified with (step, total) while a long summarization is running
+pub type ProgressHandler = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Token count of a single message, to replace the default estimate with the model's tokenizer
+pub type Tokenizer = Arc<dyn Fn(&ChatMessage) -> u32 + Send + Sync>;
+
+/// Keeps track of the context usage and summarizes older messages
+/// once the conversation gets close to the model context window.
+/// Clones share the token count, so usage reported on one is seen by all.
+#[derive(Clone)]
+pub struct ContextCompressor {
+    pub max_tokens: u32,
+    current_tokens: Arc<AtomicU32>,
+    /// fraction of max_tokens kept verbatim at the end of the conversation
+    pub recent_ratio: f32,
+    /// the last messages are always kept verbatim, even over the recent token budget
+    pub recent_messages_to_keep: usize,
+    /// fraction of max_tokens above which the conversation is compressed
+    pub compress_threshold: f32,
+    pub llm_client: Option<Arc<LlmClient>>,
+    pub model: Option<String>,
+    pub on_progress: Option<ProgressHandler>,
+    /// counts the tokens of a message, the default is `estimate_message_tokens`
+    pub tokenizer: Option<Tokenizer>,
+    /// named system messages kept by a compression, all of them by default
+    pub system_filter: SystemMessageFilter,
+}
+
+/// Builder for ContextCompressor, every option left out keeps its default
+pub struct ContextCompressorBuilder {
+    max_tokens: u32,
+    llm_client: Option<Arc<LlmClient>>,
+    model: Option<String>,
+    compress_threshold: f32,
+    recent_ratio: f32,
+    recent_messages_to_keep: usize,
+    tokenizer: Option<Tokenizer>,
+    system_filter: SystemMessageFilter,
+}
+
+impl Default for ContextCompressorBuilder {
+    fn default() -> Self {
+        Self {
+            max_tokens: 0,
+            llm_client: None,
+            model: None,
+            compress_threshold: COMPRESSION_THRESHOLD,
+            recent_ratio: DEFAULT_RECENT_RATIO,
+            recent_messages_to_keep: DEFAULT_RECENT_MESSAGES_TO_KEEP,
+            tokenizer: None,
+            system_filter: SystemMessageFilter::default(),
+        }
+    }
+}
+
+impl ContextCompressorBuilder {
+    /// Context window of the model, 0 disables the compression
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Client used to summarize, without it a compression falls back to an outline
+    pub fn llm(mut self, llm_client: Arc<LlmClient>) -> Self {
+        self.llm_client = Some(llm_client);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn compress_threshold(mut self, threshold: f32) -> Self {
+        self.compress_threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn recent_ratio(mut self, ratio: f32) -> Self {
+        self.recent_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn recent_keep(mut self, count: usize) -> Self {
+        self.recent_messages_to_keep = count;
+        self
+    }
+
+    pub fn tokenizer(mut self, tokenizer: impl Fn(&ChatMessage) -> u32 + Send + Sync + 'static) -> Self {
+        self.tokenizer = Some(Arc::new(tokenizer));
+        self
+    }
+
+    /// Named system messages to keep on compression, the others are dropped. Names set by the
+    /// agent itself (like its prompt suffix) must be listed too to survive.
+    pub fn keep_system_messages<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.system_filter = SystemMessageFilter::allow(names);
+        self
+    }
+
+    /// Named system messages to drop on compression, the others are kept
+    pub fn drop_system_messages<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.system_filter = SystemMessageFilter::deny(names);
+        self
+    }
+
+    pub fn build(self) -> ContextCompressor {
+        ContextCompressor {
+            max_tokens: self.max_tokens,
+            current_tokens: Arc::new(AtomicU32::new(0)),
+            recent_ratio: self.recent_ratio,
+            recent_messages_to_keep: self.recent_messages_to_keep,
+            compress_threshold: self.compress_threshold,
+            llm_client: self.llm_client,
+            model: self.model,
+            on_progress: None,
+            tokenizer: self.tokenizer,
+            system_filter: self.system_filter,
+        }
+    }
+}
+
+impl ContextCompressor {
+    pub fn builder() -> ContextCompressorBuilder {
+        ContextCompressorBuilder::default()
+    }
+
+    pub fn new(max_tokens: u32) -> Self {
+        Self::builder().max_tokens(max_tokens).build()
+    }
+
+    pub fn new_with_llm(max_tokens: u32, llm_client: Arc<LlmClient>, model: String) -> Self {
+        Self::builder().max_tokens(max_tokens).llm(llm_client).model(model).build()
+    }
+
+    pub fn with_recent_ratio(mut self, ratio: f32) -> Self {
+        self.recent_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_recent_messages_to_keep(mut self, count: usize) -> Self {
+        self.recent_messages_to_keep = count;
+        self
+    }
+
+    pub fn set_progress_handler(&mut self, handler: Option<ProgressHandler>) {
+        self.on_progress = handler;
+    }
+
+    /// Tokens of the messages, counted with the tokenizer if one was given
+    pub fn count_tokens(&self, messages: &[ChatMessage]) -> u32 {
+        match &self.tokenizer {
+            Some(tokenizer) => messages.iter().map(|m| tokenizer(m)).sum(),
+            None => estimate_tokens(messages),
+        }
+    }
+
+    fn count_message_tokens(&self, message: &ChatMessage) -> u32 {
+        match &self.tokenizer {
+            Some(tokenizer) => tokenizer(message),
+            None => estimate_message_tokens(message),
+        }
+    }
+
+    /// Current context size, as last reported or estimated
+    pub fn current_tokens(&self) -> u32 {
+        self.current_tokens.load(Ordering::Relaxed)
+    }
+
+    /// Update the current context size, usually with the prompt tokens reported by the provider
+    pub fn update_token_count(&self, tokens: u32) {
+        self.current_tokens.store(tokens, Ordering::Relaxed);
+    }
+
+    /// Re-estimate the context size from the messages, for when the provider has not reported usage yet (e.g. a resumed session)
+    pub fn estimate_token_count(&self, messages: &[ChatMessage]) {
+        self.update_token_count(self.count_tokens(messages));
+    }
+
+    /// Store the size of a rewritten conversation, unless a usage report came in
+    /// since `seen` was read in which case the newer count is kept
+    fn settle_token_count(&self, seen: u32, tokens: u32) {
+        let _ = self.current_tokens.compare_exchange(seen, tokens, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn should_compress_conversation(&self) -> bool {
+        self.exceeds_threshold(self.current_tokens())
+    }
+
+    /// Same as should_compress_conversation but also gauges the messages themselves,
+    /// so an oversized conversation is caught before the provider ever reported usage
+    pub fn should_compress_messages(&self, messages: &[ChatMessage]) -> bool {
+        self.exceeds_threshold(self.current_tokens().max(self.count_tokens(messages)))
+    }
+
+    fn exceeds_threshold(&self, tokens: u32) -> bool {
+        self.max_tokens > 0
+            && tokens as f32 >= self.max_tokens as f32 * self.compress_threshold
+    }
+
+    /// Token count above which the automatic compression kicks in
+    pub fn threshold_tokens(&self) -> u32 {
+        (self.max_tokens as f32 * self.compress_threshold) as u32
+    }
+
+    /// Outcome of a pass that left the conversation unchanged
+    pub fn skipped(&self, reason: SkipReason) -> CompressionOutcome {
+        CompressionOutcome::Skipped {
+            reason,
+            current_tokens: self.current_tokens(),
+            threshold: self.threshold_tokens(),
+        }
+    }
+
+    /// Token budget for the messages kept verbatim after compression
+    pub fn recent_budget(&self) -> u32 {
+        (self.max_tokens as f32 * self.recent_ratio) as u32
+    }
+
+    /// Compress the conversation if it is over the threshold
+    pub async fn compress_messages(&mut self, messages: Vec<ChatMessage>, full_trace: &[ChatMessage]) -> (Vec<ChatMessage>, CompressionOutcome) {
+        if !self.should_compress_conversation() {
+            return (messages, self.skipped(SkipReason::BelowThreshold));
+        }
+        self.compress_messages_internal(messages, full_trace, None).await
+    }
+
+    /// Compress the conversation regardless of the current token count, used by the automatic
+    /// compression. A failed summarization, missing llm included, falls back to an outline.
+    pub async fn compress_messages_now(&mut self, messages: Vec<ChatMessage>, full_trace: &[ChatMessage]) -> (Vec<ChatMessage>, CompressionOutcome) {
+        self.compress_messages_internal(messages, full_trace, None).await
+    }
+
+    /// Compress the conversation regardless of the current token count.
+    /// Fails right away if no llm is configured instead of dropping messages for a fallback notice.
+    pub async fn compress_messages_force(&mut self, messages: Vec<ChatMessage>, full_trace: &[ChatMessage]) -> Result<(Vec<ChatMessage>, CompressionOutcome), CompressionError> {
+        if self.llm_client.is_none() || self.model.is_none() {
+            return Err(CompressionError::NoLlmClient);
+        }
+        Ok(self.compress_messages_internal(messages, full_trace, None).await)
+    }
+
+    /// Compress the conversation regardless of the current token count, can be aborted
+    /// with the token in which case the messages are returned unchanged.
+    /// A failed summarization falls back to a notice, as for the automatic compression.
+    pub async fn compress_messages_cancellable(&mut self, messages: Vec<ChatMessage>, full_trace: &[ChatMessage], cancellation_token: &CancellationToken) -> (Vec<ChatMessage>, CompressionOutcome) {
+        self.compress_messages_internal(messages, full_trace, Some(cancellation_token)).await
+    }
+
+    /// True if the messages would not fit in the context window next to `reserved_tokens`
+    /// sent along with them (system prompt, tool definitions)
+    pub fn exceeds_window(&self, messages: &[ChatMessage], reserved_tokens: u32) -> bool {
+        self.max_tokens > 0 && self.count_tokens(messages) + reserved_tokens > self.max_tokens
+    }
+
+    /// Last resort when even a summary does not fit: drop the oldest messages (system messages,
+    /// summaries and pinned messages excepted) until the conversation fits in the window next
+    /// to `reserved_tokens`. The last message is always kept. Returns the kept messages and how
+    /// many were dropped.
+    pub fn truncate_to_fit(&mut self, messages: Vec<ChatMessage>, reserved_tokens: u32) -> (Vec<ChatMessage>, usize) {
+        let (mut kept, mut conversation): (Vec<_>, Vec<_>) = messages.into_iter()
+            .partition(|m| matches!(m, ChatMessage::System { .. }) || is_pinned(m));
+
+        let budget = self.max_tokens.saturating_sub(reserved_tokens);
+        let mut dropped = 0;
+        let mut tokens = self.count_tokens(&kept) + self.count_tokens(&conversation);
+        while tokens > budget && conversation.len() > 1 {
+            tokens -= self.count_message_tokens(&conversation.remove(0));
+            dropped += 1;
+            // tool results cannot outlive the assistant message that called them
+            while conversation.len() > 1 && matches!(conversation[0], ChatMessage::Tool { .. }) {
+                tokens -= self.count_message_tokens(&conversation.remove(0));
+                dropped += 1;
+            }
+        }
+
+        kept.extend(conversation);
+        self.update_token_count(tokens);
+        (kept, dropped)
+    }
+
+    /// Show what a forced compression would summarize and keep, without touching any state
+    pub fn preview_compression(&self, messages: &[ChatMessage], full_trace: &[ChatMessage]) -> CompressionPreview {
+        let tokens_before = self.current_tokens().max(self.count_tokens(messages));
+        let Partition { system, pinned, middle, recent, .. } = self.partition(messages.to_vec());
+
+        let messages_kept = pinned.len() + recent.len();
+        let mut messages_to_keep = system;
+        messages_to_keep.extend(pinned);
+        messages_to_keep.extend(recent);
+        CompressionPreview {
+            projected_tokens_saved: self.count_tokens(&middle),
+            messages_to_summarize: middle,
+            messages_to_keep,
+            messages_kept,
+            first_user_message: first_user_message(full_trace),
+            tokens_before,
+        }
+    }
+
+    /// Split the conversation, system messages (the prompt as well as named ones like the
+    /// agent's prompt suffix) are kept unless the system filter leaves them out, and previous
+    /// summaries get folded into the new one. Pinned messages that fall before the recent
+    /// window are kept verbatim instead of summarized. Shared by preview and compression.
+    fn partition(&self, messages: Vec<ChatMessage>) -> Partition {
+        let mut system = Vec::new();
+        let mut dropped = Vec::new();
+        let mut conversation = Vec::new();
+        for message in messages {
+            match &message {
+                ChatMessage::System { name, .. } if !is_summary(&message) => {
+                    if self.system_filter.keeps(name.as_deref()) {
+                        system.push(message);
+                    } else {
+                        dropped.push(message);
+                    }
+                }
+                _ => conversation.push(message),
+            }
+        }
+
+        let split = self.recent_window_start(&conversation);
+        let recent = conversation.split_off(split);
+        let (pinned, middle): (Vec<_>, Vec<_>) = conversation.into_iter().partition(is_pinned);
+        Partition { system, dropped, pinned, middle, recent }
+    }
+
+    async fn compress_messages_internal(&mut self, messages: Vec<ChatMessage>, full_trace: &[ChatMessage], cancellation_token: Option<&CancellationToken>) -> (Vec<ChatMessage>, CompressionOutcome) {
+        let seen_tokens = self.current_tokens();
+        let tokens_before = seen_tokens.max(self.count_tokens(&messages));
+        let original = cancellation_token.map(|_| messages.clone());
+
+        let Partition { system: system_messages, dropped, pinned, middle, recent } = self.partition(messages);
+        if middle.is_empty() {
+            debug!(target: "compacter", "nothing to compress");
+            // nothing is compressed, the filtered system messages stay as well
+            let mut kept = system_messages;
+            kept.extend(dropped);
+            kept.extend(pinned);
+            kept.extend(recent);
+            return (kept, self.skipped(SkipReason::NothingToSummarize));
+        }
+
+        let messages_summarized = middle.len();
+        let middle = dedupe_tool_outputs(middle);
+        let first_user_message = first_user_message(full_trace);
+        let (summary, summary_tokens) = match self.summarize_conversation(&middle, first_user_message.as_deref(), cancellation_token).await {
+            Ok(result) => result,
+            Err(CompressionError::Cancelled) => {
+                debug!(target: "compacter", "compression cancelled, keeping the conversation unchanged");
+                return (original.unwrap_or_default(), self.skipped(SkipReason::Cancelled));
+            }
+            Err(e) => {
+                warn!(target: "compacter", error = %e, "summarization failed, falling back to an outline");
+                (outline_messages(&middle), 0)
+            }
+        };
+
+        let mut compressed = system_messages;
+        compressed.push(ChatMessage::System {
+            content: ChatMessageContent::Text(format!("Summary of the previous conversation:\n{}", summary)),
+            name: Some(SUMMARY_NAME.to_string()),
+        });
+        let messages_kept = pinned.len() + recent.len();
+        compressed.extend(pinned);
+        compressed.extend(recent);
+
+        // system messages, summary and kept messages alike, whatever usage got reported meanwhile
+        let tokens_after = self.count_tokens(&compressed);
+        self.settle_token_count(seen_tokens, tokens_after);
+        debug!(target: "compacter", tokens_before, tokens_after, summarized = messages_summarized, kept = messages_kept);
+
+        let info = CompressionInfo {
+            tokens_before,
+            current_tokens: tokens_after,
+            max_tokens: self.max_tokens,
+            messages_summarized,
+            messages_kept,
+            recent_messages_to_keep: self.recent_messages_to_keep,
+            summary_tokens,
+        };
+        (compressed, CompressionOutcome::Compressed(info))
+    }
+
+    /// Index of the first message kept verbatim. Messages are accumulated from the end
+    /// until the recent token budget is spent, the latest `recent_messages_to_keep`
+    /// messages (and at least the last one) are always kept.
+    fn recent_window_start(&self, conversation: &[ChatMessage]) -> usize {
+        let budget = self.recent_budget();
+        let mut used = 0u32;
+        let mut start = conversation.len();
+        for (i, message) in conversation.iter().enumerate().rev() {
+            let tokens = self.count_message_tokens(message);
+            if start < conversation.len() && used + tokens > budget {
+                break;
+            }
+            used += tokens;
+            start = i;
+        }
+        start = start.min(conversation.len().saturating_sub(self.recent_messages_to_keep));
+
+        // a tool result cannot be kept without the assistant message that called it,
+        // skip leading tool results or, if only tool results are left, keep their caller as well
+        let is_tool = |i: usize| matches!(conversation[i], ChatMessage::Tool { .. });
+        let mut forward = start;
+        while forward < conversation.len() && is_tool(forward) {
+            forward += 1;
+        }
+        if forward < conversation.len() {
+            return forward;
+        }
+        while start > 0 && is_tool(start) {
+            start -= 1;
+        }
+        start
+    }
+
+    /// Token budget of a single summarization request, leaves room for the prompt and the answer
+    fn chunk_budget(&self) -> u32 {
+        (self.max_tokens / 2).max(1)
+    }
+
+    /// Ask the llm for a summary of the given messages, returns the summary and the tokens spent.
+    /// Conversations that do not fit in a single request are summarized chunk by chunk
+    /// and the partial summaries are then merged in a final pass.
+    pub async fn summarize_conversation(&self, messages: &[ChatMessage], first_user_message: Option<&str>, cancellation_token: Option<&CancellationToken>) -> Result<(String, u32), CompressionError> {
+        let budget = self.chunk_budget();
+        let texts = messages.iter()
+            .filter_map(message_to_text)
+            .collect::<Vec<_>>();
+
+        let mut chunks = chunk_texts(texts, budget);
+        let mut total_tokens = 0;
+
+        // map: summarize each chunk until everything fits in a single request
+        while chunks.len() > 1 {
+            debug!(target: "compacter", chunks = chunks.len(), "summarizing conversation in chunks");
+            let mut partials = Vec::with_capacity(chunks.len());
+            for (i, chunk) in chunks.iter().enumerate() {
+                if let Some(on_progress) = &self.on_progress {
+                    on_progress(i + 1, chunks.len());
+                }
+                let prompt = get_chunk_summary_prompt(i + 1, chunks.len(), chunk);
+                let (summary, tokens) = self.request_summary(prompt, cancellation_token).await?;
+                total_tokens += tokens;
+                partials.push(format!("Part {}:\n{}", i + 1, summary));
+            }
+
+            let next = chunk_texts(partials.clone(), budget);
+            if next.len() >= chunks.len() {
+                // summaries are not getting any shorter, merge what we have with an equal share
+                // of the budget for each part so that the final request still fits
+                // (one token of each share is left for the separator)
+                let share = (budget / partials.len() as u32).saturating_sub(1);
+                chunks = vec![partials.into_iter()
+                    .map(|partial| truncate_text(partial, share))
+                    .collect::<Vec<_>>()
+                    .join("\n\n")];
+                break;
+            }
+            chunks = next;
+        }
+
+        // reduce: final summary keeping the first user message verbatim
+        let conversation_text = chunks.pop().unwrap_or_default();
+        let prompt = get_compression_summary_prompt(first_user_message.unwrap_or("(unknown)"), &conversation_text);
+        let (summary, tokens) = self.request_summary(prompt, cancellation_token).await?;
+        Ok((summary, total_tokens + tokens))
+    }
+
+    async fn request_summary(&self, prompt: String, cancellation_token: Option<&CancellationToken>) -> Result<(String, u32), CompressionError> {
+        if cancellation_token.map_or(false, |t| t.is_cancelled()) {
+            return Err(CompressionError::Cancelled);
+        }
+        let (llm, model) = match (&self.llm_client, &self.model) {
+            (Some(llm), Some(model)) => (llm, model),
+            _ => return Err(CompressionError::NoLlmClient),
+        };
+
+        let request = ChatCompletionParametersBuilder::default()
+            .model(model)
+            .messages(vec![ChatMessage::User {
+                content: ChatMessageContent::Text(prompt),
+                name: None,
+            }])
+            .temperature(0.1)
+            .build()
+            .map_err(|e| CompressionError::LlmFailed(e.to_string()))?;
+
+        let response = match cancellation_token {
+            Some(token) => tokio::select! {
+                response = llm.chat(request) => response,
+                _ = token.cancelled() => return Err(CompressionError::Cancelled),
+            },
+            None => llm.chat(request).await,
+        }.map_err(|e| CompressionError::LlmFailed(e.to_string()))?;
+        // not every provider reports usage, the summary is still good without it
+        let tokens = response.usage.as_ref()
+            .map_or(0, |u| u.prompt_tokens.unwrap_or(0) + u.completion_tokens.unwrap_or(0));
+
+        match response.choices.first().map(|c| &c.message) {
+            Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. }) => Ok((text.clone(), tokens)),
+            _ => Err(CompressionError::EmptySummary),
+        }
+    }
+}
+
+/// Cut a text to `budget` tokens (4 characters each), the mark of the cut included
+fn truncate_text(mut text: String, budget: u32) -> String {
+    const MARK: &str = " [truncated]";
+    let max_chars = budget as usize * 4;
+    if text.len() > max_chars {
+        let mut cut = max_chars.saturating_sub(MARK.len());
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        text.push_str(MARK);
+    }
+    text
+}
+
+/// Group texts into chunks that each fit in the token budget,
+/// a single text larger than the budget is truncated
+fn chunk_texts(texts: Vec<String>, budget: u32) -> Vec<String> {
+    let max_chars = budget as usize * 4;
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for text in texts {
+        let text = truncate_text(text, budget);
+        if !current.is_empty() && current.len() + text.len() > max_chars {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&text);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Merge consecutive tool results that are exact duplicates (same file read twice in a row,
+/// same ls...) into the first one with a "(repeated Nx)" note. A repeat further away is kept,
+/// what happened in between may have changed its meaning.
+/// Only used on messages that are about to be summarized, the tool calls are not paired anymore.
+fn dedupe_tool_outputs(messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    let mut deduped: Vec<ChatMessage> = Vec::with_capacity(messages.len());
+    let mut repeats: Vec<(usize, usize)> = Vec::new(); // (index in deduped, occurrences)
+    for message in messages {
+        if let ChatMessage::Tool { content, .. } = &message {
+            // the previous tool result is always the last one recorded in repeats
+            if matches!(deduped.last(), Some(ChatMessage::Tool { content: c, .. }) if c == content) {
+                if let Some((_, count)) = repeats.last_mut() {
+                    *count += 1;
+                }
+                continue;
+            }
+            repeats.push((deduped.len(), 1));
+        }
+        deduped.push(message);
+    }
+
+    for (i, count) in repeats.into_iter().filter(|(_, count)| *count > 1) {
+        if let ChatMessage::Tool { content, .. } = &mut deduped[i] {
+            content.push_str(&format!("\n(repeated {}x)", count));
+        }
+    }
+    deduped
+}
+
+pub fn is_summary(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::System { name: Some(name), .. } if name == SUMMARY_NAME)
+}
+
+/// User messages pinned with `AgentController::pin_last_message`, never summarized
+pub fn is_pinned(message: &ChatMessage) -> bool {
+    matches!(message, ChatMessage::User { name: Some(name), .. } if name == PINNED_NAME)
+}
+
+/// Text of the earliest user message of the trace. The summary prompt asks to reproduce the
+/// first request of the user, so give it the uncompressed trace.
+pub fn first_user_message(trace: &[ChatMessage]) -> Option<String> {
+    trace.iter().find_map(user_text)
+}
+
+/// Text of the most recent user message of the trace
+pub fn latest_user_message(trace: &[ChatMessage]) -> Option<String> {
+    trace.iter().rev().find_map(user_text)
+}
+
+fn user_text(message: &ChatMessage) -> Option<String> {
+    match message {
+        ChatMessage::User { content, .. } => content_to_text(content),
+        _ => None,
+    }
+}
+
+/// Longest line kept per message in the outline fallback
+const OUTLINE_LINE_MAX_CHARS: usize = 120;
+
+/// Fallback summary when the llm is unavailable: the role and first line of each message,
+/// so some context survives. Bounded to half the size of the messages it replaces.
+fn outline_messages(messages: &[ChatMessage]) -> String {
+    let budget = messages.iter()
+        .filter_map(message_to_text)
+        .map(|text| text.len())
+        .sum::<usize>() / 2;
+
+    let mut outline = format!("[AI summary unavailable] outline of {} earlier messages:\n", messages.len());
+    let mut used = 0;
+    let mut listed = 0;
+    for line in messages.iter().filter_map(outline_line) {
+        if used + line.len() > budget {
+            break;
+        }
+        used += line.len();
+        listed += 1;
+        outline.push_str(&line);
+    }
+    if listed < messages.len() {
+        outline.push_str(&format!("- ... {} more\n", messages.len() - listed));
+    }
+    outline
+}
+
+fn outline_line(message: &ChatMessage) -> Option<String> {
+    let first_line = |text: &str| {
+        let line = text.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+        line.chars().take(OUTLINE_LINE_MAX_CHARS).collect::<String>()
+    };
+    let line = match message {
+        ChatMessage::Assistant { content, tool_calls: Some(calls), .. } if !calls.is_empty() => {
+            let names = calls.iter().map(|c| c.function.name.as_str()).collect::<Vec<_>>().join(", ");
+            match content.as_ref().and_then(content_to_text).filter(|text| !text.trim().is_empty()) {
+                Some(text) => format!("{} [called {}]", first_line(&format!("Assistant: {}", text)), names),
+                None => format!("Assistant: [called {}]", names),
+            }
+        }
+        _ => first_line(&message_to_text(message)?),
+    };
+    Some(format!("- {}\n", line))
+}
+
+fn message_to_text(message: &ChatMessage) -> Option<String> {
+    match message {
+        ChatMessage::User { content, .. } => Some(format!("User: {}", content_to_text(content)?)),
+        ChatMessage::Assistant { content, tool_calls, .. } => {
+            // the calls are what the agent did, the summary must not lose them
+            let text = content.as_ref().and_then(content_to_text).filter(|text| !text.trim().is_empty());
+            let lines = text.map(|text| format!("Assistant: {}", text)).into_iter()
+                .chain(tool_calls.iter().flatten().map(|call| format!("Assistant called {}", tool_call_to_text(call))))
+                .collect::<Vec<_>>();
+            (!lines.is_empty()).then(|| lines.join("\n"))
+        }
+        ChatMessage::Tool { content, .. } => Some(format!("Tool: {}", content)),
+        ChatMessage::System { content, name } if name.as_deref() == Some(SUMMARY_NAME) => Some(format!("Previous summary: {}", content_to_text(content)?)),
+        _ => None,
+    }
+}
+
+/// Longest argument value kept when a tool call is written out, file contents and patches are cut
+const TOOL_ARGUMENT_MAX_CHARS: usize = 200;
+
+/// A tool call as `name(key=value, ...)`, long values are shortened
+fn tool_call_to_text(call: &ToolCall) -> String {
+    let shorten = |value: &str| {
+        let mut short = value.chars().take(TOOL_ARGUMENT_MAX_CHARS).collect::<String>();
+        if short.len() < value.len() {
+            short.push_str("...");
+        }
+        short
+    };
+    let arguments = match serde_json::from_str::<serde_json::Value>(&call.function.arguments) {
+        Ok(serde_json::Value::Object(arguments)) => arguments.iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::String(text) => format!("{}={}", key, shorten(text)),
+                other => format!("{}={}", key, shorten(&other.to_string())),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => shorten(call.function.arguments.trim()),
+    };
+    format!("{}({})", call.function.name, arguments)
+}
+
+/// Text of a message content, the parts that are not text (images, audio) are kept as placeholders
+/// so the summary still knows they were there
+fn content_to_text(content: &ChatMessageContent) -> Option<String> {
+    match content {
+        ChatMessageContent::Text(text) => Some(text.clone()),
+        ChatMessageContent::ContentPart(parts) if !parts.is_empty() => Some(parts.iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(text_part) => text_part.text.clone(),
+                ChatMessageContentPart::Image(_) => "[image]".to_string(),
+                _ => "[attachment]".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    fn summary(text: &str) -> ChatMessage {
+        ChatMessage::System { content: ChatMessageContent::Text(text.to_string()), name: Some(SUMMARY_NAME.to_string()) }
+    }
+
+    #[test]
+    fn test_should_compress_messages_without_usage() {
+        let compressor = ContextCompressor::new(100);
+        assert!(!compressor.should_compress_conversation());
+        assert!(compressor.should_compress_messages(&[user(&"a".repeat(400))]));
+        assert!(!compressor.should_compress_messages(&[user("hello")]));
+    }
+
+    #[test]
+    fn test_estimate_token_count_from_messages() {
+        let compressor = ContextCompressor::new(1000);
+        compressor.estimate_token_count(&[user(&"a".repeat(400)), user(&"b".repeat(400))]);
+        assert!(compressor.current_tokens() >= 200);
+        assert!(compressor.current_tokens() < 250);
+    }
+
+    fn summaries(messages: &[ChatMessage]) -> usize {
+        messages.iter().filter(|m| is_summary(m)).count()
+    }
+
+    #[tokio::test]
+    async fn test_message_compression() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![ChatMessage::System {
+            content: ChatMessageContent::Text("you are a coder".to_string()),
+            name: None,
+        }];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+        let full_trace = messages.clone();
+
+        let (compressed, outcome) = compressor.compress_messages_now(messages, &full_trace).await;
+        let info = outcome.into_info().expect("conversation should have been compressed");
+
+        assert!(matches!(&compressed[0], ChatMessage::System { name: None, .. }));
+        assert_eq!(summaries(&compressed), 1);
+        assert_eq!(compressed.len(), 2 + info.messages_kept);
+        assert_eq!(info.messages_summarized + info.messages_kept, 10);
+        assert!(info.current_tokens < info.tokens_before);
+        assert!(matches!(compressed.last(), Some(ChatMessage::User { content: ChatMessageContent::Text(t), .. }) if t == "message number 9"));
+    }
+
+    #[tokio::test]
+    async fn test_named_system_messages_survive_compression() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![ChatMessage::System {
+            content: ChatMessageContent::Text("use tabs".to_string()),
+            name: Some("instructions".to_string()),
+        }];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+
+        compressor.update_token_count(100);
+        let (compressed, outcome) = compressor.compress_messages(messages.clone(), &messages).await;
+
+        assert!(outcome.info().is_some());
+        assert!(matches!(&compressed[0], ChatMessage::System { name: Some(name), .. } if name == "instructions"));
+        assert_eq!(summaries(&compressed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_system_messages_are_filtered_by_name() {
+        let system = |name: &str| ChatMessage::System {
+            content: ChatMessageContent::Text(format!("{} content", name)),
+            name: Some(name.to_string()),
+        };
+        let names = |messages: &[ChatMessage]| -> Vec<String> {
+            messages.iter().filter(|m| !is_summary(m)).filter_map(|m| match m {
+                ChatMessage::System { name, .. } => Some(name.clone().unwrap_or_default()),
+                _ => None,
+            }).collect()
+        };
+        let mut messages = vec![
+            ChatMessage::System { content: ChatMessageContent::Text("you are a coder".to_string()), name: None },
+            system("project"),
+            system("reminder"),
+        ];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+
+        let mut denying = ContextCompressor::builder().max_tokens(100).drop_system_messages(["reminder"]).build();
+        denying.update_token_count(100);
+        let (compressed, outcome) = denying.compress_messages(messages.clone(), &messages).await;
+        assert!(outcome.info().is_some());
+        assert_eq!(names(&compressed), vec!["", "project"]);
+        assert!(!denying.preview_compression(&messages, &messages).messages_to_keep.iter()
+            .any(|m| matches!(m, ChatMessage::System { name: Some(name), .. } if name == "reminder")));
+
+        let mut allowing = ContextCompressor::builder().max_tokens(100).keep_system_messages(["reminder"]).build();
+        allowing.update_token_count(100);
+        let (compressed, _) = allowing.compress_messages(messages.clone(), &messages).await;
+        assert_eq!(names(&compressed), vec!["", "reminder"]);
+
+        // the default keeps everything
+        let mut default = ContextCompressor::new(100);
+        default.update_token_count(100);
+        let (compressed, _) = default.compress_messages(messages.clone(), &messages).await;
+        assert_eq!(names(&compressed), vec!["", "project", "reminder"]);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_messages_survive_compression() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![ChatMessage::User {
+            content: ChatMessageContent::Text("never touch the migrations folder".to_string()),
+            name: Some(PINNED_NAME.to_string()),
+        }];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+
+        let preview = compressor.preview_compression(&messages, &messages);
+        assert!(!preview.messages_to_summarize.iter().any(is_pinned));
+
+        compressor.update_token_count(100);
+        let (compressed, outcome) = compressor.compress_messages(messages.clone(), &messages).await;
+
+        assert!(outcome.info().is_some());
+        assert_eq!(summaries(&compressed), 1);
+        assert!(is_summary(&compressed[0]));
+        assert!(is_pinned(&compressed[1]));
+    }
+
+    #[tokio::test]
+    async fn test_preview_matches_compression() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![ChatMessage::System {
+            content: ChatMessageContent::Text("you are a coder".to_string()),
+            name: None,
+        }];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+
+        let preview = compressor.preview_compression(&messages, &messages);
+        assert_eq!(compressor.current_tokens(), 0);
+        assert_eq!(preview.first_user_message.as_deref(), Some("message number 0"));
+        assert!(preview.projected_tokens_saved > 0);
+
+        let (_, outcome) = compressor.compress_messages_now(messages.clone(), &messages).await;
+        let info = outcome.into_info().unwrap();
+        assert_eq!(preview.messages_to_summarize.len(), info.messages_summarized);
+        assert_eq!(preview.messages_kept, info.messages_kept);
+        // the system prompt is kept as well, without being counted
+        assert_eq!(preview.messages_to_keep.len(), info.messages_kept + 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_compression_keeps_messages() {
+        let mut compressor = ContextCompressor::new(100);
+        let messages: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let (kept, outcome) = compressor.compress_messages_cancellable(messages.clone(), &messages, &token).await;
+
+        assert!(matches!(outcome, CompressionOutcome::Skipped { reason: SkipReason::Cancelled, .. }));
+        assert_eq!(kept.len(), messages.len());
+        assert_eq!(compressor.current_tokens(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_forced_compression_without_llm_is_an_error() {
+        let mut compressor = ContextCompressor::new(100);
+        let messages: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+
+        let result = compressor.compress_messages_force(messages.clone(), &messages).await;
+
+        let err = result.err().expect("forced compression should fail without an llm");
+        assert_eq!(err, CompressionError::NoLlmClient);
+        assert!(err.is_configuration_error());
+        assert_eq!(compressor.current_tokens(), 0);
+    }
+
+    #[test]
+    fn test_tool_calls_are_kept_in_the_transcript() {
+        let call = |name: &str, arguments: &str| ToolCall {
+            id: format!("call_{}", name),
+            r#type: "function".to_string(),
+            function: shai_llm::Function { name: name.to_string(), arguments: arguments.to_string() },
+        };
+        let message = ChatMessage::Assistant {
+            content: None,
+            reasoning_content: None,
+            tool_calls: Some(vec![
+                call("edit_file", &serde_json::json!({ "path": "src/main.rs", "new_string": "x".repeat(500) }).to_string()),
+                call("ls", "{}"),
+            ]),
+            refusal: None,
+            name: None,
+            audio: None,
+        };
+
+        let text = message_to_text(&message).expect("tool calls are part of the conversation");
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("Assistant called edit_file(") && lines[0].contains("path=src/main.rs"), "{}", lines[0]);
+        assert!(lines[0].len() < 300, "long arguments are shortened");
+        assert_eq!(lines[1], "Assistant called ls()");
+        assert_eq!(outline_line(&message).as_deref(), Some("- Assistant: [called edit_file, ls]\n"));
+    }
+
+    #[test]
+    fn test_multimodal_messages_are_textualized() {
+        let content: ChatMessageContent = serde_json::from_value(serde_json::json!([
+            { "type": "text", "text": "what is wrong in this screenshot?" },
+            { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+        ])).unwrap();
+        let message = ChatMessage::User { content, name: None };
+
+        assert_eq!(message_to_text(&message).as_deref(), Some("User: what is wrong in this screenshot? [image]"));
+        assert_eq!(first_user_message(&[message]).as_deref(), Some("what is wrong in this screenshot? [image]"));
+    }
+
+    #[test]
+    fn test_first_user_message_comes_from_full_trace() {
+        let full_trace = vec![user("fix the parser"), user("also add tests")];
+        let compressed = vec![summary("old summary"), user("also add tests")];
+
+        assert_eq!(first_user_message(&full_trace).as_deref(), Some("fix the parser"));
+        assert_eq!(first_user_message(&compressed).as_deref(), Some("also add tests"));
+    }
+
+    #[test]
+    fn test_first_and_latest_user_messages() {
+        let trace = vec![
+            summary("old summary"),
+            user("fix the parser"),
+            ChatMessage::Tool { tool_call_id: "call_1".to_string(), content: "parser.rs".to_string() },
+            user("also add tests"),
+            ChatMessage::Tool { tool_call_id: "call_2".to_string(), content: "tests.rs".to_string() },
+        ];
+
+        assert_eq!(first_user_message(&trace).as_deref(), Some("fix the parser"));
+        assert_eq!(latest_user_message(&trace).as_deref(), Some("also add tests"));
+        assert_eq!(first_user_message(&[summary("only a summary")]), None);
+        assert_eq!(latest_user_message(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_old_summaries_are_replaced_on_recompression() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![summary("first summary"), summary("second summary")];
+        messages.extend((0..10).map(|i| user(&format!("message number {}", i))));
+        let full_trace = messages.clone();
+
+        let (compressed, outcome) = compressor.compress_messages_now(messages, &full_trace).await;
+
+        assert!(outcome.info().is_some());
+        assert_eq!(summaries(&compressed), 1);
+        assert!(!compressed.iter().any(|m| matches!(m, ChatMessage::System { content: ChatMessageContent::Text(t), .. } if t == "first summary" || t == "second summary")));
+    }
+
+    #[test]
+    fn test_recent_window_is_token_based() {
+        let compressor = ContextCompressor::new(1000).with_recent_ratio(0.2).with_recent_messages_to_keep(0);
+        let conversation = vec![
+            user("first"),
+            user("second"),
+            user(&"x".repeat(4000)),
+            user("small"),
+            user("last"),
+        ];
+
+        // the huge message does not fit in the 200 tokens budget
+        assert_eq!(compressor.recent_window_start(&conversation), 3);
+    }
+
+    #[test]
+    fn test_chunk_texts_fit_in_budget() {
+        let texts = vec!["a".repeat(30), "b".repeat(30), "c".repeat(30), "d".repeat(100)];
+        let chunks = chunk_texts(texts, 16);
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.len() <= 64));
+        assert!(chunks[2].ends_with("[truncated]"));
+    }
+
+    #[tokio::test]
+    async fn test_merged_partial_summaries_fit_in_the_budget() {
+        use shai_llm::providers::mock::MockProvider;
+
+        // partial summaries as long as what they summarize never shrink the chunk count
+        let mock = MockProvider::new();
+        for _ in 0..4 {
+            mock.push_text(&"s".repeat(300), None);
+        }
+        mock.push_text("final summary", None);
+        let compressor = ContextCompressor::new_with_llm(100, Arc::new(LlmClient::from_provider(mock.clone())), "mock-model".to_string());
+        let messages: Vec<_> = ["a", "b", "c", "d"].iter().map(|c| user(&c.repeat(180))).collect();
+
+        let (summary, _) = compressor.summarize_conversation(&messages, Some("hello"), None).await.unwrap();
+        assert_eq!(summary, "final summary");
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 5);
+        let Some(ChatMessage::User { content: ChatMessageContent::Text(prompt), .. }) = requests[4].messages.first() else {
+            panic!("the final request should be a user prompt");
+        };
+        let template = get_compression_summary_prompt("hello", "").len();
+        assert!(prompt.len() <= template + compressor.chunk_budget() as usize * 4, "{} chars", prompt.len());
+    }
+
+    #[test]
+    fn test_recent_window_keeps_tool_results_with_their_call() {
+        let compressor = ContextCompressor::new(1000).with_recent_ratio(0.01).with_recent_messages_to_keep(0);
+        let conversation = vec![
+            user("first"),
+            ChatMessage::Assistant {
+                content: None,
+                reasoning_content: None,
+                tool_calls: Some(vec![shai_llm::ToolCall {
+                    id: "call_1".to_string(),
+                    r#type: "function".to_string(),
+                    function: shai_llm::Function { name: "ls".to_string(), arguments: "{}".to_string() },
+                }]),
+                name: None,
+                audio: None,
+                refusal: None,
+            },
+            ChatMessage::Tool { tool_call_id: "call_1".to_string(), content: "a.txt".to_string() },
+        ];
+
+        assert_eq!(compressor.recent_window_start(&conversation), 1);
+    }
+
+    #[test]
+    fn test_recent_window_keeps_last_message() {
+        let compressor = ContextCompressor::new(1000).with_recent_ratio(0.1).with_recent_messages_to_keep(0);
+        let conversation = vec![user("first"), user(&"x".repeat(8000))];
+
+        assert_eq!(compressor.recent_window_start(&conversation), 1);
+    }
+
+    #[test]
+    fn test_recent_messages_to_keep_moves_the_boundary() {
+        let conversation: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+        let start = |count: usize| ContextCompressor::new(100)
+            .with_recent_ratio(0.0)
+            .with_recent_messages_to_keep(count)
+            .recent_window_start(&conversation);
+
+        assert_eq!(start(1), 9);
+        assert_eq!(start(4), 6);
+        assert_eq!(start(6), 4);
+        // not enough messages, everything is kept and nothing summarized
+        assert_eq!(start(20), 0);
+    }
+
+    #[test]
+    fn test_truncate_to_fit_drops_oldest_messages() {
+        let mut compressor = ContextCompressor::new(100);
+        let mut messages = vec![summary("earlier work")];
+        messages.extend((0..5).map(|i| user(&format!("{}{}", i, "x".repeat(100)))));
+        assert!(compressor.exceeds_window(&messages, 0));
+
+        let (kept, dropped) = compressor.truncate_to_fit(messages, 0);
+
+        assert!(!compressor.exceeds_window(&kept, 0));
+        assert_eq!(dropped, 2);
+        assert_eq!(summaries(&kept), 1);
+        assert!(matches!(&kept[1], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text.starts_with('2')));
+    }
+
+    #[test]
+    fn test_truncate_to_fit_keeps_pinned_messages_and_reserved_room() {
+        let mut compressor = ContextCompressor::new(100);
+        let pinned = ChatMessage::User {
+            content: ChatMessageContent::Text("never touch the migrations folder".to_string()),
+            name: Some(PINNED_NAME.to_string()),
+        };
+        let mut messages = vec![pinned];
+        messages.extend((0..3).map(|i| user(&format!("{}{}", i, "x".repeat(100)))));
+        assert!(!compressor.exceeds_window(&messages, 0));
+        assert!(compressor.exceeds_window(&messages, 30));
+
+        let (kept, dropped) = compressor.truncate_to_fit(messages, 30);
+
+        assert!(!compressor.exceeds_window(&kept, 30));
+        assert_eq!(dropped, 1);
+        assert!(is_pinned(&kept[0]));
+    }
+
+    #[tokio::test]
+    async fn test_compression_without_llm_keeps_an_outline() {
+        let mut compressor = ContextCompressor::new(1000).with_recent_messages_to_keep(1);
+        let messages: Vec<_> = (0..10)
+            .map(|i| user(&format!("step {} of the plan\n{}", i, "details ".repeat(50))))
+            .collect();
+
+        compressor.update_token_count(1000);
+        let (compressed, outcome) = compressor.compress_messages(messages.clone(), &messages).await;
+
+        assert!(outcome.info().is_some());
+        let ChatMessage::System { content: ChatMessageContent::Text(summary), .. } = &compressed[0] else {
+            panic!("expected the summary first");
+        };
+        assert!(summary.contains("[AI summary unavailable]"));
+        assert!(summary.contains("- User: step 0 of the plan\n"));
+        assert!(!summary.contains("details"));
+    }
+
+    #[tokio::test]
+    async fn test_short_conversation_is_not_compressed() {
+        let mut compressor = ContextCompressor::new(100).with_recent_messages_to_keep(20);
+        let messages: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+
+        compressor.update_token_count(100);
+        let (kept, outcome) = compressor.compress_messages(messages.clone(), &messages).await;
+
+        assert!(matches!(outcome, CompressionOutcome::Skipped { reason: SkipReason::NothingToSummarize, .. }));
+        assert_eq!(kept.len(), messages.len());
+    }
+
+    #[test]
+    fn test_clones_share_the_token_count() {
+        let compressor = ContextCompressor::new(1000);
+        let clone = compressor.clone();
+        clone.update_token_count(420);
+        assert_eq!(compressor.current_tokens(), 420);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_token_count_is_shared_across_tasks() {
+        let compressor = ContextCompressor::new(1000);
+        let writers: Vec<_> = (1..=8u32).map(|i| {
+            let compressor = compressor.clone();
+            tokio::spawn(async move {
+                for round in 0..100 {
+                    compressor.update_token_count(i * 1000 + round);
+                    tokio::task::yield_now().await;
+                }
+            })
+        }).collect();
+        let readers: Vec<_> = (0..4).map(|_| {
+            let compressor = compressor.clone();
+            tokio::spawn(async move {
+                for _ in 0..100 {
+                    // never a torn value, only one that a writer stored (or the initial 0)
+                    let tokens = compressor.current_tokens();
+                    assert!(tokens == 0 || (1000..=8099).contains(&tokens) && tokens % 1000 < 100, "{}", tokens);
+                    tokio::task::yield_now().await;
+                }
+            })
+        }).collect();
+
+        for task in writers.into_iter().chain(readers) {
+            task.await.unwrap();
+        }
+        // every writer finished on its last round, the count is one of them
+        let tokens = compressor.current_tokens();
+        assert_eq!(tokens % 1000, 99);
+        assert!((1..=8).contains(&(tokens / 1000)));
+    }
+
+    #[tokio::test]
+    async fn test_token_update_during_compression_is_kept() {
+        let mut compressor = ContextCompressor::new(100);
+        let messages: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+        compressor.update_token_count(100);
+
+        // usage reported through another handle while the summary is being written
+        let seen = compressor.current_tokens();
+        compressor.clone().update_token_count(95);
+        compressor.settle_token_count(seen, 20);
+        assert_eq!(compressor.current_tokens(), 95);
+
+        // without concurrent report the compressed size is stored
+        let (compressed, outcome) = compressor.compress_messages(messages, &[]).await;
+        let info = outcome.into_info().expect("conversation should have been compressed");
+        assert_eq!(compressor.current_tokens(), estimate_tokens(&compressed));
+        assert_eq!(info.current_tokens, estimate_tokens(&compressed));
+        assert_eq!(info.tokens_saved(), 95 - info.current_tokens);
+    }
+
+    #[tokio::test]
+    async fn test_skip_below_threshold_is_explained() {
+        let mut compressor = ContextCompressor::new(1000);
+        let messages: Vec<_> = (0..10).map(|i| user(&format!("message number {}", i))).collect();
+
+        compressor.update_token_count(100);
+        let (kept, outcome) = compressor.compress_messages(messages.clone(), &messages).await;
+
+        assert_eq!(kept.len(), messages.len());
+        match outcome {
+            CompressionOutcome::Skipped { reason, current_tokens, threshold } => {
+                assert_eq!(reason, SkipReason::BelowThreshold);
+                assert_eq!(current_tokens, 100);
+                assert_eq!(threshold, 900);
+            }
+            CompressionOutcome::Compressed(_) => panic!("should not compress below the threshold"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_tool_outputs_are_merged() {
+        let tool = |id: &str, content: &str| ChatMessage::Tool { tool_call_id: id.to_string(), content: content.to_string() };
+        let messages = vec![
+            user("list the files"),
+            tool("call_1", "a.txt b.txt"),
+            tool("call_2", "a.txt b.txt"),
+            tool("call_3", "hello"),
+            tool("call_4", "a.txt b.txt"),
+            tool("call_5", "a.txt b.txt"),
+        ];
+
+        let deduped = dedupe_tool_outputs(messages);
+
+        // only the adjacent repeats are merged
+        assert_eq!(deduped.len(), 4);
+        assert!(matches!(&deduped[1], ChatMessage::Tool { content, .. } if content == "a.txt b.txt\n(repeated 2x)"));
+        assert!(matches!(&deduped[2], ChatMessage::Tool { content, .. } if content == "hello"));
+        assert!(matches!(&deduped[3], ChatMessage::Tool { content, .. } if content == "a.txt b.txt\n(repeated 2x)"));
+    }
+
+    #[test]
+    fn test_builder_threshold_and_tokenizer_are_used() {
+        let compressor = ContextCompressor::builder()
+            .max_tokens(1000)
+            .compress_threshold(0.5)
+            .recent_keep(2)
+            .tokenizer(|_| 100)
+            .build();
+        let messages: Vec<_> = (0..5).map(|i| user(&format!("message {}", i))).collect();
+
+        assert_eq!(compressor.threshold_tokens(), 500);
+        assert_eq!(compressor.recent_messages_to_keep, 2);
+        assert_eq!(compressor.count_tokens(&messages), 500);
+        assert!(compressor.should_compress_messages(&messages));
+        assert!(!compressor.should_compress_messages(&messages[..4]));
+        assert_eq!(ContextCompressor::new(1000).threshold_tokens(), 900);
+    }
+}
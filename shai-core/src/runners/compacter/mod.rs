@@ -1 +1,4 @@
-pub mod compact;
\ No newline at end of file
+pub mod compact;
+pub mod prompt;
+
+pub use compact::{is_pinned, is_summary, first_user_message, latest_user_message, PINNED_NAME, CompressionError, CompressionInfo, CompressionOutcome, SkipReason, CompressionPreview, ContextCompressor, ContextCompressorBuilder, SystemMessageFilter, Tokenizer};
@@ -0,0 +1,40 @@
+
+static COMPRESSION_SUMMARY_PROMPT: &str = r#"
+You are summarizing a conversation between a user and a coding assistant so that the assistant can continue the work with a much smaller context.
+
+Your summary MUST follow this structure:
+
+1. **Original request**: reproduce the first user message verbatim, without rewording or shortening it:
+[Insert complete first user message here]
+
+2. **Work done so far**: the actions taken, the commands run and their outcome.
+3. **Files involved**: every file that was read, created or modified, with the relevant details.
+4. **Decisions**: technical choices made along the way and why.
+5. **Pending work**: what remains to be done and any open question.
+
+Be precise and factual, do not invent anything that is not in the conversation.
+
+Conversation to summarize:
+{}
+"#;
+
+static CHUNK_SUMMARY_PROMPT: &str = r#"
+Summarize this part ({}/{}) of a conversation between a user and a coding assistant.
+Keep the files involved, the decisions taken and any pending work.
+
+{}
+"#;
+
+pub fn get_compression_summary_prompt(first_user_message: &str, conversation_text: &str) -> String {
+    // substitute the conversation first so braces in the user message are left untouched
+    COMPRESSION_SUMMARY_PROMPT
+        .replacen("{}", conversation_text, 1)
+        .replacen("[Insert complete first user message here]", first_user_message, 1)
+}
+
+pub fn get_chunk_summary_prompt(index: usize, total: usize, chunk_text: &str) -> String {
+    CHUNK_SUMMARY_PROMPT
+        .replacen("{}", &index.to_string(), 1)
+        .replacen("{}", &total.to_string(), 1)
+        .replacen("{}", chunk_text, 1)
+}
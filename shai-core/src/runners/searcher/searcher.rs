@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
 use openai_dive::v1::resources::chat::{ChatCompletionParametersBuilder, ChatCompletionToolChoice};
-use shai_llm::{client::LlmClient, ChatMessage, ChatMessageContent};
+use shai_llm::{client::LlmClient, get_max_context, ChatMessage, ChatMessageContent};
 use async_trait::async_trait;
 
 use crate::agent::brain::ThinkerDecision;
 use crate::agent::{Agent, AgentBuilder, AgentError, Brain, ThinkerContext};
+use crate::runners::compacter::ContextCompressor;
 use crate::tools::{AnyTool, FetchTool, FindTool, LsTool, ReadTool, TodoReadTool, TodoWriteTool, TodoStorage};
 
 use super::prompt::searcher_next_step;
@@ -13,12 +14,14 @@ use super::prompt::searcher_next_step;
 #[derive(Clone)]
 pub struct SearcherBrain {
     pub llm: Arc<LlmClient>,
-    pub model: String
+    pub model: String,
+    pub context_compressor: ContextCompressor,
 }
 
 impl SearcherBrain {
     pub fn new(llm: Arc<LlmClient>, model: String) -> Self {
-        Self { llm, model }
+        let context_compressor = ContextCompressor::new_with_llm(get_max_context(&model), llm.clone(), model.clone());
+        Self { llm, model, context_compressor }
     }
 
     /// Generic method to make LLM requests with custom system prompts and tools
@@ -27,7 +30,7 @@ impl SearcherBrain {
         messages: Vec<ChatMessage>,
         tools: &Vec<Arc<dyn AnyTool>>,
         tool_choice: ChatCompletionToolChoice,
-    ) -> Result<ChatMessage, AgentError> {
+    ) -> Result<(ChatMessage, Option<(u32, u32)>), AgentError> {
         let request = ChatCompletionParametersBuilder::default()
             .model(&self.model)
             .messages(messages)
@@ -43,7 +46,10 @@ impl SearcherBrain {
             .await
             .map_err(|e| AgentError::LlmError(e.to_string()))?;
 
-        Ok(response.choices[0].message.clone())
+        let token_usage = response.usage.as_ref().map(|usage| {
+            (usage.prompt_tokens.unwrap_or(0), usage.completion_tokens.unwrap_or(0))
+        });
+        Ok((response.choices[0].message.clone(), token_usage))
     }
 }
 
@@ -57,7 +63,7 @@ impl Brain for SearcherBrain {
             content: ChatMessageContent::Text(searcher_next_step()),
             name: None,
         });
-        let brain_decision = self.chat_with_tools(
+        let (brain_decision, token_usage) = self.chat_with_tools(
             trace,
             &context.available_tools,
             ChatCompletionToolChoice::Auto,
@@ -67,11 +73,21 @@ impl Brain for SearcherBrain {
         // stop here if there's no other tool calls
         if let ChatMessage::Assistant { reasoning_content, content, tool_calls, .. } = &brain_decision {
             if tool_calls.as_ref().map_or(true, |calls| calls.is_empty()) {
-                return Ok(ThinkerDecision::agent_pause(brain_decision));
+                return Ok(match token_usage {
+                    Some((input_tokens, output_tokens)) => ThinkerDecision::agent_pause_with_tokens(brain_decision, input_tokens, output_tokens),
+                    None => ThinkerDecision::agent_pause(brain_decision),
+                });
             }
         } 
 
-        Ok(ThinkerDecision::agent_continue(brain_decision))
+        Ok(match token_usage {
+            Some((input_tokens, output_tokens)) => ThinkerDecision::agent_continue_with_tokens(brain_decision, input_tokens, output_tokens),
+            None => ThinkerDecision::agent_continue(brain_decision),
+        })
+    }
+
+    fn context_compressor(&mut self) -> Option<&mut ContextCompressor> {
+        Some(&mut self.context_compressor)
     }
 }
 
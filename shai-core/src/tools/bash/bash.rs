@@ -1,12 +1,12 @@
 use super::structs::BashToolParams;
-use crate::tools::{tool, ToolResult};
+use crate::tools::{tool, ToolOutputSink, ToolResult};
 use serde_json::json;
 use tokio_util::sync::CancellationToken;
 use std::collections::HashMap;
 use std::process::Stdio;
 use std::time::{Duration, Instant};
 use tokio::process::Command;
-use tokio::io::{AsyncReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 
 pub struct BashTool;
 
@@ -71,10 +71,19 @@ impl BashTool {
         
         // Read output asynchronously (needed to prevent blocking on full buffers)
         let stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
+        // stdout is read line by line so it can be streamed to the ui while the command runs
+        let sink = ToolOutputSink::current();
         let stdout_task = tokio::spawn(async move {
             let mut reader = BufReader::new(stdout);
             let mut output = String::new();
-            reader.read_to_string(&mut output).await?;
+            let mut line = String::new();
+            while reader.read_line(&mut line).await? > 0 {
+                if let Some(sink) = &sink {
+                    sink.send(line.as_str());
+                }
+                output.push_str(&line);
+                line.clear();
+            }
             Ok::<String, std::io::Error>(output)
         });
         let stderr = child.stderr.take().ok_or("Failed to capture stderr")?;
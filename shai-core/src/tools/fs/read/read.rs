@@ -128,12 +128,14 @@ impl ReadTool {
 **Usage:**
 - An absolute `path` to the file is required.
 - For large files, you can read a specific portion by specifying `line_start` and `line_end`. If omitted, the entire file is read (within system limits).
+- A reference like `src/main.rs:40-80` given by the user can be passed as is, the range after the colon is applied.
 - The output is formatted with line numbers for easy reference, which is crucial context for subsequent `edit` operations.
 
 **Best Practices:**
 - When investigating a task, it is often effective to read multiple potentially relevant files in a single turn to build a complete understanding of the context."#, capabilities = [Read])]
 impl ReadTool {
     async fn execute(&self, params: ReadToolParams) -> ToolResult {
+        let params = params.with_range_from_path();
         let path = Path::new(&params.path);
         
         // Check if file exists
@@ -14,4 +14,36 @@ pub struct ReadToolParams {
     /// Whether to include line numbers in the output
     #[serde(default)]
     pub show_line_numbers: bool,
-}
\ No newline at end of file
+}
+
+impl ReadToolParams {
+    /// Take the line range from a `path:start-end` (or `path:line`) reference, as inserted
+    /// by the @ file picker. Explicit line_start/line_end win, existing paths are left alone.
+    pub fn with_range_from_path(mut self) -> Self {
+        if std::path::Path::new(&self.path).exists() {
+            return self;
+        }
+        if let Some((path, start, end)) = split_line_range(&self.path) {
+            self.line_start = self.line_start.or(Some(start));
+            self.line_end = self.line_end.or(end);
+            self.path = path.to_string();
+        }
+        self
+    }
+}
+
+/// Split `file:40-80` into ("file", 40, Some(80)), `file:40` reads a single line and `file:40-` to the end
+pub fn split_line_range(reference: &str) -> Option<(&str, u32, Option<u32>)> {
+    let (path, range) = reference.rsplit_once(':')?;
+    if path.is_empty() {
+        return None;
+    }
+    match range.split_once('-') {
+        Some((start, "")) => Some((path, start.parse().ok()?, None)),
+        Some((start, end)) => Some((path, start.parse().ok()?, Some(end.parse().ok()?))),
+        None => {
+            let line = range.parse().ok()?;
+            Some((path, line, Some(line)))
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use super::read::ReadTool;
-use super::structs::ReadToolParams;
+use super::structs::{split_line_range, ReadToolParams};
 use crate::tools::{Tool, ToolCapability, FsOperationLog};
 use shai_llm::ToolDescription;
 use tempfile::TempDir;
@@ -182,4 +182,44 @@ async fn test_read_tool_line_range_reading() {
             panic!("Read tool was denied");
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_split_line_range() {
+    assert_eq!(split_line_range("src/main.rs:40-80"), Some(("src/main.rs", 40, Some(80))));
+    assert_eq!(split_line_range("src/main.rs:12"), Some(("src/main.rs", 12, Some(12))));
+    assert_eq!(split_line_range("src/main.rs:40-"), Some(("src/main.rs", 40, None)));
+    assert_eq!(split_line_range("src/main.rs"), None);
+    assert_eq!(split_line_range("C:\\code\\main.rs"), None);
+}
+
+#[tokio::test]
+async fn test_read_tool_range_in_path() {
+    let temp_dir = TempDir::new().expect("Failed to create temp directory");
+    let test_content = (1..=20)
+        .map(|i| format!("Line {}", i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let test_file_path = temp_dir.path().join("numbered_test.txt");
+    fs::write(&test_file_path, test_content).expect("Failed to write test file");
+
+    let read_tool = ReadTool::new(Arc::new(FsOperationLog::new()));
+    let params = ReadToolParams {
+        path: format!("{}:5-7", test_file_path.to_string_lossy()),
+        line_start: None,
+        line_end: None,
+        show_line_numbers: false,
+    };
+
+    match read_tool.execute(params, None).await {
+        crate::tools::ToolResult::Success { output, .. } => {
+            assert_eq!(output, "Line 5\nLine 6\nLine 7");
+        },
+        crate::tools::ToolResult::Error { error, .. } => {
+            panic!("Read tool should understand the range, got error: {}", error);
+        },
+        crate::tools::ToolResult::Denied => {
+            panic!("Read tool was denied");
+        }
+    }
+}
@@ -1,5 +1,7 @@
 pub mod types;
+pub mod output;
 pub mod highlight;
+pub mod sandbox;
 pub mod todo;
 pub mod fs;
 pub mod fetch;
@@ -10,6 +12,8 @@ pub mod mcp;
 mod tests_llm;
 
 pub use shai_macros::tool;
+pub use output::ToolOutputSink;
+pub use sandbox::Sandbox;
 pub use types::{Tool, ToolCall, ToolResult, ToolError, ToolCapability, AnyTool, AnyToolBox, ToolEmptyParams};
 
 // Re-export all tools
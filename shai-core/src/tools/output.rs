@@ -0,0 +1,35 @@
+use std::future::Future;
+use tokio::sync::mpsc;
+
+tokio::task_local! {
+    static TOOL_OUTPUT: ToolOutputSink;
+}
+
+/// Channel a running tool writes its output to as it is produced.
+/// The agent installs one around each tool execution, tools pick it up with `current()`.
+#[derive(Clone)]
+pub struct ToolOutputSink {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl ToolOutputSink {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, rx)
+    }
+
+    /// The sink of the tool being executed, if any. Capture it before spawning
+    /// subtasks since task locals are not inherited.
+    pub fn current() -> Option<Self> {
+        TOOL_OUTPUT.try_with(|sink| sink.clone()).ok()
+    }
+
+    pub fn send(&self, chunk: impl Into<String>) {
+        let _ = self.tx.send(chunk.into());
+    }
+
+    /// Run the tool future with this sink installed
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        TOOL_OUTPUT.scope(self, f).await
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use super::{AnyTool, ToolCapability};
+
+/// Parameters of the file system tools holding a path
+const PATH_PARAMETERS: &[&str] = &["path", "file_path", "directory", "working_dir"];
+
+/// Directory the file system tools are confined to. Paths are canonicalized (symlinks and `..`
+/// included) before being compared to the root, relative paths are resolved from the current
+/// directory like the tools do. Commands run by bash are not confined, only its working_dir is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+    root: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", root.display())));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve a path given to a tool, failing if it points outside of the root
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let requested = Path::new(path);
+        let absolute = if requested.is_absolute() {
+            requested.to_path_buf()
+        } else {
+            std::env::current_dir()
+                .map_err(|e| format!("cannot resolve {}: {}", path, e))?
+                .join(requested)
+        };
+
+        let resolved = canonicalize_lenient(&absolute)
+            .ok_or_else(|| format!("path {} cannot be resolved inside the sandbox {}", path, self.root.display()))?;
+        if !resolved.starts_with(&self.root) {
+            return Err(format!("path {} is outside of the sandbox {}", path, self.root.display()));
+        }
+        Ok(resolved)
+    }
+
+    /// Check every path parameter of a call to a file system tool
+    pub fn check_call(&self, tool: &dyn AnyTool, parameters: &Value) -> Result<(), String> {
+        let touches_files = tool.capabilities().iter()
+            .any(|c| matches!(c, ToolCapability::Read | ToolCapability::Write));
+        if !touches_files {
+            return Ok(());
+        }
+        for key in PATH_PARAMETERS {
+            if let Some(path) = parameters.get(key).and_then(Value::as_str) {
+                self.resolve(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Canonicalize a path that may not exist yet (e.g. a file about to be written): the existing
+/// part is canonicalized and the missing names are appended. A `..` in the missing part cannot
+/// be resolved safely and gives None.
+fn canonicalize_lenient(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut current = path;
+    loop {
+        if let Ok(mut resolved) = current.canonicalize() {
+            for name in missing.iter().rev() {
+                resolved.push(name);
+            }
+            return Some(resolved);
+        }
+        missing.push(current.file_name()?.to_owned());
+        current = current.parent()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox() -> (PathBuf, Sandbox) {
+        let root = std::env::temp_dir().join(format!("shai-sandbox-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let sandbox = Sandbox::new(&root).unwrap();
+        (root, sandbox)
+    }
+
+    #[test]
+    fn test_paths_inside_the_root_are_allowed() {
+        let (root, sandbox) = sandbox();
+        assert!(sandbox.resolve(root.join("src").to_str().unwrap()).is_ok());
+        assert!(sandbox.resolve(root.join("src/new/file.rs").to_str().unwrap()).is_ok());
+        assert!(sandbox.resolve(root.join("src/../Cargo.toml").to_str().unwrap()).is_ok());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_escapes_are_rejected() {
+        let (root, sandbox) = sandbox();
+        assert!(sandbox.resolve("/etc/passwd").is_err());
+        assert!(sandbox.resolve(root.join("../outside.txt").to_str().unwrap()).is_err());
+        assert!(sandbox.resolve(root.join("src/missing/../../../x").to_str().unwrap()).is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", root.join("etc")).unwrap();
+            assert!(sandbox.resolve(root.join("etc/passwd").to_str().unwrap()).is_err());
+        }
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -119,6 +119,12 @@ pub trait Tool: ToolDescription + Send + Sync {
 
     fn capabilities(&self) -> &'static [ToolCapability];
 
+    /// whether calls of this tool can run alongside the other calls of the same step,
+    /// by default only the tools that do not write can
+    fn parallel_safe(&self) -> bool {
+        !self.capabilities().contains(&ToolCapability::Write)
+    }
+
     /// execute the tool.
     /// parameters are specific for each tool
     async fn execute(&self, params: Self::Params, cancel_token: Option<CancellationToken>) -> ToolResult;
@@ -147,6 +153,10 @@ pub trait Tool: ToolDescription + Send + Sync {
 #[async_trait]
 pub trait AnyTool: ToolDescription + Send + Sync {
     fn capabilities(&self) -> &[ToolCapability];
+
+    fn parallel_safe(&self) -> bool {
+        !self.capabilities().contains(&ToolCapability::Write)
+    }
     
     async fn execute_json(&self, params: serde_json::Value, cancel_token: Option<CancellationToken>) -> ToolResult;
     async fn execute_preview_json(&self, params: serde_json::Value) -> Option<ToolResult>;
@@ -161,6 +171,10 @@ where
     fn capabilities(&self) -> &[ToolCapability] {
         <T as Tool>::capabilities(self)
     }
+
+    fn parallel_safe(&self) -> bool {
+        <T as Tool>::parallel_safe(self)
+    }
     
     async fn execute_json(&self, params: serde_json::Value, cancel_token: Option<CancellationToken>) -> ToolResult {
         self.execute_json(params, cancel_token).await
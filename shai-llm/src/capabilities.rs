@@ -0,0 +1,76 @@
+/// What a model is able to do with tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    pub functions: bool,
+    pub structured_output: bool,
+}
+
+impl ModelCapabilities {
+    pub const ALL: ModelCapabilities = ModelCapabilities { functions: true, structured_output: true };
+    pub const NONE: ModelCapabilities = ModelCapabilities { functions: false, structured_output: false };
+}
+
+/// Models known to lack native tool calling, keyed by family (first match wins)
+static MODEL_CAPABILITIES: &[(&str, ModelCapabilities)] = &[
+    ("deepseek-r1", ModelCapabilities { functions: false, structured_output: true }),
+    ("deepseek-coder", ModelCapabilities::NONE),
+    ("codellama", ModelCapabilities::NONE),
+    ("llama2", ModelCapabilities::NONE),
+    ("llama-2", ModelCapabilities::NONE),
+    ("tinyllama", ModelCapabilities::NONE),
+    ("starcoder", ModelCapabilities::NONE),
+    ("gemma", ModelCapabilities { functions: false, structured_output: true }),
+    ("phi", ModelCapabilities { functions: false, structured_output: true }),
+];
+
+/// Whether the model name belongs to the family: the name without its vendor prefix
+/// starts with the family, not directly followed by another letter (so "phi3" is phi but "phind" is not)
+fn is_family(model: &str, family: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    name.strip_prefix(family)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// Get the capabilities of a model, unknown models are assumed to support everything
+pub fn get_capabilities(model: &str) -> ModelCapabilities {
+    let model = model.to_lowercase();
+    MODEL_CAPABILITIES.iter()
+        .find(|(family, _)| is_family(&model, family))
+        .map(|(_, caps)| *caps)
+        .unwrap_or(ModelCapabilities::ALL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_match_on_family() {
+        let cases = [
+            ("phi3", false),
+            ("phi-4:14b", false),
+            ("microsoft/Phi-3-mini-4k-instruct", false),
+            ("gemma2:9b", false),
+            ("google/gemma-7b-it", false),
+            ("deepseek-r1:8b", false),
+            ("llama2", false),
+            ("codellama:13b", false),
+            ("dolphin-mistral", true),
+            ("dolphin-phi", true),
+            ("phind-codellama", true),
+            ("codegemma", true),
+            ("llama3.1", true),
+            ("gpt-4o", true),
+        ];
+        for (model, functions) in cases {
+            assert_eq!(get_capabilities(model).functions, functions, "{}", model);
+        }
+    }
+
+    #[test]
+    fn test_structured_output_follows_the_family() {
+        assert!(get_capabilities("gemma2").structured_output);
+        assert!(!get_capabilities("starcoder2:7b").structured_output);
+        assert_eq!(get_capabilities("mistral-nemo"), ModelCapabilities::ALL);
+    }
+}
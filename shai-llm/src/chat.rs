@@ -14,6 +14,15 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::pin::Pin;
 
+/// Error given when the base url serves a web page, typically a chat UI instead of its api
+pub const HTML_RESPONSE_ERROR: &str = "endpoint returned HTML, not JSON - check your base URL, it should be the API root (e.g. https://host/v1) and not the page of a web UI";
+
+/// Whether a response body is a web page rather than json
+pub fn looks_like_html(body: &str) -> bool {
+    let head: String = body.trim_start().chars().take(16).collect::<String>().to_lowercase();
+    ["<!doctype html", "<html", "<head", "<body"].iter().any(|tag| head.starts_with(tag))
+}
+
 /// Trait for JSON manipulation hooks
 #[async_trait]
 pub trait JsonHooks: Send + Sync {
@@ -101,6 +110,9 @@ impl ChatClient {
                 } else {
                     let status = response.status();
                     let error_text = response.text().await.unwrap_or_default();
+                    if looks_like_html(&error_text) {
+                        return Err(APIError::UnknownError(status.as_u16(), HTML_RESPONSE_ERROR.to_string()));
+                    }
                     
                     match status.as_u16() {
                         400 => Err(APIError::InvalidRequestError(error_text)),
@@ -143,6 +155,9 @@ impl ChatClient {
             .text()
             .await
             .map_err(|error| APIError::ParseError(error.to_string()))?;
+        if looks_like_html(&response_text) {
+            return Err(APIError::ParseError(HTML_RESPONSE_ERROR.to_string()));
+        }
 
         let mut response_json: Value = serde_json::from_str(&response_text)
             .map_err(|e| APIError::ParseError(e.to_string()))?;
@@ -2,7 +2,7 @@ use crate::tool::ToolBox;
 use crate::ToolCallMethod;
 
 // llm/client.rs
-use super::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo};
+use super::provider::{LlmProvider, LlmError, LlmStream, ModelInfo, ProviderInfo};
 use super::providers::{
     openai::OpenAIProvider,
     openai_compatible::OpenAICompatibleProvider,
@@ -10,7 +10,8 @@ use super::providers::{
     ovhcloud::OvhCloudProvider,
     anthropic::AnthropicProvider,
     ollama::OllamaProvider,
-    mistral::MistralProvider
+    mistral::MistralProvider,
+    gemini::GeminiProvider
 };
 use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
 use openai_dive::v1::resources::{
@@ -18,110 +19,125 @@ use openai_dive::v1::resources::{
     model::ListModelResponse,
 };
 use regex::Regex;
+use std::sync::Arc;
+use futures::StreamExt;
+use tokio::sync::Semaphore;
+
+/// Requests a client sends to its provider at once by default, a conservative value that
+/// stays under the per-key concurrency limits of most gateways. SHAI_MAX_CONCURRENT_REQUESTS
+/// overrides it.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
 
 #[derive(Debug)]
 pub struct LlmClient {
     provider: Box<dyn LlmProvider>,
+    // permits of the requests in flight, shared by every user of the client (brain, compression, agents)
+    requests: Arc<Semaphore>,
 }
 
 /// Provider Factory related method
 impl LlmClient {
+    fn wrap(provider: Box<dyn LlmProvider>) -> Self {
+        let max = std::env::var("SHAI_MAX_CONCURRENT_REQUESTS").ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS);
+        Self {
+            provider,
+            requests: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    /// Cap the requests sent to the provider at once, the others wait for a slot.
+    /// Applies to chat and chat_stream, a stream holds its slot until it is dropped.
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.requests = Arc::new(Semaphore::new(max.max(1)));
+        self
+    }
+
     /// Create an OpenAI provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_openai() -> Option<Self> {
-        OpenAIProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        OpenAIProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create an Anthropic provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_anthropic() -> Option<Self> {
-        AnthropicProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        AnthropicProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create an Ollama provider from environment variables
     /// Always returns Some since Ollama has a default base URL
     pub fn from_env_ollama() -> Option<Self> {
-        OllamaProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        OllamaProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create an OpenRouter provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_openrouter() -> Option<Self> {
-        OpenRouterProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        OpenRouterProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create an OpenAI Compatible provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_openai_compatible() -> Option<Self> {
-        OpenAICompatibleProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        OpenAICompatibleProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create an OVH Cloud provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_ovhcloud() -> Option<Self> {
-        OvhCloudProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        OvhCloudProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
     }
 
     /// Create a Mistral provider from environment variables
     /// Returns None if required environment variables are not set
     pub fn from_env_mistral() -> Option<Self> {
-        MistralProvider::from_env().map(|provider| Self {
-            provider: Box::new(provider),
-        })
+        MistralProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
+    }
+
+    /// Create a Gemini provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env_gemini() -> Option<Self> {
+        GeminiProvider::from_env().map(|provider| Self::wrap(Box::new(provider)))
+    }
+
+    /// Wrap any provider, e.g. a custom one or the MockProvider of the tests
+    pub fn from_provider(provider: impl LlmProvider + 'static) -> Self {
+        Self::wrap(Box::new(provider))
     }
 
     pub fn openai(api_key: String) -> Self {
-        Self {
-            provider: Box::new(OpenAIProvider::new(api_key)),
-        }
+        Self::wrap(Box::new(OpenAIProvider::new(api_key)))
     }
 
     pub fn compatible(api_key: String, base_url: String) -> Self {
-        Self {
-            provider: Box::new(OpenAICompatibleProvider::new(api_key, base_url)),
-        }
+        Self::wrap(Box::new(OpenAICompatibleProvider::new(api_key, base_url)))
     }
 
     pub fn openrouter(api_key: String) -> Self {
-        Self {
-            provider: Box::new(OpenRouterProvider::new(api_key)),
-        }
+        Self::wrap(Box::new(OpenRouterProvider::new(api_key)))
     }
 
     pub fn ovhcloud(api_key: String, base_url: Option<String>) -> Self {
-        Self {
-            provider: Box::new(OvhCloudProvider::new(api_key, base_url)),
-        }
+        Self::wrap(Box::new(OvhCloudProvider::new(api_key, base_url)))
     }
 
     pub fn anthropic(api_key: String) -> Self {
-        Self {
-            provider: Box::new(AnthropicProvider::new(api_key)),
-        }
+        Self::wrap(Box::new(AnthropicProvider::new(api_key)))
     }
 
     pub fn ollama(base_url: String) -> Self {
-        Self {
-            provider: Box::new(OllamaProvider::new(Some(base_url))),
-        }
+        Self::wrap(Box::new(OllamaProvider::new(Some(base_url))))
     }
 
     pub fn mistral(api_key: String) -> Self {
-        Self {
-            provider: Box::new(MistralProvider::new(api_key)),
-        }
+        Self::wrap(Box::new(MistralProvider::new(api_key)))
+    }
+
+    pub fn gemini(api_key: String) -> Self {
+        Self::wrap(Box::new(GeminiProvider::new(api_key)))
     }
 
 
@@ -137,6 +153,7 @@ impl LlmClient {
                 "openrouter" => return Self::from_env_openrouter(),
                 "openai_compatible" => return Self::from_env_openai_compatible(),
                 "ollama" => return Self::from_env_ollama(),
+                "gemini" => return Self::from_env_gemini(),
                 _ => {} // Fall through to default behavior
             }
         }
@@ -153,6 +170,9 @@ impl LlmClient {
         if let Some(client) = Self::from_env_anthropic() {
             return Some(client);
         }
+        if let Some(client) = Self::from_env_gemini() {
+            return Some(client);
+        }
         if let Some(client) = Self::from_env_openrouter() {
             return Some(client);
         }
@@ -174,6 +194,7 @@ impl LlmClient {
             OpenAICompatibleProvider::info(),
             OpenRouterProvider::info(),
             AnthropicProvider::info(),
+            GeminiProvider::info(),
             OpenAIProvider::info(),
         ]
     }
@@ -192,16 +213,28 @@ impl LlmClient {
                 Ok(Self::anthropic(api_key.clone()))
             },
             "ollama" => {
-                let base_url = env_values.get("OLLAMA_BASE_URL")
+                let base_url = env_values.get("OLLAMA_HOST")
+                    .or_else(|| env_values.get("OLLAMA_BASE_URL"))
+                    .filter(|url| !url.is_empty())
                     .cloned()
-                    .unwrap_or_else(|| "http://localhost:11434/v1".to_string());
-                Ok(Self::ollama(base_url))
+                    .unwrap_or_else(|| "http://localhost:11434".to_string());
+                let provider = OllamaProvider::new(Some(base_url));
+                let provider = match env_values.get("OLLAMA_KEEP_ALIVE").filter(|k| !k.is_empty()) {
+                    Some(keep_alive) => provider.with_keep_alive(keep_alive.clone()),
+                    None => provider,
+                };
+                Ok(Self::wrap(Box::new(provider)))
             },
             "mistral" => {
                 let api_key = env_values.get("MISTRAL_API_KEY")
                     .ok_or("MISTRAL_API_KEY not found")?;
                 Ok(Self::mistral(api_key.clone()))
             },
+            "gemini" => {
+                let api_key = env_values.get("GEMINI_API_KEY")
+                    .ok_or("GEMINI_API_KEY not found")?;
+                Ok(Self::gemini(api_key.clone()))
+            },
             "ovhcloud" => {
                 let api_key = env_values.get("OVH_API_KEY").map_or("", |v| v);
                 let base_url = env_values.get("OVH_BASE_URL").cloned();
@@ -217,7 +250,11 @@ impl LlmClient {
                     .ok_or("OPENAI_COMPATIBLE_API_KEY not found")?;
                 let base_url = env_values.get("OPENAI_COMPATIBLE_BASE_URL")
                     .ok_or("OPENAI_COMPATIBLE_BASE_URL not found")?;
-                Ok(Self::compatible(api_key.clone(), base_url.clone()))
+                let strict = env_values.get("OPENAI_COMPATIBLE_STRICT_MESSAGES")
+                    .is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+                let provider = OpenAICompatibleProvider::new(api_key.clone(), base_url.clone())
+                    .with_strict_messages(strict);
+                Ok(Self::wrap(Box::new(provider)))
             },
             _ => Err(format!("Unknown provider: {}", provider_name).into())
         }
@@ -231,6 +268,10 @@ impl LlmClient {
         self.provider.models().await
     }
 
+    pub async fn list_models_detailed(&self) -> Result<Vec<ModelInfo>, LlmError> {
+        self.provider.list_models_detailed().await
+    }
+
     pub async fn default_model(&self) -> Result<String, LlmError> {
         if let Ok(model) = std::env::var("SHAI_MODEL") {
             Ok(model)
@@ -243,10 +284,26 @@ impl LlmClient {
         self.provider.name()
     }
 
+    /// Check the provider answers before the session starts, so a wrong url or key is reported
+    /// right away instead of on the first prompt
+    pub async fn health_check(&self) -> Result<(), LlmError> {
+        self.provider.health_check().await.map_err(|e| {
+            let target = self.provider.base_url().unwrap_or_else(|| self.provider.name().to_string());
+            format!("cannot reach provider at {}: {}", target, e).into()
+        })
+    }
+
     /// Get a reference to the underlying provider (for testing)
     pub fn provider(&self) -> &dyn LlmProvider {
         &*self.provider
     }
+
+    /// Tool call method to start with for a model, from what the provider says it supports
+    pub fn detect_tool_call_method(&self, model: &str) -> ToolCallMethod {
+        ToolCallMethod::for_capabilities(
+            self.provider.supports_functions(model.to_string()),
+            self.provider.supports_structured_output(model.to_string()))
+    }
 }
 
 /// Higher level chat client
@@ -255,6 +312,8 @@ impl LlmClient {
         let request = request
             .fix_mistral_alternating();
 
+        let _permit = self.requests.acquire().await
+            .map_err(|e| Box::new(e) as LlmError)?;
         let response = self.provider
             .chat(request)
             .await?
@@ -267,7 +326,14 @@ impl LlmClient {
         let request = request
             .fix_mistral_alternating();
 
-        self.provider.chat_stream(request).await
+        let permit = self.requests.clone().acquire_owned().await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        let stream = self.provider.chat_stream(request).await?;
+        // the request is in flight until the stream is done with
+        Ok(Box::new(stream.map(move |chunk| {
+            let _ = &permit;
+            chunk
+        })))
     }
 
 
@@ -338,4 +404,35 @@ impl FixMistralAlternating for ChatCompletionParameters {
         }
         res
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::mock::MockProvider;
+
+    fn request() -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default()
+            .model("mock-model")
+            .messages(vec![ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None }])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_requests_wait_for_a_free_slot() {
+        let mock = MockProvider::new();
+        mock.push_text("streamed", None).push_text("waited", None);
+        let client = LlmClient::from_provider(mock.clone()).with_max_concurrent_requests(1);
+
+        // an open stream is a request in flight
+        let stream = client.chat_stream(request()).await.unwrap();
+        let blocked = tokio::time::timeout(std::time::Duration::from_millis(100), client.chat(request())).await;
+        assert!(blocked.is_err(), "the second request should wait for the stream");
+        assert_eq!(mock.requests().len(), 1);
+
+        drop(stream);
+        let response = client.chat(request()).await.unwrap();
+        assert!(matches!(&response.choices[0].message, ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } if text == "waited"));
+    }
+}
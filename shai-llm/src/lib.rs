@@ -3,9 +3,25 @@ pub mod providers;
 pub mod provider;
 pub mod chat;
 pub mod tool;
+pub mod max_context;
+pub mod pricing;
+pub mod capabilities;
+pub mod tokens;
+pub mod wire_log;
+pub mod normalize;
+pub mod retry;
+pub mod stream;
 
 // Re-export our client
 pub use client::LlmClient;
+pub use max_context::get_max_context;
+pub use pricing::{get_price, ModelPrice, PriceTable};
+pub use capabilities::{get_capabilities, ModelCapabilities};
+pub use tokens::{estimate_tokens, estimate_message_tokens};
+pub use wire_log::WireLog;
+pub use normalize::normalize_messages;
+pub use retry::RetryPolicy;
+pub use stream::{assemble_stream, assemble_stream_with_timeout, StreamAssembler, StreamError, DEFAULT_STREAM_IDLE_TIMEOUT};
 
 pub use tool::{
     ToolDescription, 
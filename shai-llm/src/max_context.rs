@@ -0,0 +1,36 @@
+/// Context window used when the model is unknown
+pub const DEFAULT_MAX_CONTEXT: u32 = 32_768;
+
+/// Known context windows, matched against the lowercased model name (first match wins)
+static MODEL_CONTEXTS: &[(&str, u32)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-5", 400_000),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini", 1_048_576),
+    ("mistral-large", 128_000),
+    ("mistral-medium", 128_000),
+    ("mistral-small", 128_000),
+    ("codestral", 256_000),
+    ("devstral", 128_000),
+    ("llama-3", 128_000),
+    ("llama3", 128_000),
+    ("qwen3", 131_072),
+    ("qwen2.5", 32_768),
+    ("deepseek", 128_000),
+    ("kimi", 131_072),
+    ("gpt-oss", 131_072),
+];
+
+/// Get the context window size of a model, falls back to DEFAULT_MAX_CONTEXT
+pub fn get_max_context(model: &str) -> u32 {
+    let model = model.to_lowercase();
+    MODEL_CONTEXTS.iter()
+        .find(|(pattern, _)| model.contains(pattern))
+        .map(|(_, size)| *size)
+        .unwrap_or(DEFAULT_MAX_CONTEXT)
+}
@@ -0,0 +1,171 @@
+// llm/normalize.rs
+use std::collections::HashSet;
+use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent};
+
+/// Content given to the tool calls left without a result
+const MISSING_TOOL_RESULT: &str = "no result: the tool call did not complete";
+
+/// Make a trace acceptable for servers that validate the conversation shape, which a trace
+/// reshuffled by compression or cancelled steps may not be:
+/// - a tool message must answer a call of the assistant message before it, orphans are dropped
+/// - every tool call gets an answer, calls left without one get a placeholder result
+/// - consecutive user messages, or consecutive assistant messages, are merged into one turn
+pub fn normalize_messages(messages: &[ChatMessage]) -> Vec<ChatMessage> {
+    let mut result: Vec<ChatMessage> = Vec::with_capacity(messages.len());
+    // calls of the last assistant message still waiting for their result, in order
+    let mut pending: Vec<String> = Vec::new();
+
+    for message in messages {
+        if let ChatMessage::Tool { tool_call_id, .. } = message {
+            if let Some(pos) = pending.iter().position(|id| id == tool_call_id) {
+                pending.remove(pos);
+                result.push(message.clone());
+            }
+            continue;
+        }
+
+        answer_pending_calls(&mut result, &mut pending);
+
+        if let ChatMessage::Assistant { tool_calls: Some(calls), .. } = message {
+            let mut seen = HashSet::new();
+            pending = calls.iter()
+                .map(|c| c.id.clone())
+                .filter(|id| seen.insert(id.clone()))
+                .collect();
+        }
+
+        match result.last_mut() {
+            Some(last) if merge_into(last, message) => {}
+            _ => result.push(message.clone()),
+        }
+    }
+    answer_pending_calls(&mut result, &mut pending);
+
+    result
+}
+
+fn answer_pending_calls(result: &mut Vec<ChatMessage>, pending: &mut Vec<String>) {
+    for tool_call_id in pending.drain(..) {
+        result.push(ChatMessage::Tool {
+            content: MISSING_TOOL_RESULT.to_string(),
+            tool_call_id,
+        });
+    }
+}
+
+/// Merge `next` into `last` when both are text turns of the same role, returns whether it did.
+/// An assistant message with tool calls can absorb the text of the assistant message before it.
+fn merge_into(last: &mut ChatMessage, next: &ChatMessage) -> bool {
+    match (last, next) {
+        (
+            ChatMessage::User { content: ChatMessageContent::Text(text), .. },
+            ChatMessage::User { content: ChatMessageContent::Text(next_text), .. },
+        ) => {
+            *text = join_text(text, next_text);
+            true
+        }
+        (
+            ChatMessage::Assistant { content, tool_calls, reasoning_content, .. },
+            ChatMessage::Assistant { content: next_content, tool_calls: next_calls, reasoning_content: next_reasoning, .. },
+        ) if tool_calls.is_none() => {
+            let (Some(text), Some(next_text)) = (text_of(content), text_of(next_content)) else {
+                return false;
+            };
+            let merged = join_text(&text, &next_text);
+            *content = (!merged.is_empty()).then_some(ChatMessageContent::Text(merged));
+            *tool_calls = next_calls.clone();
+            if reasoning_content.is_none() {
+                *reasoning_content = next_reasoning.clone();
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Text of an assistant content, None when it holds something else than text
+fn text_of(content: &Option<ChatMessageContent>) -> Option<String> {
+    match content {
+        Some(ChatMessageContent::Text(text)) => Some(text.clone()),
+        None => Some(String::new()),
+        _ => None,
+    }
+}
+
+fn join_text(first: &str, second: &str) -> String {
+    match (first.is_empty(), second.is_empty()) {
+        (true, _) => second.to_string(),
+        (_, true) => first.to_string(),
+        _ => format!("{}\n\n{}", first, second),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai_dive::v1::resources::chat::{Function, ToolCall};
+
+    fn user(text: &str) -> ChatMessage {
+        ChatMessage::User { content: ChatMessageContent::Text(text.to_string()), name: None }
+    }
+
+    fn assistant(text: Option<&str>, calls: &[&str]) -> ChatMessage {
+        ChatMessage::Assistant {
+            content: text.map(|t| ChatMessageContent::Text(t.to_string())),
+            reasoning_content: None,
+            tool_calls: (!calls.is_empty()).then(|| calls.iter().map(|id| ToolCall {
+                id: id.to_string(),
+                r#type: "function".to_string(),
+                function: Function { name: "read".to_string(), arguments: "{}".to_string() },
+            }).collect()),
+            name: None,
+            audio: None,
+            refusal: None,
+        }
+    }
+
+    fn tool(id: &str) -> ChatMessage {
+        ChatMessage::Tool { content: format!("result {}", id), tool_call_id: id.to_string() }
+    }
+
+    #[test]
+    fn test_post_compression_trace_is_fixed() {
+        // the summary replaced the assistant message whose calls call_1 and call_2 answer
+        let trace = vec![
+            ChatMessage::System { content: ChatMessageContent::Text("system".to_string()), name: None },
+            user("summary of the conversation"),
+            tool("call_1"),
+            tool("call_2"),
+            assistant(Some("I read the files."), &[]),
+            assistant(Some("Now let me check the tests."), &["call_3", "call_4"]),
+            tool("call_3"),
+            user("also look at main.rs"),
+            user("please"),
+        ];
+        let normalized = normalize_messages(&trace);
+
+        assert_eq!(normalized.len(), 6);
+        assert!(matches!(&normalized[0], ChatMessage::System { .. }));
+        assert!(matches!(&normalized[1], ChatMessage::User { .. }));
+        assert!(matches!(&normalized[2], ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), tool_calls: Some(calls), .. }
+            if text == "I read the files.\n\nNow let me check the tests." && calls.len() == 2));
+        assert!(matches!(&normalized[3], ChatMessage::Tool { tool_call_id, .. } if tool_call_id == "call_3"));
+        assert!(matches!(&normalized[4], ChatMessage::Tool { tool_call_id, content } if tool_call_id == "call_4" && content == MISSING_TOOL_RESULT));
+        assert!(matches!(&normalized[5], ChatMessage::User { content: ChatMessageContent::Text(text), .. } if text == "also look at main.rs\n\nplease"));
+    }
+
+    #[test]
+    fn test_valid_trace_is_unchanged() {
+        let trace = vec![
+            user("read a.rs"),
+            assistant(None, &["call_1"]),
+            tool("call_1"),
+            assistant(Some("done"), &[]),
+            user("thanks"),
+        ];
+        let normalized = normalize_messages(&trace);
+        assert_eq!(normalized.len(), trace.len());
+        assert!(matches!(&normalized[1], ChatMessage::Assistant { content: None, tool_calls: Some(_), .. }));
+        assert!(matches!(&normalized[2], ChatMessage::Tool { tool_call_id, .. } if tool_call_id == "call_1"));
+    }
+}
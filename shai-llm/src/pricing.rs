@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+/// Price of a model in USD per 1000 tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+impl ModelPrice {
+    pub const fn new(input_per_1k: f64, output_per_1k: f64) -> Self {
+        Self { input_per_1k, output_per_1k }
+    }
+
+    pub fn cost(&self, input_tokens: u64, output_tokens: u64) -> f64 {
+        (input_tokens as f64 * self.input_per_1k + output_tokens as f64 * self.output_per_1k) / 1000.0
+    }
+}
+
+/// Public list prices, matched against the lowercased model name (first match wins, so the
+/// smaller variants come before the model they extend). Self-hosted models are left out.
+static MODEL_PRICES: &[(&str, ModelPrice)] = &[
+    ("gpt-4.1-nano", ModelPrice::new(0.0001, 0.0004)),
+    ("gpt-4.1-mini", ModelPrice::new(0.0004, 0.0016)),
+    ("gpt-4.1", ModelPrice::new(0.002, 0.008)),
+    ("gpt-4o-mini", ModelPrice::new(0.00015, 0.0006)),
+    ("gpt-4o", ModelPrice::new(0.0025, 0.01)),
+    ("gpt-5-nano", ModelPrice::new(0.00005, 0.0004)),
+    ("gpt-5-mini", ModelPrice::new(0.00025, 0.002)),
+    ("gpt-5", ModelPrice::new(0.00125, 0.01)),
+    ("o4-mini", ModelPrice::new(0.0011, 0.0044)),
+    ("o3-mini", ModelPrice::new(0.0011, 0.0044)),
+    ("o3", ModelPrice::new(0.002, 0.008)),
+    ("o1", ModelPrice::new(0.015, 0.06)),
+    ("claude-opus", ModelPrice::new(0.015, 0.075)),
+    ("claude-sonnet", ModelPrice::new(0.003, 0.015)),
+    ("claude-3-5-haiku", ModelPrice::new(0.0008, 0.004)),
+    ("claude-haiku", ModelPrice::new(0.001, 0.005)),
+    ("gemini-2.5-pro", ModelPrice::new(0.00125, 0.01)),
+    ("gemini-2.5-flash", ModelPrice::new(0.0003, 0.0025)),
+    ("mistral-large", ModelPrice::new(0.002, 0.006)),
+    ("mistral-medium", ModelPrice::new(0.0004, 0.002)),
+    ("mistral-small", ModelPrice::new(0.0001, 0.0003)),
+    ("codestral", ModelPrice::new(0.0003, 0.0009)),
+    ("devstral-medium", ModelPrice::new(0.0004, 0.002)),
+    ("devstral", ModelPrice::new(0.0001, 0.0003)),
+    ("deepseek-reasoner", ModelPrice::new(0.00055, 0.00219)),
+    ("deepseek-chat", ModelPrice::new(0.00027, 0.0011)),
+];
+
+/// Get the list price of a model, None when it is not known
+pub fn get_price(model: &str) -> Option<ModelPrice> {
+    let model = model.to_lowercase();
+    MODEL_PRICES.iter()
+        .find(|(pattern, _)| model.contains(pattern))
+        .map(|(_, price)| *price)
+}
+
+/// Prices used to estimate what a session cost: the user's own rates (self-hosted models,
+/// negotiated discounts) first, then the list prices. Overrides are matched like the list,
+/// on a part of the lowercased model name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PriceTable {
+    overrides: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_price(mut self, pattern: &str, price: ModelPrice) -> Self {
+        self.overrides.insert(pattern.to_lowercase(), price);
+        self
+    }
+
+    pub fn with_prices(self, prices: &HashMap<String, ModelPrice>) -> Self {
+        prices.iter().fold(self, |table, (pattern, price)| table.with_price(pattern, *price))
+    }
+
+    /// Price of a model, the longest matching override wins over the list
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        let lowercase = model.to_lowercase();
+        self.overrides.iter()
+            .filter(|(pattern, _)| lowercase.contains(pattern.as_str()))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, price)| *price)
+            .or_else(|| get_price(model))
+    }
+
+    /// Cost in USD of the tokens of one model, None when its price is unknown
+    pub fn estimate_cost(&self, model: &str, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+        self.price(model).map(|price| price.cost(input_tokens, output_tokens))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_are_matched_before_their_model() {
+        assert_eq!(get_price("gpt-4o-mini-2024-07-18"), Some(ModelPrice::new(0.00015, 0.0006)));
+        assert_eq!(get_price("openai/GPT-4o"), Some(ModelPrice::new(0.0025, 0.01)));
+        assert_eq!(get_price("llama-3.3-70b"), None);
+    }
+
+    #[test]
+    fn test_overrides_come_first() {
+        let table = PriceTable::new()
+            .with_price("llama", ModelPrice::new(0.0, 0.0))
+            .with_price("gpt-4o", ModelPrice::new(0.001, 0.005));
+
+        assert_eq!(table.estimate_cost("llama-3.3-70b", 10_000, 1_000), Some(0.0));
+        assert_eq!(table.estimate_cost("gpt-4o", 2_000, 1_000), Some(0.007));
+        // the longer list entry does not beat an override
+        assert_eq!(table.price("gpt-4o-mini"), Some(ModelPrice::new(0.001, 0.005)));
+        assert_eq!(table.estimate_cost("qwen3-coder", 1_000, 1_000), None);
+    }
+}
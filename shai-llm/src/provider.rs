@@ -3,6 +3,7 @@ use async_trait::async_trait;
 use futures::Stream;
 use std::error::Error;
 use openai_dive::v1::endpoints::chat::Chat;
+use crate::max_context::get_max_context;
 use openai_dive::v1::resources::{
     chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse},
     model::ListModelResponse,
@@ -11,6 +12,15 @@ use openai_dive::v1::resources::{
 pub type LlmError = Box<dyn Error + Send + Sync>;
 pub type LlmStream = Box<dyn Stream<Item = Result<ChatCompletionChunkResponse, LlmError>> + Send + Unpin>;
 
+/// Model description with what the agent needs to know about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub id: String,
+    pub context_length: u32,
+    pub supports_functions: bool,
+    pub supports_structured_output: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct EnvVar {
     pub name: String,
@@ -55,6 +65,20 @@ pub trait LlmProvider: Send + Sync {
             .ok_or_else(|| "no model available".into())
     }
 
+    /// Same as models() along with the context length and capabilities of each model
+    async fn list_models_detailed(&self) -> Result<Vec<ModelInfo>, LlmError> {
+        let models = self.models().await?;
+        Ok(models.data
+            .into_iter()
+            .map(|m| ModelInfo {
+                context_length: get_max_context(&m.id),
+                supports_functions: self.supports_functions(m.id.clone()),
+                supports_structured_output: self.supports_structured_output(m.id.clone()),
+                id: m.id,
+            })
+            .collect())
+    }
+
     async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError>;
     
     async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError>;
@@ -64,6 +88,16 @@ pub trait LlmProvider: Send + Sync {
     fn supports_structured_output(&self, model: String) -> bool;
     
     fn name(&self) -> &'static str;
+
+    /// Address of the api, for providers where it can be configured
+    fn base_url(&self) -> Option<String> {
+        None
+    }
+
+    /// Check the provider can be reached with the configured credentials, with a cheap request
+    async fn health_check(&self) -> Result<(), LlmError> {
+        self.models().await.map(|_| ())
+    }
     
     /// Returns provider information including environment variables
     fn info() -> ProviderInfo where Self: Sized;
@@ -0,0 +1,269 @@
+// Gemini provider using its OpenAI compatible endpoint, fixed up with JSON hooks
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::chat::{ChatClient, JsonHooks};
+use serde_json::{json, Value};
+use async_trait::async_trait;
+use futures::StreamExt;
+use openai_dive::v1::error::APIError;
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse},
+    model::ListModelResponse,
+};
+
+pub struct GeminiProvider {
+    client: ChatClient,
+    hooks: GeminiHooks,
+}
+
+impl GeminiProvider {
+    pub fn new(api_key: String) -> Self {
+        let client = ChatClient::new(api_key, "https://generativelanguage.googleapis.com/v1beta/openai".to_string());
+        Self {
+            client,
+            hooks: GeminiHooks,
+        }
+    }
+
+    /// Create Gemini provider from environment variables
+    /// Returns None if required environment variables are not set
+    pub fn from_env() -> Option<Self> {
+        std::env::var("GEMINI_API_KEY")
+            .ok()
+            .map(|api_key| Self::new(api_key))
+    }
+}
+
+/// Gemini-specific hooks, its OpenAI endpoint is stricter than OpenAI on the conversation shape
+#[derive(Clone, Copy)]
+pub struct GeminiHooks;
+
+#[async_trait]
+impl JsonHooks for GeminiHooks {
+    async fn before_send(&self, mut json: Value) -> Result<Value, APIError> {
+        if let Some(messages) = json.get_mut("messages").and_then(|m| m.as_array_mut()) {
+            normalize_gemini_messages(messages);
+        }
+        // not part of the compatible subset, rejected instead of ignored
+        if let Some(params) = json.as_object_mut() {
+            params.remove("parallel_tool_calls");
+        }
+        Ok(json)
+    }
+
+    async fn after_receive(&self, mut json: Value) -> Result<Value, APIError> {
+        if let Some(choices) = json.get_mut("choices").and_then(|c| c.as_array_mut()) {
+            for choice in choices {
+                if let Some(message) = choice.get_mut("message") {
+                    fix_tool_calls(message);
+                }
+            }
+        }
+        Ok(json)
+    }
+
+    async fn after_receive_stream(&self, mut json: Value) -> Result<Value, APIError> {
+        if let Some(choices) = json.get_mut("choices").and_then(|c| c.as_array_mut()) {
+            for choice in choices {
+                if let Some(delta) = choice.get_mut("delta") {
+                    fix_tool_calls(delta);
+                }
+            }
+        }
+        Ok(json)
+    }
+}
+
+/// Gemini may leave out the type and the id of its tool calls, both are needed to answer them
+fn fix_tool_calls(message: &mut Value) {
+    let Some(tool_calls) = message.get_mut("tool_calls").and_then(|tc| tc.as_array_mut()) else {
+        return;
+    };
+    for (i, tool_call) in tool_calls.iter_mut().enumerate() {
+        let Some(tool_call) = tool_call.as_object_mut() else {
+            continue;
+        };
+        if tool_call.get("type").is_none() {
+            tool_call.insert("type".to_string(), Value::String("function".to_string()));
+        }
+        let has_id = tool_call.get("id").and_then(Value::as_str).is_some_and(|id| !id.is_empty());
+        if !has_id {
+            tool_call.insert("id".to_string(), Value::String(format!("call_{}_{}", i, uuid::Uuid::new_v4().simple())));
+        }
+    }
+}
+
+/// Reshape the messages the way Gemini accepts them:
+/// - all system messages become a single one at the start
+/// - consecutive user (or plain assistant) messages are merged into one turn
+/// - the conversation starts with a user turn
+pub fn normalize_gemini_messages(messages: &mut Vec<Value>) {
+    let (system, rest): (Vec<Value>, Vec<Value>) = std::mem::take(messages)
+        .into_iter()
+        .partition(|m| role(m) == Some("system"));
+
+    let system_text: Vec<String> = system.iter().filter_map(text_content).collect();
+    if !system_text.is_empty() {
+        messages.push(json!({ "role": "system", "content": system_text.join("\n\n") }));
+    }
+
+    for message in rest {
+        if let Some(last) = messages.last_mut() {
+            if can_merge(last, &message) {
+                let merged = format!(
+                    "{}\n\n{}",
+                    text_content(last).unwrap_or_default(),
+                    text_content(&message).unwrap_or_default()
+                );
+                last["content"] = Value::String(merged);
+                continue;
+            }
+        }
+        messages.push(message);
+    }
+
+    let first_turn = messages.iter().position(|m| role(m) != Some("system"));
+    if let Some(i) = first_turn {
+        if role(&messages[i]) != Some("user") {
+            messages.insert(i, json!({ "role": "user", "content": "Go ahead." }));
+        }
+    }
+}
+
+fn role(message: &Value) -> Option<&str> {
+    message.get("role").and_then(Value::as_str)
+}
+
+/// Text of a message whose content is a string or a list of text parts
+fn text_content(message: &Value) -> Option<String> {
+    match message.get("content")? {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(parts) => {
+            let texts: Vec<&str> = parts.iter()
+                .filter_map(|p| p.get("text").and_then(Value::as_str))
+                .collect();
+            (texts.len() == parts.len()).then(|| texts.join("\n"))
+        }
+        _ => None,
+    }
+}
+
+/// Only text turns of the same role are merged, tool calls and results keep their own message
+fn can_merge(previous: &Value, next: &Value) -> bool {
+    let same_role = matches!((role(previous), role(next)), (Some("user"), Some("user")) | (Some("assistant"), Some("assistant")));
+    let has_tool_calls = |m: &Value| m.get("tool_calls").is_some_and(|tc| !tc.is_null());
+    same_role
+        && !has_tool_calls(previous)
+        && !has_tool_calls(next)
+        && text_content(previous).is_some()
+        && text_content(next).is_some()
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        let url = format!("{}/models", self.client.base_url);
+
+        let response = self.client.http_client
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.client.api_key))
+            .send()
+            .await
+            .map_err(|e| Box::new(e) as LlmError)?;
+
+        let mut models: ListModelResponse = response
+            .json()
+            .await
+            .map_err(|e| Box::new(e) as LlmError)?;
+
+        // ids come as "models/gemini-...", the chat endpoint expects the bare name
+        for model in &mut models.data {
+            if let Some(id) = model.id.strip_prefix("models/") {
+                model.id = id.to_string();
+            }
+        }
+        models.data.retain(|m| m.id.starts_with("gemini"));
+        Ok(models)
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        Ok("gemini-2.5-flash".to_string())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        let response = self.client.chat_completion(&request, &self.hooks).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+        Ok(response)
+    }
+
+    async fn chat_stream(&self, mut request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        // Ensure streaming is enabled
+        request.stream = Some(true);
+
+        let stream = self.client.chat_completion_stream(&request, self.hooks).await
+            .map_err(|e| Box::new(e) as LlmError)?;
+
+        let converted_stream = stream.map(|result| {
+            result.map_err(|e| Box::new(e) as LlmError)
+        });
+
+        Ok(Box::new(Box::pin(converted_stream)))
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "gemini",
+            display_name: "Google Gemini",
+            env_vars: vec![
+                EnvVar::required("GEMINI_API_KEY", "Google AI Studio API key"),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_gemini_messages() {
+        let mut messages = vec![
+            json!({ "role": "system", "content": "You are a coder." }),
+            json!({ "role": "assistant", "content": "Hello!" }),
+            json!({ "role": "user", "content": "read a.rs" }),
+            json!({ "role": "user", "content": [{ "type": "text", "text": "and b.rs" }] }),
+            json!({ "role": "system", "content": "Follow AGENTS.md." }),
+            json!({ "role": "assistant", "content": null, "tool_calls": [{ "id": "1", "type": "function", "function": { "name": "read", "arguments": "{}" } }] }),
+            json!({ "role": "tool", "tool_call_id": "1", "content": "fn main() {}" }),
+            json!({ "role": "assistant", "content": "Done" }),
+            json!({ "role": "assistant", "content": "Anything else?" }),
+        ];
+        normalize_gemini_messages(&mut messages);
+
+        let roles: Vec<&str> = messages.iter().filter_map(role).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant", "user", "assistant", "tool", "assistant"]);
+        assert_eq!(messages[0]["content"], "You are a coder.\n\nFollow AGENTS.md.");
+        assert_eq!(messages[1]["content"], "Go ahead.");
+        assert_eq!(messages[3]["content"], "read a.rs\n\nand b.rs");
+        assert_eq!(messages[6]["content"], "Done\n\nAnything else?");
+    }
+
+    #[test]
+    fn test_fix_tool_calls() {
+        let mut message = json!({ "tool_calls": [{ "function": { "name": "ls", "arguments": "{}" } }] });
+        fix_tool_calls(&mut message);
+        assert_eq!(message["tool_calls"][0]["type"], "function");
+        assert!(message["tool_calls"][0]["id"].as_str().unwrap().starts_with("call_0_"));
+    }
+}
@@ -0,0 +1,239 @@
+// llm/providers/mock.rs
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo};
+use async_trait::async_trait;
+use futures::stream;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse, ChatMessage, ChatMessageContent, ChatCompletionChoice, ToolCall, Function},
+    model::{ListModelResponse, Model},
+    shared::{FinishReason, Usage},
+};
+
+/// Provider answering from a queue of scripted responses, for deterministic tests of the code
+/// driving an llm. Clones share the same queue, so a test can keep one to script more answers
+/// and look at the requests once the other one is boxed in an `LlmClient`.
+#[derive(Clone, Default)]
+pub struct MockProvider {
+    responses: Arc<Mutex<VecDeque<Result<ChatCompletionResponse, String>>>>,
+    requests: Arc<Mutex<Vec<ChatCompletionParameters>>>,
+    models: Vec<String>,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self {
+            models: vec!["mock-model".to_string()],
+            ..Self::default()
+        }
+    }
+
+    /// Models listed by `models()`, the first one is the default
+    pub fn with_models(mut self, models: &[&str]) -> Self {
+        self.models = models.iter().map(|m| m.to_string()).collect();
+        self
+    }
+
+    /// Queue a response as is
+    pub fn push_response(&self, response: ChatCompletionResponse) -> &Self {
+        self.responses.lock().unwrap().push_back(Ok(response));
+        self
+    }
+
+    /// Queue an assistant answer without tool calls
+    pub fn push_text(&self, text: &str, usage: Option<(u32, u32)>) -> &Self {
+        self.push_response(Self::response(Some(text), None, usage))
+    }
+
+    /// Queue an assistant message calling tools, given as (name, json arguments). Calls are
+    /// numbered call_1, call_2... within the message.
+    pub fn push_tool_calls(&self, calls: &[(&str, &str)], usage: Option<(u32, u32)>) -> &Self {
+        let calls = calls.iter().enumerate().map(|(i, (name, arguments))| ToolCall {
+            id: format!("call_{}", i + 1),
+            r#type: "function".to_string(),
+            function: Function { name: name.to_string(), arguments: arguments.to_string() },
+        }).collect();
+        self.push_response(Self::response(None, Some(calls), usage))
+    }
+
+    /// Queue an answer whose stream ends without a finish_reason, like a server dropping the
+    /// connection halfway. Not streamed, it is returned as is.
+    pub fn push_truncated(&self, text: &str) -> &Self {
+        let mut response = Self::response(Some(text), None, None);
+        response.choices[0].finish_reason = None;
+        self.push_response(response)
+    }
+
+    /// Queue a failure, e.g. "503 Service Unavailable" to exercise the retries
+    pub fn push_error(&self, message: &str) -> &Self {
+        self.responses.lock().unwrap().push_back(Err(message.to_string()));
+        self
+    }
+
+    /// Responses not consumed yet
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().unwrap().len()
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<ChatCompletionParameters> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn response(text: Option<&str>, tool_calls: Option<Vec<ToolCall>>, usage: Option<(u32, u32)>) -> ChatCompletionResponse {
+        ChatCompletionResponse {
+            id: Some(format!("mock-{}", uuid::Uuid::new_v4())),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: "mock-model".to_string(),
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatMessage::Assistant {
+                    content: text.map(|t| ChatMessageContent::Text(t.to_string())),
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                    tool_calls,
+                },
+                finish_reason: Some(FinishReason::StopSequenceReached),
+                logprobs: None,
+            }],
+            usage: usage.map(|(prompt, completion)| Usage {
+                prompt_tokens: Some(prompt),
+                completion_tokens: Some(completion),
+                total_tokens: prompt + completion,
+                prompt_tokens_details: None,
+                completion_tokens_details: None,
+            }),
+            service_tier: None,
+            system_fingerprint: None,
+        }
+    }
+
+    fn next_response(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.requests.lock().unwrap().push(request);
+        match self.responses.lock().unwrap().pop_front() {
+            Some(Ok(response)) => Ok(response),
+            Some(Err(message)) => Err(message.into()),
+            None => Err("mock provider: no scripted response left".into()),
+        }
+    }
+
+    /// The whole response as a single chunk, built from its wire format so the tool calls
+    /// get their delta shape
+    fn into_chunk(response: ChatCompletionResponse) -> Result<ChatCompletionChunkResponse, LlmError> {
+        let choices: Vec<_> = response.choices.into_iter().map(|choice| {
+            let (content, tool_calls) = match choice.message {
+                ChatMessage::Assistant { content, tool_calls, .. } => (content, tool_calls),
+                _ => (None, None),
+            };
+            let tool_calls = tool_calls.map(|calls| calls.into_iter().enumerate().map(|(i, call)| json!({
+                "index": i,
+                "id": call.id,
+                "type": call.r#type,
+                "function": { "name": call.function.name, "arguments": call.function.arguments },
+            })).collect::<Vec<_>>());
+            json!({
+                "index": choice.index,
+                "delta": { "role": "assistant", "content": content, "tool_calls": tool_calls },
+                "finish_reason": choice.finish_reason.as_ref().map(|_| "stop"),
+            })
+        }).collect();
+
+        Ok(serde_json::from_value(json!({
+            "id": response.id,
+            "object": "chat.completion.chunk",
+            "created": response.created,
+            "model": response.model,
+            "choices": choices,
+            "usage": response.usage,
+        }))?)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        Ok(ListModelResponse {
+            object: "list".to_string(),
+            data: self.models.iter().map(|id| Model {
+                id: id.clone(),
+                object: "model".to_string(),
+                created: None,
+                owned_by: "mock".to_string(),
+            }).collect(),
+        })
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        self.next_response(request)
+    }
+
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        let chunk = Self::into_chunk(self.next_response(request)?);
+        Ok(Box::new(stream::iter(vec![chunk])))
+    }
+
+    fn supports_functions(&self, _model: String) -> bool {
+        true
+    }
+
+    fn supports_structured_output(&self, _model: String) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "mock",
+            display_name: "Mock",
+            env_vars: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::LlmClient;
+    use futures::StreamExt;
+    use openai_dive::v1::resources::chat::ChatCompletionParametersBuilder;
+
+    fn request() -> ChatCompletionParameters {
+        ChatCompletionParametersBuilder::default()
+            .model("mock-model")
+            .messages(vec![ChatMessage::User { content: ChatMessageContent::Text("hello".to_string()), name: None }])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_responses_are_played_in_order() {
+        let mock = MockProvider::new();
+        mock.push_tool_calls(&[("read", r#"{"path":"a.rs"}"#)], Some((10, 5)))
+            .push_error("503 Service Unavailable")
+            .push_text("done", None);
+        let client = LlmClient::from_provider(mock.clone());
+
+        let response = client.chat(request()).await.unwrap();
+        assert!(matches!(&response.choices[0].message, ChatMessage::Assistant { tool_calls: Some(calls), .. }
+            if calls[0].id == "call_1" && calls[0].function.name == "read"));
+        assert_eq!(response.usage.unwrap().prompt_tokens, Some(10));
+
+        assert!(client.chat(request()).await.unwrap_err().to_string().contains("503"));
+
+        let mut stream = client.chat_stream(request()).await.unwrap();
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(chunk.choices.len(), 1);
+        assert!(stream.next().await.is_none());
+
+        assert!(client.chat(request()).await.is_err(), "the queue is empty");
+        assert_eq!(mock.requests().len(), 4);
+        assert_eq!(mock.remaining(), 0);
+    }
+}
@@ -5,6 +5,9 @@ pub mod ovhcloud;
 pub mod anthropic;
 pub mod ollama;
 pub mod mistral;
+pub mod gemini;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 // pub mod mistral_native; // TODO: Complete implementation
 
 #[cfg(test)]
@@ -0,0 +1,63 @@
+use serde::{Serialize, Deserialize};
+
+// Ollama native api types (/api/chat and /api/tags)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaChatResponse {
+    pub model: String,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    pub message: Option<OllamaMessage>,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub done_reason: Option<String>,
+    #[serde(default)]
+    pub prompt_eval_count: Option<u32>,
+    #[serde(default)]
+    pub eval_count: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaFunction {
+    pub name: String,
+    /// arguments are a json object, not a string as in the openai api
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaTagsResponse {
+    pub models: Vec<OllamaModel>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    #[serde(default)]
+    pub modified_at: Option<String>,
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OllamaErrorResponse {
+    pub error: String,
+}
+
+pub const OLLAMA_DEFAULT_HOST: &str = "http://127.0.0.1:11434";
@@ -0,0 +1,5 @@
+pub mod api;
+pub mod ollama;
+pub mod tests;
+
+pub use ollama::OllamaProvider;
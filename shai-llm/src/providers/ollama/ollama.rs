@@ -0,0 +1,459 @@
+// llm/providers/ollama/ollama.rs
+use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::capabilities::get_capabilities;
+use crate::max_context::get_max_context;
+use super::api::*;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashMap;
+use futures::{future, stream, StreamExt};
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse, ChatMessage, DeltaChatMessage, ChatMessageContent, ChatCompletionChoice, ChatCompletionChunkChoice, DeltaToolCall, ToolCall, Function},
+    model::{ListModelResponse, Model},
+    shared::{FinishReason, Usage},
+};
+
+pub struct OllamaProvider {
+    base_url: String,
+    keep_alive: Option<String>,
+    client: Client,
+}
+
+impl OllamaProvider {
+    pub fn new(base_url: Option<String>) -> Self {
+        Self {
+            base_url: normalize_host(base_url.as_deref().unwrap_or(OLLAMA_DEFAULT_HOST)),
+            keep_alive: None,
+            client: Client::new(),
+        }
+    }
+
+    /// How long the model stays loaded after a request (e.g. "5m", "-1" to keep it forever)
+    pub fn with_keep_alive(mut self, keep_alive: String) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Create Ollama provider from environment variables
+    /// OLLAMA_HOST is read first, OLLAMA_BASE_URL is still accepted for older configs
+    /// Falls back to the local default host when neither is set, so this always returns Some
+    pub fn from_env() -> Option<Self> {
+        let host = std::env::var("OLLAMA_HOST")
+            .or_else(|_| std::env::var("OLLAMA_BASE_URL"))
+            .ok();
+        let provider = Self::new(host);
+        Some(match std::env::var("OLLAMA_KEEP_ALIVE") {
+            Ok(keep_alive) => provider.with_keep_alive(keep_alive),
+            Err(_) => provider,
+        })
+    }
+
+    /// Download a model on the ollama server, blocks until the pull is complete
+    pub async fn pull_model(&self, model: &str) -> Result<(), LlmError> {
+        let response = self.client
+            .post(&format!("{}/api/pull", self.base_url))
+            .json(&json!({ "model": model, "stream": false }))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::api_error(response).await);
+        }
+        Ok(())
+    }
+
+    async fn api_error(response: reqwest::Response) -> LlmError {
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<OllamaErrorResponse>(&text)
+            .map(|e| e.error)
+            .unwrap_or(text);
+        format!("Ollama API error ({}): {}", status, message).into()
+    }
+
+    pub(crate) fn convert_to_ollama_format(&self, request: &ChatCompletionParameters) -> serde_json::Value {
+        let mut options = json!({
+            "num_ctx": get_max_context(&request.model),
+        });
+        if let Some(temperature) = request.temperature {
+            options["temperature"] = json!(temperature);
+        }
+
+        let mut ollama_request = json!({
+            "model": request.model,
+            "messages": self.convert_messages(&request.messages),
+            "stream": false,
+            "options": options,
+        });
+
+        if let Some(tools) = &request.tools {
+            ollama_request["tools"] = json!(self.convert_tools(tools));
+        }
+
+        // structured output: ollama takes the json schema itself in "format"
+        if let Some(format) = request.response_format.as_ref().and_then(|f| serde_json::to_value(f).ok()) {
+            match format["type"].as_str() {
+                Some("json_schema") => ollama_request["format"] = format["json_schema"]["schema"].clone(),
+                Some("json_object") => ollama_request["format"] = json!("json"),
+                _ => {}
+            }
+        }
+
+        if let Some(keep_alive) = &self.keep_alive {
+            ollama_request["keep_alive"] = json!(keep_alive);
+        }
+
+        ollama_request
+    }
+
+    fn convert_messages(&self, messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+        // ollama has no tool call id, tool results are matched by tool name instead
+        let mut tool_names: HashMap<String, String> = HashMap::new();
+        let mut converted_messages = Vec::new();
+
+        for msg in messages {
+            match msg {
+                ChatMessage::System { content, .. } => {
+                    converted_messages.push(json!({
+                        "role": "system",
+                        "content": self.extract_content_text(content)
+                    }));
+                }
+                ChatMessage::User { content, .. } | ChatMessage::Developer { content, .. } => {
+                    converted_messages.push(json!({
+                        "role": "user",
+                        "content": self.extract_content_text(content)
+                    }));
+                }
+                ChatMessage::Assistant { content, tool_calls, .. } => {
+                    let mut message = json!({
+                        "role": "assistant",
+                        "content": content.as_ref().map(|c| self.extract_content_text(c)).unwrap_or_default()
+                    });
+                    if let Some(calls) = tool_calls.as_ref().filter(|calls| !calls.is_empty()) {
+                        message["tool_calls"] = json!(calls.iter().map(|call| {
+                            tool_names.insert(call.id.clone(), call.function.name.clone());
+                            let arguments = serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({}));
+                            json!({
+                                "function": {
+                                    "name": call.function.name,
+                                    "arguments": arguments
+                                }
+                            })
+                        }).collect::<Vec<_>>());
+                    }
+                    converted_messages.push(message);
+                }
+                ChatMessage::Tool { content, tool_call_id, .. } => {
+                    let mut message = json!({
+                        "role": "tool",
+                        "content": content.clone()
+                    });
+                    if let Some(name) = tool_names.get(tool_call_id) {
+                        message["tool_name"] = json!(name);
+                    }
+                    converted_messages.push(message);
+                }
+            }
+        }
+
+        converted_messages
+    }
+
+    fn convert_tools(&self, tools: &[openai_dive::v1::resources::chat::ChatCompletionTool]) -> Vec<serde_json::Value> {
+        tools.iter().map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool.function.name,
+                    "description": tool.function.description.as_ref().unwrap_or(&tool.function.name),
+                    "parameters": tool.function.parameters
+                }
+            })
+        }).collect()
+    }
+
+    fn extract_content_text(&self, content: &ChatMessageContent) -> String {
+        match content {
+            ChatMessageContent::Text(text) => text.clone(),
+            ChatMessageContent::ContentPart(parts) => {
+                parts.iter().filter_map(|part| {
+                    match part {
+                        openai_dive::v1::resources::chat::ChatMessageContentPart::Text(text_part) => {
+                            Some(text_part.text.clone())
+                        }
+                        _ => None, // Skip images, audio, etc.
+                    }
+                }).collect::<Vec<_>>().join(" ")
+            }
+            ChatMessageContent::None => String::new(),
+        }
+    }
+
+    pub(crate) fn convert_from_ollama_format(&self, response: OllamaChatResponse) -> ChatCompletionResponse {
+        let message = response.message.unwrap_or(OllamaMessage {
+            role: "assistant".to_string(),
+            content: String::new(),
+            thinking: None,
+            tool_calls: None,
+        });
+
+        let tool_calls = message.tool_calls
+            .filter(|calls| !calls.is_empty())
+            .map(|calls| calls.into_iter().map(|call| ToolCall {
+                id: new_call_id(),
+                r#type: "function".to_string(),
+                function: Function {
+                    name: call.function.name,
+                    arguments: serde_json::to_string(&call.function.arguments).unwrap_or_default(),
+                }
+            }).collect::<Vec<_>>());
+
+        let content = Some(message.content.trim().to_string())
+            .filter(|text| !text.is_empty())
+            .map(ChatMessageContent::Text);
+        let has_tool_calls = tool_calls.is_some();
+
+        ChatCompletionResponse {
+            id: Some(format!("ollama-{}", uuid::Uuid::new_v4())),
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: response.model,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatMessage::Assistant {
+                    content,
+                    reasoning_content: message.thinking.filter(|t| !t.is_empty()),
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                    tool_calls,
+                },
+                finish_reason: Some(finish_reason(response.done_reason.as_deref(), has_tool_calls)),
+                logprobs: None,
+            }],
+            usage: Some(usage(&response.prompt_eval_count, &response.eval_count)),
+            service_tier: None,
+            system_fingerprint: None,
+        }
+    }
+
+    /// Ollama streams one json object per line, lines may be split across network chunks and
+    /// so may multi-byte characters, bytes are kept until their line is complete
+    fn parse_ollama_stream(response: reqwest::Response) -> LlmStream {
+        let parsed_stream = response.bytes_stream()
+            .scan((Vec::<u8>::new(), false), |(buffer, called_tools), chunk_result| {
+                let results = match chunk_result {
+                    Ok(chunk) => {
+                        buffer.extend_from_slice(&chunk);
+                        let mut results = Vec::new();
+                        while let Some(pos) = buffer.iter().position(|byte| *byte == b'\n') {
+                            let line: Vec<u8> = buffer.drain(..=pos).collect();
+                            let line = String::from_utf8_lossy(&line);
+                            if !line.trim().is_empty() {
+                                results.push(Self::parse_stream_line(line.trim(), called_tools));
+                            }
+                        }
+                        results
+                    }
+                    Err(e) => vec![Err(Box::new(e) as LlmError)],
+                };
+                future::ready(Some(results))
+            })
+            .flat_map(|results| stream::iter(results));
+
+        Box::new(Box::pin(parsed_stream))
+    }
+
+    /// Convert a line of the stream, `called_tools` remembers whether an earlier line had tool
+    /// calls since ollama sends them whole before the last line, which ends with "stop"
+    pub(crate) fn parse_stream_line(line: &str, called_tools: &mut bool) -> Result<ChatCompletionChunkResponse, LlmError> {
+        if let Ok(error) = serde_json::from_str::<OllamaErrorResponse>(line) {
+            return Err(format!("Ollama API error: {}", error.error).into());
+        }
+        let response: OllamaChatResponse = serde_json::from_str(line)?;
+
+        let content = response.message.as_ref()
+            .map(|m| m.content.clone())
+            .filter(|text| !text.is_empty())
+            .map(ChatMessageContent::Text);
+        let reasoning_content = response.message.as_ref()
+            .and_then(|m| m.thinking.clone())
+            .filter(|text| !text.is_empty());
+        // every call comes complete, each gets its own id so that the assembler keeps them apart
+        let tool_calls = response.message.as_ref()
+            .and_then(|m| m.tool_calls.as_ref())
+            .filter(|calls| !calls.is_empty())
+            .map(|calls| calls.iter()
+                .map(|call| serde_json::from_value::<DeltaToolCall>(json!({
+                    "id": new_call_id(),
+                    "type": "function",
+                    "function": {
+                        "name": call.function.name,
+                        "arguments": serde_json::to_string(&call.function.arguments).unwrap_or_default(),
+                    }
+                })))
+                .collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+        *called_tools = *called_tools || tool_calls.is_some();
+
+        Ok(ChatCompletionChunkResponse {
+            id: Some(format!("ollama-{}", uuid::Uuid::new_v4())),
+            object: "chat.completion.chunk".to_string(),
+            created: 0,
+            model: response.model.clone(),
+            choices: vec![ChatCompletionChunkChoice {
+                index: Some(0),
+                delta: DeltaChatMessage::Assistant {
+                    content,
+                    reasoning_content,
+                    refusal: None,
+                    name: None,
+                    tool_calls,
+                },
+                finish_reason: response.done.then(|| finish_reason(response.done_reason.as_deref(), *called_tools)),
+                logprobs: None,
+            }],
+            usage: response.done.then(|| usage(&response.prompt_eval_count, &response.eval_count)),
+            system_fingerprint: None,
+        })
+    }
+}
+
+/// Add the scheme and default port when missing, OLLAMA_HOST is often just "127.0.0.1" or "0.0.0.0:11434".
+/// A url with a scheme is taken as is, its port being the scheme's default when not given.
+/// A trailing /v1 (openai compatible endpoint) is removed.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_end_matches('/');
+    let host = host.strip_suffix("/v1").unwrap_or(host);
+    if host.contains("://") {
+        return host.to_string();
+    }
+    if host.contains(':') || host.contains('/') {
+        format!("http://{}", host)
+    } else {
+        format!("http://{}:11434", host)
+    }
+}
+
+/// Ollama has no tool call ids, they are made up so that results can be matched with their call
+fn new_call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Ollama ends turns that call tools with "stop", the openai api tells them apart
+fn finish_reason(done_reason: Option<&str>, called_tools: bool) -> FinishReason {
+    match done_reason {
+        _ if called_tools => FinishReason::ToolCalls,
+        Some("length") => FinishReason::TokenLimitReached,
+        _ => FinishReason::StopSequenceReached,
+    }
+}
+
+fn usage(prompt_tokens: &Option<u32>, completion_tokens: &Option<u32>) -> Usage {
+    Usage {
+        prompt_tokens: *prompt_tokens,
+        completion_tokens: *completion_tokens,
+        total_tokens: prompt_tokens.unwrap_or(0) + completion_tokens.unwrap_or(0),
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    async fn models(&self) -> Result<ListModelResponse, LlmError> {
+        let response = self.client
+            .get(&format!("{}/api/tags", self.base_url))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::api_error(response).await);
+        }
+
+        let tags: OllamaTagsResponse = response.json().await?;
+        Ok(ListModelResponse {
+            object: "list".to_string(),
+            data: tags.models.into_iter().map(|m| Model {
+                id: m.name,
+                object: "model".to_string(),
+                created: None,
+                owned_by: "ollama".to_string(),
+            }).collect(),
+        })
+    }
+
+    async fn default_model(&self) -> Result<String, LlmError> {
+        let models = self.models().await?; // Get the models
+
+        models.data.iter()
+            .find(|m| m.id.to_lowercase().contains("smol"))
+            .or_else(|| models.data.first())
+            .map(|m| m.id.clone())
+            .ok_or_else(|| "no model available".into())
+    }
+
+    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        let ollama_request = self.convert_to_ollama_format(&request);
+
+        let response = self.client
+            .post(&format!("{}/api/chat", self.base_url))
+            .json(&ollama_request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::api_error(response).await);
+        }
+
+        let ollama_response: OllamaChatResponse = response.json().await?;
+        Ok(self.convert_from_ollama_format(ollama_response))
+    }
+
+    async fn chat_stream(&self, request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
+        let mut ollama_request = self.convert_to_ollama_format(&request);
+        ollama_request["stream"] = json!(true);
+
+        let response = self.client
+            .post(&format!("{}/api/chat", self.base_url))
+            .json(&ollama_request)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Self::api_error(response).await);
+        }
+
+        Ok(Self::parse_ollama_stream(response))
+    }
+
+    fn supports_functions(&self, model: String) -> bool {
+        get_capabilities(&model).functions
+    }
+
+    fn supports_structured_output(&self, model: String) -> bool {
+        get_capabilities(&model).structured_output
+    }
+
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn base_url(&self) -> Option<String> {
+        Some(self.base_url.clone())
+    }
+
+    fn info() -> ProviderInfo {
+        ProviderInfo {
+            name: "ollama",
+            display_name: "Ollama",
+            env_vars: vec![
+                EnvVar::optional("OLLAMA_HOST", "ollama server url"),
+                EnvVar::optional("OLLAMA_KEEP_ALIVE", "how long the model stays loaded (e.g. 5m)"),
+            ],
+        }
+    }
+
+}
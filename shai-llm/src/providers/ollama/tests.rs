@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests {
+    use crate::providers::ollama::OllamaProvider;
+    use crate::providers::ollama::ollama::normalize_host;
+    use crate::providers::ollama::api::OllamaChatResponse;
+    use crate::stream::StreamAssembler;
+    use openai_dive::v1::resources::chat::{ChatMessage, ChatMessageContent, ChatCompletionParametersBuilder, ToolCall, Function};
+    use openai_dive::v1::resources::shared::FinishReason;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("127.0.0.1"), "http://127.0.0.1:11434");
+        assert_eq!(normalize_host("0.0.0.0:8080"), "http://0.0.0.0:8080");
+        assert_eq!(normalize_host("http://localhost:11434/v1"), "http://localhost:11434");
+        assert_eq!(normalize_host("https://ollama.example.com/"), "https://ollama.example.com");
+        assert_eq!(normalize_host("http://localhost"), "http://localhost");
+    }
+
+    #[test]
+    fn test_streamed_tool_calls_are_kept() {
+        let mut called_tools = false;
+        let calls = OllamaProvider::parse_stream_line(
+            r#"{"model":"qwen3:8b","message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"ls","arguments":{"path":"."}}}]},"done":false}"#,
+            &mut called_tools,
+        ).unwrap();
+        let done = OllamaProvider::parse_stream_line(
+            r#"{"model":"qwen3:8b","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","prompt_eval_count":12,"eval_count":3}"#,
+            &mut called_tools,
+        ).unwrap();
+
+        let mut assembler = StreamAssembler::new();
+        assembler.push(&calls);
+        assembler.push(&done);
+        let response = assembler.finish();
+        assert!(matches!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls)));
+        let ChatMessage::Assistant { tool_calls: Some(calls), .. } = &response.choices[0].message else {
+            panic!("expected the streamed tool call");
+        };
+        assert_eq!(calls[0].function.name, "ls");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&calls[0].function.arguments).unwrap(), json!({"path": "."}));
+    }
+
+    #[test]
+    fn test_done_reason_is_mapped() {
+        let provider = OllamaProvider::new(None);
+        let response: OllamaChatResponse = serde_json::from_value(json!({
+            "model": "qwen3:8b",
+            "message": { "role": "assistant", "content": "a long answer" },
+            "done": true,
+            "done_reason": "length"
+        })).unwrap();
+
+        let converted = provider.convert_from_ollama_format(response);
+        assert!(matches!(converted.choices[0].finish_reason, Some(FinishReason::TokenLimitReached)));
+    }
+
+    #[test]
+    fn test_tool_calls_are_converted_to_ollama_format() {
+        let provider = OllamaProvider::new(None);
+        let request = ChatCompletionParametersBuilder::default()
+            .model("qwen3:8b")
+            .messages(vec![
+                ChatMessage::User {
+                    content: ChatMessageContent::Text("list the files".to_string()),
+                    name: None,
+                },
+                ChatMessage::Assistant {
+                    content: None,
+                    reasoning_content: None,
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                    tool_calls: Some(vec![ToolCall {
+                        id: "call_1".to_string(),
+                        r#type: "function".to_string(),
+                        function: Function { name: "ls".to_string(), arguments: r#"{"path":"."}"#.to_string() },
+                    }]),
+                },
+                ChatMessage::Tool {
+                    content: "a.txt".to_string(),
+                    tool_call_id: "call_1".to_string(),
+                },
+            ])
+            .build()
+            .unwrap();
+
+        let ollama_format = provider.convert_to_ollama_format(&request);
+
+        let messages = ollama_format["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1]["tool_calls"][0]["function"]["arguments"], json!({"path": "."}));
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_name"], "ls");
+        assert!(ollama_format["options"]["num_ctx"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_response_is_converted_from_ollama_format() {
+        let provider = OllamaProvider::new(None);
+        let response: OllamaChatResponse = serde_json::from_value(json!({
+            "model": "qwen3:8b",
+            "created_at": "2025-01-01T00:00:00Z",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [{ "function": { "name": "ls", "arguments": { "path": "." } } }]
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 120,
+            "eval_count": 15
+        })).unwrap();
+
+        let converted = provider.convert_from_ollama_format(response);
+
+        let ChatMessage::Assistant { content, tool_calls, .. } = &converted.choices[0].message else {
+            panic!("Expected Assistant message");
+        };
+        assert!(content.is_none());
+        let calls = tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].function.name, "ls");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&calls[0].function.arguments).unwrap(), json!({"path": "."}));
+        let usage = converted.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, Some(120));
+        assert_eq!(usage.completion_tokens, Some(15));
+    }
+}
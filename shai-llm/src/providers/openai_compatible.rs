@@ -1,9 +1,16 @@
 // llm/providers/openai_compatible.rs
+use std::collections::HashMap;
+use crate::capabilities::{get_capabilities, ModelCapabilities};
 use crate::provider::{LlmProvider, LlmError, LlmStream, ProviderInfo, EnvVar};
+use crate::stream::StreamAssembler;
+use crate::wire_log::WireLog;
+use crate::normalize::normalize_messages;
+use crate::chat::{looks_like_html, HTML_RESPONSE_ERROR};
 use async_trait::async_trait;
 use futures::StreamExt;
 use openai_dive::v1::{
     api::Client,
+    error::APIError,
     resources::{
         chat::{ChatCompletionParameters, ChatCompletionResponse, ChatCompletionChunkResponse},
         model::ListModelResponse,
@@ -14,13 +21,74 @@ use serde_json::Value;
 
 pub struct OpenAICompatibleProvider {
     client: Client,
+    capability_overrides: HashMap<String, ModelCapabilities>,
+    wire_log: Option<WireLog>,
+    strict_messages: bool,
 }
 
 impl OpenAICompatibleProvider {
     pub fn new(api_key: String, base_url: String) -> Self {
+        let wire_log = WireLog::from_env().map(|log| log.redact(api_key.clone()));
         let mut client = Client::new(api_key);
-        client.set_base_url(&base_url);
-        Self { client }
+        client.set_base_url(&normalize_base_url(&base_url));
+        Self { client, capability_overrides: HashMap::new(), wire_log, strict_messages: false }
+    }
+
+    /// Normalize the conversation before sending it, for servers rejecting consecutive
+    /// turns of the same role or tool results without their call
+    pub fn with_strict_messages(mut self, strict: bool) -> Self {
+        self.strict_messages = strict;
+        self
+    }
+
+    /// Record the request and response bodies to a file, also enabled by SHAI_LLM_LOG
+    pub fn with_wire_log(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.wire_log = Some(WireLog::new(path).redact(self.client.api_key.clone()));
+        self
+    }
+
+    /// Force the capabilities of a model, bypassing the built-in table
+    pub fn with_capability_override(mut self, model: impl Into<String>, capabilities: ModelCapabilities) -> Self {
+        self.capability_overrides.insert(model.into(), capabilities);
+        self
+    }
+
+    /// openai_dive only tells that a body did not parse, or hands over the body of an http
+    /// error. A web page there means the base url is not the api root (e.g. a chat UI ending
+    /// in /chat), which is worth saying plainly.
+    async fn explain_error(&self, error: APIError) -> LlmError {
+        let is_html = match &error {
+            APIError::ParseError(_) => self.serves_html().await,
+            APIError::InvalidRequestError(body)
+            | APIError::AuthenticationError(body)
+            | APIError::PermissionError(body)
+            | APIError::NotFoundError(body)
+            | APIError::RateLimitError(body)
+            | APIError::UnknownError(_, body) => looks_like_html(body),
+            _ => false,
+        };
+        if is_html {
+            return HTML_RESPONSE_ERROR.into();
+        }
+        Box::new(error)
+    }
+
+    /// Fetch the model list again, this time looking at what the body is
+    async fn serves_html(&self) -> bool {
+        let url = format!("{}/models", self.client.base_url);
+        let Ok(response) = reqwest::Client::new().get(url).bearer_auth(&self.client.api_key).send().await else {
+            return false;
+        };
+        let is_html_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("text/html"));
+        is_html_type || response.text().await.is_ok_and(|body| looks_like_html(&body))
+    }
+
+    fn capabilities(&self, model: &str) -> ModelCapabilities {
+        self.capability_overrides.get(model)
+            .copied()
+            .unwrap_or_else(|| get_capabilities(model))
     }
 
     /// Create OpenAI Compatible provider from environment variables
@@ -28,53 +96,115 @@ impl OpenAICompatibleProvider {
     pub fn from_env() -> Option<Self> {
         match (std::env::var("OPENAI_COMPATIBLE_API_KEY"), std::env::var("OPENAI_COMPATIBLE_BASE_URL")) {
             (Ok(api_key), Ok(base_url)) => {
-                Some(Self::new(api_key, base_url))
+                let strict = std::env::var("OPENAI_COMPATIBLE_STRICT_MESSAGES")
+                    .is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+                Some(Self::new(api_key, base_url).with_strict_messages(strict))
             }
             _ => None
         }
     }
 }
 
+/// Make `https://host`, `https://host/` and `https://host/v1` point to the same endpoints.
+/// A bare host gets the default `/v1`, any other path (`/openai/v1`, a gateway prefix...)
+/// is kept as given, only without its trailing slashes.
+pub fn normalize_base_url(base_url: &str) -> String {
+    let base_url = base_url.trim().trim_end_matches('/');
+    let after_scheme = base_url.find("://").map(|i| i + 3).unwrap_or(0);
+    let has_path = base_url[after_scheme..].contains('/');
+    if has_path {
+        base_url.to_string()
+    } else {
+        format!("{}/v1", base_url)
+    }
+}
+
 #[async_trait]
 impl LlmProvider for OpenAICompatibleProvider {
     async fn models(&self) -> Result<ListModelResponse, LlmError> {
-        let response = self.client.models().list().await
-            .map_err(|e| Box::new(e) as LlmError)?;
-        Ok(response)
+        match self.client.models().list().await {
+            Ok(response) => Ok(response),
+            Err(e) => Err(self.explain_error(e).await),
+        }
     }
 
-    async fn chat(&self, request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
-        let mut response = self.client.chat().create(request).await
-            .map_err(|e| Box::new(e) as LlmError)?;
+    async fn chat(&self, mut request: ChatCompletionParameters) -> Result<ChatCompletionResponse, LlmError> {
+        if self.strict_messages {
+            request.messages = normalize_messages(&request.messages);
+        }
+        if let Some(log) = &self.wire_log {
+            log.request(self.name(), &request);
+        }
 
-        Ok(response)
+        let result = self.client.chat().create(request).await;
+        if let Some(log) = &self.wire_log {
+            match &result {
+                Ok(response) => log.response(self.name(), response),
+                Err(e) => log.error(self.name(), &e.to_string()),
+            }
+        }
+        match result {
+            Ok(response) => Ok(response),
+            Err(e) => Err(self.explain_error(e).await),
+        }
     }
 
     async fn chat_stream(&self, mut request: ChatCompletionParameters) -> Result<LlmStream, LlmError> {
         // Ensure streaming is enabled
         request.stream = Some(true);
+        if self.strict_messages {
+            request.messages = normalize_messages(&request.messages);
+        }
+        if let Some(log) = &self.wire_log {
+            log.request(self.name(), &request);
+        }
         
-        let stream = self.client.chat().create_stream(request).await
-            .map_err(|e| Box::new(e) as LlmError)?;
+        let stream = match self.client.chat().create_stream(request).await {
+            Ok(stream) => stream,
+            Err(e) => return Err(self.explain_error(e).await),
+        };
 
         let converted_stream = stream.map(|result| {
             result.map_err(|e| Box::new(e) as LlmError)
         });
 
-        Ok(Box::new(Box::pin(converted_stream)))
+        let Some(log) = self.wire_log.clone() else {
+            return Ok(Box::new(Box::pin(converted_stream)));
+        };
+
+        // log the message rebuilt from the chunks once the stream is over
+        let provider = self.name();
+        let logged_stream = async_stream::stream! {
+            let mut stream = Box::pin(converted_stream);
+            let mut assembler = StreamAssembler::new();
+            while let Some(item) = stream.next().await {
+                match &item {
+                    Ok(chunk) => { assembler.push(chunk); }
+                    Err(e) => log.error(provider, &e.to_string()),
+                }
+                yield item;
+            }
+            log.stream_response(provider, &assembler);
+        };
+
+        Ok(Box::new(Box::pin(logged_stream)))
     }
 
     fn supports_functions(&self, model: String) -> bool {
-        true
+        self.capabilities(&model).functions
     }
 
     fn supports_structured_output(&self, model: String) -> bool {
-        true
+        self.capabilities(&model).structured_output
     }
 
     fn name(&self) -> &'static str {
         "openai_compatible"
     }
+
+    fn base_url(&self) -> Option<String> {
+        Some(self.client.base_url.clone())
+    }
     
     fn info() -> ProviderInfo {
         ProviderInfo {
@@ -83,9 +213,68 @@ impl LlmProvider for OpenAICompatibleProvider {
             env_vars: vec![
                 EnvVar::required("OPENAI_COMPATIBLE_API_KEY", "API key for OpenAI-compatible service"),
                 EnvVar::required("OPENAI_COMPATIBLE_BASE_URL", "Base URL for OpenAI-compatible service"),
+                EnvVar::optional("OPENAI_COMPATIBLE_STRICT_MESSAGES", "Set to 1 if the server rejects consecutive turns of the same role"),
             ],
         }
     }
     
 }
 
+#[cfg(test)]
+mod tests {
+    use super::normalize_base_url;
+
+    #[test]
+    fn test_normalize_base_url() {
+        assert_eq!(normalize_base_url("https://host"), "https://host/v1");
+        assert_eq!(normalize_base_url("https://host/"), "https://host/v1");
+        assert_eq!(normalize_base_url("https://host/v1"), "https://host/v1");
+        assert_eq!(normalize_base_url("https://host/v1/"), "https://host/v1");
+        assert_eq!(normalize_base_url("http://localhost:8080"), "http://localhost:8080/v1");
+        assert_eq!(normalize_base_url("https://host/openai/v1"), "https://host/openai/v1");
+        assert_eq!(normalize_base_url("https://gateway.example.com/custom/path//"), "https://gateway.example.com/custom/path");
+    }
+
+    #[test]
+    fn test_tool_call_method_follows_the_capabilities() {
+        use crate::{ModelCapabilities, ToolCallMethod};
+
+        let provider = super::OpenAICompatibleProvider::new("key".to_string(), "http://127.0.0.1:9".to_string())
+            .with_capability_override("schema-only", ModelCapabilities { functions: false, structured_output: true });
+        let client = crate::client::LlmClient::from_provider(provider);
+
+        assert_eq!(client.detect_tool_call_method("gpt-4o"), ToolCallMethod::FunctionCall);
+        assert_eq!(client.detect_tool_call_method("schema-only"), ToolCallMethod::StructuredOutput);
+        assert_eq!(client.detect_tool_call_method("codellama-13b"), ToolCallMethod::Parsing);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_fails_the_health_check() {
+        // nothing listens on port 9 (discard) of the loopback
+        let client = crate::client::LlmClient::compatible("key".to_string(), "http://127.0.0.1:9".to_string());
+        let error = client.health_check().await.expect_err("the server should be unreachable");
+        assert!(error.to_string().starts_with("cannot reach provider at http://127.0.0.1:9/v1"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_web_page_instead_of_the_api_is_explained() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a web UI answering every path with its html page
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0u8; 4096];
+                let _ = socket.read(&mut request).await;
+                let body = "<!DOCTYPE html><html><body>Chat</body></html>";
+                let response = format!("HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", body.len(), body);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let client = crate::client::LlmClient::compatible("key".to_string(), format!("http://{}/chat", address));
+        let error = client.health_check().await.expect_err("a web page is not a model list");
+        assert!(error.to_string().contains("returned HTML, not JSON"), "{}", error);
+    }
+}
@@ -120,6 +120,10 @@ impl LlmProvider for OpenRouterProvider {
     fn name(&self) -> &'static str {
         "openrouter"
     }
+
+    fn base_url(&self) -> Option<String> {
+        Some(self.base_url.clone())
+    }
     
     fn info() -> ProviderInfo {
         ProviderInfo {
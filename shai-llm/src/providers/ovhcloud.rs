@@ -98,6 +98,10 @@ impl LlmProvider for OvhCloudProvider {
     fn name(&self) -> &'static str {
         "ovhcloud"
     }
+
+    fn base_url(&self) -> Option<String> {
+        Some(self.client.base_url.clone())
+    }
     
     fn info() -> ProviderInfo {
         ProviderInfo {
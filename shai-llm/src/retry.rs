@@ -0,0 +1,172 @@
+// llm/retry.rs
+use std::future::Future;
+use std::time::Duration;
+use std::io::ErrorKind;
+use openai_dive::v1::error::APIError;
+use regex::Regex;
+use crate::provider::LlmError;
+use crate::stream::StreamError;
+
+/// How llm calls failing for a transient reason (rate limit, overloaded server, network hiccup)
+/// are retried: up to `max_retries` times, waiting `base_delay` doubled at every attempt and
+/// capped at `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub fn none() -> Self {
+        Self { max_retries: 0, ..Self::default() }
+    }
+
+    /// Wait before the given retry, the first retry being attempt 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Errors worth retrying, anything else (bad request, authentication, ...) would fail again
+pub fn is_transient(error: &LlmError) -> bool {
+    // a stream cut short (idle, closed or reset connection) is worth another try
+    if error.downcast_ref::<StreamError>().is_some() {
+        return true;
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        if error.is_timeout() || error.is_connect() {
+            return true;
+        }
+        if let Some(status) = error.status() {
+            return is_transient_status(status.as_u16());
+        }
+    }
+    if let Some(error) = error.downcast_ref::<std::io::Error>() {
+        if matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused | ErrorKind::ConnectionAborted) {
+            return true;
+        }
+    }
+    match error.downcast_ref::<APIError>() {
+        Some(APIError::RateLimitError(_)) => return true,
+        Some(APIError::UnknownError(status, _)) => return is_transient_status(*status),
+        _ => {}
+    }
+
+    // providers wrap their http errors in their own types or in a plain message, only a status
+    // given as such is trusted, digits elsewhere (token counts, ports, model names) mean nothing
+    let message = error.to_string().to_lowercase();
+    if let Some(status) = http_status(&message) {
+        return is_transient_status(status);
+    }
+    ["rate limit", "rate_limit", "too many requests", "overloaded"].iter()
+        .any(|phrase| message.contains(phrase))
+}
+
+fn is_transient_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+/// Http status of an error message, when it starts with one ("503 Service Unavailable") or
+/// gives it after "status", "http" or "error" ("Ollama API error (503 ...)")
+fn http_status(message: &str) -> Option<u16> {
+    let status_regex = Regex::new(r"(?:^|\bstatus(?: code)?:?\s*|\bhttp(?:/[\d.]+)?\s*|\berror:?\s*\(?)([1-5]\d\d)\b").unwrap();
+    status_regex.captures(message)
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+/// Run an llm call, running it again on transient errors as allowed by the policy.
+/// `on_retry(attempt, delay)` is called before waiting for each retry.
+pub async fn with_retry<T, F, Fut>(policy: &RetryPolicy, on_retry: impl Fn(u32, Duration), mut call: F) -> Result<T, LlmError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(error) if attempt < policy.max_retries && is_transient(&error) => {
+                attempt += 1;
+                let delay = policy.delay(attempt);
+                on_retry(attempt, delay);
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_delay_doubles_up_to_the_cap() {
+        let policy = RetryPolicy { max_retries: 5, base_delay: Duration::from_secs(1), max_delay: Duration::from_secs(5) };
+        let delays: Vec<u64> = (1..=5).map(|attempt| policy.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_only_transient_errors_are_retried() {
+        let policy = RetryPolicy { max_retries: 3, base_delay: Duration::from_millis(1), max_delay: Duration::from_millis(1) };
+        let (calls, retries) = (&AtomicU32::new(0), &AtomicU32::new(0));
+
+        let result: Result<&str, LlmError> = with_retry(&policy, |_, _| { retries.fetch_add(1, Ordering::SeqCst); }, || async move {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(LlmError::from("503 Service Unavailable")),
+                _ => Ok("answer"),
+            }
+        }).await;
+        assert_eq!(result.unwrap(), "answer");
+        assert_eq!(retries.load(Ordering::SeqCst), 2);
+
+        calls.store(0, Ordering::SeqCst);
+        let result: Result<(), LlmError> = with_retry(&policy, |_, _| {}, || async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(LlmError::from("401 invalid api key"))
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_only_an_http_status_given_as_such_is_trusted() {
+        let transient = [
+            "503 Service Unavailable",
+            "HTTP 502 Bad Gateway",
+            "Ollama API error (503 Service Unavailable): model is loading",
+            "OpenRouter API error 429 Too Many Requests: slow down",
+            "Anthropic API error: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\"}}",
+            "Rate limit reached for requests",
+        ];
+        for message in transient {
+            assert!(is_transient(&LlmError::from(message)), "{}", message);
+        }
+
+        let permanent = [
+            "401 invalid api key",
+            "max_tokens 5000 is too large for this model",
+            "model gpt-4-0503 not found",
+            "base url http://localhost:5003/v1 returned a web page",
+            "OpenRouter API error 400 Bad Request: the prompt has 5020 tokens",
+            "This model's maximum context length is 8192 tokens",
+        ];
+        for message in permanent {
+            assert!(!is_transient(&LlmError::from(message)), "{}", message);
+        }
+    }
+}
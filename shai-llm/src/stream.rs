@@ -0,0 +1,339 @@
+// llm/stream.rs
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use openai_dive::v1::resources::{
+    chat::{ChatCompletionChunkResponse, ChatCompletionChoice, ChatCompletionResponse, ChatMessage, ChatMessageContent, DeltaChatMessage, DeltaToolCall, Function, ToolCall},
+    shared::{FinishReason, Usage},
+};
+use crate::provider::{LlmError, LlmStream};
+
+/// Time without a chunk after which a stream is given up, servers that drop the connection
+/// without closing it would keep the agent waiting forever
+pub const DEFAULT_STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A stream that did not get to its finish_reason, with what was received until then.
+/// Any of them is deemed transient, the request is worth sending again.
+#[derive(Debug)]
+pub enum StreamError {
+    /// no chunk came for `after`
+    Idle { after: Duration, partial: Box<ChatCompletionResponse> },
+    /// the stream ended without a terminal chunk
+    Truncated { partial: Box<ChatCompletionResponse> },
+    /// a chunk could not be received, e.g. the connection was reset
+    Interrupted { error: String, partial: Box<ChatCompletionResponse> },
+}
+
+impl StreamError {
+    /// The response assembled from the chunks received before the failure
+    pub fn partial(&self) -> &ChatCompletionResponse {
+        match self {
+            StreamError::Idle { partial, .. } | StreamError::Truncated { partial } | StreamError::Interrupted { partial, .. } => partial,
+        }
+    }
+
+    pub fn into_partial(self) -> ChatCompletionResponse {
+        match self {
+            StreamError::Idle { partial, .. } | StreamError::Truncated { partial } | StreamError::Interrupted { partial, .. } => *partial,
+        }
+    }
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Idle { after, .. } => write!(f, "stream timed out, no chunk received for {}s", after.as_secs()),
+            StreamError::Truncated { .. } => write!(f, "stream ended before the response was complete, connection closed by the server"),
+            StreamError::Interrupted { error, .. } => write!(f, "stream interrupted: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Tool call being received, its arguments come as json fragments
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+/// Rebuilds the assistant message of a streamed answer: text and reasoning deltas are
+/// concatenated, tool call fragments are grouped by their index and their arguments joined.
+#[derive(Debug, Default)]
+pub struct StreamAssembler {
+    id: Option<String>,
+    model: String,
+    content: String,
+    reasoning: String,
+    tool_calls: BTreeMap<u32, PartialToolCall>,
+    usage: Option<Usage>,
+    finish_reason: Option<FinishReason>,
+    chunks: usize,
+}
+
+impl StreamAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a chunk, returns the text it carries so that it can be shown as it arrives
+    pub fn push(&mut self, chunk: &ChatCompletionChunkResponse) -> Option<String> {
+        self.chunks += 1;
+        if self.id.is_none() {
+            self.id = chunk.id.clone();
+        }
+        if self.model.is_empty() {
+            self.model = chunk.model.clone();
+        }
+        if let Some(usage) = &chunk.usage {
+            self.usage = Some(usage.clone());
+        }
+
+        let mut text = String::new();
+        for choice in chunk.choices.iter().filter(|c| c.index.unwrap_or(0) == 0) {
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason.clone();
+            }
+            let (content, reasoning, tool_calls) = match &choice.delta {
+                DeltaChatMessage::Assistant { content, reasoning_content, tool_calls, .. } => (content, reasoning_content.as_ref(), tool_calls),
+                DeltaChatMessage::Untagged { content, tool_calls, .. } => (content, None, tool_calls),
+                _ => continue,
+            };
+            if let Some(ChatMessageContent::Text(delta)) = content {
+                text.push_str(delta);
+            }
+            if let Some(reasoning) = reasoning {
+                self.reasoning.push_str(reasoning);
+            }
+            for call in tool_calls.iter().flatten() {
+                self.push_tool_call(call);
+            }
+        }
+
+        self.content.push_str(&text);
+        (!text.is_empty()).then_some(text)
+    }
+
+    fn push_tool_call(&mut self, delta: &DeltaToolCall) {
+        // without an index a fragment continues the last call, unless it opens a new one with its id
+        let index = delta.index.unwrap_or_else(|| {
+            let last = self.tool_calls.keys().next_back().copied();
+            match (last, &delta.id) {
+                (Some(last), Some(id)) if self.tool_calls[&last].id.as_ref().is_some_and(|known| known != id) => last + 1,
+                (Some(last), _) => last,
+                (None, _) => 0,
+            }
+        });
+        let call = self.tool_calls.entry(index).or_default();
+        if let Some(id) = &delta.id {
+            call.id = Some(id.clone());
+        }
+        if let Some(name) = &delta.function.name {
+            call.name.push_str(name);
+        }
+        if let Some(arguments) = &delta.function.arguments {
+            call.arguments.push_str(arguments);
+        }
+    }
+
+    /// Whether the answer calls tools, known before the stream is over
+    pub fn has_tool_calls(&self) -> bool {
+        !self.tool_calls.is_empty()
+    }
+
+    /// Whether a chunk carried the finish_reason, a stream ending before that was cut short
+    pub fn is_finished(&self) -> bool {
+        self.finish_reason.is_some()
+    }
+
+    /// What was assembled so far, as written to the wire log once the stream is over
+    pub fn to_json(&self) -> Value {
+        let tool_calls: Vec<Value> = self.tool_calls.values()
+            .map(|call| json!({
+                "id": call.id,
+                "type": "function",
+                "function": { "name": call.name, "arguments": call.arguments },
+            }))
+            .collect();
+        json!({
+            "chunks": self.chunks,
+            "message": {
+                "role": "assistant",
+                "content": self.content,
+                "reasoning_content": (!self.reasoning.is_empty()).then_some(&self.reasoning),
+                "tool_calls": (!tool_calls.is_empty()).then_some(tool_calls),
+            },
+            "finish_reason": self.finish_reason,
+            "usage": self.usage,
+        })
+    }
+
+    /// The response the same request would have got without streaming
+    pub fn finish(self) -> ChatCompletionResponse {
+        let tool_calls: Vec<ToolCall> = self.tool_calls.into_iter().map(|(index, call)| ToolCall {
+            id: call.id.unwrap_or_else(|| format!("call_{}", index)),
+            r#type: "function".to_string(),
+            function: Function {
+                name: call.name,
+                arguments: if call.arguments.trim().is_empty() { "{}".to_string() } else { call.arguments },
+            },
+        }).collect();
+
+        let finish_reason = match self.finish_reason {
+            None if !tool_calls.is_empty() => Some(FinishReason::ToolCalls),
+            None => Some(FinishReason::StopSequenceReached),
+            reason => reason,
+        };
+
+        ChatCompletionResponse {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            created: 0,
+            model: self.model,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatMessage::Assistant {
+                    content: (!self.content.is_empty()).then_some(ChatMessageContent::Text(self.content)),
+                    reasoning_content: (!self.reasoning.is_empty()).then_some(self.reasoning),
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    refusal: None,
+                    name: None,
+                    audio: None,
+                },
+                finish_reason,
+                logprobs: None,
+            }],
+            usage: self.usage,
+            service_tier: None,
+            system_fingerprint: None,
+        }
+    }
+}
+
+/// Read a stream to its end and assemble the response, `on_text` is given the text deltas.
+/// Gives up after DEFAULT_STREAM_IDLE_TIMEOUT without a chunk, see `assemble_stream_with_timeout`.
+pub async fn assemble_stream(stream: LlmStream, on_text: impl FnMut(&str)) -> Result<ChatCompletionResponse, LlmError> {
+    assemble_stream_with_timeout(stream, DEFAULT_STREAM_IDLE_TIMEOUT, on_text).await
+}
+
+/// Read a stream to its end and assemble the response. A stream going silent for `idle_timeout`,
+/// failing or ending without a finish_reason fails with a StreamError holding the partial response.
+pub async fn assemble_stream_with_timeout(mut stream: LlmStream, idle_timeout: Duration, mut on_text: impl FnMut(&str)) -> Result<ChatCompletionResponse, LlmError> {
+    let mut assembler = StreamAssembler::new();
+    loop {
+        let chunk = match tokio::time::timeout(idle_timeout, stream.next()).await {
+            Err(_) => return Err(Box::new(StreamError::Idle { after: idle_timeout, partial: Box::new(assembler.finish()) })),
+            Ok(None) if !assembler.is_finished() => return Err(Box::new(StreamError::Truncated { partial: Box::new(assembler.finish()) })),
+            Ok(None) => return Ok(assembler.finish()),
+            Ok(Some(Err(error))) => return Err(Box::new(StreamError::Interrupted { error: error.to_string(), partial: Box::new(assembler.finish()) })),
+            Ok(Some(Ok(chunk))) => chunk,
+        };
+        if let Some(text) = assembler.push(&chunk) {
+            on_text(&text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(delta: serde_json::Value, finish_reason: Option<&str>) -> ChatCompletionChunkResponse {
+        serde_json::from_value(json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": "gpt",
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_tool_call_fragments_are_joined() {
+        let chunks = vec![
+            chunk(json!({ "role": "assistant", "content": "Let me " }), None),
+            chunk(json!({ "content": "look." }), None),
+            chunk(json!({ "tool_calls": [{ "index": 0, "id": "call_a", "type": "function", "function": { "name": "read", "arguments": "" } }] }), None),
+            chunk(json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "{\"path\":" } }] }), None),
+            chunk(json!({ "tool_calls": [{ "index": 1, "id": "call_b", "type": "function", "function": { "name": "ls", "arguments": "" } }] }), None),
+            chunk(json!({ "tool_calls": [{ "index": 0, "function": { "arguments": " \"a.rs\"}" } }] }), None),
+            chunk(json!({}), Some("tool_calls")),
+        ];
+
+        let mut assembler = StreamAssembler::new();
+        let text: String = chunks.iter().filter_map(|c| assembler.push(c)).collect();
+        assert_eq!(text, "Let me look.");
+        assert!(assembler.has_tool_calls());
+
+        let response = assembler.finish();
+        assert!(matches!(response.choices[0].finish_reason, Some(FinishReason::ToolCalls)));
+        let ChatMessage::Assistant { content: Some(ChatMessageContent::Text(content)), tool_calls: Some(calls), .. } = &response.choices[0].message else {
+            panic!("expected an assistant message with tool calls");
+        };
+        assert_eq!(content, "Let me look.");
+        assert_eq!(calls.len(), 2);
+        assert_eq!((calls[0].id.as_str(), calls[0].function.name.as_str(), calls[0].function.arguments.as_str()), ("call_a", "read", "{\"path\": \"a.rs\"}"));
+        assert_eq!((calls[1].id.as_str(), calls[1].function.arguments.as_str()), ("call_b", "{}"));
+    }
+
+    fn partial_text(error: &LlmError) -> String {
+        let error = error.downcast_ref::<StreamError>().expect("expected a stream error");
+        match &error.partial().choices[0].message {
+            ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), .. } => text.clone(),
+            _ => String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_stream_keeps_the_partial_answer() {
+        let chunks: Vec<Result<ChatCompletionChunkResponse, LlmError>> = vec![
+            Ok(chunk(json!({ "role": "assistant", "content": "The answer " }), None)),
+            Ok(chunk(json!({ "content": "is" }), None)),
+        ];
+        let stream: LlmStream = Box::new(futures::stream::iter(chunks));
+
+        let error = assemble_stream(stream, |_| {}).await.expect_err("a stream without finish_reason is incomplete");
+        assert!(matches!(error.downcast_ref::<StreamError>(), Some(StreamError::Truncated { .. })));
+        assert_eq!(partial_text(&error), "The answer is");
+        assert!(crate::retry::is_transient(&error));
+    }
+
+    #[tokio::test]
+    async fn test_silent_stream_times_out() {
+        let first = futures::stream::iter(vec![Ok::<_, LlmError>(chunk(json!({ "role": "assistant", "content": "Thinking" }), None))]);
+        let stream: LlmStream = Box::new(first.chain(futures::stream::pending()));
+
+        let error = assemble_stream_with_timeout(stream, Duration::from_millis(50), |_| {}).await.expect_err("the stream should time out");
+        assert!(matches!(error.downcast_ref::<StreamError>(), Some(StreamError::Idle { .. })));
+        assert_eq!(partial_text(&error), "Thinking");
+        assert!(crate::retry::is_transient(&error));
+    }
+
+    #[test]
+    fn test_assembled_stream_as_json() {
+        let mut assembler = StreamAssembler::new();
+        assembler.push(&chunk(json!({ "content": "Hel" }), None));
+        assembler.push(&chunk(json!({ "content": "lo" }), None));
+        assembler.push(&chunk(json!({ "tool_calls": [{ "index": 0, "id": "call_1", "type": "function", "function": { "name": "read", "arguments": "{\"path\":" } }] }), None));
+        assembler.push(&chunk(json!({ "tool_calls": [{ "index": 0, "function": { "arguments": "\"a.rs\"}" } }] }), Some("tool_calls")));
+
+        let assembled = assembler.to_json();
+        assert_eq!(assembled["chunks"], 4);
+        assert_eq!(assembled["message"]["content"], "Hello");
+        assert_eq!(assembled["message"]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(assembled["message"]["tool_calls"][0]["function"]["arguments"], "{\"path\":\"a.rs\"}");
+        assert_eq!(assembled["finish_reason"], "tool_calls");
+    }
+
+    #[test]
+    fn test_text_only_answer() {
+        let mut assembler = StreamAssembler::new();
+        assembler.push(&chunk(json!({ "role": "assistant", "content": "hello" }), Some("stop")));
+        let response = assembler.finish();
+        assert!(matches!(&response.choices[0].message, ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), tool_calls: None, .. } if text == "hello"));
+    }
+}
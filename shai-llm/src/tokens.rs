@@ -0,0 +1,43 @@
+use openai_dive::v1::resources::chat::ChatMessageContentPart;
+use crate::{ChatMessage, ChatMessageContent};
+
+/// Rough number of characters per token, good enough to gauge the size of a conversation
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Per message overhead (role, separators) added by chat templates
+const MESSAGE_OVERHEAD: u32 = 4;
+
+/// Estimate the tokens of a message without asking the provider (~4 characters per token)
+pub fn estimate_message_tokens(message: &ChatMessage) -> u32 {
+    let chars = match message {
+        ChatMessage::System { content, .. }
+        | ChatMessage::Developer { content, .. }
+        | ChatMessage::User { content, .. } => content_len(content),
+        ChatMessage::Assistant { content, tool_calls, .. } => {
+            content.as_ref().map(content_len).unwrap_or(0)
+                + tool_calls.iter().flatten()
+                    .map(|c| c.function.name.len() + c.function.arguments.len())
+                    .sum::<usize>()
+        }
+        ChatMessage::Tool { content, .. } => content.len(),
+    };
+    (chars / CHARS_PER_TOKEN) as u32 + MESSAGE_OVERHEAD
+}
+
+/// Estimate the tokens of a whole conversation, usable before any usage was reported by the provider
+pub fn estimate_tokens(messages: &[ChatMessage]) -> u32 {
+    messages.iter().map(estimate_message_tokens).sum()
+}
+
+fn content_len(content: &ChatMessageContent) -> usize {
+    match content {
+        ChatMessageContent::Text(text) => text.len(),
+        ChatMessageContent::ContentPart(parts) => parts.iter()
+            .map(|part| match part {
+                ChatMessageContentPart::Text(text_part) => text_part.text.len(),
+                _ => 0,
+            })
+            .sum(),
+        ChatMessageContent::None => 0,
+    }
+}
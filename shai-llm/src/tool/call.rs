@@ -3,7 +3,7 @@ use async_trait::async_trait;
 
 use openai_dive::v1::resources::chat::{ChatCompletionFunction, ChatCompletionParameters, ChatCompletionParametersBuilder, ChatCompletionResponse, ChatCompletionTool, ChatCompletionToolChoice, ChatCompletionToolType, ChatMessage};
 
-use crate::{provider::LlmError, tool::{call_fc_auto::ToolCallFunctionCallingAuto, call_fc_required::ToolCallFunctionCallingRequired, call_structured_output::ToolCallStructuredOutput, ToolBox}, LlmClient, ToolCallMethod, ToolDescription};
+use crate::{provider::LlmError, retry::is_transient, tool::{call_fc_auto::ToolCallFunctionCallingAuto, call_fc_required::ToolCallFunctionCallingRequired, call_parsing::ToolCallParsing, call_structured_output::ToolCallStructuredOutput, ToolBox}, LlmClient, ToolCallMethod, ToolDescription};
 
 
 #[async_trait]
@@ -14,6 +14,16 @@ pub trait LlmToolCall {
         tools: &ToolBox,
         method: ToolCallMethod
     ) -> Result<ChatCompletionResponse, LlmError>;
+
+    /// Same as chat_with_tools but walks down the method chain when the provider rejects the
+    /// method itself (tools or response_format not supported). Returns the method to keep using,
+    /// which is the given one unless the provider forced a fallback.
+    async fn chat_with_tools_fallback(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        method: ToolCallMethod
+    ) -> Result<(ChatCompletionResponse, ToolCallMethod), LlmError>;
 }
 
 #[async_trait]
@@ -24,7 +34,7 @@ impl LlmToolCall for LlmClient {
         tools: &ToolBox,
         method: ToolCallMethod
     ) -> Result<ChatCompletionResponse, LlmError> {
-        match method {
+        match self.resolve_method(method, &request.model) {
             ToolCallMethod::Auto => {
                 self.chat_with_tools_try_all(request, tools).await
             }
@@ -38,12 +48,76 @@ impl LlmToolCall for LlmClient {
                 self.chat_with_tools_so(request, tools).await
             }
             ToolCallMethod::Parsing => {
-                Err(LlmError::from("method not supported"))
+                self.chat_with_tools_parsing(request, tools).await
+            }
+        }
+    }
+
+    async fn chat_with_tools_fallback(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox,
+        method: ToolCallMethod
+    ) -> Result<(ChatCompletionResponse, ToolCallMethod), LlmError> {
+        let first = match self.resolve_method(method, &request.model) {
+            ToolCallMethod::Auto => ToolCallMethod::FunctionCall,
+            method => method,
+        };
+
+        // transient errors are left to the caller's retries and garbled tool calls to the agent,
+        // another method would not do any better with them
+        let mut current = first;
+        loop {
+            match self.chat_with_tools(request.clone(), tools, current).await {
+                Ok(response) => return Ok((response, if current == first { method } else { current })),
+                Err(e) if is_unsupported_method(&e) => match current.fallback() {
+                    Some(next) => current = next,
+                    None => return Err(e),
+                },
+                Err(e) => return Err(e),
             }
         }
     }
 }
 
+/// Whether the provider refused the request because of the tool call method it relies on,
+/// e.g. a 400 saying tools or response_format are not supported by the model
+pub fn is_unsupported_method(error: &LlmError) -> bool {
+    if is_transient(error) {
+        return false;
+    }
+    if let Some(status) = error.downcast_ref::<reqwest::Error>().and_then(|error| error.status()) {
+        if status.as_u16() != 400 && status.as_u16() != 422 {
+            return false;
+        }
+    }
+
+    let message = error.to_string().to_lowercase();
+    let feature = ["tool", "function", "response_format", "json_schema", "structured output"]
+        .iter().any(|pattern| message.contains(pattern));
+    let unsupported = ["not supported", "unsupported", "does not support", "not available", "not enabled"]
+        .iter().any(|pattern| message.contains(pattern));
+    feature && unsupported
+}
+
+impl LlmClient {
+    /// Downgrade to prompt parsing when the model lacks the capability the method relies on
+    fn resolve_method(&self, method: ToolCallMethod, model: &str) -> ToolCallMethod {
+        let provider = self.provider();
+        match method {
+            ToolCallMethod::Auto
+            | ToolCallMethod::FunctionCall
+            | ToolCallMethod::FunctionCallRequired if !provider.supports_functions(model.to_string()) => {
+                ToolCallMethod::Parsing
+            }
+            ToolCallMethod::StructuredOutput if !provider.supports_structured_output(model.to_string()) => {
+                ToolCallMethod::Parsing
+            }
+            method => method,
+        }
+    }
+}
+
 #[async_trait]
 pub trait ToolCallAuto {
     async fn chat_with_tools_try_all(
@@ -60,14 +134,35 @@ impl ToolCallAuto for LlmClient {
         request: ChatCompletionParameters,
         tools: &ToolBox
     ) -> Result<ChatCompletionResponse, LlmError> {
-        if let Ok(result) = self.chat_with_tools_fc_auto(request.clone(), tools).await {
-            return Ok(result);
+        self.chat_with_tools_fallback(request, tools, ToolCallMethod::Auto)
+            .await
+            .map(|(response, _)| response)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_method_rejections_trigger_a_fallback() {
+        let rejected = [
+            "400 Bad Request: tools are not supported for this model",
+            "invalid_request_error: response_format json_schema is unsupported",
+            "this model does not support function calling",
+        ];
+        for message in rejected {
+            assert!(is_unsupported_method(&LlmError::from(message)), "{}", message);
         }
-        
-        if let Ok(result) = self.chat_with_tools_fc_required(request.clone(), tools).await {
-            return Ok(result);
+
+        let others = [
+            "503 Service Unavailable",
+            "429 rate limit reached for tool use",
+            "401 invalid api key",
+            "400 Bad Request: messages must not be empty",
+            "invalid tool call with method FunctionCall",
+        ];
+        for message in others {
+            assert!(!is_unsupported_method(&LlmError::from(message)), "{}", message);
         }
-        
-        self.chat_with_tools_so(request, tools).await
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,115 @@
+use async_trait::async_trait;
+use openai_dive::v1::resources::chat::{ChatCompletionParameters, ChatCompletionResponse};
+use crate::provider::LlmError;
+use crate::tool::{AssistantResponse, IntoChatMessage, ToolBox};
+use crate::{ChatMessage, ChatMessageContent, LlmClient};
+
+#[async_trait]
+pub trait ToolCallParsing {
+    async fn chat_with_tools_parsing(
+        &self,
+        request: ChatCompletionParameters,
+        tools: &ToolBox
+    ) -> Result<ChatCompletionResponse, LlmError>;
+}
+
+#[async_trait]
+impl ToolCallParsing for LlmClient {
+    /// For models without function calling nor response_format: document the tools
+    /// in the system prompt, ask for a json block and parse it back from the content
+    async fn chat_with_tools_parsing(
+        &self,
+        mut request: ChatCompletionParameters,
+        tools: &ToolBox
+    ) -> Result<ChatCompletionResponse, LlmError> {
+        let instructions = parsing_instructions(tools);
+        match request.messages.get_mut(0) {
+            Some(ChatMessage::System { content: ChatMessageContent::Text(ref mut system_text), .. }) => {
+                system_text.push_str(&instructions);
+            }
+            _ => {
+                request.messages.insert(0, ChatMessage::System {
+                    content: ChatMessageContent::Text(instructions),
+                    name: None,
+                });
+            }
+        }
+        request.tools = None;
+        request.tool_choice = None;
+        request.response_format = None;
+
+        let mut response = self.chat(request).await?;
+
+        if let Some(ChatMessage::Assistant { content: Some(ChatMessageContent::Text(text)), reasoning_content, .. }) = response.choices.first().map(|c| &c.message) {
+            // a model that ignored the format simply answered without tools
+            if let Some(mut parsed) = parse_assistant_response(text) {
+                if parsed.reasoning_content.is_none() {
+                    parsed.reasoning_content = reasoning_content.clone();
+                }
+                response.choices[0].message = parsed.into_chatmessage();
+            }
+        }
+        Ok(response)
+    }
+}
+
+fn parsing_instructions(tools: &ToolBox) -> String {
+    let mut doc = String::from("\n\n# Available Tools\n\nYou have access to the following tools:\n\n");
+    for tool in tools {
+        doc.push_str(&format!("## {}\n", tool.name()));
+        doc.push_str(&format!("**Description**: {}\n\n", tool.description()));
+        doc.push_str("**Parameters Schema**:\n```json\n");
+        doc.push_str(&serde_json::to_string_pretty(&tool.parameters_schema()).unwrap_or_default());
+        doc.push_str("\n```\n\n");
+    }
+    doc.push_str("# Response Format\n\n");
+    doc.push_str("Always answer with a single ```json block containing an object of the form ");
+    doc.push_str("{\"content\": \"<your message>\", \"tools\": [{\"tool_name\": \"<name>\", \"tool_parameter\": {...}}]}. ");
+    doc.push_str("Set \"tools\" to null when no tool is needed.\n");
+    doc
+}
+
+/// Extract an AssistantResponse from a fenced json block, or from the outermost braces
+pub(crate) fn parse_assistant_response(text: &str) -> Option<AssistantResponse> {
+    let fenced = text.find("```json")
+        .map(|start| &text[start + 7..])
+        .and_then(|rest| rest.find("```").map(|end| &rest[..end]));
+
+    let candidate = match fenced {
+        Some(block) => block.trim(),
+        None => {
+            let start = text.find('{')?;
+            let end = text.rfind('}')?;
+            if end < start {
+                return None;
+            }
+            &text[start..=end]
+        }
+    };
+
+    serde_json::from_str(candidate).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fenced_block() {
+        let text = "Let me look.\n```json\n{\"content\": \"reading\", \"tools\": [{\"tool_name\": \"read\", \"tool_parameter\": {\"path\": \"a.rs\"}}]}\n```";
+        let parsed = parse_assistant_response(text).unwrap();
+        assert_eq!(parsed.content, "reading");
+        let tools = parsed.tools.unwrap();
+        assert_eq!(tools[0].tool_name, "read");
+        assert_eq!(tools[0].tool_parameter["path"], "a.rs");
+    }
+
+    #[test]
+    fn test_parse_bare_object_and_plain_text() {
+        let parsed = parse_assistant_response("sure: {\"content\": \"done\", \"tools\": null}").unwrap();
+        assert_eq!(parsed.content, "done");
+        assert!(parsed.tools.is_none());
+
+        assert!(parse_assistant_response("no json here").is_none());
+    }
+}
@@ -0,0 +1,162 @@
+use serde_json::Value;
+
+/// Check tool arguments against the tool's parameter schema before dispatching them.
+/// Covers what the schemas generated for tools use: type (or list of types), required,
+/// properties, additionalProperties: false, enum, items, anyOf/oneOf and local $ref.
+/// Errors are joined in a message meant to be sent back to the model.
+pub fn validate_arguments(arguments: &Value, schema: &Value) -> Result<(), String> {
+    let mut errors = Vec::new();
+    validate(arguments, schema, schema, "arguments", &mut errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+fn validate(value: &Value, schema: &Value, root: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // `true` or an empty schema accepts anything
+        return;
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if let Some(target) = resolve_ref(root, reference) {
+            validate(value, target, root, path, errors);
+        }
+        return;
+    }
+
+    for key in ["anyOf", "oneOf"] {
+        if let Some(variants) = schema.get(key).and_then(Value::as_array) {
+            let matches = variants.iter().any(|variant| {
+                let mut variant_errors = Vec::new();
+                validate(value, variant, root, path, &mut variant_errors);
+                variant_errors.is_empty()
+            });
+            if !matches {
+                errors.push(format!("{} does not match any of the allowed shapes", path));
+            }
+            return;
+        }
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{} should be {}, got {}", path, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            let allowed: Vec<String> = allowed.iter().map(Value::to_string).collect();
+            errors.push(format!("{} should be one of {}", path, allowed.join(", ")));
+        }
+    }
+
+    if let Value::Object(fields) = value {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !fields.contains_key(name) {
+                    errors.push(format!("{}.{} is required", path, name));
+                }
+            }
+        }
+        for (name, field) in fields {
+            match properties.and_then(|p| p.get(name)) {
+                Some(field_schema) => validate(field, field_schema, root, &format!("{}.{}", path, name), errors),
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    errors.push(format!("{}.{} is not a known parameter", path, name));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate(item, item_schema, root, &format!("{}[{}]", path, i), errors);
+        }
+    }
+}
+
+/// Resolve `#/$defs/Name` (or `#/definitions/Name`) inside the root schema
+fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": { "type": "string" },
+                "line_start": { "type": ["integer", "null"] },
+                "mode": { "enum": ["read", "write"] },
+                "options": { "$ref": "#/$defs/Options" }
+            },
+            "required": ["path"],
+            "additionalProperties": false,
+            "$defs": {
+                "Options": {
+                    "type": "object",
+                    "properties": { "recursive": { "type": "boolean" } }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_valid_arguments() {
+        let arguments = json!({ "path": "src/main.rs", "line_start": 4, "mode": "read", "options": { "recursive": true } });
+        assert!(validate_arguments(&arguments, &schema()).is_ok());
+        assert!(validate_arguments(&json!({ "path": "a", "line_start": null }), &schema()).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_all_reported() {
+        let arguments = json!({ "line_start": "4", "mode": "delete", "force": true, "options": { "recursive": "yes" } });
+        let error = validate_arguments(&arguments, &schema()).unwrap_err();
+        assert!(error.contains("arguments.path is required"));
+        assert!(error.contains("arguments.line_start should be integer or null, got string"));
+        assert!(error.contains("arguments.mode should be one of"));
+        assert!(error.contains("arguments.force is not a known parameter"));
+        assert!(error.contains("arguments.options.recursive should be boolean"));
+    }
+}
@@ -193,6 +193,6 @@ mod structured_output_integration_tests {
         anthropic: "claude-3-5-sonnet-20241022", "ANTHROPIC_API_KEY";
         mistral: "mistral-large-latest", "MISTRAL_API_KEY";
         ovhcloud: "Mistral-Nemo-Instruct-2407", "MISTRAL_API_KEY";
-        ollama: "smollm2:latest", "OLLAMA_BASE_URL"
+        ollama: "smollm2:latest", "OLLAMA_HOST"
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ToolCallMethod {
     /// let the system decide what technique to use
     Auto,               
@@ -15,6 +15,29 @@ pub enum ToolCallMethod {
     Parsing,            
 }
 
+impl ToolCallMethod {
+    /// Best method a model can use: native function calling, then a json schema enforced on
+    /// the answer, then tags parsed out of plain text which any model can produce
+    pub fn for_capabilities(functions: bool, structured_output: bool) -> ToolCallMethod {
+        match (functions, structured_output) {
+            (true, _) => ToolCallMethod::FunctionCall,
+            (false, true) => ToolCallMethod::StructuredOutput,
+            (false, false) => ToolCallMethod::Parsing,
+        }
+    }
+
+    /// Method to try next when this one is rejected by the provider, Auto starts the chain at FunctionCall
+    pub fn fallback(self) -> Option<ToolCallMethod> {
+        match self {
+            ToolCallMethod::Auto => Some(ToolCallMethod::FunctionCall),
+            ToolCallMethod::FunctionCall => Some(ToolCallMethod::FunctionCallRequired),
+            ToolCallMethod::FunctionCallRequired => Some(ToolCallMethod::StructuredOutput),
+            ToolCallMethod::StructuredOutput => Some(ToolCallMethod::Parsing),
+            ToolCallMethod::Parsing => None,
+        }
+    }
+}
+
 /// A tool must be able to describe its parameter as a json schema
 pub trait ToolDescription: Send + Sync {
 
@@ -0,0 +1,92 @@
+// llm/wire_log.rs
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::stream::StreamAssembler;
+
+/// Environment variable holding the file the provider traffic is appended to
+pub const WIRE_LOG_ENV: &str = "SHAI_LLM_LOG";
+
+/// Appends the exact request and response bodies exchanged with a provider to a file,
+/// one json record per line. Secrets registered with `redact` never reach the file.
+#[derive(Debug, Clone)]
+pub struct WireLog {
+    path: PathBuf,
+    secrets: Vec<String>,
+}
+
+impl WireLog {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), secrets: Vec::new() }
+    }
+
+    /// Enabled when SHAI_LLM_LOG points to a file
+    pub fn from_env() -> Option<Self> {
+        std::env::var(WIRE_LOG_ENV).ok()
+            .filter(|path| !path.is_empty())
+            .map(Self::new)
+    }
+
+    /// Replace this value (typically the api key) wherever it would appear in the log
+    pub fn redact(mut self, secret: impl Into<String>) -> Self {
+        let secret = secret.into();
+        if !secret.is_empty() {
+            self.secrets.push(secret);
+        }
+        self
+    }
+
+    pub fn request(&self, provider: &str, request: &impl Serialize) {
+        self.record(provider, "request", serde_json::to_value(request).unwrap_or(Value::Null));
+    }
+
+    pub fn response(&self, provider: &str, response: &impl Serialize) {
+        self.record(provider, "response", serde_json::to_value(response).unwrap_or(Value::Null));
+    }
+
+    /// The message rebuilt from all the chunks of a streamed response
+    pub fn stream_response(&self, provider: &str, assembled: &StreamAssembler) {
+        self.record(provider, "stream_response", assembled.to_json());
+    }
+
+    pub fn error(&self, provider: &str, error: &str) {
+        self.record(provider, "error", Value::String(error.to_string()));
+    }
+
+    fn record(&self, provider: &str, kind: &str, body: Value) {
+        let record = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "provider": provider,
+            "kind": kind,
+            "body": body,
+        });
+        let mut line = record.to_string();
+        for secret in &self.secrets {
+            line = line.replace(secret.as_str(), "[REDACTED]");
+        }
+        // logging must never break a request, a failing write is dropped
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let path = std::env::temp_dir().join(format!("shai-wire-log-{}.jsonl", uuid::Uuid::new_v4()));
+        let log = WireLog::new(&path).redact("sk-secret");
+        log.request("test", &json!({ "authorization": "Bearer sk-secret", "model": "m" }));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(!written.contains("sk-secret"));
+        assert!(written.contains("Bearer [REDACTED]"));
+        assert!(written.contains("\"kind\":\"request\""));
+    }
+}